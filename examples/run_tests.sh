@@ -13,7 +13,7 @@ cargo build --release 2>&1 | tail -1
 RUNEXP="./target/release/runexp"
 
 # Clean up any existing results
-rm -f test_results*.csv
+rm -f test_results*.csv test_results*.csv.meta test_results*.csv.snapshot
 
 # Helper function to count CSV rows (excluding header)
 # Uses python to properly handle quoted multiline fields
@@ -351,6 +351,6 @@ echo "=== All tests passed! ==="
 echo "Results can be opened in Excel or any CSV viewer."
 
 # Clean up test files
-rm -f test_results*.csv
+rm -f test_results*.csv test_results*.csv.meta test_results*.csv.snapshot
 
 echo "✓ Cleaned up test files"
@@ -21,7 +21,7 @@ echo "=== Testing concurrent execution in faulty scenarios ==="
 echo
 
 # Clean up any previous test files
-rm -f test_concurrent_*.csv test_concurrent_*.py
+rm -f test_concurrent_*.csv test_concurrent_*.csv.meta test_concurrent_*.csv.snapshot test_concurrent_*.py
 
 # Build the project
 cargo build --release
@@ -261,6 +261,6 @@ echo -e "${GREEN}✓ Concurrent resume works correctly - no corruption or duplic
 echo
 
 # Clean up
-rm -f test_concurrent_*.csv test_concurrent_*.py
+rm -f test_concurrent_*.csv test_concurrent_*.csv.meta test_concurrent_*.csv.snapshot test_concurrent_*.py
 
 echo "=== All concurrent failure scenario tests passed! ==="
@@ -0,0 +1,144 @@
+"""Python bindings for runexp.
+
+Sweeps are defined in Python and expanded and run by the runexp library in-process,
+so the scheduler, result files and resume logic are the same as on the command line.
+
+    from runexp import Sweep
+
+    sweep = (Sweep()
+             .param("lr", "0.1,0.01")
+             .param("batch", "32,64")
+             .command("python", "train.py")
+             .metrics("accuracy", "loss"))
+    for combo in sweep.combinations():
+        print(combo)               # {'LR': 0.1, 'BATCH': 32}
+    for run in sweep.run():
+        print(run["LR"], run["accuracy"])
+
+The library is built with `cargo build --release` (target/release/librunexp.so, or
+runexp.dll / librunexp.dylib). Set RUNEXP_LIB to load it from another path.
+"""
+
+import csv
+import ctypes
+import io
+import os
+import sys
+
+__all__ = ["Sweep", "RunexpError"]
+
+
+class RunexpError(Exception):
+    pass
+
+
+def _library_path():
+    if "RUNEXP_LIB" in os.environ:
+        return os.environ["RUNEXP_LIB"]
+    if sys.platform == "win32":
+        name = "runexp.dll"
+    elif sys.platform == "darwin":
+        name = "librunexp.dylib"
+    else:
+        name = "librunexp.so"
+    root = os.path.dirname(os.path.dirname(os.path.abspath(__file__)))
+    for profile in ("release", "debug"):
+        path = os.path.join(root, "target", profile, name)
+        if os.path.exists(path):
+            return path
+    return name
+
+
+_lib = None
+
+
+def _load():
+    global _lib
+    if _lib is None:
+        _lib = ctypes.CDLL(_library_path())
+        signature = [ctypes.c_int, ctypes.POINTER(ctypes.c_char_p), ctypes.POINTER(ctypes.c_void_p)]
+        for name in ("runexp_combinations", "runexp_run"):
+            getattr(_lib, name).argtypes = signature
+            getattr(_lib, name).restype = ctypes.c_int
+        _lib.runexp_free.argtypes = [ctypes.c_void_p]
+    return _lib
+
+
+def _call(function, args):
+    lib = _load()
+    argv = (ctypes.c_char_p * len(args))(*[a.encode() for a in args])
+    out = ctypes.c_void_p()
+    code = getattr(lib, function)(len(args), argv, ctypes.byref(out))
+    try:
+        text = ctypes.string_at(out).decode()
+    finally:
+        lib.runexp_free(out)
+    if code != 0:
+        raise RunexpError(text)
+    return list(csv.DictReader(io.StringIO(text)))
+
+
+def _number(value):
+    for convert in (int, float):
+        try:
+            return convert(value)
+        except ValueError:
+            pass
+    return value
+
+
+class Sweep:
+    """A parameter sweep. Methods return the sweep so calls can be chained."""
+
+    def __init__(self):
+        self._params = []
+        self._options = []
+        self._command = []
+
+    def param(self, name, values):
+        """Add a parameter using the command line syntax, e.g. "1,2,4" or "1:8:2"."""
+        self._params += ["--" + name, str(values)]
+        return self
+
+    def command(self, *args):
+        """The command run for every combination."""
+        self._command = [str(a) for a in args]
+        return self
+
+    def script(self, text):
+        """A bash script run for every combination."""
+        return self.command("bash", "-c", text)
+
+    def metrics(self, *names):
+        return self.option("--metrics", ",".join(names))
+
+    def output(self, path):
+        return self.option("--output", path)
+
+    def concurrency(self, n):
+        return self.option("--concurrency", n)
+
+    def option(self, *args):
+        """Any other command line option, e.g. sweep.option("--tag", "host=gpu1")."""
+        self._options += [str(a) for a in args]
+        return self
+
+    def _args(self, command):
+        return self._options + self._params + command
+
+    def combinations(self):
+        """The combinations that would run, as dicts of parameter values."""
+        # The command is not run, but one is needed to parse the arguments
+        rows = _call("runexp_combinations", self._args(self._command or ["true"]))
+        return [{key: _number(value) for key, value in row.items()} for row in rows]
+
+    def run(self):
+        """Run the sweep and return one dict per combination. Values are converted to
+        numbers where possible; `resumed` tells if a result came from an earlier run."""
+        if not self._command:
+            raise RunexpError("No command specified")
+        rows = _call("runexp_run", self._args(self._command))
+        for row in rows:
+            for key, value in row.items():
+                row[key] = value == "true" if key == "resumed" else _number(value)
+        return rows
@@ -0,0 +1,151 @@
+use crate::csv::escape_csv_field;
+use crate::executor::RunResult;
+use std::collections::HashMap;
+use std::fs;
+
+// With --repeat N every combination runs N times, one row per repetition with a REP
+// column. The repetitions of each combination are summarized in a second file, e.g.
+// results.agg.csv for results.csv, with the mean, standard deviation and number of
+// values of every metric.
+
+// Parameters that differ between the repetitions of one combination
+const PER_REPETITION: [&str; 2] = ["REP", "SEED"];
+
+pub fn aggregate_path(output_file: &str) -> String {
+    match output_file.strip_suffix(".csv") {
+        Some(stem) => format!("{}.agg.csv", stem),
+        None => format!("{}.agg.csv", output_file),
+    }
+}
+
+// Header and one row per combination, in the order of their first run: the parameters
+// other than REP and SEED, then <metric>_mean, <metric>_std and <metric>_n for each
+// metric column. Failed runs and non-numeric values are left out; the standard
+// deviation is that of a sample and empty for fewer than two values.
+pub fn aggregate_runs(
+    runs: &[RunResult],
+    params: &[String],
+    metrics: &[String],
+) -> Vec<Vec<String>> {
+    let params: Vec<&String> = params
+        .iter()
+        .filter(|name| !PER_REPETITION.contains(&name.as_str()))
+        .collect();
+
+    let mut groups: Vec<(Vec<String>, Vec<&RunResult>)> = Vec::new();
+    let mut index: HashMap<Vec<String>, usize> = HashMap::new();
+    for run in runs {
+        let key: Vec<String> = params
+            .iter()
+            .map(|name| run.params.get(*name).cloned().unwrap_or_default())
+            .collect();
+        let i = *index.entry(key.clone()).or_insert_with(|| {
+            groups.push((key, Vec::new()));
+            groups.len() - 1
+        });
+        if !run.failed {
+            groups[i].1.push(run);
+        }
+    }
+
+    let mut header: Vec<String> = params.iter().map(|name| name.to_string()).collect();
+    for metric in metrics {
+        for stat in ["mean", "std", "n"] {
+            header.push(format!("{}_{}", metric, stat));
+        }
+    }
+    let mut rows = vec![header];
+    for (key, group) in groups {
+        let mut row = key;
+        for metric in metrics {
+            let values: Vec<f64> = group.iter().filter_map(|run| run.metric(metric)).collect();
+            let (mean, std) = mean_std(&values);
+            row.push(mean.map(format_stat).unwrap_or_default());
+            row.push(std.map(format_stat).unwrap_or_default());
+            row.push(values.len().to_string());
+        }
+        rows.push(row);
+    }
+    rows
+}
+
+pub fn write_aggregate(path: &str, rows: &[Vec<String>]) -> Result<(), String> {
+    let content: String = rows
+        .iter()
+        .map(|row| {
+            let fields: Vec<String> = row.iter().map(|v| escape_csv_field(v)).collect();
+            format!("{}\n", fields.join(","))
+        })
+        .collect();
+    fs::write(path, content).map_err(|e| format!("Failed to write {}: {}", path, e))
+}
+
+fn mean_std(values: &[f64]) -> (Option<f64>, Option<f64>) {
+    if values.is_empty() {
+        return (None, None);
+    }
+    let n = values.len() as f64;
+    let mean = values.iter().sum::<f64>() / n;
+    if values.len() < 2 {
+        return (Some(mean), None);
+    }
+    let variance = values.iter().map(|v| (v - mean).powi(2)).sum::<f64>() / (n - 1.0);
+    (Some(mean), Some(variance.sqrt()))
+}
+
+// Six decimals without trailing zeros, e.g. "0.5" rather than "0.500000"
+fn format_stat(value: f64) -> String {
+    let text = format!("{:.6}", value);
+    let text = text.trim_end_matches('0').trim_end_matches('.');
+    if text == "-0" {
+        "0".to_string()
+    } else {
+        text.to_string()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn run(x: &str, rep: &str, loss: &str, failed: bool) -> RunResult {
+        RunResult {
+            index: 0,
+            params: HashMap::from([
+                ("X".to_string(), x.to_string()),
+                ("REP".to_string(), rep.to_string()),
+            ]),
+            metrics: HashMap::from([("loss".to_string(), loss.to_string())]),
+            stdout: Vec::new(),
+            stderr: Vec::new(),
+            artifacts: Vec::new(),
+            resumed: false,
+            failed,
+            duration_s: None,
+            usage: None,
+        }
+    }
+
+    #[test]
+    fn test_aggregate_runs() {
+        let runs = vec![
+            run("1", "1", "0.5", false),
+            run("1", "2", "0.7", false),
+            run("2", "1", "0.4", false),
+            run("2", "2", "", true),
+            run("1", "3", "0.6", false),
+        ];
+        let params = ["X", "REP"].map(String::from);
+        let rows = aggregate_runs(&runs, &params, &["loss".to_string()]);
+        assert_eq!(
+            rows,
+            vec![
+                vec!["X", "loss_mean", "loss_std", "loss_n"],
+                vec!["1", "0.6", "0.1", "3"],
+                vec!["2", "0.4", "", "1"],
+            ]
+        );
+        assert_eq!(aggregate_path("out/results.csv"), "out/results.agg.csv");
+        assert_eq!(aggregate_path("results.tsv"), "results.tsv.agg.csv");
+    }
+}
@@ -0,0 +1,137 @@
+use crate::glob::glob_paths;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+// Files a run leaves in the working directory (--collect) are moved into a directory of
+// their own, e.g. "checkpoints/best.pt" of the run with id 3f2a9c1b7e04 to
+// "artifacts/3f2a9c1b7e04/checkpoints/best.pt", so the next run cannot overwrite them. With
+// --collect-copy the files are copied instead and stay where the run left them.
+
+// Move (or copy) the files under `base` matching `patterns` into `dest`, keeping their
+// relative paths, and return their new paths. Nothing is created when no file matches.
+pub fn collect_artifacts(
+    base: &Path,
+    patterns: &[String],
+    dest: &Path,
+    copy: bool,
+) -> Result<Vec<PathBuf>, String> {
+    let mut collected = Vec::new();
+    let mut cleared = false;
+    // Patterns are expanded one at a time, so overlapping patterns do not move a file twice
+    for pattern in patterns {
+        for path in glob_paths(base, pattern) {
+            // Leftovers of an earlier attempt of the same combination are replaced
+            if !cleared {
+                if dest.exists() {
+                    fs::remove_dir_all(dest)
+                        .map_err(|e| format!("Failed to clear {}: {}", dest.display(), e))?;
+                }
+                cleared = true;
+            }
+            let target = dest.join(&path);
+            if let Some(parent) = target.parent() {
+                fs::create_dir_all(parent)
+                    .map_err(|e| format!("Failed to create {}: {}", parent.display(), e))?;
+            }
+            if copy {
+                copy_path(&base.join(&path), &target)?;
+            } else {
+                move_path(&base.join(&path), &target)?;
+            }
+            collected.push(target);
+        }
+    }
+    Ok(collected)
+}
+
+// Rename, or copy and delete when the destination is on another file system
+fn move_path(from: &Path, to: &Path) -> Result<(), String> {
+    if fs::rename(from, to).is_ok() {
+        return Ok(());
+    }
+    copy_path(from, to)?;
+    let removed = if from.is_dir() {
+        fs::remove_dir_all(from)
+    } else {
+        fs::remove_file(from)
+    };
+    removed.map_err(|e| format!("Failed to remove {}: {}", from.display(), e))
+}
+
+// Copy a file, or a directory with everything in it
+fn copy_path(from: &Path, to: &Path) -> Result<(), String> {
+    let error = |e: std::io::Error| {
+        format!(
+            "Failed to copy {} to {}: {}",
+            from.display(),
+            to.display(),
+            e
+        )
+    };
+    if from.is_dir() {
+        fs::create_dir_all(to).map_err(error)?;
+        for entry in fs::read_dir(from).map_err(error)? {
+            let entry = entry.map_err(error)?;
+            copy_path(&entry.path(), &to.join(entry.file_name()))?;
+        }
+        Ok(())
+    } else {
+        fs::copy(from, to).map(|_| ()).map_err(error)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_collect_artifacts() {
+        let base = std::env::temp_dir().join("runexp_test_artifacts");
+        let _ = fs::remove_dir_all(&base);
+        fs::create_dir_all(base.join("plots")).unwrap();
+        fs::create_dir_all(base.join("checkpoints")).unwrap();
+        for file in ["plots/a.png", "plots/b.png", "plots/.c.png", "plots/d.txt"] {
+            fs::write(base.join(file), file).unwrap();
+        }
+        fs::write(base.join("checkpoints/best.pt"), "weights").unwrap();
+
+        let dest = base.join("artifacts/1");
+        let patterns = [
+            "checkpoints/best.pt",
+            "plots/*.png",
+            "plots/a.png",
+            "missing/*",
+        ]
+        .map(String::from)
+        .to_vec();
+        let collected = collect_artifacts(&base, &patterns, &dest, false).unwrap();
+        assert_eq!(
+            collected,
+            vec![
+                dest.join("checkpoints/best.pt"),
+                dest.join("plots/a.png"),
+                dest.join("plots/b.png"),
+            ]
+        );
+        assert_eq!(fs::read_to_string(&collected[1]).unwrap(), "plots/a.png");
+        assert!(!base.join("plots/a.png").exists());
+        assert!(base.join("plots/.c.png").exists());
+        assert!(base.join("plots/d.txt").exists());
+
+        // Nothing matches any more; the collected files stay where they are
+        assert!(
+            collect_artifacts(&base, &patterns, &dest, false)
+                .unwrap()
+                .is_empty()
+        );
+        assert!(dest.join("plots/b.png").exists());
+
+        // Copies leave the files in place, replacing the earlier collection
+        let patterns = vec!["plots/*.txt".to_string()];
+        let collected = collect_artifacts(&base, &patterns, &dest, true).unwrap();
+        assert_eq!(collected, vec![dest.join("plots/d.txt")]);
+        assert!(base.join("plots/d.txt").exists());
+        assert!(!dest.join("plots/b.png").exists());
+        let _ = fs::remove_dir_all(&base);
+    }
+}
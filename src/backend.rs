@@ -0,0 +1,1298 @@
+use crate::parser::{Options, Stage};
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::Read;
+use std::path::Path;
+use std::process::{Child, Command, Stdio};
+use std::sync::Mutex;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::time::{Duration, Instant};
+
+#[cfg(unix)]
+use std::ffi::c_int;
+#[cfg(target_os = "linux")]
+use std::ffi::c_long;
+#[cfg(unix)]
+use std::os::unix::process::CommandExt;
+
+#[cfg(windows)]
+use std::os::windows::process::CommandExt;
+
+// Variable holding the cores a run is pinned to with --cpus-per-run, as a list like
+// "0-3" or "0-1,4-5"; the local backend sets the affinity of commands that get it
+pub const CPUS_VARIABLE: &str = "RUNEXP_CPUS";
+
+// Variables holding the scheduling priority of a run's commands (--nice, --ionice), which
+// the local backend applies to the commands that get them
+pub const NICE_VARIABLE: &str = "RUNEXP_NICE";
+pub const IONICE_VARIABLE: &str = "RUNEXP_IONICE";
+
+// Variable holding the file a run's commands read on stdin (--stdin-file), opened by the
+// local backend relative to runexp's own working directory
+pub const STDIN_VARIABLE: &str = "RUNEXP_STDIN";
+
+// A command started by a backend. The id is chosen by the backend, e.g. a counter,
+// a Slurm job id or a container id, and is only meaningful to the backend that issued it.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Job {
+    pub id: u64,
+}
+
+// Resources a finished job used, as reported by the operating system (--record-usage)
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct ResourceUsage {
+    pub max_rss_kb: u64,
+    pub user_cpu_s: f64,
+    pub sys_cpu_s: f64,
+}
+
+impl ResourceUsage {
+    // Usage of consecutive jobs: CPU times add up, the peak is the larger one
+    pub fn combine(self, other: ResourceUsage) -> ResourceUsage {
+        ResourceUsage {
+            max_rss_kb: self.max_rss_kb.max(other.max_rss_kb),
+            user_cpu_s: self.user_cpu_s + other.user_cpu_s,
+            sys_cpu_s: self.sys_cpu_s + other.sys_cpu_s,
+        }
+    }
+}
+
+// Where and how stage commands run. The executor drives every stage through
+// prepare/launch/poll/collect, so backends such as SSH, Slurm or containers (or one
+// supplied through the library API) plug in without changes to scheduling or resuming.
+// All running jobs are polled from a single thread, so poll and read_output should
+// return without blocking and a backend should not need a thread per job; the local
+// backend reads what a child wrote whenever it is polled.
+pub trait ExecutionBackend: Send + Sync {
+    // Called once before the first command of a sweep, e.g. to check that a host is
+    // reachable. With --clean-env, commands should get only the variables passed to launch,
+    // and with --pty their output should go to a pseudo-terminal.
+    fn prepare(&self, _stages: &[Stage], _options: &Options) -> Result<(), String> {
+        Ok(())
+    }
+
+    // Start a command with the given environment variables
+    fn launch(&self, command: &[String], env: &[(String, String)]) -> Result<Job, String>;
+
+    // Like launch, with `dir` as the working directory (--workdir)
+    fn launch_in(
+        &self,
+        _command: &[String],
+        _env: &[(String, String)],
+        _dir: &Path,
+    ) -> Result<Job, String> {
+        Err("This backend cannot run commands in another directory".to_string())
+    }
+
+    // Block until the job finishes and return its exit code (None if it was killed)
+    fn wait(&self, job: &Job) -> Result<Option<i32>, String>;
+
+    // Check whether the job has finished without blocking: Some(exit code) once it has.
+    // Backends that cannot check cheaply may block, at the cost of running one job at a time.
+    fn poll(&self, job: &Job) -> Result<Option<Option<i32>>, String> {
+        self.wait(job).map(Some)
+    }
+
+    // Stop a running job, e.g. when it exceeds --timeout. It still has to be polled
+    // or waited for and collected afterwards.
+    fn kill(&self, _job: &Job) -> Result<(), String> {
+        Err("This backend cannot stop jobs".to_string())
+    }
+
+    // Stdout and stderr written since the previous call, so output can be processed
+    // while the job runs. Backends that only get the output at the end keep the default.
+    fn read_output(&self, _job: &Job) -> Result<(Vec<u8>, Vec<u8>), String> {
+        Ok((Vec::new(), Vec::new()))
+    }
+
+    // Resources used by a finished job, if the backend can tell; asked before collect
+    fn usage(&self, _job: &Job) -> Option<ResourceUsage> {
+        None
+    }
+
+    // Memory a running job uses now in kilobytes, including the processes it started,
+    // if the backend can tell (--memory-limit)
+    fn memory_kb(&self, _job: &Job) -> Option<u64> {
+        None
+    }
+
+    // Captured stdout and stderr of a finished job, except what read_output returned
+    fn collect(&self, job: Job) -> Result<(String, String), String>;
+}
+
+// A child process whose stdout and stderr are read without blocking whenever it is
+// polled, and handed to read_output and collect through memory, so neither a thread
+// per child nor a spool file is needed
+struct LocalJob {
+    child: Child,
+    stdout: Capture,
+    stderr: Capture,
+    // Exit code once the child has been reaped, and when that was
+    code: Option<Option<i32>>,
+    exited: Option<Instant>,
+    usage: Option<ResourceUsage>,
+}
+
+impl LocalJob {
+    // The child has exited and its output has been read to the end, or given up on
+    fn finished(&self) -> bool {
+        self.code.is_some() && self.stdout.source.is_none() && self.stderr.source.is_none()
+    }
+}
+
+// One output stream of a job: the pipe (or with --pty the pseudo-terminal) it is read
+// from, and what was read that read_output has not returned yet
+struct Capture {
+    // None once the stream is closed
+    source: Option<File>,
+    pending: Pending,
+    // Terminals turn line breaks into "\r\n"; they are turned back so the output reads
+    // as if written to a file
+    terminal: bool,
+    carriage_return: bool,
+}
+
+// Output that read_output has not returned yet. A caller that keeps reading, like the
+// executor (which writes the full stream to --log-dir), gets every byte; for one that
+// only collects at the end, the middle of a long output is dropped, so only its
+// beginning and end are held in memory.
+#[derive(Default)]
+struct Pending {
+    head: Vec<u8>,
+    tail: Vec<u8>,
+    dropped: usize,
+}
+
+// Output of a stream held until it is read: half of it from the beginning, half from the end
+const PENDING_BYTES: usize = 16 << 20;
+
+// Output read from a stream per poll at most, so a child that writes without pause
+// does not hold up the other jobs
+const FILL_BYTES: usize = 1 << 20;
+
+// How long a finished job's output is waited for, in case a process it started in the
+// background still holds its stdout or stderr
+const READER_GRACE: Duration = Duration::from_secs(1);
+
+impl Pending {
+    fn push(&mut self, bytes: &[u8]) {
+        // The head only fills up while the tail is empty, so the order is kept
+        let head = (PENDING_BYTES / 2 - self.head.len()).min(bytes.len());
+        self.head.extend_from_slice(&bytes[..head]);
+        self.tail.extend_from_slice(&bytes[head..]);
+        // Trim only once the tail has doubled, so each byte is moved a bounded number of times
+        if self.tail.len() > PENDING_BYTES {
+            let excess = self.tail.len() - PENDING_BYTES / 2;
+            self.tail.drain(..excess);
+            self.dropped += excess;
+        }
+    }
+
+    fn take(&mut self) -> Vec<u8> {
+        let mut bytes = std::mem::take(&mut self.head);
+        if self.dropped > 0 {
+            bytes.extend_from_slice(
+                format!("\n[... {} bytes truncated ...]\n", self.dropped).as_bytes(),
+            );
+            self.dropped = 0;
+        }
+        bytes.append(&mut self.tail);
+        bytes
+    }
+}
+
+impl Capture {
+    fn new(source: File, terminal: bool) -> Result<Self, String> {
+        set_nonblocking(&source)
+            .map_err(|e| format!("Failed to capture the output of a command: {}", e))?;
+        Ok(Capture {
+            source: Some(source),
+            pending: Pending::default(),
+            terminal,
+            carriage_return: false,
+        })
+    }
+
+    // Move what the child has written so far into pending, without waiting for more
+    fn fill(&mut self) {
+        let Some(source) = &mut self.source else {
+            return;
+        };
+        let mut buffer = [0u8; 8192];
+        let mut total = 0;
+        while total < FILL_BYTES {
+            // Reading a terminal fails with EIO once the child side is closed
+            let n = match read_available(source, &mut buffer) {
+                Ok(Some(0)) | Err(_) => {
+                    self.close();
+                    return;
+                }
+                Ok(Some(n)) => n,
+                Ok(None) => return,
+            };
+            total += n;
+            let mut bytes = Vec::with_capacity(n + 1);
+            for &byte in &buffer[..n] {
+                if self.terminal && self.carriage_return && byte != b'\n' {
+                    bytes.push(b'\r');
+                }
+                self.carriage_return = self.terminal && byte == b'\r';
+                if !self.carriage_return {
+                    bytes.push(byte);
+                }
+            }
+            self.pending.push(&bytes);
+        }
+    }
+
+    // Stop reading; a process still holding the other end gets EPIPE from then on
+    fn close(&mut self) {
+        self.source = None;
+        if std::mem::take(&mut self.carriage_return) {
+            self.pending.push(b"\r");
+        }
+    }
+
+    fn read(&mut self) -> Vec<u8> {
+        self.fill();
+        self.pending.take()
+    }
+}
+
+// Read what is available without blocking: None if nothing is, Some(0) at the end
+#[cfg(unix)]
+fn read_available(source: &mut File, buffer: &mut [u8]) -> std::io::Result<Option<usize>> {
+    loop {
+        match source.read(buffer) {
+            Ok(n) => return Ok(Some(n)),
+            Err(e) if e.kind() == std::io::ErrorKind::WouldBlock => return Ok(None),
+            Err(e) if e.kind() == std::io::ErrorKind::Interrupted => continue,
+            Err(e) => return Err(e),
+        }
+    }
+}
+
+// Anonymous pipes cannot be made non-blocking on Windows, so only what PeekNamedPipe
+// reports as available is read
+#[cfg(windows)]
+fn read_available(source: &mut File, buffer: &mut [u8]) -> std::io::Result<Option<usize>> {
+    use std::ffi::c_void;
+    use std::os::windows::io::AsRawHandle;
+
+    unsafe extern "system" {
+        fn PeekNamedPipe(
+            pipe: *mut c_void,
+            buffer: *mut c_void,
+            size: u32,
+            read: *mut u32,
+            available: *mut u32,
+            left: *mut u32,
+        ) -> i32;
+    }
+    let mut available = 0u32;
+    // SAFETY: the handle is open for the lifetime of source and available is valid for
+    // writes; no data is copied since the buffer is null
+    let ok = unsafe {
+        PeekNamedPipe(
+            source.as_raw_handle(),
+            std::ptr::null_mut(),
+            0,
+            std::ptr::null_mut(),
+            &mut available,
+            std::ptr::null_mut(),
+        )
+    };
+    // Fails with ERROR_BROKEN_PIPE once the child's end is closed
+    if ok == 0 {
+        return Ok(Some(0));
+    }
+    if available == 0 {
+        return Ok(None);
+    }
+    let size = buffer.len().min(available as usize);
+    source.read(&mut buffer[..size]).map(Some)
+}
+
+#[cfg(unix)]
+fn set_nonblocking(source: &File) -> std::io::Result<()> {
+    use std::os::unix::io::AsRawFd;
+
+    unsafe extern "C" {
+        fn fcntl(fd: c_int, command: c_int, ...) -> c_int;
+    }
+    const F_GETFL: c_int = 3;
+    const F_SETFL: c_int = 4;
+    let fd = source.as_raw_fd();
+    // SAFETY: fd is open for the lifetime of source; F_GETFL and F_SETFL only read and
+    // set its status flags
+    unsafe {
+        let flags = fcntl(fd, F_GETFL);
+        if flags < 0 || fcntl(fd, F_SETFL, flags | O_NONBLOCK) < 0 {
+            return Err(std::io::Error::last_os_error());
+        }
+    }
+    Ok(())
+}
+
+#[cfg(windows)]
+fn set_nonblocking(_source: &File) -> std::io::Result<()> {
+    Ok(())
+}
+
+// The O_NONBLOCK flag, which differs between systems and Linux architectures
+#[cfg(unix)]
+const O_NONBLOCK: c_int = if cfg!(not(target_os = "linux")) {
+    0x4
+} else if cfg!(any(target_arch = "mips", target_arch = "mips64")) {
+    0x80
+} else if cfg!(target_arch = "sparc64") {
+    0x4000
+} else {
+    0o4000
+};
+
+// Runs commands as child processes of runexp
+#[derive(Default)]
+pub struct LocalBackend {
+    next_id: AtomicU64,
+    jobs: Mutex<HashMap<u64, LocalJob>>,
+    // Children do not inherit runexp's environment (--clean-env)
+    clean_env: AtomicBool,
+    // Children write to pseudo-terminals (--pty)
+    pty: AtomicBool,
+    // Resource limits set in children (--rlimit), as (resource, soft limit)
+    #[cfg(unix)]
+    rlimits: Mutex<Vec<(i32, RlimT)>>,
+}
+
+impl LocalBackend {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    fn with_job<T>(
+        &self,
+        job: &Job,
+        f: impl FnOnce(&mut LocalJob) -> Result<T, String>,
+    ) -> Result<T, String> {
+        let mut jobs = self
+            .jobs
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner());
+        let local = jobs
+            .get_mut(&job.id)
+            .ok_or_else(|| format!("Unknown job {}", job.id))?;
+        f(local)
+    }
+
+    // Start a child process, in `dir` if given, with its output captured
+    fn spawn(
+        &self,
+        command: &[String],
+        env: &[(String, String)],
+        dir: Option<&Path>,
+    ) -> Result<Job, String> {
+        let (cmd, args) = match command.split_first() {
+            Some(split) => split,
+            None => return Err("No command specified".to_string()),
+        };
+
+        // Set up the command
+        let mut child = Command::new(cmd);
+        child.args(args);
+        if self.clean_env.load(Ordering::SeqCst) {
+            child.env_clear();
+        }
+        child.envs(env.iter().map(|(name, value)| (name, value)));
+        if let Some(dir) = dir {
+            child.current_dir(dir);
+        }
+        if let Some((_, path)) = env.iter().find(|(name, _)| name == STDIN_VARIABLE) {
+            let input = File::open(path)
+                .map_err(|e| format!("Failed to open stdin file {}: {}", path, e))?;
+            child.stdin(Stdio::from(input));
+        }
+
+        // Capture stdout and stderr
+        let id = self.next_id.fetch_add(1, Ordering::SeqCst);
+        let terminals = if self.pty.load(Ordering::SeqCst) {
+            let (stdout, terminal) = pty_capture()?;
+            child.stdout(terminal);
+            let (stderr, terminal) = pty_capture()?;
+            child.stderr(terminal);
+            Some((stdout, stderr))
+        } else {
+            child.stdout(Stdio::piped());
+            child.stderr(Stdio::piped());
+            None
+        };
+
+        // On Unix systems, create a new process group for the child process
+        // so it receives signals (e.g., SIGINT) independently.
+        #[cfg(unix)]
+        {
+            child.process_group(0);
+            let rlimits = self
+                .rlimits
+                .lock()
+                .unwrap_or_else(|poisoned| poisoned.into_inner())
+                .clone();
+            #[cfg(target_os = "linux")]
+            if let Some((_, cpus)) = env.iter().find(|(name, _)| name == CPUS_VARIABLE) {
+                let mask = cpu_mask(cpus)?;
+                // SAFETY: the closure runs in the child between fork and exec and only
+                // calls sched_setaffinity
+                unsafe {
+                    child.pre_exec(move || {
+                        if sched_setaffinity(0, std::mem::size_of::<CpuSet>(), &mask) != 0 {
+                            return Err(std::io::Error::last_os_error());
+                        }
+                        Ok(())
+                    });
+                }
+            }
+            let nice = env
+                .iter()
+                .find(|(name, _)| name == NICE_VARIABLE)
+                .map(|(_, value)| parse_nice(value))
+                .transpose()?;
+            #[cfg(target_os = "linux")]
+            let ionice = env
+                .iter()
+                .find(|(name, _)| name == IONICE_VARIABLE)
+                .map(|(_, value)| parse_ionice(value))
+                .transpose()?;
+            #[cfg(target_os = "linux")]
+            if ionice.is_some() && SYS_IOPRIO_SET.is_none() {
+                return Err(format!(
+                    "--ionice is not supported on {}",
+                    std::env::consts::ARCH
+                ));
+            }
+            #[cfg(not(target_os = "linux"))]
+            if env.iter().any(|(name, _)| name == IONICE_VARIABLE) {
+                return Err("--ionice is only supported on Linux".to_string());
+            }
+            if let Some(nice) = nice {
+                // SAFETY: the closure runs in the child between fork and exec and only
+                // calls setpriority
+                unsafe {
+                    child.pre_exec(move || {
+                        const PRIO_PROCESS: i32 = 0;
+                        if setpriority(PRIO_PROCESS, 0, nice) != 0 {
+                            return Err(std::io::Error::last_os_error());
+                        }
+                        Ok(())
+                    });
+                }
+            }
+            #[cfg(target_os = "linux")]
+            if let (Some(ioprio), Some(number)) = (ionice, SYS_IOPRIO_SET) {
+                // SAFETY: as above, with the ioprio_set system call, whose arguments are
+                // all ints
+                unsafe {
+                    child.pre_exec(move || {
+                        const IOPRIO_WHO_PROCESS: c_int = 1;
+                        if syscall(number, IOPRIO_WHO_PROCESS, 0 as c_int, ioprio as c_int) != 0 {
+                            return Err(std::io::Error::last_os_error());
+                        }
+                        Ok(())
+                    });
+                }
+            }
+            if !rlimits.is_empty() {
+                // SAFETY: the closure runs in the child between fork and exec; it does not
+                // allocate and only calls getrlimit and setrlimit
+                unsafe {
+                    child.pre_exec(move || {
+                        for &(resource, value) in &rlimits {
+                            let mut limit = Rlimit {
+                                rlim_cur: 0,
+                                rlim_max: 0,
+                            };
+                            if getrlimit(resource, &mut limit) != 0 {
+                                return Err(std::io::Error::last_os_error());
+                            }
+                            limit.rlim_cur = value;
+                            if setrlimit(resource, &limit) != 0 {
+                                return Err(std::io::Error::last_os_error());
+                            }
+                        }
+                        Ok(())
+                    });
+                }
+            }
+        }
+
+        // On Windows MSVC, explicitly use default creation flags so child shares
+        // parent's console and receives Ctrl-C events.
+        #[cfg(all(windows, target_env = "msvc"))]
+        {
+            child.creation_flags(0);
+        }
+
+        // On MSYS2/MinGW, use CREATE_NEW_PROCESS_GROUP for proper Ctrl-C handling.
+        #[cfg(all(windows, target_env = "gnu"))]
+        {
+            const CREATE_NEW_PROCESS_GROUP: u32 = 0x00000200;
+            child.creation_flags(CREATE_NEW_PROCESS_GROUP);
+        }
+
+        let mut child = child
+            .spawn()
+            .map_err(|e| format!("Failed to execute command: {}", e))?;
+        let (stdout, stderr) = match terminals {
+            Some(captures) => captures,
+            None => (
+                Capture::new(pipe(child.stdout.take().unwrap()), false)?,
+                Capture::new(pipe(child.stderr.take().unwrap()), false)?,
+            ),
+        };
+
+        self.jobs
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+            .insert(
+                id,
+                LocalJob {
+                    child,
+                    stdout,
+                    stderr,
+                    code: None,
+                    exited: None,
+                    usage: None,
+                },
+            );
+        Ok(Job { id })
+    }
+}
+
+impl ExecutionBackend for LocalBackend {
+    fn prepare(&self, _stages: &[Stage], options: &Options) -> Result<(), String> {
+        self.clean_env.store(options.clean_env, Ordering::SeqCst);
+        self.pty.store(options.pty, Ordering::SeqCst);
+        if options.memory_limit.is_some() && !cfg!(target_os = "linux") {
+            return Err("--memory-limit is only supported on Linux".to_string());
+        }
+        #[cfg(unix)]
+        {
+            let mut rlimits = Vec::new();
+            for (name, value) in &options.rlimits {
+                let resource = rlimit_resource(name)
+                    .ok_or_else(|| format!("--rlimit {} is not supported on this system", name))?;
+                let value = value.unwrap_or(RLIM_INFINITY);
+                // Only root may raise the hard limit, so fail now rather than in every run
+                let hard = get_rlimit(resource)?.rlim_max;
+                if value > hard {
+                    return Err(format!(
+                        "--rlimit {}={} exceeds the hard limit of {}",
+                        name,
+                        value,
+                        if hard == RLIM_INFINITY {
+                            "unlimited".to_string()
+                        } else {
+                            hard.to_string()
+                        }
+                    ));
+                }
+                rlimits.push((resource, value));
+            }
+            *self.rlimits.lock().unwrap_or_else(|p| p.into_inner()) = rlimits;
+        }
+        Ok(())
+    }
+
+    fn launch(&self, command: &[String], env: &[(String, String)]) -> Result<Job, String> {
+        self.spawn(command, env, None)
+    }
+
+    fn launch_in(
+        &self,
+        command: &[String],
+        env: &[(String, String)],
+        dir: &Path,
+    ) -> Result<Job, String> {
+        self.spawn(command, env, Some(dir))
+    }
+
+    fn wait(&self, job: &Job) -> Result<Option<i32>, String> {
+        // Polling keeps the job table unlocked, so other jobs can be launched meanwhile
+        loop {
+            if let Some(code) = self.poll(job)? {
+                return Ok(code);
+            }
+            std::thread::sleep(Duration::from_millis(10));
+        }
+    }
+
+    fn poll(&self, job: &Job) -> Result<Option<Option<i32>>, String> {
+        self.with_job(job, |local| {
+            if local.finished() {
+                return Ok(local.code);
+            }
+            if local.code.is_none() {
+                #[cfg(unix)]
+                if let Some((code, usage)) = try_wait_with_usage(&local.child)? {
+                    local.code = Some(code);
+                    local.usage = Some(usage);
+                }
+                #[cfg(not(unix))]
+                {
+                    let status = local
+                        .child
+                        .try_wait()
+                        .map_err(|e| format!("Failed to execute command: {}", e))?;
+                    local.code = status.map(|status| status.code());
+                }
+                if local.code.is_some() {
+                    local.exited = Some(Instant::now());
+                }
+            }
+            // Read even while the caller does not, so the child never blocks on a full pipe
+            local.stdout.fill();
+            local.stderr.fill();
+            // The pipes are closed when the child exits, unless a process it started in
+            // the background still holds them
+            if local
+                .exited
+                .is_some_and(|exited| exited.elapsed() >= READER_GRACE)
+            {
+                local.stdout.close();
+                local.stderr.close();
+            }
+            Ok(local.code.filter(|_| local.finished()))
+        })
+    }
+
+    fn kill(&self, job: &Job) -> Result<(), String> {
+        self.with_job(job, |local| {
+            // The child leads its own process group; stop the whole group so commands
+            // started by a shell script are stopped too
+            #[cfg(unix)]
+            {
+                unsafe extern "C" {
+                    fn kill(pid: i32, signal: i32) -> i32;
+                }
+                const SIGKILL: i32 = 9;
+                // SAFETY: kill only sends a signal; a stale group id makes it fail harmlessly
+                if unsafe { kill(-(local.child.id() as i32), SIGKILL) } == 0 {
+                    return Ok(());
+                }
+            }
+            local
+                .child
+                .kill()
+                .map_err(|e| format!("Failed to stop command: {}", e))
+        })
+    }
+
+    fn read_output(&self, job: &Job) -> Result<(Vec<u8>, Vec<u8>), String> {
+        self.with_job(job, |local| Ok((local.stdout.read(), local.stderr.read())))
+    }
+
+    fn usage(&self, job: &Job) -> Option<ResourceUsage> {
+        self.with_job(job, |local| Ok(local.usage)).ok().flatten()
+    }
+
+    #[cfg(target_os = "linux")]
+    fn memory_kb(&self, job: &Job) -> Option<u64> {
+        let group = self.with_job(job, |local| Ok(local.child.id())).ok()?;
+        Some(group_rss_kb(group))
+    }
+
+    fn collect(&self, job: Job) -> Result<(String, String), String> {
+        let mut jobs = self
+            .jobs
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner());
+        match jobs.get(&job.id).map(|local| local.finished()) {
+            None => return Err(format!("Unknown job {}", job.id)),
+            Some(false) => return Err(format!("Job {} has not finished", job.id)),
+            Some(true) => {}
+        }
+        let mut local = jobs.remove(&job.id).unwrap();
+        drop(jobs);
+        let read = |capture: &mut Capture| String::from_utf8_lossy(&capture.read()).to_string();
+        Ok((read(&mut local.stdout), read(&mut local.stderr)))
+    }
+}
+
+// The read end of a child's stdout or stderr pipe as a file
+#[cfg(unix)]
+fn pipe(stream: impl Into<std::os::unix::io::OwnedFd>) -> File {
+    File::from(stream.into())
+}
+
+#[cfg(windows)]
+fn pipe(stream: impl Into<std::os::windows::io::OwnedHandle>) -> File {
+    File::from(stream.into())
+}
+
+// A pseudo-terminal for one output stream of a child (--pty): returns the capture of what
+// the child writes and the terminal side for the child
+#[cfg(unix)]
+fn pty_capture() -> Result<(Capture, Stdio), String> {
+    use std::ffi::{CStr, c_char};
+    use std::os::unix::fs::OpenOptionsExt;
+    use std::os::unix::io::AsRawFd;
+
+    unsafe extern "C" {
+        fn grantpt(fd: c_int) -> c_int;
+        fn unlockpt(fd: c_int) -> c_int;
+        fn ptsname(fd: c_int) -> *const c_char;
+    }
+    // Opening the terminal must not make it runexp's controlling terminal
+    #[cfg(target_os = "linux")]
+    const O_NOCTTY: i32 = 0o400;
+    #[cfg(target_os = "macos")]
+    const O_NOCTTY: i32 = 0x20000;
+    #[cfg(not(any(target_os = "linux", target_os = "macos")))]
+    const O_NOCTTY: i32 = 0;
+
+    let error = |e: std::io::Error| format!("Failed to open a pseudo-terminal: {}", e);
+    let open = |path: &str| {
+        std::fs::OpenOptions::new()
+            .read(true)
+            .write(true)
+            .custom_flags(O_NOCTTY)
+            .open(path)
+            .map_err(error)
+    };
+    let master = open("/dev/ptmx")?;
+    let fd = master.as_raw_fd();
+    // SAFETY: fd is an open file descriptor of the master side; ptsname returns a
+    // NUL-terminated string, copied before any other call could overwrite it
+    let name = unsafe {
+        if grantpt(fd) != 0 || unlockpt(fd) != 0 {
+            return Err(error(std::io::Error::last_os_error()));
+        }
+        let name = ptsname(fd);
+        if name.is_null() {
+            return Err(error(std::io::Error::last_os_error()));
+        }
+        CStr::from_ptr(name).to_string_lossy().into_owned()
+    };
+    let terminal = open(&name)?;
+
+    Ok((Capture::new(master, true)?, Stdio::from(terminal)))
+}
+
+#[cfg(not(unix))]
+fn pty_capture() -> Result<(Capture, Stdio), String> {
+    Err("--pty is only supported on Unix".to_string())
+}
+
+// Niceness from -20 (highest priority) to 19 (lowest)
+pub fn parse_nice(value: &str) -> Result<i32, String> {
+    value
+        .trim()
+        .parse::<i32>()
+        .ok()
+        .filter(|nice| (-20..=19).contains(nice))
+        .ok_or_else(|| format!("Invalid niceness (expected -20 to 19): {}", value))
+}
+
+// I/O priority like ionice(1): "idle", or "best-effort" or "realtime" with an optional
+// level from 0 (highest) to 7, e.g. "best-effort:7"; returned as the ioprio value
+pub fn parse_ionice(value: &str) -> Result<i32, String> {
+    let error = || {
+        format!(
+            "Invalid I/O priority (expected idle, best-effort[:0-7] or realtime[:0-7]): {}",
+            value
+        )
+    };
+    let (class, level) = match value.trim().split_once(':') {
+        Some((class, level)) => (class, Some(level)),
+        None => (value.trim(), None),
+    };
+    let (class, level) = match (class, level) {
+        ("idle", None) => (3, 0),
+        ("realtime" | "best-effort", level) => {
+            let level = level
+                .unwrap_or("4")
+                .parse::<i32>()
+                .ok()
+                .filter(|level| (0..8).contains(level))
+                .ok_or_else(error)?;
+            (if class == "realtime" { 1 } else { 2 }, level)
+        }
+        _ => return Err(error()),
+    };
+    const IOPRIO_CLASS_SHIFT: i32 = 13;
+    Ok(class << IOPRIO_CLASS_SHIFT | level)
+}
+
+#[cfg(unix)]
+unsafe extern "C" {
+    fn setpriority(which: i32, who: u32, prio: i32) -> i32;
+}
+
+#[cfg(target_os = "linux")]
+unsafe extern "C" {
+    fn syscall(number: c_long, ...) -> c_long;
+}
+
+// The number of the ioprio_set system call, which differs between architectures; None
+// where runexp does not know it, so --ionice is refused there
+#[cfg(target_os = "linux")]
+const SYS_IOPRIO_SET: Option<c_long> = if cfg!(target_arch = "x86_64") {
+    if cfg!(target_pointer_width = "32") {
+        // x32 system calls are flagged with bit 30
+        Some(0x4000_0000 + 251)
+    } else {
+        Some(251)
+    }
+} else if cfg!(target_arch = "x86") {
+    Some(289)
+} else if cfg!(target_arch = "arm") {
+    Some(314)
+} else if cfg!(any(
+    target_arch = "aarch64",
+    target_arch = "riscv32",
+    target_arch = "riscv64",
+    target_arch = "loongarch64"
+)) {
+    Some(30)
+} else if cfg!(any(target_arch = "powerpc", target_arch = "powerpc64")) {
+    Some(273)
+} else if cfg!(target_arch = "s390x") {
+    Some(282)
+} else if cfg!(target_arch = "sparc64") {
+    Some(196)
+} else if cfg!(target_arch = "mips") {
+    Some(4314)
+} else if cfg!(all(target_arch = "mips64", target_pointer_width = "64")) {
+    Some(5273)
+} else {
+    None
+};
+
+// CPU affinity (--cpus-per-run): a cpu_set_t of 1024 bits
+#[cfg(target_os = "linux")]
+type CpuSet = [u64; 16];
+
+#[cfg(target_os = "linux")]
+unsafe extern "C" {
+    fn sched_getaffinity(pid: i32, size: usize, mask: *mut CpuSet) -> i32;
+    fn sched_setaffinity(pid: i32, size: usize, mask: *const CpuSet) -> i32;
+}
+
+// The cores runexp may run on, which its children may be pinned to
+#[cfg(target_os = "linux")]
+pub fn allowed_cpus() -> Result<Vec<usize>, String> {
+    let mut mask: CpuSet = [0; 16];
+    // SAFETY: mask is valid for writes of its size for the duration of the call
+    if unsafe { sched_getaffinity(0, std::mem::size_of::<CpuSet>(), &mut mask) } != 0 {
+        return Err(format!(
+            "Failed to read the CPU affinity: {}",
+            std::io::Error::last_os_error()
+        ));
+    }
+    Ok((0..mask.len() * 64)
+        .filter(|cpu| mask[cpu / 64] & (1 << (cpu % 64)) != 0)
+        .collect())
+}
+
+#[cfg(not(target_os = "linux"))]
+pub fn allowed_cpus() -> Result<Vec<usize>, String> {
+    Err("--cpus-per-run is only supported on Linux".to_string())
+}
+
+// The mask of a list like "0-3" or "0-1,4-5"
+#[cfg(target_os = "linux")]
+fn cpu_mask(cpus: &str) -> Result<CpuSet, String> {
+    let mut mask: CpuSet = [0; 16];
+    for item in cpus.split(',') {
+        let (start, end) = item.split_once('-').unwrap_or((item, item));
+        let (Ok(start), Ok(end)) = (start.trim().parse::<usize>(), end.trim().parse::<usize>())
+        else {
+            return Err(format!("Invalid {}: {}", CPUS_VARIABLE, cpus));
+        };
+        if start > end || end >= mask.len() * 64 {
+            return Err(format!("Invalid {}: {}", CPUS_VARIABLE, cpus));
+        }
+        for cpu in start..=end {
+            mask[cpu / 64] |= 1 << (cpu % 64);
+        }
+    }
+    Ok(mask)
+}
+
+// Resource limits of setrlimit(2) (--rlimit); rlim_t is 64 bits wide on the 64-bit
+// Linux and macOS systems runexp runs on
+#[cfg(unix)]
+type RlimT = u64;
+
+#[cfg(target_os = "linux")]
+const RLIM_INFINITY: RlimT = RlimT::MAX;
+#[cfg(all(unix, not(target_os = "linux")))]
+const RLIM_INFINITY: RlimT = (1 << 63) - 1;
+
+#[cfg(unix)]
+#[repr(C)]
+struct Rlimit {
+    rlim_cur: RlimT,
+    rlim_max: RlimT,
+}
+
+#[cfg(unix)]
+unsafe extern "C" {
+    fn getrlimit(resource: i32, rlim: *mut Rlimit) -> i32;
+    fn setrlimit(resource: i32, rlim: *const Rlimit) -> i32;
+}
+
+// The RLIMIT_ constant of a resource named like in --rlimit
+#[cfg(unix)]
+fn rlimit_resource(name: &str) -> Option<i32> {
+    let linux = cfg!(target_os = "linux");
+    if !linux && !cfg!(target_os = "macos") {
+        return None;
+    }
+    Some(match name {
+        "cpu" => 0,
+        "fsize" => 1,
+        "data" => 2,
+        "stack" => 3,
+        "core" => 4,
+        "as" if linux => 9,
+        "as" => 5,
+        "nproc" if linux => 6,
+        "nproc" => 7,
+        "nofile" if linux => 7,
+        "nofile" => 8,
+        "memlock" if linux => 8,
+        "memlock" => 6,
+        _ => return None,
+    })
+}
+
+#[cfg(unix)]
+fn get_rlimit(resource: i32) -> Result<Rlimit, String> {
+    let mut limit = Rlimit {
+        rlim_cur: 0,
+        rlim_max: 0,
+    };
+    // SAFETY: limit is valid for writes for the duration of the call
+    if unsafe { getrlimit(resource, &mut limit) } != 0 {
+        return Err(format!(
+            "Failed to read resource limits: {}",
+            std::io::Error::last_os_error()
+        ));
+    }
+    Ok(limit)
+}
+
+// Resident memory of the processes in a process group, read from /proc. The child leads
+// its own group, which the commands started by a shell script join as well.
+#[cfg(target_os = "linux")]
+fn group_rss_kb(group: u32) -> u64 {
+    let Ok(entries) = std::fs::read_dir("/proc") else {
+        return 0;
+    };
+    let group = group.to_string();
+    let mut total = 0;
+    for entry in entries.flatten() {
+        let path = entry.path();
+        // The fields after the command name in parentheses, which may contain spaces:
+        // state, parent pid, process group, ...
+        let Ok(stat) = std::fs::read_to_string(path.join("stat")) else {
+            continue;
+        };
+        let in_group = stat
+            .rsplit_once(')')
+            .and_then(|(_, fields)| fields.split_whitespace().nth(2))
+            == Some(group.as_str());
+        if !in_group {
+            continue;
+        }
+        // A process that just exited has no VmRSS line
+        let rss = std::fs::read_to_string(path.join("status"))
+            .ok()
+            .and_then(|status| {
+                let line = status.lines().find(|line| line.starts_with("VmRSS:"))?;
+                line.split_whitespace().nth(1)?.parse::<u64>().ok()
+            });
+        total += rss.unwrap_or(0);
+    }
+    total
+}
+
+// Reap the child if it has exited, like Child::try_wait, but through wait4 to also get
+// its resource usage (which includes the children it waited for, e.g. the commands of
+// a shell script). Returns the exit code, None if the child was killed by a signal.
+#[cfg(unix)]
+fn try_wait_with_usage(child: &Child) -> Result<Option<(Option<i32>, ResourceUsage)>, String> {
+    use std::os::raw::{c_int, c_long};
+
+    #[cfg(target_os = "macos")]
+    type Suseconds = i32;
+    #[cfg(not(target_os = "macos"))]
+    type Suseconds = c_long;
+
+    #[repr(C)]
+    struct Timeval {
+        tv_sec: c_long,
+        tv_usec: Suseconds,
+    }
+    // struct rusage: two timevals followed by 14 longs, the first being ru_maxrss
+    #[repr(C)]
+    struct Rusage {
+        ru_utime: Timeval,
+        ru_stime: Timeval,
+        ru_maxrss: c_long,
+        ru_rest: [c_long; 13],
+    }
+    unsafe extern "C" {
+        fn wait4(pid: c_int, status: *mut c_int, options: c_int, rusage: *mut Rusage) -> c_int;
+    }
+    const WNOHANG: c_int = 1;
+
+    let mut status: c_int = 0;
+    // SAFETY: Rusage is plain data, so all zeros is a valid value
+    let mut rusage: Rusage = unsafe { std::mem::zeroed() };
+    // SAFETY: both pointers are valid for writes for the duration of the call
+    let pid = unsafe { wait4(child.id() as c_int, &mut status, WNOHANG, &mut rusage) };
+    if pid < 0 {
+        return Err(format!(
+            "Failed to execute command: {}",
+            std::io::Error::last_os_error()
+        ));
+    }
+    if pid == 0 {
+        return Ok(None);
+    }
+
+    // WIFEXITED and WEXITSTATUS
+    let code = (status & 0x7f == 0).then_some((status >> 8) & 0xff);
+    let seconds = |time: &Timeval| time.tv_sec as f64 + time.tv_usec as f64 / 1e6;
+    // ru_maxrss is in kilobytes, except on macOS where it is in bytes
+    let max_rss = rusage.ru_maxrss.max(0) as u64;
+    let max_rss_kb = if cfg!(target_os = "macos") {
+        max_rss / 1024
+    } else {
+        max_rss
+    };
+    Ok(Some((
+        code,
+        ResourceUsage {
+            max_rss_kb,
+            user_cpu_s: seconds(&rusage.ru_utime),
+            sys_cpu_s: seconds(&rusage.ru_stime),
+        },
+    )))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[cfg(unix)]
+    #[test]
+    fn test_local_backend_runs_command() {
+        let backend = LocalBackend::new();
+        let command: Vec<String> = ["sh", "-c", "echo out $X; echo err >&2; exit 3"]
+            .iter()
+            .map(|s| s.to_string())
+            .collect();
+        let job = backend
+            .launch(&command, &[("X".to_string(), "42".to_string())])
+            .unwrap();
+
+        assert_eq!(backend.wait(&job).unwrap(), Some(3));
+        let usage = backend.usage(&job).unwrap();
+        assert!(usage.max_rss_kb > 0);
+        let (stdout, stderr) = backend.collect(job.clone()).unwrap();
+        assert_eq!(stdout, "out 42\n");
+        assert_eq!(stderr, "err\n");
+
+        // Collecting releases the job
+        assert!(backend.collect(job).is_err());
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_local_backend_clean_env() {
+        let backend = LocalBackend::new();
+        let options = Options {
+            clean_env: true,
+            ..Options::default()
+        };
+        backend.prepare(&[], &options).unwrap();
+        let command = vec!["/usr/bin/env".to_string()];
+        let job = backend
+            .launch(&command, &[("X".to_string(), "42".to_string())])
+            .unwrap();
+        assert_eq!(backend.wait(&job).unwrap(), Some(0));
+        let (stdout, _) = backend.collect(job).unwrap();
+        assert_eq!(stdout, "X=42\n");
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_local_backend_pty() {
+        let backend = LocalBackend::new();
+        let options = Options {
+            pty: true,
+            ..Options::default()
+        };
+        backend.prepare(&[], &options).unwrap();
+        let command: Vec<String> = [
+            "sh",
+            "-c",
+            "test -t 1 && printf 'tty\\n10%%\\r100%%\\n'; test -t 2 && echo err >&2",
+        ]
+        .iter()
+        .map(|s| s.to_string())
+        .collect();
+        let job = backend.launch(&command, &[]).unwrap();
+        assert_eq!(backend.wait(&job).unwrap(), Some(0));
+        let (stdout, stderr) = backend.collect(job).unwrap();
+        assert_eq!(stdout, "tty\n10%\r100%\n");
+        assert_eq!(stderr, "err\n");
+    }
+
+    #[cfg(target_os = "linux")]
+    #[test]
+    fn test_local_backend_cpu_affinity() {
+        let backend = LocalBackend::new();
+        let cpu = allowed_cpus().unwrap().last().unwrap().to_string();
+        let command: Vec<String> = ["sh", "-c", "grep Cpus_allowed_list /proc/self/status"]
+            .iter()
+            .map(|s| s.to_string())
+            .collect();
+        let job = backend
+            .launch(&command, &[(CPUS_VARIABLE.to_string(), cpu.clone())])
+            .unwrap();
+        assert_eq!(backend.wait(&job).unwrap(), Some(0));
+        let (stdout, _) = backend.collect(job).unwrap();
+        assert_eq!(stdout, format!("Cpus_allowed_list:\t{}\n", cpu));
+
+        assert!(cpu_mask("0-1,4").is_ok());
+        assert!(cpu_mask("3-1").is_err());
+        assert!(cpu_mask("all").is_err());
+    }
+
+    #[cfg(target_os = "linux")]
+    #[test]
+    fn test_local_backend_priority() {
+        let backend = LocalBackend::new();
+        let command: Vec<String> = ["sh", "-c", "nice; ionice"]
+            .iter()
+            .map(|s| s.to_string())
+            .collect();
+        let env = [
+            (NICE_VARIABLE.to_string(), "5".to_string()),
+            (IONICE_VARIABLE.to_string(), "idle".to_string()),
+        ];
+        let job = backend.launch(&command, &env).unwrap();
+        assert_eq!(backend.wait(&job).unwrap(), Some(0));
+        let (stdout, _) = backend.collect(job).unwrap();
+        assert_eq!(stdout, "5\nidle\n");
+
+        assert_eq!(parse_ionice("best-effort:7"), Ok(2 << 13 | 7));
+        assert_eq!(parse_ionice("best-effort"), Ok(2 << 13 | 4));
+        assert!(parse_ionice("idle:3").is_err());
+        assert!(parse_ionice("best-effort:8").is_err());
+        assert!(parse_nice("20").is_err());
+        let env = [(NICE_VARIABLE.to_string(), "low".to_string())];
+        assert!(backend.launch(&command, &env).is_err());
+    }
+
+    #[cfg(any(target_os = "linux", target_os = "macos"))]
+    #[test]
+    fn test_local_backend_rlimits() {
+        let backend = LocalBackend::new();
+        let options = Options {
+            rlimits: vec![
+                ("nofile".to_string(), Some(64)),
+                ("core".to_string(), Some(0)),
+            ],
+            ..Options::default()
+        };
+        backend.prepare(&[], &options).unwrap();
+        let command: Vec<String> = ["sh", "-c", "ulimit -n; ulimit -c"]
+            .iter()
+            .map(|s| s.to_string())
+            .collect();
+        let job = backend.launch(&command, &[]).unwrap();
+        assert_eq!(backend.wait(&job).unwrap(), Some(0));
+        assert_eq!(backend.collect(job).unwrap().0, "64\n0\n");
+
+        // Raising a hard limit needs privileges
+        let options = Options {
+            rlimits: vec![("nofile".to_string(), None)],
+            ..Options::default()
+        };
+        if get_rlimit(rlimit_resource("nofile").unwrap())
+            .unwrap()
+            .rlim_max
+            != RLIM_INFINITY
+        {
+            assert!(backend.prepare(&[], &options).is_err());
+        }
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_local_backend_poll_and_kill() {
+        let backend = LocalBackend::new();
+        let command: Vec<String> = ["sh", "-c", "echo started; sleep 10; echo done"]
+            .iter()
+            .map(|s| s.to_string())
+            .collect();
+        let job = backend.launch(&command, &[]).unwrap();
+        assert_eq!(backend.poll(&job).unwrap(), None);
+        assert!(backend.collect(job.clone()).is_err());
+        #[cfg(target_os = "linux")]
+        assert!(backend.memory_kb(&job).unwrap() > 0);
+
+        std::thread::sleep(Duration::from_millis(300));
+        let (stdout, _) = backend.read_output(&job).unwrap();
+        assert_eq!(stdout, b"started\n");
+
+        backend.kill(&job).unwrap();
+        assert_eq!(backend.wait(&job).unwrap(), None);
+        // Output already read is not returned again
+        assert_eq!(
+            backend.collect(job).unwrap(),
+            (String::new(), String::new())
+        );
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_local_backend_background_process() {
+        // A process left in the background keeps the pipes open; the job still finishes
+        let backend = LocalBackend::new();
+        let command: Vec<String> = ["sh", "-c", "sleep 5 & echo done"]
+            .iter()
+            .map(|s| s.to_string())
+            .collect();
+        let job = backend.launch(&command, &[]).unwrap();
+        let started = std::time::Instant::now();
+        assert_eq!(backend.wait(&job).unwrap(), Some(0));
+        assert!(started.elapsed() < Duration::from_secs(4));
+        assert_eq!(backend.collect(job).unwrap().0, "done\n");
+    }
+
+    #[test]
+    fn test_pending_keeps_head_and_tail() {
+        let mut pending = Pending::default();
+        pending.push(b"start");
+        assert_eq!(pending.take(), b"start");
+        let chunk = vec![b'x'; PENDING_BYTES / 4];
+        for _ in 0..8 {
+            pending.push(&chunk);
+        }
+        pending.push(b"end");
+        let bytes = pending.take();
+        let marker = format!("\n[... {} bytes truncated ...]\n", 3 * PENDING_BYTES / 4);
+        assert_eq!(
+            bytes.len(),
+            PENDING_BYTES / 2 + marker.len() + 3 * PENDING_BYTES / 4 + 3
+        );
+        assert!(bytes[PENDING_BYTES / 2..].starts_with(marker.as_bytes()));
+        assert!(bytes.ends_with(b"xend"));
+        assert!(pending.take().is_empty());
+    }
+}
@@ -0,0 +1,189 @@
+use crate::error::Error;
+use crate::gzip::GzipWriter;
+use crate::hash::sha256_hex;
+use crate::metadata::{metadata_path, metadata_value, read_metadata};
+use crate::optimize::state_path;
+use crate::snapshot::snapshot_path;
+use crate::tar::TarWriter;
+use std::fs::{self, File};
+use std::io::{BufWriter, Write};
+use std::path::{Path, PathBuf};
+
+// `runexp bundle ARCHIVE [RESULTS] [--log-dir DIR]`: package everything needed to
+// understand and reproduce a sweep into one .tar.gz (or .tar) archive: the results file,
+// its metadata and environment snapshots, the script run with -f, and per-run logs.
+pub fn run_bundle(args: &[String]) -> Result<(), Error> {
+    let mut archive = None;
+    let mut results = None;
+    let mut log_dir = None;
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        if arg == "--log-dir" {
+            let dir = args
+                .next()
+                .ok_or_else(|| Error::usage("--log-dir requires a value"))?;
+            log_dir = Some(dir.clone());
+        } else if arg.starts_with('-') {
+            return Err(Error::usage(format!("Unexpected argument: {}", arg)));
+        } else if archive.is_none() {
+            archive = Some(arg.clone());
+        } else if results.is_none() {
+            results = Some(arg.clone());
+        } else {
+            return Err(Error::usage(format!("Unexpected argument: {}", arg)));
+        }
+    }
+    let archive = archive.ok_or_else(|| {
+        Error::usage("Usage: runexp bundle ARCHIVE.tar.gz [RESULTS] [--log-dir DIR]")
+    })?;
+    let results = results.unwrap_or_else(|| "results.csv".to_string());
+
+    let files = bundle_files(&results, log_dir)?;
+    let root = archive_root(&archive);
+    let file = File::create(&archive).map_err(|e| Error::io(&archive, e))?;
+    let file = BufWriter::new(file);
+    let write_error = |message: String| Error::Io {
+        path: archive.clone(),
+        message,
+    };
+    if archive.ends_with(".tar") {
+        write_archive(TarWriter::new(file), &root, &files)
+            .map_err(write_error)?
+            .flush()
+            .map_err(|e| Error::io(&archive, e))?;
+    } else {
+        let gzip = GzipWriter::new(file).map_err(|e| Error::io(&archive, e))?;
+        write_archive(TarWriter::new(gzip), &root, &files)
+            .map_err(write_error)?
+            .finish()
+            .map_err(|e| Error::io(&archive, e))?;
+    }
+
+    println!("Wrote {} files to {}", files.len(), archive);
+    Ok(())
+}
+
+// Files to archive as (name in the archive, path)
+fn bundle_files(results: &str, log_dir: Option<String>) -> Result<Vec<(String, PathBuf)>, Error> {
+    if !Path::new(results).is_file() {
+        return Err(Error::Io {
+            path: results.to_string(),
+            message: format!("Results file not found: {}", results),
+        });
+    }
+    let mut files = vec![(file_name(Path::new(results)), PathBuf::from(results))];
+    for sidecar in [
+        metadata_path(results),
+        snapshot_path(results),
+        state_path(results),
+    ] {
+        if Path::new(&sidecar).is_file() {
+            files.push((file_name(Path::new(&sidecar)), PathBuf::from(sidecar)));
+        }
+    }
+
+    let metadata = read_metadata(&metadata_path(results)).unwrap_or_default();
+    if let Some(script) = metadata_value(&metadata, "script") {
+        match fs::read(script) {
+            Ok(content) => {
+                if metadata_value(&metadata, "script_sha256") != Some(&sha256_hex(&content)) {
+                    eprintln!(
+                        "Warning: {} has changed since {} was created",
+                        script, results
+                    );
+                }
+                files.push((
+                    format!("script/{}", file_name(Path::new(script))),
+                    PathBuf::from(script),
+                ));
+            }
+            Err(_) => eprintln!("Warning: script {} not found, not bundled", script),
+        }
+    }
+
+    let log_dir = log_dir.or_else(|| metadata_value(&metadata, "log_dir").map(String::from));
+    if let Some(dir) = log_dir {
+        let entries = fs::read_dir(&dir).map_err(|e| Error::io(&dir, e))?;
+        let mut logs: Vec<PathBuf> = entries
+            .filter_map(|entry| entry.ok().map(|e| e.path()))
+            .filter(|path| path.is_file())
+            .collect();
+        logs.sort();
+        files.extend(
+            logs.into_iter()
+                .map(|path| (format!("logs/{}", file_name(&path)), path)),
+        );
+    }
+
+    Ok(files)
+}
+
+fn write_archive<W: Write>(
+    mut tar: TarWriter<W>,
+    root: &str,
+    files: &[(String, PathBuf)],
+) -> Result<W, String> {
+    for (name, path) in files {
+        tar.append_file(&format!("{}/{}", root, name), path)?;
+    }
+    tar.finish()
+}
+
+fn file_name(path: &Path) -> String {
+    path.file_name()
+        .map(|name| name.to_string_lossy().to_string())
+        .unwrap_or_default()
+}
+
+// Files are placed in a directory named like the archive: "sweep.tar.gz" -> "sweep/"
+fn archive_root(archive: &str) -> String {
+    let name = file_name(Path::new(archive));
+    [".tar.gz", ".tgz", ".tar"]
+        .iter()
+        .find_map(|ext| name.strip_suffix(ext))
+        .unwrap_or(&name)
+        .to_string()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_archive_root() {
+        assert_eq!(archive_root("out/sweep.tar.gz"), "sweep");
+        assert_eq!(archive_root("sweep.tgz"), "sweep");
+        assert_eq!(archive_root("sweep.zip"), "sweep.zip");
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_bundle_lists_with_tar() {
+        let dir = std::env::temp_dir().join("runexp_test_bundle");
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(dir.join("logs")).unwrap();
+        let results = dir.join("results.csv");
+        fs::write(&results, "X,y\n1,2\n").unwrap();
+        let log_dir = dir.join("logs").to_string_lossy().to_string();
+        fs::write(
+            metadata_path(&results.to_string_lossy()),
+            format!("params=X\nlog_dir={}\n", log_dir),
+        )
+        .unwrap();
+        fs::write(dir.join("logs/1.stdout"), "y: 2\n").unwrap();
+
+        let archive = dir.join("sweep.tar.gz").to_string_lossy().to_string();
+        run_bundle(&[archive.clone(), results.to_string_lossy().to_string()]).unwrap();
+
+        let listing = std::process::Command::new("tar")
+            .args(["-tzf", &archive])
+            .output()
+            .unwrap();
+        assert!(listing.status.success());
+        assert_eq!(
+            String::from_utf8_lossy(&listing.stdout),
+            "sweep/results.csv\nsweep/results.csv.meta\nsweep/logs/1.stdout\n"
+        );
+        let _ = fs::remove_dir_all(&dir);
+    }
+}
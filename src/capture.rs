@@ -0,0 +1,270 @@
+// Incremental capture of one output stream of a running stage. Complete lines are
+// parsed for metrics as they arrive and the whole stream can be written to a log file,
+// while only the beginning and the end of it are kept in memory (--max-output-bytes),
+// so commands printing gigabytes of logs do not exhaust runexp's memory or bloat the
+// results file. With --verbose, lines are also echoed to the terminal as they arrive.
+
+use crate::metrics::parse_output;
+use crate::parser::Options;
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::Write;
+use std::path::Path;
+
+// Output kept by default for --preserve-output columns and for printing failed runs
+pub const OUTPUT_BYTES: usize = 64 << 10;
+
+// Lines longer than this are not parsed for metrics
+const MAX_LINE_BYTES: usize = 64 << 10;
+
+pub struct OutputCapture {
+    // At most `limit` bytes are kept: the first half of them and the last bytes
+    limit: usize,
+    head: Vec<u8>,
+    tail: Vec<u8>,
+    total: usize,
+    // The last line, until its line break arrives
+    line: Vec<u8>,
+    overlong: bool,
+    parse: bool,
+    metrics: HashMap<String, String>,
+    log: Option<File>,
+    echo: Option<Echo>,
+}
+
+// Echo of a stream to runexp's own stdout or stderr, each line prefixed to tell
+// concurrent runs apart
+struct Echo {
+    prefix: String,
+    to_stderr: bool,
+    line: Vec<u8>,
+}
+
+impl Echo {
+    // The prefixed lines completed by `bytes`
+    fn lines(&mut self, bytes: &[u8]) -> Vec<String> {
+        let mut lines = Vec::new();
+        for &byte in bytes {
+            if byte == b'\n' || byte == b'\r' {
+                if !self.line.is_empty() {
+                    lines.push(self.take());
+                }
+            } else {
+                self.line.push(byte);
+            }
+        }
+        lines
+    }
+
+    fn take(&mut self) -> String {
+        let line = format!("{}{}", self.prefix, String::from_utf8_lossy(&self.line));
+        self.line.clear();
+        line
+    }
+
+    fn print(&self, lines: &[String]) {
+        let text: String = lines.iter().map(|line| format!("{}\n", line)).collect();
+        // Errors writing to the terminal must not fail the run
+        let _ = if self.to_stderr {
+            std::io::stderr().lock().write_all(text.as_bytes())
+        } else {
+            std::io::stdout().lock().write_all(text.as_bytes())
+        };
+    }
+}
+
+impl OutputCapture {
+    // `parse` tells whether metrics are taken from this stream; `limit` is the number of
+    // bytes kept of it
+    pub fn new(log: Option<&Path>, parse: bool, limit: usize) -> Result<Self, String> {
+        let log = match log {
+            Some(path) => Some(
+                File::create(path)
+                    .map_err(|e| format!("Failed to create {}: {}", path.display(), e))?,
+            ),
+            None => None,
+        };
+        Ok(OutputCapture {
+            limit,
+            head: Vec::new(),
+            tail: Vec::new(),
+            total: 0,
+            line: Vec::new(),
+            overlong: false,
+            parse,
+            metrics: HashMap::new(),
+            log,
+            echo: None,
+        })
+    }
+
+    // Also print the stream line by line, to stderr if `to_stderr`, after `prefix`
+    pub fn echo(mut self, prefix: String, to_stderr: bool) -> Self {
+        self.echo = Some(Echo {
+            prefix,
+            to_stderr,
+            line: Vec::new(),
+        });
+        self
+    }
+
+    pub fn feed(&mut self, bytes: &[u8], options: &Options) -> Result<(), String> {
+        if let Some(log) = &mut self.log {
+            log.write_all(bytes)
+                .map_err(|e| format!("Failed to write log: {}", e))?;
+        }
+        if let Some(echo) = &mut self.echo {
+            let lines = echo.lines(bytes);
+            echo.print(&lines);
+        }
+
+        self.total += bytes.len();
+        let head = (self.limit / 2 - self.head.len()).min(bytes.len());
+        self.head.extend_from_slice(&bytes[..head]);
+        self.tail.extend_from_slice(&bytes[head..]);
+        // Trim only once the tail has doubled, so each byte is moved a bounded number of times
+        if self.tail.len() > 2 * self.tail_limit() {
+            self.trim();
+        }
+
+        if self.parse {
+            for &byte in bytes {
+                if byte == b'\n' || byte == b'\r' {
+                    self.parse_line(options);
+                } else if !self.overlong {
+                    self.line.push(byte);
+                    if self.line.len() > MAX_LINE_BYTES {
+                        self.line.clear();
+                        self.overlong = true;
+                    }
+                }
+            }
+        }
+        Ok(())
+    }
+
+    fn parse_line(&mut self, options: &Options) {
+        if !self.overlong && !self.line.is_empty() {
+            let line = String::from_utf8_lossy(&self.line);
+            parse_output(&line, &mut self.metrics, &options.metrics, &options.parsers);
+        }
+        self.line.clear();
+        self.overlong = false;
+    }
+
+    // Metrics parsed so far
+    pub fn metrics(&self) -> &HashMap<String, String> {
+        &self.metrics
+    }
+
+    fn tail_limit(&self) -> usize {
+        self.limit - self.limit / 2
+    }
+
+    fn trim(&mut self) {
+        let mut start = self.tail.len() - self.tail_limit();
+        // Do not start in the middle of a UTF-8 character
+        while start < self.tail.len() && (self.tail[start] & 0xC0) == 0x80 {
+            start += 1;
+        }
+        self.tail.drain(..start);
+    }
+
+    // The kept output, with a marker in place of the bytes dropped from its middle, and
+    // the parsed metrics
+    pub fn finish(mut self, options: &Options) -> (String, HashMap<String, String>) {
+        if self.parse {
+            self.parse_line(options);
+        }
+        if let Some(echo) = &mut self.echo
+            && !echo.line.is_empty()
+        {
+            let line = echo.take();
+            echo.print(&[line]);
+        }
+        if self.tail.len() > self.tail_limit() {
+            self.trim();
+        }
+        let truncated = self.head.len() + self.tail.len() < self.total;
+        // Do not end the head in the middle of a UTF-8 character either
+        if truncated
+            && let Err(e) = std::str::from_utf8(&self.head)
+            && e.error_len().is_none()
+        {
+            self.head.truncate(e.valid_up_to());
+        }
+        let mut output = self.head;
+        if truncated {
+            let dropped = self.total - output.len() - self.tail.len();
+            output
+                .extend_from_slice(format!("\n[... {} bytes truncated ...]\n", dropped).as_bytes());
+        }
+        output.extend_from_slice(&self.tail);
+        (String::from_utf8_lossy(&output).to_string(), self.metrics)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_capture_parses_lines_across_chunks() {
+        let options = Options::default();
+        let mut capture = OutputCapture::new(None, true, OUTPUT_BYTES).unwrap();
+        capture.feed(b"loss: 0.5\naccu", &options).unwrap();
+        capture.feed(b"racy: 0.9\rstep: 3", &options).unwrap();
+
+        let (output, metrics) = capture.finish(&options);
+        assert_eq!(output, "loss: 0.5\naccuracy: 0.9\rstep: 3");
+        assert_eq!(metrics.get("loss: "), Some(&"0.5".to_string()));
+        assert_eq!(metrics.get("accuracy: "), Some(&"0.9".to_string()));
+        assert_eq!(metrics.get("step: "), Some(&"3".to_string()));
+    }
+
+    #[test]
+    fn test_echo_prefixes_complete_lines() {
+        let mut echo = Echo {
+            prefix: "[2] ".to_string(),
+            to_stderr: false,
+            line: Vec::new(),
+        };
+        assert_eq!(echo.lines(b"epoch 1\nep"), ["[2] epoch 1"]);
+        assert!(echo.lines(b"och").is_empty());
+        assert_eq!(echo.lines(b" 2\r\n\nstep"), ["[2] epoch 2"]);
+        assert_eq!(echo.take(), "[2] step");
+    }
+
+    #[test]
+    fn test_capture_keeps_bounded_head_and_tail() {
+        let options = Options::default();
+        let log = std::env::temp_dir().join("runexp_test_capture.log");
+        let mut capture = OutputCapture::new(Some(&log), false, OUTPUT_BYTES).unwrap();
+        capture.feed(b"first: 1\n", &options).unwrap();
+        let line = format!("{}\n", "é".repeat(100));
+        for _ in 0..(3 * OUTPUT_BYTES / line.len()) {
+            capture.feed(line.as_bytes(), &options).unwrap();
+        }
+        capture.feed(b"last: 1\n", &options).unwrap();
+
+        let (output, metrics) = capture.finish(&options);
+        assert!(output.len() <= OUTPUT_BYTES + 40);
+        assert!(output.starts_with("first: 1\n"));
+        assert!(output.contains(" bytes truncated ...]\n"));
+        assert!(output.ends_with("last: 1\n"));
+        assert!(!output.contains('\u{FFFD}'));
+        assert!(metrics.is_empty());
+
+        // The log file has everything
+        let logged = std::fs::metadata(&log).unwrap().len() as usize;
+        assert!(logged > 2 * OUTPUT_BYTES);
+        let _ = std::fs::remove_file(&log);
+
+        let mut capture = OutputCapture::new(None, false, 8).unwrap();
+        capture.feed(b"0123456789", &options).unwrap();
+        assert_eq!(
+            capture.finish(&options).0,
+            "0123\n[... 2 bytes truncated ...]\n6789"
+        );
+    }
+}
@@ -0,0 +1,107 @@
+use crate::cost::CostExpr;
+
+// Conditions on the metrics of a running stage, e.g. "loss>100" for --abort-if or
+// "accuracy >= 0.99" for --stop-when: two --cost style expressions compared with one of
+// <, <=, >, >=, == and !=. A condition referring to a metric that was not printed yet
+// does not hold.
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub(crate) enum Comparison {
+    Less,
+    LessEqual,
+    Greater,
+    GreaterEqual,
+    Equal,
+    NotEqual,
+}
+
+impl Comparison {
+    pub(crate) fn test<T: PartialOrd>(self, left: T, right: T) -> bool {
+        match self {
+            Comparison::Less => left < right,
+            Comparison::LessEqual => left <= right,
+            Comparison::Greater => left > right,
+            Comparison::GreaterEqual => left >= right,
+            Comparison::Equal => left == right,
+            Comparison::NotEqual => left != right,
+        }
+    }
+}
+
+// Split "left OP right" at its first comparison operator
+pub(crate) fn split_comparison(source: &str) -> Result<(&str, Comparison, &str), String> {
+    let start = source
+        .find(['<', '>', '=', '!'])
+        .ok_or_else(|| "expected a comparison like loss>100".to_string())?;
+    let (comparison, len) = match &source[start..] {
+        s if s.starts_with("<=") => (Comparison::LessEqual, 2),
+        s if s.starts_with(">=") => (Comparison::GreaterEqual, 2),
+        s if s.starts_with("==") => (Comparison::Equal, 2),
+        s if s.starts_with("!=") => (Comparison::NotEqual, 2),
+        s if s.starts_with('<') => (Comparison::Less, 1),
+        s if s.starts_with('>') => (Comparison::Greater, 1),
+        _ => return Err("use == to compare for equality".to_string()),
+    };
+    Ok((&source[..start], comparison, &source[start + len..]))
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Condition {
+    source: String,
+    left: CostExpr,
+    comparison: Comparison,
+    right: CostExpr,
+}
+
+impl Condition {
+    pub fn parse(source: &str) -> Result<Self, String> {
+        let error = |message: String| format!("Invalid condition {}: {}", source, message);
+        let (left, comparison, right) = split_comparison(source).map_err(error)?;
+        Ok(Condition {
+            source: source.to_string(),
+            left: CostExpr::parse(left).map_err(error)?,
+            comparison,
+            right: CostExpr::parse(right).map_err(error)?,
+        })
+    }
+
+    pub fn source(&self) -> &str {
+        &self.source
+    }
+
+    // Whether the condition holds, with `lookup` resolving variable names
+    pub fn holds(&self, lookup: &dyn Fn(&str) -> Option<f64>) -> bool {
+        let (Ok(left), Ok(right)) = (self.left.eval(lookup), self.right.eval(lookup)) else {
+            return false;
+        };
+        self.comparison.test(left, right)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_condition() {
+        let lookup = |name: &str| match name {
+            "loss" => Some(120.0),
+            "accuracy" => Some(0.99),
+            _ => None,
+        };
+        let holds = |source: &str| Condition::parse(source).unwrap().holds(&lookup);
+
+        assert!(holds("loss>100"));
+        assert!(!holds("loss < 100"));
+        assert!(holds("accuracy >= 0.99"));
+        assert!(holds("loss / 2 <= 60"));
+        assert!(holds("accuracy != 1"));
+        assert!(!holds("accuracy == 1"));
+        // Not printed yet
+        assert!(!holds("eval.loss > 0"));
+
+        assert!(Condition::parse("loss").is_err());
+        assert!(Condition::parse("loss=1").is_err());
+        assert!(Condition::parse("loss >").is_err());
+    }
+}
@@ -0,0 +1,207 @@
+use std::fs;
+use std::path::PathBuf;
+
+// A value in the config file. Numbers are kept as their source text since
+// they are passed on as option arguments.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Value {
+    String(String),
+    Bool(bool),
+    Array(Vec<String>),
+}
+
+// A `[section]` header and its `key = value` entries
+pub type Section = (String, Vec<(String, Value)>);
+
+// Location of the user config file: $RUNEXP_CONFIG, else
+// $XDG_CONFIG_HOME/runexp/config.toml, else ~/.config/runexp/config.toml
+pub fn config_path() -> Option<PathBuf> {
+    if let Ok(path) = std::env::var("RUNEXP_CONFIG") {
+        return Some(PathBuf::from(path));
+    }
+    let base = std::env::var("XDG_CONFIG_HOME")
+        .map(PathBuf::from)
+        .or_else(|_| std::env::var("HOME").map(|home| PathBuf::from(home).join(".config")))
+        .or_else(|_| std::env::var("USERPROFILE").map(|home| PathBuf::from(home).join(".config")))
+        .ok()?;
+    Some(base.join("runexp").join("config.toml"))
+}
+
+// Load the entries of `[profile.NAME]`. A missing config file or section yields None.
+pub fn load_profile(name: &str) -> Result<Option<Vec<(String, Value)>>, String> {
+    let Some(path) = config_path() else {
+        return Ok(None);
+    };
+    let Ok(content) = fs::read_to_string(&path) else {
+        return Ok(None);
+    };
+
+    let sections = parse_toml(&content).map_err(|e| format!("{}: {}", path.display(), e))?;
+    let section = format!("profile.{}", name);
+    Ok(sections
+        .into_iter()
+        .find(|(header, _)| *header == section)
+        .map(|(_, entries)| entries))
+}
+
+// Parse the subset of TOML used by the config file: `[section]` headers and
+// `key = value` lines where value is a string, number, boolean or array of those.
+pub fn parse_toml(content: &str) -> Result<Vec<Section>, String> {
+    let mut sections: Vec<Section> = vec![(String::new(), Vec::new())];
+
+    for (idx, line) in content.lines().enumerate() {
+        let line = strip_comment(line).trim();
+        if line.is_empty() {
+            continue;
+        }
+        let error = |msg: &str| format!("line {}: {}", idx + 1, msg);
+
+        if let Some(header) = line.strip_prefix('[') {
+            let header = header
+                .strip_suffix(']')
+                .ok_or_else(|| error("unterminated section header"))?;
+            sections.push((header.trim().to_string(), Vec::new()));
+            continue;
+        }
+
+        let (key, value) = line
+            .split_once('=')
+            .ok_or_else(|| error("expected key = value"))?;
+        let key = unquote(key.trim()).unwrap_or_else(|| key.trim().to_string());
+        let value = parse_value(value.trim()).map_err(|e| error(&e))?;
+        if let Some((_, entries)) = sections.last_mut() {
+            entries.push((key, value));
+        }
+    }
+
+    Ok(sections)
+}
+
+fn parse_value(value: &str) -> Result<Value, String> {
+    if let Some(inner) = value.strip_prefix('[') {
+        let inner = inner
+            .strip_suffix(']')
+            .ok_or("arrays must be on a single line")?;
+        return split_array(inner)
+            .iter()
+            .map(|item| scalar(item.trim()))
+            .collect::<Result<Vec<_>, _>>()
+            .map(Value::Array);
+    }
+
+    match value {
+        "true" => Ok(Value::Bool(true)),
+        "false" => Ok(Value::Bool(false)),
+        _ => scalar(value).map(Value::String),
+    }
+}
+
+// A string or number as text
+fn scalar(value: &str) -> Result<String, String> {
+    if let Some(s) = unquote(value) {
+        return Ok(s);
+    }
+    if value.parse::<f64>().is_ok() {
+        return Ok(value.to_string());
+    }
+    Err(format!("unsupported value: {}", value))
+}
+
+fn unquote(value: &str) -> Option<String> {
+    if value.len() >= 2 && value.starts_with('\'') && value.ends_with('\'') {
+        return Some(value[1..value.len() - 1].to_string());
+    }
+    let inner = value.strip_prefix('"')?.strip_suffix('"')?;
+    let mut result = String::new();
+    let mut chars = inner.chars();
+    while let Some(c) = chars.next() {
+        if c == '\\' {
+            match chars.next() {
+                Some('n') => result.push('\n'),
+                Some('t') => result.push('\t'),
+                Some(other) => result.push(other),
+                None => return None,
+            }
+        } else {
+            result.push(c);
+        }
+    }
+    Some(result)
+}
+
+// Split array items on commas outside of quotes
+fn split_array(inner: &str) -> Vec<String> {
+    let mut items = Vec::new();
+    let mut current = String::new();
+    let mut quote: Option<char> = None;
+
+    for c in inner.chars() {
+        match (quote, c) {
+            (None, '"' | '\'') => {
+                quote = Some(c);
+                current.push(c);
+            }
+            (Some(q), _) if c == q => {
+                quote = None;
+                current.push(c);
+            }
+            (None, ',') => items.push(std::mem::take(&mut current)),
+            _ => current.push(c),
+        }
+    }
+    if !current.trim().is_empty() {
+        items.push(current);
+    }
+    items
+}
+
+// Remove a trailing `# comment` that is not inside a string
+fn strip_comment(line: &str) -> &str {
+    let mut quote: Option<char> = None;
+    for (idx, c) in line.char_indices() {
+        match (quote, c) {
+            (None, '"' | '\'') => quote = Some(c),
+            (Some(q), _) if c == q => quote = None,
+            (None, '#') => return &line[..idx],
+            _ => {}
+        }
+    }
+    line
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_toml() {
+        let content = r#"
+# runexp config
+[profile.cluster]
+concurrency = 8   # one per node
+output = "cluster results.csv"
+preserve-output = true
+tag = ["site=a", 'queue=gpu#1']
+"#;
+        let sections = parse_toml(content).unwrap();
+        assert_eq!(sections[1].0, "profile.cluster");
+        assert_eq!(
+            sections[1].1,
+            vec![
+                ("concurrency".to_string(), Value::String("8".to_string())),
+                (
+                    "output".to_string(),
+                    Value::String("cluster results.csv".to_string())
+                ),
+                ("preserve-output".to_string(), Value::Bool(true)),
+                (
+                    "tag".to_string(),
+                    Value::Array(vec!["site=a".to_string(), "queue=gpu#1".to_string()])
+                ),
+            ]
+        );
+
+        assert!(parse_toml("[profile.x\nkey = 1").is_err());
+        assert!(parse_toml("key = bare").is_err());
+    }
+}
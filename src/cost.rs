@@ -0,0 +1,184 @@
+// Arithmetic expressions for --cost, e.g. "GPU*duration_s/3600*2.5". Unlike parameter
+// expressions these are evaluated with floating point numbers and support - and /.
+// Variables are parameters, metric columns and duration_s, the run's wall time.
+
+#[derive(Debug, Clone, PartialEq)]
+enum Expr {
+    Number(f64),
+    Variable(String),
+    Negate(Box<Expr>),
+    Binary(char, Box<Expr>, Box<Expr>),
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct CostExpr {
+    source: String,
+    expr: Expr,
+}
+
+impl CostExpr {
+    pub fn parse(source: &str) -> Result<Self, String> {
+        let mut parser = Parser {
+            chars: source.chars().collect(),
+            pos: 0,
+        };
+        let expr = parser.sum()?;
+        parser.skip_whitespace();
+        if parser.pos < parser.chars.len() {
+            return Err(format!("Unexpected '{}'", parser.chars[parser.pos]));
+        }
+        Ok(CostExpr {
+            source: source.to_string(),
+            expr,
+        })
+    }
+
+    pub fn source(&self) -> &str {
+        &self.source
+    }
+
+    // Evaluate with `lookup` resolving variable names
+    pub fn eval(&self, lookup: &dyn Fn(&str) -> Option<f64>) -> Result<f64, String> {
+        eval(&self.expr, lookup)
+    }
+}
+
+fn eval(expr: &Expr, lookup: &dyn Fn(&str) -> Option<f64>) -> Result<f64, String> {
+    Ok(match expr {
+        Expr::Number(n) => *n,
+        Expr::Variable(name) => {
+            lookup(name).ok_or_else(|| format!("Unknown or non-numeric variable: {}", name))?
+        }
+        Expr::Negate(e) => -eval(e, lookup)?,
+        Expr::Binary(op, a, b) => {
+            let (a, b) = (eval(a, lookup)?, eval(b, lookup)?);
+            match op {
+                '+' => a + b,
+                '-' => a - b,
+                '*' => a * b,
+                '/' => a / b,
+                _ => a.powf(b),
+            }
+        }
+    })
+}
+
+// Recursive descent over: sum = product (('+'|'-') product)*,
+// product = unary (('*'|'/') unary)*, unary = '-' unary | power, power = atom ('^' unary)?
+struct Parser {
+    chars: Vec<char>,
+    pos: usize,
+}
+
+impl Parser {
+    fn skip_whitespace(&mut self) {
+        while self.chars.get(self.pos).is_some_and(|c| c.is_whitespace()) {
+            self.pos += 1;
+        }
+    }
+
+    // The next non-space character, consumed if it is one of `ops`
+    fn operator(&mut self, ops: &str) -> Option<char> {
+        self.skip_whitespace();
+        let c = *self.chars.get(self.pos)?;
+        ops.contains(c).then(|| {
+            self.pos += 1;
+            c
+        })
+    }
+
+    fn sum(&mut self) -> Result<Expr, String> {
+        let mut expr = self.product()?;
+        while let Some(op) = self.operator("+-") {
+            expr = Expr::Binary(op, Box::new(expr), Box::new(self.product()?));
+        }
+        Ok(expr)
+    }
+
+    fn product(&mut self) -> Result<Expr, String> {
+        let mut expr = self.unary()?;
+        while let Some(op) = self.operator("*/") {
+            expr = Expr::Binary(op, Box::new(expr), Box::new(self.unary()?));
+        }
+        Ok(expr)
+    }
+
+    fn unary(&mut self) -> Result<Expr, String> {
+        if self.operator("-").is_some() {
+            return Ok(Expr::Negate(Box::new(self.unary()?)));
+        }
+        let base = self.atom()?;
+        if self.operator("^").is_some() {
+            return Ok(Expr::Binary('^', Box::new(base), Box::new(self.unary()?)));
+        }
+        Ok(base)
+    }
+
+    fn atom(&mut self) -> Result<Expr, String> {
+        self.skip_whitespace();
+        let start = self.pos;
+        match self.chars.get(self.pos) {
+            None => Err("Unexpected end of expression".to_string()),
+            Some('(') => {
+                self.pos += 1;
+                let expr = self.sum()?;
+                self.operator(")")
+                    .map(|_| expr)
+                    .ok_or_else(|| "Missing ')'".to_string())
+            }
+            Some(c) if c.is_ascii_digit() || *c == '.' => {
+                while self.chars.get(self.pos).is_some_and(|c| {
+                    c.is_ascii_digit()
+                        || *c == '.'
+                        || matches!(c, 'e' | 'E')
+                        || (matches!(c, '+' | '-') && matches!(self.chars[self.pos - 1], 'e' | 'E'))
+                }) {
+                    self.pos += 1;
+                }
+                let text: String = self.chars[start..self.pos].iter().collect();
+                text.parse()
+                    .map(Expr::Number)
+                    .map_err(|_| format!("Invalid number: {}", text))
+            }
+            // Metric columns of named stages contain a dot, e.g. eval.tokens
+            Some(c) if c.is_alphabetic() || *c == '_' => {
+                while self
+                    .chars
+                    .get(self.pos)
+                    .is_some_and(|c| c.is_alphanumeric() || *c == '_' || *c == '.')
+                {
+                    self.pos += 1;
+                }
+                Ok(Expr::Variable(self.chars[start..self.pos].iter().collect()))
+            }
+            Some(c) => Err(format!("Unexpected '{}'", c)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_cost_expression() {
+        let lookup = |name: &str| match name {
+            "GPU" => Some(4.0),
+            "duration_s" => Some(1800.0),
+            "eval.tokens" => Some(2e6),
+            _ => None,
+        };
+        let cost = |source: &str| CostExpr::parse(source).unwrap().eval(&lookup);
+
+        assert_eq!(cost("GPU*duration_s/3600*2.5"), Ok(5.0));
+        assert_eq!(cost("(GPU - 1) * 2 ^ 3"), Ok(24.0));
+        assert_eq!(cost("-GPU + 10"), Ok(6.0));
+        assert_eq!(cost("eval.tokens / 1e6 * 0.5"), Ok(1.0));
+        assert_eq!(cost("100 / 4 / 5"), Ok(5.0));
+        assert!(cost("BATCH * 2").is_err());
+
+        assert!(CostExpr::parse("GPU *").is_err());
+        assert!(CostExpr::parse("(GPU").is_err());
+        assert!(CostExpr::parse("GPU $ 2").is_err());
+    }
+}
@@ -0,0 +1,90 @@
+use std::fs;
+use std::io::{BufRead, BufReader};
+
+// Escape CSV field according to RFC 4180
+pub fn escape_csv_field(field: &str) -> String {
+    // If field contains comma, quote, or newline, it needs to be quoted
+    if field.contains(',') || field.contains('"') || field.contains('\n') || field.contains('\r') {
+        // Escape quotes by doubling them
+        let escaped = field.replace('"', "\"\"");
+        format!("\"{}\"", escaped)
+    } else {
+        field.to_string()
+    }
+}
+
+// Parse entire CSV content handling multi-line fields
+pub fn parse_csv(content: &str) -> Result<Vec<Vec<String>>, String> {
+    let mut records = Vec::new();
+    let mut current_record = Vec::new();
+    let mut current_field = String::new();
+    let mut in_quotes = false;
+    let mut chars = content.chars().peekable();
+
+    while let Some(c) = chars.next() {
+        if in_quotes {
+            if c == '"' {
+                // Check if it's an escaped quote (doubled)
+                if chars.peek() == Some(&'"') {
+                    current_field.push('"');
+                    chars.next();
+                } else {
+                    in_quotes = false;
+                }
+            } else {
+                current_field.push(c);
+            }
+        } else if c == '"' {
+            in_quotes = true;
+        } else if c == ',' {
+            current_record.push(std::mem::take(&mut current_field));
+        } else if c == '\n' {
+            current_record.push(std::mem::take(&mut current_field));
+            if current_record.iter().any(|s| !s.is_empty()) {
+                records.push(std::mem::take(&mut current_record));
+            } else {
+                current_record.clear();
+            }
+        } else if c != '\r' {
+            current_field.push(c);
+        }
+    }
+
+    // Handle last record (file may not end with newline)
+    if !current_field.is_empty() || !current_record.is_empty() {
+        current_record.push(current_field);
+        if current_record.iter().any(|s| !s.is_empty()) {
+            records.push(current_record);
+        }
+    }
+
+    Ok(records)
+}
+
+pub fn read_csv(filename: &str) -> Result<Vec<Vec<String>>, String> {
+    let contents =
+        fs::read_to_string(filename).map_err(|_| format!("Could not read file: {}", filename))?;
+    parse_csv(&contents)
+}
+
+// Parse only the first record, without reading the rest of the file
+pub fn read_csv_header(filename: &str) -> Result<Vec<String>, String> {
+    let file =
+        fs::File::open(filename).map_err(|_| format!("Could not read file: {}", filename))?;
+    let mut reader = BufReader::new(file);
+    let mut content = String::new();
+    loop {
+        let read = reader
+            .read_line(&mut content)
+            .map_err(|_| format!("Could not read file: {}", filename))?;
+        // A record ends at a line break outside quotes, i.e. after an even number of quotes
+        let complete = content.matches('"').count().is_multiple_of(2);
+        if read == 0 || complete && !content.trim().is_empty() {
+            break;
+        }
+    }
+    parse_csv(&content)?
+        .into_iter()
+        .next()
+        .ok_or_else(|| "Empty results file".to_string())
+}
@@ -0,0 +1,489 @@
+use crate::csv::read_csv;
+use crate::error::Error;
+use crate::metadata::{metadata_path, metadata_value, read_metadata};
+use std::collections::hash_map::Entry;
+use std::collections::{HashMap, HashSet};
+
+// `runexp diff OLD NEW`: match the rows of two results files by their parameters and
+// report how each metric changed, e.g. to compare a sweep before and after a code change.
+// Metric columns are taken from --metrics or the sweep metadata; every other column the
+// two files share (name, tags, parameters) identifies a row.
+pub fn run_diff(args: &[String]) -> Result<(), Error> {
+    let mut files = Vec::new();
+    let mut settings = DiffSettings::default();
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        let mut value = || {
+            args.next()
+                .map(|v| v.as_str())
+                .ok_or_else(|| Error::usage(format!("{} requires a value", arg)))
+        };
+        match arg.as_str() {
+            "--metrics" => settings.metrics = split_list(value()?),
+            "--maximize" => settings.maximize = split_list(value()?),
+            "--minimize" => settings.minimize = split_list(value()?),
+            "--threshold" => {
+                let v = value()?;
+                let percent: f64 = v
+                    .trim_end_matches('%')
+                    .parse()
+                    .ok()
+                    .filter(|p: &f64| p.is_finite() && *p >= 0.0)
+                    .ok_or_else(|| Error::usage(format!("Invalid threshold: {}", v)))?;
+                settings.threshold = percent / 100.0;
+            }
+            "--abs-threshold" => {
+                let v = value()?;
+                settings.abs_threshold = v
+                    .parse()
+                    .ok()
+                    .filter(|t: &f64| t.is_finite() && *t >= 0.0)
+                    .ok_or_else(|| Error::usage(format!("Invalid threshold: {}", v)))?;
+            }
+            _ if !arg.starts_with('-') && files.len() < 2 => files.push(arg.clone()),
+            _ => return Err(Error::usage(format!("Unexpected argument: {}", arg))),
+        }
+    }
+    let [old, new] = &files[..] else {
+        return Err(Error::usage(
+            "Usage: runexp diff OLD.csv NEW.csv [--metrics M,...] [--threshold PCT]",
+        ));
+    };
+
+    if settings.metrics.is_empty() {
+        for file in [old, new] {
+            let metadata = read_metadata(&metadata_path(file)).unwrap_or_default();
+            for metric in metadata_value(&metadata, "metrics").map_or(vec![], split_list) {
+                if !settings.metrics.contains(&metric) {
+                    settings.metrics.push(metric);
+                }
+            }
+        }
+        if settings.metrics.is_empty() {
+            return Err(Error::usage(
+                "No sweep metadata found to tell metrics apart; use --metrics",
+            ));
+        }
+    }
+
+    let read = |file: &String| {
+        read_csv(file).map_err(|message| Error::Io {
+            path: file.clone(),
+            message,
+        })
+    };
+    let old_records = read(old)?;
+    let new_records = read(new)?;
+    let diff = diff_results((old, &old_records), (new, &new_records), &settings)?;
+    print!("{}", diff.render(&settings));
+    Ok(())
+}
+
+struct DiffSettings {
+    metrics: Vec<String>,
+    // Metrics where a higher (or lower) value is an improvement
+    maximize: Vec<String>,
+    minimize: Vec<String>,
+    // A change is significant when both its relative and absolute size reach these
+    threshold: f64,
+    abs_threshold: f64,
+}
+
+impl Default for DiffSettings {
+    fn default() -> Self {
+        DiffSettings {
+            metrics: Vec::new(),
+            maximize: Vec::new(),
+            minimize: Vec::new(),
+            threshold: 0.05,
+            abs_threshold: 0.0,
+        }
+    }
+}
+
+fn split_list(value: &str) -> Vec<String> {
+    value
+        .split(',')
+        .map(|s| s.trim())
+        .filter(|s| !s.is_empty())
+        .map(String::from)
+        .collect()
+}
+
+// Columns of named stages are prefixed, e.g. metric "accuracy" in column "eval.accuracy"
+pub fn is_metric_column(column: &str, metrics: &[String]) -> bool {
+    metrics.iter().any(|m| {
+        column == m
+            || column
+                .strip_suffix(m.as_str())
+                .is_some_and(|prefix| prefix.ends_with('.'))
+    })
+}
+
+// Columns describing how a run went rather than which run it was
+const RUN_COLUMNS: [&str; 11] = [
+    "artifacts",
+    "log",
+    "status",
+    "exit_code",
+    "duration_s",
+    "max_rss_kb",
+    "user_cpu_s",
+    "sys_cpu_s",
+    "started_at",
+    "hostname",
+    "git_commit",
+];
+
+pub fn is_run_column(column: &str) -> bool {
+    RUN_COLUMNS.contains(&column)
+}
+
+pub fn is_output_column(column: &str) -> bool {
+    column == "stdout"
+        || column == "stderr"
+        || column.ends_with(".stdout")
+        || column.ends_with(".stderr")
+}
+
+#[derive(Debug, PartialEq)]
+enum Change {
+    Same,
+    Minor,
+    Improved,
+    Regressed,
+    // Significant, but the metric has no direction
+    Changed,
+    // Not comparable as numbers, e.g. missing in one of the files
+    Different,
+}
+
+struct MetricChange {
+    key: String,
+    old: String,
+    new: String,
+    delta: Option<(f64, f64)>,
+    change: Change,
+}
+
+struct Diff {
+    old_file: String,
+    new_file: String,
+    old_rows: usize,
+    new_rows: usize,
+    matched: usize,
+    only_old: Vec<String>,
+    only_new: Vec<String>,
+    // Columns present in only one of the files, ignored for matching
+    ignored: Vec<String>,
+    metrics: Vec<(String, Vec<MetricChange>)>,
+}
+
+fn diff_results(
+    (old_file, old): (&str, &[Vec<String>]),
+    (new_file, new): (&str, &[Vec<String>]),
+    settings: &DiffSettings,
+) -> Result<Diff, String> {
+    let (Some(old_header), Some(new_header)) = (old.first(), new.first()) else {
+        return Err("Empty results file".to_string());
+    };
+    let position = |header: &[String], column: &str| header.iter().position(|h| h == column);
+
+    let mut key_columns = Vec::new();
+    let mut metric_columns = Vec::new();
+    let mut ignored = Vec::new();
+    for column in old_header.iter().chain(new_header) {
+        if is_output_column(column)
+            || is_run_column(column)
+            || key_columns.contains(column)
+            || metric_columns.contains(column)
+            || ignored.contains(column)
+        {
+            continue;
+        }
+        let shared = old_header.contains(column) && new_header.contains(column);
+        if is_metric_column(column, &settings.metrics) {
+            metric_columns.push(column.clone());
+        } else if shared {
+            key_columns.push(column.clone());
+        } else {
+            ignored.push(column.clone());
+        }
+    }
+    if metric_columns.is_empty() {
+        return Err(format!(
+            "No metric columns found for metrics: {}",
+            settings.metrics.join(",")
+        ));
+    }
+
+    // Rows are identified by their key columns, e.g. "LR=0.1, BATCH=32"
+    let key = |header: &[String], row: &[String]| -> String {
+        key_columns
+            .iter()
+            .map(|column| {
+                let value = position(header, column).and_then(|i| row.get(i));
+                format!("{}={}", column, value.map_or("", |v| v.as_str()))
+            })
+            .collect::<Vec<_>>()
+            .join(", ")
+    };
+    // When a key appears more than once, the first row is used, as on resume
+    let mut new_rows: HashMap<String, &Vec<String>> = HashMap::new();
+    let mut new_keys = Vec::new();
+    for row in &new[1..] {
+        let k = key(new_header, row);
+        if let Entry::Vacant(entry) = new_rows.entry(k.clone()) {
+            entry.insert(row);
+            new_keys.push(k);
+        }
+    }
+
+    let mut diff = Diff {
+        old_file: old_file.to_string(),
+        new_file: new_file.to_string(),
+        old_rows: old.len() - 1,
+        new_rows: new.len() - 1,
+        matched: 0,
+        only_old: Vec::new(),
+        only_new: Vec::new(),
+        ignored,
+        metrics: metric_columns
+            .iter()
+            .map(|m| (m.clone(), Vec::new()))
+            .collect(),
+    };
+    let mut seen = HashSet::new();
+    for old_row in &old[1..] {
+        let k = key(old_header, old_row);
+        if !seen.insert(k.clone()) {
+            continue;
+        }
+        let Some(new_row) = new_rows.get(&k) else {
+            diff.only_old.push(k);
+            continue;
+        };
+        diff.matched += 1;
+        for (column, changes) in &mut diff.metrics {
+            let value = |header: &[String], row: &[String]| {
+                position(header, column)
+                    .and_then(|i| row.get(i))
+                    .cloned()
+                    .unwrap_or_default()
+            };
+            let old_value = value(old_header, old_row);
+            let new_value = value(new_header, new_row);
+            let (delta, change) = compare(column, &old_value, &new_value, settings);
+            changes.push(MetricChange {
+                key: k.clone(),
+                old: old_value,
+                new: new_value,
+                delta,
+                change,
+            });
+        }
+    }
+    diff.only_new = new_keys.into_iter().filter(|k| !seen.contains(k)).collect();
+    Ok(diff)
+}
+
+// The (absolute, relative) change and how it is classified
+fn compare(
+    column: &str,
+    old: &str,
+    new: &str,
+    settings: &DiffSettings,
+) -> (Option<(f64, f64)>, Change) {
+    let (Ok(a), Ok(b)) = (old.trim().parse::<f64>(), new.trim().parse::<f64>()) else {
+        let change = if old == new {
+            Change::Same
+        } else {
+            Change::Different
+        };
+        return (None, change);
+    };
+    let delta = b - a;
+    let relative = if a != 0.0 {
+        delta / a.abs()
+    } else if delta == 0.0 {
+        0.0
+    } else {
+        f64::INFINITY * delta.signum()
+    };
+    let change = if delta == 0.0 {
+        Change::Same
+    } else if relative.abs() < settings.threshold || delta.abs() < settings.abs_threshold {
+        Change::Minor
+    } else if is_metric_column(column, &settings.maximize) {
+        if delta > 0.0 {
+            Change::Improved
+        } else {
+            Change::Regressed
+        }
+    } else if is_metric_column(column, &settings.minimize) {
+        if delta < 0.0 {
+            Change::Improved
+        } else {
+            Change::Regressed
+        }
+    } else {
+        Change::Changed
+    };
+    (Some((delta, relative)), change)
+}
+
+impl Diff {
+    fn render(&self, settings: &DiffSettings) -> String {
+        let mut out = format!(
+            "Comparing {} ({} rows) with {} ({} rows): {} matched, {} only in {}, {} only in {}\n",
+            self.old_file,
+            self.old_rows,
+            self.new_file,
+            self.new_rows,
+            self.matched,
+            self.only_old.len(),
+            self.old_file,
+            self.only_new.len(),
+            self.new_file
+        );
+        out += &format!(
+            "Significant: at least {}% relative change",
+            settings.threshold * 100.0
+        );
+        if settings.abs_threshold > 0.0 {
+            out += &format!(" and {} absolute change", settings.abs_threshold);
+        }
+        out += "\n";
+        if !self.ignored.is_empty() {
+            out += &format!(
+                "Columns in only one file (ignored): {}\n",
+                self.ignored.join(", ")
+            );
+        }
+
+        for (metric, changes) in &self.metrics {
+            let count = |kind: Change| changes.iter().filter(|c| c.change == kind).count();
+            let significant: Vec<&MetricChange> = changes
+                .iter()
+                .filter(|c| !matches!(c.change, Change::Same | Change::Minor))
+                .collect();
+            let relative: Vec<f64> = changes
+                .iter()
+                .filter_map(|c| c.delta.map(|(_, r)| r))
+                .filter(|r| r.is_finite())
+                .collect();
+
+            out += &format!(
+                "\n{}: {} of {} changed significantly",
+                metric,
+                significant.len(),
+                changes.len()
+            );
+            let (improved, regressed) = (count(Change::Improved), count(Change::Regressed));
+            if improved + regressed > 0 {
+                out += &format!(" ({} improved, {} regressed)", improved, regressed);
+            }
+            if !relative.is_empty() {
+                let mean = relative.iter().sum::<f64>() / relative.len() as f64;
+                out += &format!(", mean change {:+.2}%", mean * 100.0);
+            }
+            out += "\n";
+
+            for change in significant {
+                let shown = |v: &str| {
+                    if v.is_empty() {
+                        "(none)".to_string()
+                    } else {
+                        v.to_string()
+                    }
+                };
+                out += &format!(
+                    "  {}: {} -> {}",
+                    change.key,
+                    shown(&change.old),
+                    shown(&change.new)
+                );
+                if let Some((delta, relative)) = change.delta {
+                    // Round away floating point noise, e.g. 0.9 - 0.81 = 0.08999999999999997
+                    let delta: f64 = format!("{:.10e}", delta).parse().unwrap_or(delta);
+                    out += &format!(" ({:+}", delta);
+                    if relative.is_finite() {
+                        out += &format!(", {:+.2}%", relative * 100.0);
+                    }
+                    out += ")";
+                }
+                match change.change {
+                    Change::Improved => out += "  improved",
+                    Change::Regressed => out += "  REGRESSED",
+                    _ => {}
+                }
+                out += "\n";
+            }
+        }
+
+        for (file, keys) in [
+            (&self.old_file, &self.only_old),
+            (&self.new_file, &self.only_new),
+        ] {
+            if !keys.is_empty() {
+                out += &format!("\nOnly in {}:\n", file);
+                for key in keys {
+                    out += &format!("  {}\n", key);
+                }
+            }
+        }
+        out
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn records(rows: &[&str]) -> Vec<Vec<String>> {
+        rows.iter()
+            .map(|row| row.split(',').map(String::from).collect())
+            .collect()
+    }
+
+    #[test]
+    fn test_diff_matches_rows_by_params() {
+        let old = records(&[
+            "LR,BS,accuracy,loss,stdout",
+            "0.1,32,0.90,0.5,x",
+            "0.1,64,0.80,0.6,x",
+            "0.2,32,0.70,0.7,x",
+        ]);
+        // Rows in another order, one new row, and one row gone
+        let new = records(&[
+            "BS,LR,accuracy,loss,stdout",
+            "64,0.1,0.81,0.3,y",
+            "32,0.1,0.85,0.5,y",
+            "16,0.1,0.5,0.5,y",
+        ]);
+        let settings = DiffSettings {
+            metrics: vec!["accuracy".to_string(), "loss".to_string()],
+            maximize: vec!["accuracy".to_string()],
+            ..DiffSettings::default()
+        };
+
+        let diff = diff_results(("old.csv", &old), ("new.csv", &new), &settings).unwrap();
+        assert_eq!(diff.matched, 2);
+        assert_eq!(diff.only_old, vec!["LR=0.2, BS=32"]);
+        assert_eq!(diff.only_new, vec!["LR=0.1, BS=16"]);
+
+        let (metric, accuracy) = &diff.metrics[0];
+        assert_eq!(metric, "accuracy");
+        assert_eq!(accuracy[0].key, "LR=0.1, BS=32");
+        assert_eq!(accuracy[0].change, Change::Regressed);
+        // 0.80 -> 0.81 is below the 5% threshold
+        assert_eq!(accuracy[1].change, Change::Minor);
+        let (_, loss) = &diff.metrics[1];
+        assert_eq!(loss[0].change, Change::Same);
+        assert_eq!(loss[1].change, Change::Changed);
+
+        let text = diff.render(&settings);
+        assert!(text.contains("accuracy: 1 of 2 changed significantly (0 improved, 1 regressed)"));
+        assert!(text.contains("  LR=0.1, BS=32: 0.90 -> 0.85 (-0.05, -5.56%)  REGRESSED"));
+    }
+}
@@ -0,0 +1,179 @@
+use std::fmt;
+
+// Errors reported by runexp. Each kind has its own exit code so scripts can tell a
+// mistake on the command line from a sweep that cannot run, and with
+// --error-format json the error is printed as a single JSON object.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Error {
+    // -h/--help was given; not a failure
+    HelpRequested,
+    // Invalid command line. `arg` is the 1-based position of the offending argument
+    // after "runexp", or None if it came from a profile, a RUNEXP_* variable, or no
+    // single argument is to blame.
+    Usage {
+        message: String,
+        arg: Option<usize>,
+    },
+    // A parameter whose values cannot be evaluated
+    Eval {
+        param: String,
+        expr: String,
+        message: String,
+    },
+    // A file that cannot be read or written; the message includes the path
+    Io {
+        path: String,
+        message: String,
+    },
+    // An existing results file that does not fit this invocation
+    Incompatible {
+        path: String,
+        message: String,
+    },
+    // An input file that does not match its --verify-input checksum
+    Input {
+        path: String,
+        message: String,
+    },
+    // Anything else, e.g. a sweep larger than --max-combinations
+    Other(String),
+}
+
+impl Error {
+    pub fn usage(message: impl Into<String>) -> Self {
+        Error::Usage {
+            message: message.into(),
+            arg: None,
+        }
+    }
+
+    pub fn io(path: &str, error: impl fmt::Display) -> Self {
+        Error::Io {
+            path: path.to_string(),
+            message: format!("{}: {}", path, error),
+        }
+    }
+
+    pub fn exit_code(&self) -> i32 {
+        match self {
+            Error::HelpRequested => 0,
+            Error::Other(_) => 1,
+            Error::Usage { .. } => 2,
+            Error::Eval { .. } => 3,
+            Error::Io { .. } => 4,
+            Error::Incompatible { .. } => 5,
+            Error::Input { .. } => 6,
+        }
+    }
+
+    pub fn kind(&self) -> &'static str {
+        match self {
+            Error::HelpRequested => "help",
+            Error::Usage { .. } => "usage",
+            Error::Eval { .. } => "eval",
+            Error::Io { .. } => "io",
+            Error::Incompatible { .. } => "incompatible",
+            Error::Input { .. } => "input",
+            Error::Other(_) => "other",
+        }
+    }
+
+    // {"kind":"eval","exit_code":3,"message":"...","param":"LR","expr":"1:0:0"}
+    pub fn to_json(&self) -> String {
+        let mut fields = vec![
+            format!("\"kind\":{}", json_string(self.kind())),
+            format!("\"exit_code\":{}", self.exit_code()),
+            format!("\"message\":{}", json_string(&self.to_string())),
+        ];
+        match self {
+            Error::Usage { arg: Some(arg), .. } => fields.push(format!("\"arg\":{}", arg)),
+            Error::Eval { param, expr, .. } => {
+                fields.push(format!("\"param\":{}", json_string(param)));
+                fields.push(format!("\"expr\":{}", json_string(expr)));
+            }
+            Error::Io { path, .. }
+            | Error::Incompatible { path, .. }
+            | Error::Input { path, .. } => fields.push(format!("\"path\":{}", json_string(path))),
+            _ => {}
+        }
+        format!("{{{}}}", fields.join(","))
+    }
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Error::HelpRequested => write!(f, "Help requested"),
+            Error::Usage {
+                message,
+                arg: Some(arg),
+            } => write!(f, "{} (argument {})", message, arg),
+            Error::Usage { message, arg: None } => write!(f, "{}", message),
+            Error::Eval {
+                param,
+                expr,
+                message,
+            } => write!(f, "Cannot evaluate {}={}: {}", param, expr, message),
+            Error::Io { message, .. }
+            | Error::Incompatible { message, .. }
+            | Error::Input { message, .. }
+            | Error::Other(message) => {
+                write!(f, "{}", message)
+            }
+        }
+    }
+}
+
+impl std::error::Error for Error {}
+
+// Errors of helpers that only produce a message
+impl From<String> for Error {
+    fn from(message: String) -> Self {
+        Error::Other(message)
+    }
+}
+
+fn json_string(s: &str) -> String {
+    let mut escaped = String::from("\"");
+    for c in s.chars() {
+        match c {
+            '"' => escaped.push_str("\\\""),
+            '\\' => escaped.push_str("\\\\"),
+            '\n' => escaped.push_str("\\n"),
+            '\r' => escaped.push_str("\\r"),
+            '\t' => escaped.push_str("\\t"),
+            c if (c as u32) < 0x20 => escaped.push_str(&format!("\\u{:04x}", c as u32)),
+            c => escaped.push(c),
+        }
+    }
+    escaped.push('"');
+    escaped
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_error_json() {
+        let error = Error::Eval {
+            param: "LR".to_string(),
+            expr: "1:0:0".to_string(),
+            message: "Range step cannot be zero".to_string(),
+        };
+        assert_eq!(error.exit_code(), 3);
+        assert_eq!(
+            error.to_json(),
+            "{\"kind\":\"eval\",\"exit_code\":3,\"message\":\"Cannot evaluate LR=1:0:0: Range step cannot be zero\",\"param\":\"LR\",\"expr\":\"1:0:0\"}"
+        );
+
+        let error = Error::Usage {
+            message: "Invalid \"x\"".to_string(),
+            arg: Some(2),
+        };
+        assert_eq!(
+            error.to_json(),
+            "{\"kind\":\"usage\",\"exit_code\":2,\"message\":\"Invalid \\\"x\\\" (argument 2)\",\"arg\":2}"
+        );
+    }
+}
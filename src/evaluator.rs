@@ -1,14 +1,53 @@
+use crate::condition::{Comparison, split_comparison};
+use crate::error::Error;
+use crate::glob::{glob_match, glob_paths};
+use crate::hash::sha256;
+use crate::parser::Strategy;
+use crate::timestamp::DateTime;
 use std::collections::{HashMap, HashSet};
+use std::sync::OnceLock;
 
 #[derive(Debug, Clone)]
 pub struct Combination {
     pub params: HashMap<String, String>,
     pub param_order: Vec<String>, // Preserve the order of parameters
+    // Position in the grid (from 0), the builtin IDX of expressions and templates;
+    // repetitions of a combination share it
+    pub index: usize,
 }
 
-pub fn evaluate_params(params: &[(String, String)]) -> Result<Vec<Combination>, String> {
-    // Topologically sort parameters based on dependencies
-    let sorted_params = topological_sort(params)?;
+// How the values of distributions like "uniform(0,1)" are drawn: `count` points (from
+// --samples) placed by `strategy` with `seed`
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Sampling {
+    pub count: usize,
+    pub seed: u64,
+    pub strategy: Strategy,
+}
+
+// Expand the grid of parameter values. Parameters in the same `zip` group (from --zip)
+// vary together instead of being crossed: their i-th values form one combination.
+// Parameters with a random distribution like "uniform(0,1)" get `samples.count` values
+// and are zipped together, so together they form `count` random points.
+pub fn evaluate_params(
+    params: &[(String, String)],
+    zip: &[Vec<String>],
+    samples: Option<Sampling>,
+) -> Result<Vec<Combination>, Error> {
+    // Sort parameters (and zip groups) based on dependencies. Parameters computed from the
+    // builtin IDX are evaluated last, once the grid positions are known.
+    let indexed = indexed_params(params)?;
+    let mut units = evaluation_units(params, zip)?;
+    if let Some(name) = indexed
+        .iter()
+        .find(|name| zip.iter().flatten().any(|n| n == *name))
+    {
+        return Err(Error::usage(format!(
+            "Parameter {} refers to IDX and cannot be zipped",
+            name
+        )));
+    }
+    units.retain(|unit| !unit.iter().any(|name| indexed.contains(name)));
 
     // Store the original order for output
     let param_order: Vec<String> = params.iter().map(|(name, _)| name.clone()).collect();
@@ -16,16 +55,22 @@ pub fn evaluate_params(params: &[(String, String)]) -> Result<Vec<Combination>,
     // Build combinations incrementally, evaluating each parameter in dependency order
     let mut combinations: Vec<HashMap<String, String>> = vec![HashMap::new()];
 
-    for name in &sorted_params {
-        // Find the value expression for this parameter
-        let value = params
-            .iter()
-            .find(|(n, _)| n == name)
-            .map(|(_, v)| v)
-            .ok_or_else(|| format!("Parameter {} not found", name))?;
-
+    for unit in &units {
         let mut new_combinations = Vec::new();
 
+        // Parameters that do not refer to others are evaluated once, e.g. so a cmd: value
+        // runs its command only once
+        let independent = unit.iter().all(|name| {
+            let value = &params.iter().find(|(n, _)| n == name).unwrap().1;
+            extract_variables(value)
+                .iter()
+                .all(|dep| !params.iter().any(|(n, _)| n == dep))
+        });
+        let fixed = match independent {
+            true => Some(evaluate_unit(params, unit, &HashMap::new(), samples)?),
+            false => None,
+        };
+
         for combo in &combinations {
             // Normalize context keys to uppercase for case-insensitive lookup
             let normalized_context: HashMap<String, String> = combo
@@ -33,12 +78,17 @@ pub fn evaluate_params(params: &[(String, String)]) -> Result<Vec<Combination>,
                 .map(|(k, v)| (k.to_uppercase(), v.clone()))
                 .collect();
 
-            // Evaluate the expression in the context of this combination
-            let values = evaluate_expression(value, &normalized_context)?;
+            // Evaluate the expressions in the context of this combination
+            let values = match &fixed {
+                Some(values) => values.clone(),
+                None => evaluate_unit(params, unit, &normalized_context, samples)?,
+            };
 
-            for val in values {
+            for row in 0..values[0].len() {
                 let mut new_combo = combo.clone();
-                new_combo.insert(name.clone(), val);
+                for (name, vals) in unit.iter().zip(&values) {
+                    new_combo.insert(name.clone(), vals[row].clone());
+                }
                 new_combinations.push(new_combo);
             }
         }
@@ -46,17 +96,525 @@ pub fn evaluate_params(params: &[(String, String)]) -> Result<Vec<Combination>,
         combinations = new_combinations;
     }
 
-    Ok(combinations
+    let mut combinations: Vec<Combination> = combinations
         .into_iter()
-        .map(|params| Combination {
+        .enumerate()
+        .map(|(index, params)| Combination {
             params,
             param_order: param_order.clone(),
+            index,
         })
+        .collect();
+    for combo in &mut combinations {
+        for name in &indexed {
+            let mut context: HashMap<String, String> = combo
+                .params
+                .iter()
+                .map(|(k, v)| (k.to_uppercase(), v.clone()))
+                .collect();
+            context.insert("IDX".to_string(), combo.index.to_string());
+            let values = evaluate_unit(params, std::slice::from_ref(name), &context, samples)?;
+            let [value] = &values[0][..] else {
+                let definition = &params.iter().find(|(n, _)| n == name).unwrap().1;
+                return Err(eval_error(
+                    name,
+                    definition,
+                    format!(
+                        "Refers to IDX, so it must have one value per combination, not {}",
+                        values[0].len()
+                    ),
+                ));
+            };
+            combo.params.insert(name.clone(), value.clone());
+        }
+    }
+    Ok(combinations)
+}
+
+// The parameters that refer to the builtin IDX, directly or through other parameters, in
+// dependency order. A parameter named IDX takes the place of the builtin.
+fn indexed_params(params: &[(String, String)]) -> Result<Vec<String>, Error> {
+    let mut indexed = Vec::new();
+    if params.iter().any(|(name, _)| name == "IDX") {
+        return Ok(indexed);
+    }
+    for name in topological_sort(params)? {
+        let value = &params.iter().find(|(n, _)| *n == name).unwrap().1;
+        if extract_variables(value)
+            .iter()
+            .any(|dep| dep == "IDX" || indexed.contains(dep))
+        {
+            indexed.push(name);
+        }
+    }
+    Ok(indexed)
+}
+
+// Count the values of each parameter without expanding the full grid, so huge sweeps
+// can be caught before any combination is built. Dependent parameters are evaluated
+// with the first value of each parameter they reference. Returned in input order, with a
+// zip group counted once under its names joined by '+'.
+pub fn count_values(
+    params: &[(String, String)],
+    zip: &[Vec<String>],
+    samples: Option<Sampling>,
+) -> Result<Vec<(String, usize)>, Error> {
+    let mut units = evaluation_units(params, zip)?;
+    // Parameters computed from IDX have one value, whatever the position
+    let mut context: HashMap<String, String> =
+        HashMap::from([("IDX".to_string(), "0".to_string())]);
+    let mut counts = Vec::new();
+
+    for unit in &units {
+        let values = evaluate_unit(params, unit, &context, samples)?;
+        for (name, vals) in unit.iter().zip(&values) {
+            if let Some(first) = vals.first() {
+                context.insert(name.to_uppercase(), first.clone());
+            }
+        }
+        counts.push(values[0].len());
+    }
+
+    let position = |name: &String| params.iter().position(|(n, _)| n == name);
+    let mut order: Vec<usize> = (0..units.len()).collect();
+    order.sort_by_key(|&i| units[i].iter().filter_map(position).min());
+    units.iter_mut().for_each(|unit| unit.sort_by_key(position));
+    Ok(order
+        .into_iter()
+        .map(|i| (units[i].join("+"), counts[i]))
         .collect())
 }
 
+// The values of each parameter in `unit` (one parameter or a zip group) in `context`. The
+// parameters of a zip group must have the same number of values.
+fn evaluate_unit(
+    params: &[(String, String)],
+    unit: &[String],
+    context: &HashMap<String, String>,
+    samples: Option<Sampling>,
+) -> Result<Vec<Vec<String>>, Error> {
+    let mut values: Vec<Vec<String>> = Vec::new();
+    for name in unit {
+        let value = params
+            .iter()
+            .find(|(n, _)| n == name)
+            .map(|(_, v)| v)
+            .ok_or_else(|| format!("Parameter {} not found", name))?;
+        let vals = match distribution(value) {
+            Some((kind, args)) => {
+                // The coordinate of the parameter in the sampled points
+                let dimension = params
+                    .iter()
+                    .filter(|(_, v)| distribution(v).is_some())
+                    .position(|(n, _)| n == name)
+                    .unwrap_or(0);
+                sample_values(name, dimension, kind, args, context, samples)
+            }
+            None => evaluate_expression(value, context),
+        }
+        .map_err(|message| eval_error(name, value, message))?;
+        // Duplicates are dropped, except in a zip group, whose values pair up by position
+        let vals = if unit.len() == 1 {
+            let mut seen = HashSet::new();
+            vals.into_iter()
+                .filter(|v| seen.insert(v.clone()))
+                .collect()
+        } else {
+            vals
+        };
+        if let Some(first) = values.first()
+            && first.len() != vals.len()
+        {
+            return Err(eval_error(
+                name,
+                value,
+                format!(
+                    "Zipped with {} but has {} values instead of {}",
+                    unit[0],
+                    vals.len(),
+                    first.len()
+                ),
+            ));
+        }
+        values.push(vals);
+    }
+    Ok(values)
+}
+
+// Parameters in the order they are evaluated, as units of one parameter or one zip group.
+// A zip group is evaluated at once, after every parameter its members depend on.
+fn evaluation_units(
+    params: &[(String, String)],
+    zip: &[Vec<String>],
+) -> Result<Vec<Vec<String>>, Error> {
+    let sorted_params = topological_sort(params)?;
+
+    // Sampled parameters that are not zipped otherwise are zipped with each other
+    let sampled: Vec<String> = params
+        .iter()
+        .filter(|(name, value)| {
+            distribution(value).is_some() && !zip.iter().flatten().any(|n| n == name)
+        })
+        .map(|(name, _)| name.clone())
+        .collect();
+    let zip: Vec<Vec<String>> = zip
+        .iter()
+        .cloned()
+        .chain(Some(sampled).filter(|group| !group.is_empty()))
+        .collect();
+    let zip = &zip[..];
+
+    let mut grouped: HashSet<&String> = HashSet::new();
+    for group in zip {
+        for name in group {
+            if !params.iter().any(|(n, _)| n == name) {
+                return Err(Error::usage(format!(
+                    "--zip refers to unknown parameter: {}",
+                    name
+                )));
+            }
+            if !grouped.insert(name) {
+                return Err(Error::usage(format!(
+                    "Parameter {} is zipped more than once",
+                    name
+                )));
+            }
+            // Members are evaluated together, so they cannot refer to each other
+            let value = &params.iter().find(|(n, _)| n == name).unwrap().1;
+            if let Some(dep) = group
+                .iter()
+                .find(|other| *other != name && extract_variables(value).contains(*other))
+            {
+                return Err(Error::usage(format!(
+                    "Parameter {} cannot depend on {}, which is zipped with it",
+                    name, dep
+                )));
+            }
+        }
+    }
+
+    // Units in the order of their first parameter in dependency order
+    let mut units: Vec<Vec<String>> = Vec::new();
+    for name in &sorted_params {
+        match zip.iter().find(|group| group.contains(name)) {
+            Some(_) if units.iter().any(|unit| unit.contains(name)) => continue,
+            Some(group) => units.push(group.clone()),
+            None => units.push(vec![name.clone()]),
+        }
+    }
+
+    // Then repeatedly take the first unit whose dependencies are all evaluated
+    let mut ordered = Vec::new();
+    let mut done: HashSet<String> = HashSet::new();
+    while !units.is_empty() {
+        let ready = |unit: &Vec<String>| {
+            unit.iter().all(|name| {
+                let value = &params.iter().find(|(n, _)| n == name).unwrap().1;
+                extract_variables(value).iter().all(|dep| {
+                    done.contains(dep)
+                        || unit.contains(dep)
+                        || !params.iter().any(|(n, _)| n == dep)
+                })
+            })
+        };
+        let Some(pos) = units.iter().position(ready) else {
+            return Err(Error::usage(format!(
+                "Zipped parameters {} depend on each other in a cycle",
+                units[0].join(",")
+            )));
+        };
+        let unit = units.remove(pos);
+        done.extend(unit.iter().cloned());
+        ordered.push(unit);
+    }
+    Ok(ordered)
+}
+
+// Run every combination `count` times for --repeat or --repeats: the copies follow each
+// other and differ in a REP parameter numbering them from `first`
+pub fn repeat_combinations(
+    combinations: Vec<Combination>,
+    count: usize,
+    first: usize,
+) -> Result<Vec<Combination>, Error> {
+    let mut repeated = Vec::with_capacity(combinations.len().saturating_mul(count));
+    for combo in combinations {
+        if combo.params.contains_key("REP") {
+            return Err(Error::usage(
+                "--repeat and --repeats cannot be used with a parameter named REP",
+            ));
+        }
+        for rep in first..first + count {
+            let mut copy = combo.clone();
+            copy.params.insert("REP".to_string(), rep.to_string());
+            copy.param_order.push("REP".to_string());
+            repeated.push(copy);
+        }
+    }
+    Ok(repeated)
+}
+
+// The budgets of the rounds of --halving NAME=MIN:MAX: MIN, MIN*eta, MIN*eta^2, ... and
+// MAX as the last one
+pub fn halving_budgets(min: f64, max: f64, eta: usize) -> Vec<String> {
+    let mut budgets = Vec::new();
+    let mut budget = min;
+    while budget < max * (1.0 - 1e-9) {
+        budgets.push(format_float(budget));
+        budget *= eta as f64;
+    }
+    budgets.push(format_float(max));
+    budgets
+}
+
+// The combinations of every round of --halving: a copy of all combinations per budget,
+// round after round, with the budget as parameter `name` and the round (from 1) as ROUND
+pub fn halving_rounds(
+    combinations: Vec<Combination>,
+    name: &str,
+    budgets: &[String],
+) -> Result<Vec<Combination>, Error> {
+    let mut rounds = Vec::with_capacity(combinations.len().saturating_mul(budgets.len()));
+    for (round, budget) in budgets.iter().enumerate() {
+        for combo in &combinations {
+            if let Some(taken) = [name, "ROUND"]
+                .into_iter()
+                .find(|n| combo.params.contains_key(*n))
+            {
+                return Err(Error::usage(format!(
+                    "--halving cannot be used with a parameter named {}",
+                    taken
+                )));
+            }
+            let mut copy = combo.clone();
+            copy.params.insert(name.to_string(), budget.clone());
+            copy.params
+                .insert("ROUND".to_string(), (round + 1).to_string());
+            copy.param_order.push(name.to_string());
+            copy.param_order.push("ROUND".to_string());
+            rounds.push(copy);
+        }
+    }
+    Ok(rounds)
+}
+
+// Internal parameters are named with a leading underscore (e.g. --_n 1,2,4)
+pub fn is_internal(name: &str) -> bool {
+    name.starts_with('_')
+}
+
+// Drop the internal parameters once the grid is complete: they take part in expressions,
+// --where and filters, but are not passed to the runs or written to the results
+pub fn drop_internal(combinations: &mut [Combination]) {
+    for combo in combinations {
+        combo.params.retain(|name, _| !is_internal(name));
+        combo.param_order.retain(|name| !is_internal(name));
+    }
+}
+
+// Drop the combinations whose parameter values are all the same as those of an earlier
+// one, e.g. when an expression like "min(_n,4)" collapses values of an internal parameter
+pub fn dedup_combinations(combinations: Vec<Combination>) -> Vec<Combination> {
+    let mut seen = HashSet::new();
+    combinations
+        .into_iter()
+        .filter(|combo| seen.insert(combination_key(combo)))
+        .collect()
+}
+
+// Add a SEED parameter to every combination for --auto-seed. The seed of a run depends
+// only on the sweep seed and the position of the combination in the full grid, so it is
+// the same whenever the sweep is repeated, also with --skip/--only.
+pub fn assign_seeds(combinations: &mut [Combination], sweep_seed: u64) -> Result<(), Error> {
+    for (index, combo) in combinations.iter_mut().enumerate() {
+        if combo.params.contains_key("SEED") {
+            return Err(Error::usage(
+                "--auto-seed cannot be used with a parameter named SEED",
+            ));
+        }
+        combo
+            .params
+            .insert("SEED".to_string(), run_seed(sweep_seed, index).to_string());
+        combo.param_order.push("SEED".to_string());
+    }
+    Ok(())
+}
+
+// SplitMix64 of the sweep seed and the run index, reduced to 31 bits so the seed is
+// accepted by every common RNG (e.g. numpy requires seeds below 2^32)
+fn run_seed(sweep_seed: u64, index: usize) -> u64 {
+    let mut z = sweep_seed.wrapping_add((index as u64 + 1).wrapping_mul(0x9E3779B97F4A7C15));
+    z = (z ^ (z >> 30)).wrapping_mul(0xBF58476D1CE4E5B9);
+    z = (z ^ (z >> 27)).wrapping_mul(0x94D049BB133111EB);
+    (z ^ (z >> 31)) >> 33
+}
+
+// Apply --skip and --only filters. A filter matches a combination when every
+// NAME=PATTERN condition in it matches; a combination is dropped if it matches any
+// skip filter, or if only-filters are given and it matches none of them.
+pub fn filter_combinations(
+    combinations: Vec<Combination>,
+    skip: &[Vec<(String, String)>],
+    only: &[Vec<(String, String)>],
+) -> Result<Vec<Combination>, Error> {
+    for (name, _) in skip.iter().chain(only.iter()).flatten() {
+        if let Some(combo) = combinations.first()
+            && !combo.params.contains_key(name)
+        {
+            return Err(Error::usage(format!(
+                "Filter refers to unknown parameter: {}",
+                name
+            )));
+        }
+    }
+
+    let matches = |combo: &Combination, filter: &[(String, String)]| {
+        filter.iter().all(|(name, pattern)| {
+            combo
+                .params
+                .get(name)
+                .is_some_and(|value| glob_match(pattern, value))
+        })
+    };
+
+    Ok(combinations
+        .into_iter()
+        .filter(|combo| !skip.iter().any(|f| matches(combo, f)))
+        .filter(|combo| only.is_empty() || only.iter().any(|f| matches(combo, f)))
+        .collect())
+}
+
+// Keep the combinations at the given positions of the generated grid for --only 3,7,12
+// or --only 0:100 (ranges with the end excluded), in grid order. Positions count from 0,
+// with the first parameter varying slowest, so they are the same on every machine.
+pub fn select_combinations(
+    combinations: Vec<Combination>,
+    ranges: &[(usize, usize)],
+) -> Result<Vec<Combination>, Error> {
+    if let Some(&(start, _)) = ranges
+        .iter()
+        .find(|(start, _)| *start >= combinations.len())
+    {
+        return Err(Error::usage(format!(
+            "--only {} is out of range: the grid has {} combinations (numbered from 0)",
+            start,
+            combinations.len()
+        )));
+    }
+    Ok(combinations
+        .into_iter()
+        .enumerate()
+        .filter(|(idx, _)| {
+            ranges
+                .iter()
+                .any(|(start, end)| (start..end).contains(&idx))
+        })
+        .map(|(_, combo)| combo)
+        .collect())
+}
+
+// Apply --where constraints: a combination is kept only if every constraint holds
+pub fn constrain_combinations(
+    combinations: Vec<Combination>,
+    constraints: &[String],
+) -> Result<Vec<Combination>, Error> {
+    let mut kept = Vec::with_capacity(combinations.len());
+    for combo in combinations {
+        let context: HashMap<String, String> = combo
+            .params
+            .iter()
+            .map(|(k, v)| (k.to_uppercase(), v.clone()))
+            .collect();
+        let mut holds = true;
+        for constraint in constraints {
+            holds &= eval_condition(constraint, &context).map_err(|message| {
+                Error::usage(format!(
+                    "Cannot evaluate --where {}: {}",
+                    constraint, message
+                ))
+            })?;
+        }
+        if holds {
+            kept.push(combo);
+        }
+    }
+    Ok(kept)
+}
+
+// Keep the combinations of one shard out of `count` (1-based `shard`, from --shard K/N).
+// A combination's shard depends only on its parameter values, not on its position in
+// the grid, so every machine computes the same disjoint split even if the grid order
+// differs, and combinations keep their shard when the grid is extended.
+pub fn shard_combinations(
+    combinations: Vec<Combination>,
+    (shard, count): (usize, usize),
+) -> Vec<Combination> {
+    combinations
+        .into_iter()
+        .filter(|combo| shard_of(combo, count) == shard)
+        .collect()
+}
+
+fn shard_of(combo: &Combination, count: usize) -> usize {
+    let digest = sha256(combination_key(combo).as_bytes());
+    let hash = u64::from_be_bytes(digest[..8].try_into().unwrap());
+    (hash % count as u64) as usize + 1
+}
+
+// Keep `count` randomly chosen combinations for --sample N, in grid order. Whether a
+// combination is chosen depends only on the seed and its parameter values (not on its
+// position), so resuming the sweep chooses the same runs, and a larger sample of the same
+// grid includes the smaller one.
+pub fn sample_combinations(
+    combinations: Vec<Combination>,
+    (count, seed): (usize, u64),
+) -> Vec<Combination> {
+    if combinations.len() <= count {
+        return combinations;
+    }
+    let mut ranked: Vec<(u64, usize)> = combinations
+        .iter()
+        .enumerate()
+        .map(|(idx, combo)| (random_rank(combo, seed), idx))
+        .collect();
+    ranked.sort_unstable();
+    let mut chosen: Vec<usize> = ranked[..count].iter().map(|&(_, idx)| idx).collect();
+    chosen.sort_unstable();
+    let mut chosen = chosen.into_iter().peekable();
+    combinations
+        .into_iter()
+        .enumerate()
+        .filter(|(idx, _)| chosen.next_if_eq(idx).is_some())
+        .map(|(_, combo)| combo)
+        .collect()
+}
+
+// A random number for a combination that depends only on the seed and its parameter
+// values, for choosing (--sample) and ordering (--shuffle) combinations reproducibly
+pub fn random_rank(combo: &Combination, seed: u64) -> u64 {
+    let digest = sha256(format!("{}\n{}", seed, combination_key(combo)).as_bytes());
+    u64::from_be_bytes(digest[..8].try_into().unwrap())
+}
+
+// The parameter values of a combination, independent of the order of the parameters
+pub fn combination_key(combo: &Combination) -> String {
+    let mut params: Vec<(&String, &String)> = combo.params.iter().collect();
+    params.sort();
+    params
+        .iter()
+        .map(|(name, value)| format!("{}={}\n", name, value))
+        .collect()
+}
+
+// Whether a parameter definition refers to any of the given parameters or to IDX
+pub fn depends_on(value: &str, params: &[(String, String)]) -> bool {
+    extract_variables(value)
+        .iter()
+        .any(|var| var == "IDX" || params.iter().any(|(name, _)| name == var))
+}
+
 // Topologically sort parameters based on their dependencies
-fn topological_sort(params: &[(String, String)]) -> Result<Vec<String>, String> {
+fn topological_sort(params: &[(String, String)]) -> Result<Vec<String>, Error> {
     // Build dependency graph
     let mut deps: HashMap<String, HashSet<String>> = HashMap::new();
     let param_names: HashSet<String> = params.iter().map(|(name, _)| name.clone()).collect();
@@ -118,20 +676,60 @@ fn topological_sort(params: &[(String, String)]) -> Result<Vec<String>, String>
     }
 
     if result.len() != param_names.len() {
-        // Circular dependency detected
-        return Err("Circular dependency detected in parameter definitions".to_string());
+        // Circular dependency detected; report the first parameter that is part of it
+        let (name, value) = params
+            .iter()
+            .find(|(name, _)| !result.contains(name))
+            .unwrap();
+        return Err(eval_error(
+            name,
+            value,
+            "Circular dependency detected in parameter definitions".to_string(),
+        ));
     }
 
     Ok(result)
 }
 
+fn eval_error(param: &str, expr: &str, message: String) -> Error {
+    Error::Eval {
+        param: param.to_string(),
+        expr: expr.to_string(),
+        message,
+    }
+}
+
 // Extract variable names from an expression
 fn extract_variables(expr: &str) -> HashSet<String> {
     let mut variables = HashSet::new();
 
+    // Commands see no parameters
+    if expr.trim_start().starts_with("cmd:") {
+        return variables;
+    }
+
     // Split by comma first
-    for part in expr.split(',') {
+    for part in split_top_level(expr, ',') {
         let part = part.trim();
+        let part = log_range_bounds(part).unwrap_or(part);
+
+        // A string expression refers to the parameters outside its quoted strings
+        if is_string_expr(part) {
+            string_variables(part, &mut variables);
+            continue;
+        }
+
+        // Values read from a file and literal values have no variables
+        if part.starts_with('@') || literal(part).is_some() {
+            continue;
+        }
+
+        // Only the placeholders of a string like "results/{gpu}g" refer to parameters
+        let names = placeholders(part);
+        if !names.is_empty() {
+            variables.extend(names.into_iter().map(|(_, name)| name.to_uppercase()));
+            continue;
+        }
 
         // Skip ranges (contain ':')
         if part.contains(':') {
@@ -159,6 +757,9 @@ fn extract_variables_from_term(term: &str, variables: &mut HashSet<String>) {
     for ch in term.chars() {
         if ch.is_alphabetic() || ch == '_' {
             current_token.push(ch);
+        } else if ch == '(' {
+            // A function name, e.g. "min(n, 8)"
+            current_token.clear();
         } else if !current_token.is_empty() {
             // Normalize to uppercase for consistency
             variables.insert(current_token.to_uppercase());
@@ -175,17 +776,76 @@ fn evaluate_expression(
     expr: &str,
     context: &HashMap<String, String>,
 ) -> Result<Vec<String>, String> {
-    // Split by comma for multiple values (supports concatenated ranges like "1:4,10:20:2")
-    let parts: Vec<&str> = expr.split(',').collect();
+    // The output lines of a command (e.g., "cmd:ls checkpoints/*.pt"), which may contain
+    // commas of its own
+    if let Some(command) = expr.trim_start().strip_prefix("cmd:") {
+        return command_values(command);
+    }
+
+    // Split by comma for multiple values (supports concatenated ranges like "1:4,10:20:2"),
+    // except for the commas between function arguments
+    let parts = split_top_level(expr, ',');
     let mut results = Vec::new();
-    let mut seen = HashSet::new();
 
     for part in parts {
         let part = part.trim();
 
+        // Check for a string expression (e.g., 'upper(model) ++ "_run"')
+        if let Some(val) = string_expr(part, context) {
+            results.push(val);
+            continue;
+        }
+
+        // Check for a literal value (e.g., '"0,1,2,3"' or "[0,1,2,3]")
+        if let Some(val) = literal(part) {
+            results.push(val);
+            continue;
+        }
+
+        // Check for values read from a file (e.g., "@datasets.txt")
+        if let Some(path) = part.strip_prefix('@') {
+            results.extend(read_values(path)?);
+            continue;
+        }
+
+        // Check for a string with placeholders (e.g., "results/{gpu}g_{batchsize}b")
+        if let Some(val) = interpolate(part, context) {
+            results.push(val);
+            continue;
+        }
+
+        // Check for conditional value (e.g., "n>4 ? fp16 : fp32")
+        if let Some(val) = parse_conditional(part, context) {
+            let val = val?;
+            results.push(val);
+            continue;
+        }
+
+        // Check for log-spaced range (e.g., "log(1e-5:1e-1:5)")
+        if let Some(inner) = log_range_bounds(part) {
+            results.extend(log_range(inner, context)?);
+            continue;
+        }
+
         // Check for range (e.g., "1:4" or "1:10:2")
         if part.contains(':') {
             let range_parts: Vec<&str> = part.split(':').collect();
+            // Ranges with a multiplicative step, e.g. "1:1024:*2"
+            if let [start, end, step] = range_parts[..]
+                && let Some(factor) = step.trim().strip_prefix('*')
+            {
+                results.extend(geometric_range(start, end, factor, context)?);
+                continue;
+            }
+            // Ranges with a fractional bound or step, e.g. "0.1:0.5:0.1"
+            if matches!(range_parts.len(), 2 | 3)
+                && range_parts
+                    .iter()
+                    .any(|p| parse_int_expr(p.trim(), context).is_err())
+            {
+                results.extend(float_range(&range_parts, context)?);
+                continue;
+            }
             if range_parts.len() == 2 {
                 let start = parse_int_expr(range_parts[0].trim(), context)?;
                 let end = parse_int_expr(range_parts[1].trim(), context)?;
@@ -197,9 +857,7 @@ fn evaluate_expression(
                 }
                 for i in start..end {
                     let val = i.to_string();
-                    if seen.insert(val.clone()) {
-                        results.push(val);
-                    }
+                    results.push(val);
                 }
                 continue;
             } else if range_parts.len() == 3 {
@@ -215,65 +873,915 @@ fn evaluate_expression(
                     return Err(format!("Invalid range {}:{}:{}", start, end, step));
                 }
 
-                let mut i = start;
-                while (step > 0 && i < end) || (step < 0 && i > end) {
-                    let val = i.to_string();
-                    if seen.insert(val.clone()) {
-                        results.push(val);
+                let mut i = start;
+                while (step > 0 && i < end) || (step < 0 && i > end) {
+                    let val = i.to_string();
+                    results.push(val);
+                    i += step;
+                }
+                continue;
+            }
+        }
+
+        // Check for a file pattern (e.g., "configs/*.yaml") that is not a product
+        if is_file_pattern(part) && parse_float_expr(part, context).is_err() {
+            results.extend(glob_values(part)?);
+            continue;
+        }
+
+        // Try to parse as expression
+        match parse_expr(part, context) {
+            Ok(val) => {
+                results.push(val);
+            }
+            Err(_) => {
+                // If parsing fails, treat as literal string
+                let val = part.to_string();
+                results.push(val);
+            }
+        }
+    }
+
+    Ok(results)
+}
+
+fn parse_expr(expr: &str, context: &HashMap<String, String>) -> Result<String, String> {
+    let expr = expr.trim();
+
+    // Digits and dashes, e.g. a date like "2024-01-01", are not a subtraction
+    if expr.chars().all(|c| c.is_ascii_digit() || c == '-')
+        && expr.get(1..).is_some_and(|rest| rest.contains('-'))
+    {
+        return Ok(expr.to_string());
+    }
+
+    // Try to parse as integer expression first
+    if let Ok(val) = parse_int_expr(expr, context) {
+        return Ok(val.to_string());
+    }
+    // Then as floating-point expression, e.g. "n/10" or "0.1n". A plain number like
+    // "0.10" or "1e-3" is kept as written.
+    if (expr.contains(['+', '-', '*', '/', '^']) || expr.chars().any(|c| c.is_alphabetic()))
+        && let Ok(val) = parse_float_expr(expr, context)
+    {
+        return Ok(format_float(val));
+    }
+    // Not a numeric expression, return as-is
+    Ok(expr.to_string())
+}
+
+// The values of a range with fractional bounds or step (1 if omitted). Each value is
+// computed as start + k*step rather than by repeated addition, so rounding errors do not
+// accumulate, and the end is excluded even when it is only reached up to rounding
+fn float_range(parts: &[&str], context: &HashMap<String, String>) -> Result<Vec<String>, String> {
+    let bound = |s: &str| match s.trim().strip_prefix('-') {
+        Some(rest) => parse_float_expr(rest, context).map(|v| -v),
+        None => parse_float_expr(s.trim(), context),
+    };
+    let start = bound(parts[0])?;
+    let end = bound(parts[1])?;
+    let step = match parts.get(2) {
+        Some(step) => bound(step)?,
+        None => 1.0,
+    };
+    if step == 0.0 {
+        return Err("Range step cannot be zero".to_string());
+    }
+    let count = ((end - start) / step * (1.0 - 1e-9)).ceil();
+    if count < 1.0 {
+        return Err(format!("Invalid range {}", parts.join(":")));
+    }
+    Ok((0..count as i64)
+        .map(|k| format_float(start + k as f64 * step))
+        .collect())
+}
+
+// The values start, start*factor, start*factor^2, ... up to end (excluded)
+fn geometric_range(
+    start: &str,
+    end: &str,
+    factor: &str,
+    context: &HashMap<String, String>,
+) -> Result<Vec<String>, String> {
+    let range = format!("{}:{}:*{}", start.trim(), end.trim(), factor.trim());
+    let start = parse_float_expr(start.trim(), context)?;
+    let end = parse_float_expr(end.trim(), context)?;
+    let factor = parse_float_expr(factor.trim(), context)?;
+    if start <= 0.0 || end <= 0.0 || factor <= 0.0 || factor == 1.0 {
+        return Err(format!(
+            "Invalid range {} (bounds and factor must be positive, and the factor not 1)",
+            range
+        ));
+    }
+    let count = ((end / start).ln() / factor.ln() * (1.0 - 1e-9)).ceil();
+    if count < 1.0 {
+        return Err(format!("Invalid range {}", range));
+    }
+    Ok((0..count as i32)
+        .map(|k| format_float(start * factor.powi(k)))
+        .collect())
+}
+
+// The kind and arguments of a random distribution like "uniform(1e-4, 1e-1)"
+fn distribution(value: &str) -> Option<(&str, &str)> {
+    let (kind, rest) = value.trim().split_once('(')?;
+    let args = rest.strip_suffix(')')?;
+    matches!(kind.trim(), "uniform" | "loguniform" | "normal").then_some((kind.trim(), args))
+}
+
+// `count` values drawn from a distribution. The k-th value of a parameter depends only on
+// the seed, the parameter name (or its `dimension` in a Sobol sequence) and k, so a sweep
+// is reproducible and parameters are drawn independently.
+fn sample_values(
+    name: &str,
+    dimension: usize,
+    kind: &str,
+    args: &str,
+    context: &HashMap<String, String>,
+    samples: Option<Sampling>,
+) -> Result<Vec<String>, String> {
+    let Sampling {
+        count,
+        seed,
+        strategy,
+    } = samples.ok_or_else(|| {
+        format!(
+            "{}(...) draws random values, so it needs a sample count (--samples N)",
+            kind
+        )
+    })?;
+    // Arguments may be written in scientific notation, e.g. "1e-5"
+    let args = split_top_level(args, ',')
+        .into_iter()
+        .map(|arg| {
+            let arg = arg.trim();
+            arg.parse::<f64>()
+                .or_else(|_| parse_float_expr(arg, context))
+                .map_err(|_| format!("Invalid {} argument: {}", kind, arg))
+        })
+        .collect::<Result<Vec<f64>, String>>()?;
+    let [a, b] = args[..] else {
+        return Err(format!("{} takes two arguments", kind));
+    };
+    match kind {
+        "uniform" if a >= b => return Err("uniform(LOW, HIGH) needs LOW < HIGH".to_string()),
+        "loguniform" if a <= 0.0 || a >= b => {
+            return Err("loguniform(LOW, HIGH) needs 0 < LOW < HIGH".to_string());
+        }
+        "normal" if b < 0.0 => {
+            return Err("normal(MEAN, STDDEV) needs STDDEV >= 0".to_string());
+        }
+        _ => {}
+    }
+
+    // Space-filling strategies place one coordinate per point in [0, 1)
+    let spread = match strategy {
+        Strategy::Random => None,
+        Strategy::Lhs => Some(latin_hypercube(name, count, seed)),
+        Strategy::Sobol => Some(sobol(dimension, count, seed)?),
+    };
+
+    Ok((0..count)
+        .map(|k| {
+            let [u, v] = random_units(seed, name, k);
+            let value = match (kind, &spread) {
+                ("uniform", None) => a + u * (b - a),
+                ("loguniform", None) => (a.ln() + u * (b.ln() - a.ln())).exp(),
+                // Box-Muller transform
+                (_, None) => {
+                    a + b * (-2.0 * (1.0 - u).ln()).sqrt() * (2.0 * std::f64::consts::PI * v).cos()
+                }
+                ("uniform", Some(units)) => a + units[k] * (b - a),
+                ("loguniform", Some(units)) => (a.ln() + units[k] * (b.ln() - a.ln())).exp(),
+                (_, Some(units)) => a + b * normal_quantile(units[k]),
+            };
+            format_float(value)
+        })
+        .collect())
+}
+
+// Two uniform numbers in [0, 1) hashed from the seed, the parameter name and k
+fn random_units(seed: u64, name: &str, k: usize) -> [f64; 2] {
+    let digest = sha256(format!("{}\n{}\n{}", seed, name, k).as_bytes());
+    let unit = |bytes: &[u8]| {
+        (u64::from_be_bytes(bytes.try_into().unwrap()) >> 11) as f64 / (1u64 << 53) as f64
+    };
+    [unit(&digest[..8]), unit(&digest[8..16])]
+}
+
+// One coordinate of a Latin hypercube of `count` points: the k-th point lies at a random
+// position in stratum perm(k) of [0, 1), for a random permutation per parameter
+fn latin_hypercube(name: &str, count: usize, seed: u64) -> Vec<f64> {
+    let mut strata: Vec<(f64, usize)> = (0..count)
+        .map(|k| (random_units(seed, &format!("{}/lhs", name), k)[0], k))
+        .collect();
+    strata.sort_by(|(a, _), (b, _)| a.total_cmp(b));
+    let mut units = vec![0.0; count];
+    for (stratum, (_, k)) in strata.into_iter().enumerate() {
+        units[k] = (stratum as f64 + random_units(seed, name, k)[0]) / count as f64;
+    }
+    units
+}
+
+// Primitive polynomials (degree, coefficients) and initial direction numbers of the Sobol
+// sequence for dimensions 2 and up, from Joe and Kuo (new-joe-kuo-6.21201)
+const SOBOL_DIRECTIONS: [(u32, u32, &[u32]); 15] = [
+    (1, 0, &[1]),
+    (2, 1, &[1, 3]),
+    (3, 1, &[1, 3, 1]),
+    (3, 2, &[1, 1, 1]),
+    (4, 1, &[1, 1, 3, 3]),
+    (4, 4, &[1, 3, 5, 13]),
+    (5, 2, &[1, 1, 5, 5, 17]),
+    (5, 4, &[1, 1, 5, 5, 5]),
+    (5, 7, &[1, 1, 7, 11, 19]),
+    (5, 11, &[1, 1, 5, 1, 1]),
+    (5, 13, &[1, 1, 1, 3, 11]),
+    (5, 14, &[1, 3, 5, 5, 31]),
+    (6, 1, &[1, 3, 3, 9, 7, 49]),
+    (6, 13, &[1, 1, 1, 15, 21, 21]),
+    (6, 16, &[1, 3, 1, 13, 27, 49]),
+];
+
+// One coordinate (0-based `dimension`) of the first `count` points of a Sobol sequence,
+// scrambled with a random digital shift so the seed matters and the first point is not 0
+fn sobol(dimension: usize, count: usize, seed: u64) -> Result<Vec<f64>, String> {
+    if dimension > SOBOL_DIRECTIONS.len() {
+        return Err(format!(
+            "sobol sampling supports up to {} parameters",
+            SOBOL_DIRECTIONS.len() + 1
+        ));
+    }
+    if count > 1 << 31 {
+        return Err("Too many points for sobol sampling".to_string());
+    }
+    let mut directions = [0u32; 32];
+    match dimension.checked_sub(1).map(|d| SOBOL_DIRECTIONS[d]) {
+        None => {
+            for (i, v) in directions.iter_mut().enumerate() {
+                *v = 1 << (31 - i);
+            }
+        }
+        Some((degree, coefficients, initial)) => {
+            let s = degree as usize;
+            for i in 0..32 {
+                directions[i] = if i < s {
+                    initial[i] << (31 - i)
+                } else {
+                    let mut v = directions[i - s] ^ (directions[i - s] >> s);
+                    for k in 1..s {
+                        if (coefficients >> (s - 1 - k)) & 1 == 1 {
+                            v ^= directions[i - k];
+                        }
+                    }
+                    v
+                };
+            }
+        }
+    }
+    let digest = sha256(format!("{}\nsobol\n{}", seed, dimension).as_bytes());
+    let shift = u32::from_be_bytes(digest[..4].try_into().unwrap());
+    Ok((0..count)
+        .map(|k| {
+            let mut x = shift;
+            for (bit, v) in directions.iter().enumerate() {
+                if (k >> bit) & 1 == 1 {
+                    x ^= v;
+                }
+            }
+            (x as f64 + 0.5) / 4294967296.0
+        })
+        .collect())
+}
+
+// The quantile function of the standard normal distribution at p in (0, 1), with the
+// rational approximation of Acklam (relative error below 1.15e-9)
+fn normal_quantile(p: f64) -> f64 {
+    const A: [f64; 6] = [
+        -3.969683028665376e1,
+        2.209460984245205e2,
+        -2.759285104469687e2,
+        1.38357751867269e2,
+        -3.066479806614716e1,
+        2.506628277459239,
+    ];
+    const B: [f64; 5] = [
+        -5.447609879822406e1,
+        1.615858368580409e2,
+        -1.556989798598866e2,
+        6.680131188771972e1,
+        -1.328068155288572e1,
+    ];
+    const C: [f64; 6] = [
+        -7.784894002430293e-3,
+        -3.223964580411365e-1,
+        -2.400758277161838,
+        -2.549732539343734,
+        4.374664141464968,
+        2.938163982698783,
+    ];
+    const D: [f64; 4] = [
+        7.784695709041462e-3,
+        3.224671290700398e-1,
+        2.445134137142996,
+        3.754408661907416,
+    ];
+    let tail = |q: f64| {
+        (((((C[0] * q + C[1]) * q + C[2]) * q + C[3]) * q + C[4]) * q + C[5])
+            / ((((D[0] * q + D[1]) * q + D[2]) * q + D[3]) * q + 1.0)
+    };
+    let p = p.clamp(1e-300, 1.0 - 1e-16);
+    if p < 0.02425 {
+        tail((-2.0 * p.ln()).sqrt())
+    } else if p > 1.0 - 0.02425 {
+        -tail((-2.0 * (1.0 - p).ln()).sqrt())
+    } else {
+        let q = p - 0.5;
+        let r = q * q;
+        (((((A[0] * r + A[1]) * r + A[2]) * r + A[3]) * r + A[4]) * r + A[5]) * q
+            / (((((B[0] * r + B[1]) * r + B[2]) * r + B[3]) * r + B[4]) * r + 1.0)
+    }
+}
+
+// The values in a file, one per non-empty line, taken as written
+// Whether a value looks like a path with wildcards, such as "configs/*.yaml" or
+// "exp_?.json": it has a directory or a file extension, so "n*2" does not
+fn is_file_pattern(part: &str) -> bool {
+    let name = part.rsplit('/').next().unwrap_or(part);
+    let extension = name.rsplit_once('.').is_some_and(|(_, ext)| {
+        ext.chars().all(|c| c.is_alphanumeric()) && ext.chars().any(|c| c.is_alphabetic())
+    });
+    part.contains(['*', '?'])
+        && !part.contains(char::is_whitespace)
+        && (part.contains('/') || extension)
+}
+
+// The paths of the files matching a pattern, in sorted order
+fn glob_values(pattern: &str) -> Result<Vec<String>, String> {
+    let (base, prefix) = match pattern.starts_with('/') {
+        true => ("/", "/"),
+        false => (".", ""),
+    };
+    let values: Vec<String> = glob_paths(std::path::Path::new(base), pattern)
+        .into_iter()
+        .map(|path| format!("{}{}", prefix, path.to_string_lossy()))
+        .collect();
+    if values.is_empty() {
+        return Err(format!("No files match {}", pattern));
+    }
+    Ok(values)
+}
+
+fn read_values(path: &str) -> Result<Vec<String>, String> {
+    let content = std::fs::read_to_string(path)
+        .map_err(|e| format!("Cannot read values from {}: {}", path, e))?;
+    let values: Vec<String> = content
+        .lines()
+        .map(str::trim)
+        .filter(|line| !line.is_empty())
+        .map(str::to_string)
+        .collect();
+    if values.is_empty() {
+        return Err(format!("No values in {}", path));
+    }
+    Ok(values)
+}
+
+// The values printed by a command, one per non-empty line, taken as written and without
+// duplicates
+fn command_values(command: &str) -> Result<Vec<String>, String> {
+    let output = std::process::Command::new("bash")
+        .args(["-c", command])
+        .stderr(std::process::Stdio::inherit())
+        .output()
+        .map_err(|e| format!("Failed to run {}: {}", command, e))?;
+    if !output.status.success() {
+        return Err(format!("Command {} failed ({})", command, output.status));
+    }
+    let mut seen = HashSet::new();
+    let values: Vec<String> = String::from_utf8_lossy(&output.stdout)
+        .lines()
+        .map(str::trim)
+        .filter(|line| !line.is_empty() && seen.insert(line.to_string()))
+        .map(str::to_string)
+        .collect();
+    if values.is_empty() {
+        return Err(format!("Command {} printed no values", command));
+    }
+    Ok(values)
+}
+
+// A conditional value "COND ? THEN : ELSE", or None if `part` is not one. COND compares
+// two expressions or parameters with one of <, <=, >, >=, == and !=, as numbers when both
+// sides are numbers and as strings otherwise; THEN and ELSE are values (ELSE may be
+// another conditional). Values without a comparison before '?' and a ':' after it, like
+// "a?b", are not conditionals
+fn parse_conditional(
+    part: &str,
+    context: &HashMap<String, String>,
+) -> Option<Result<String, String>> {
+    let (cond, branches) = part.split_once('?')?;
+    let (then, otherwise) = branches.split_once(':')?;
+    if !cond.contains(['<', '>', '=', '!']) {
+        return None;
+    }
+    Some(eval_condition(cond, context).and_then(|holds| {
+        let branch = if holds { then } else { otherwise };
+        match parse_conditional(branch.trim(), context) {
+            Some(val) => val,
+            None => parse_expr(branch, context),
+        }
+    }))
+}
+
+fn eval_condition(cond: &str, context: &HashMap<String, String>) -> Result<bool, String> {
+    let (left, comparison, right) =
+        split_comparison(cond).map_err(|e| format!("Invalid condition {}: {}", cond.trim(), e))?;
+    // A side is a parameter (whatever its value) or an expression
+    let side = |s: &str| match context.get(&s.trim().to_uppercase()) {
+        Some(value) => Ok(value.clone()),
+        None => parse_expr(s, context),
+    };
+    let (left, right) = (side(left)?, side(right)?);
+    match (left.parse::<f64>(), right.parse::<f64>()) {
+        (Ok(l), Ok(r)) => Ok(comparison.test(l, r)),
+        _ if matches!(comparison, Comparison::Equal | Comparison::NotEqual) => {
+            Ok(comparison.test(left, right))
+        }
+        _ => Err(format!(
+            "Cannot compare {} and {} in {}",
+            left,
+            right,
+            cond.trim()
+        )),
+    }
+}
+
+// The "start:end:count" inside "log(...)"
+fn log_range_bounds(part: &str) -> Option<&str> {
+    part.strip_prefix("log(")?.strip_suffix(')')
+}
+
+// `count` values from start to end (both included), evenly spaced in log space
+fn log_range(inner: &str, context: &HashMap<String, String>) -> Result<Vec<String>, String> {
+    let parts: Vec<&str> = inner.split(':').collect();
+    let [start, end, count] = parts[..] else {
+        return Err(format!(
+            "Invalid log range log({}) (expected log(start:end:count))",
+            inner
+        ));
+    };
+    // Bounds may be written in scientific notation, e.g. "1e-5"
+    let bound = |s: &str| {
+        s.trim()
+            .parse::<f64>()
+            .or_else(|_| parse_float_expr(s.trim(), context))
+            .ok()
+            .filter(|v| *v > 0.0 && v.is_finite())
+            .ok_or_else(|| format!("Log range bound must be a positive number: {}", s.trim()))
+    };
+    let (start, end) = (bound(start)?.ln(), bound(end)?.ln());
+    let count = parse_int_expr(count, context)?;
+    if count < 2 {
+        return Err(format!("Log range needs at least 2 points, got {}", count));
+    }
+    let step = (end - start) / (count - 1) as f64;
+    Ok((0..count)
+        .map(|k| format_float((start + k as f64 * step).exp()))
+        .collect())
+}
+
+// Results of floating-point expressions are rounded to 12 significant digits, so
+// "0.1*3" gives 0.3 rather than 0.30000000000000004, and whole numbers are written
+// without a fractional part
+pub fn format_float(value: f64) -> String {
+    let rounded: f64 = format!("{:.11e}", value).parse().unwrap_or(value);
+    if rounded == rounded.trunc() && rounded.abs() < 1e15 {
+        (rounded as i64).to_string()
+    } else {
+        rounded.to_string()
+    }
+}
+
+fn parse_float_expr(expr: &str, context: &HashMap<String, String>) -> Result<f64, String> {
+    let mut sum = 0.0;
+    for (negate, term) in split_terms(expr) {
+        let value = parse_float_mult_expr(term.trim(), context)?;
+        sum += if negate { -value } else { value };
+    }
+    if !sum.is_finite() {
+        return Err(format!("Not a finite number: {}", expr));
+    }
+    Ok(sum)
+}
+
+// Products and quotients, evaluated from left to right
+fn parse_float_mult_expr(expr: &str, context: &HashMap<String, String>) -> Result<f64, String> {
+    let mut result = 1.0;
+    let mut op = '*';
+    let mut rest = expr;
+    loop {
+        let end = find_top_level(rest, &['*', '/']).unwrap_or(rest.len());
+        let value = parse_float_exp_expr(rest[..end].trim(), context)?;
+        if op == '*' {
+            result *= value;
+        } else if value == 0.0 {
+            return Err(format!("Division by zero: {}", expr));
+        } else {
+            result /= value;
+        }
+        let Some(next) = rest[end..].chars().next() else {
+            return Ok(result);
+        };
+        op = next;
+        rest = &rest[end + 1..];
+    }
+}
+
+fn parse_float_exp_expr(expr: &str, context: &HashMap<String, String>) -> Result<f64, String> {
+    if let Some(i) = find_top_level(expr, &['^']) {
+        let base = parse_float_atom_expr(expr[..i].trim(), context)?;
+        let exp = parse_float_exp_expr(expr[i + 1..].trim(), context)?; // Right associative
+        return Ok(base.powf(exp));
+    }
+    parse_float_atom_expr(expr, context)
+}
+
+// The position of the first of `ops` that is not inside parentheses
+fn find_top_level(expr: &str, ops: &[char]) -> Option<usize> {
+    let mut depth = 0;
+    for (i, c) in expr.char_indices() {
+        match c {
+            '(' => depth += 1,
+            ')' => depth -= 1,
+            _ if depth == 0 && ops.contains(&c) => return Some(i),
+            _ => {}
+        }
+    }
+    None
+}
+
+// A function call like "min(n, 8)", or a parenthesized expression
+fn parse_float_call(expr: &str, context: &HashMap<String, String>) -> Result<f64, String> {
+    let open = expr.find('(').ok_or("Missing '('")?;
+    let name = expr[..open].trim();
+    let inner = &expr[open + 1..expr.len() - 1];
+    let args = split_top_level(inner, ',')
+        .into_iter()
+        .map(|arg| parse_float_expr(arg.trim(), context))
+        .collect::<Result<Vec<f64>, String>>()?;
+    match (name, &args[..]) {
+        ("", [value]) => Ok(*value),
+        ("min", [_, ..]) => Ok(args.iter().copied().fold(f64::INFINITY, f64::min)),
+        ("max", [_, ..]) => Ok(args.iter().copied().fold(f64::NEG_INFINITY, f64::max)),
+        ("abs", [value]) => Ok(value.abs()),
+        ("floor", [value]) => Ok(value.floor()),
+        ("ceil", [value]) => Ok(value.ceil()),
+        ("pow", [base, exp]) => Ok(base.powf(*exp)),
+        ("min" | "max" | "abs" | "floor" | "ceil" | "pow", _) => {
+            Err(format!("Wrong number of arguments to {}: {}", name, expr))
+        }
+        _ => Err(format!("Unknown function {}: {}", name, expr)),
+    }
+}
+
+fn parse_float_atom_expr(expr: &str, context: &HashMap<String, String>) -> Result<f64, String> {
+    if let Some(rest) = expr.strip_prefix('-') {
+        return Ok(-parse_float_atom_expr(rest.trim(), context)?);
+    }
+    if expr.ends_with(')') && !expr.starts_with(|c: char| c.is_ascii_digit() || c == '.') {
+        return parse_float_call(expr, context);
+    }
+
+    // A number of digits and at most one decimal point, e.g. "0.5" or ".5"
+    let is_number = |s: &str| {
+        s.chars().any(|c| c.is_ascii_digit())
+            && s.chars().all(|c| c.is_ascii_digit() || c == '.')
+            && s.matches('.').count() <= 1
+    };
+
+    // Implicit multiplication, e.g. "0.1n"
+    let num_end = expr
+        .find(|c: char| !c.is_ascii_digit() && c != '.')
+        .unwrap_or(expr.len());
+    if num_end > 0 && num_end < expr.len() {
+        let (num_part, var_part) = expr.split_at(num_end);
+        if !is_number(num_part) {
+            return Err(format!("Invalid number: {}", num_part));
+        }
+        let num: f64 = num_part.parse().map_err(|_| "Invalid number")?;
+        return Ok(num * parse_float_atom_expr(var_part, context)?);
+    }
+
+    if let Some(value) = context.get(&expr.to_uppercase()) {
+        return value
+            .trim()
+            .parse::<f64>()
+            .ok()
+            .filter(|v| v.is_finite())
+            .ok_or_else(|| format!("Variable {} is not a number", expr));
+    }
+
+    if !is_number(expr) {
+        return Err(format!("Cannot parse as number: {}", expr));
+    }
+    expr.parse::<f64>()
+        .map_err(|_| format!("Cannot parse as number: {}", expr))
+}
+
+fn parse_int_expr(expr: &str, context: &HashMap<String, String>) -> Result<i64, String> {
+    let expr = expr.trim();
+
+    // Handle addition and subtraction (lowest precedence)
+    let mut sum: i64 = 0;
+    for (negate, term) in split_terms(expr) {
+        let value = parse_mult_expr(term.trim(), context)?;
+        sum = if negate {
+            sum.checked_sub(value)
+        } else {
+            sum.checked_add(value)
+        }
+        .ok_or_else(|| format!("Integer overflow: {}", expr))?;
+    }
+    Ok(sum)
+}
+
+// Split at each `sep` that is not inside parentheses, brackets or quotes, or escaped
+fn split_top_level(expr: &str, sep: char) -> Vec<&str> {
+    let mut parts = Vec::new();
+    let mut depth = 0;
+    let mut start = 0;
+    let mut quote = None;
+    let mut chars = expr.char_indices();
+    while let Some((i, c)) = chars.next() {
+        match (quote, c) {
+            // Inside quotes only the closing quote counts, and a backslash escapes it
+            (Some(_), '\\') => {
+                chars.next();
+            }
+            (Some(q), _) if c == q => quote = None,
+            (Some(_), _) => {}
+            // Quotes count only around a whole value or a string term, so "it's" is no quote
+            (None, '"' | '\'') if opens_quote(&expr[start..i]) => quote = Some(c),
+            // "\," is a comma within a value
+            (None, '\\') if expr[i + 1..].starts_with(sep) => {
+                chars.next();
+            }
+            (None, '(' | '[' | '{') => depth += 1,
+            (None, ')' | ']' | '}') => depth = (depth - 1).max(0),
+            (None, _) if c == sep && depth == 0 => {
+                parts.push(&expr[start..i]);
+                start = i + c.len_utf8();
+            }
+            _ => {}
+        }
+    }
+    parts.push(&expr[start..]);
+    parts
+}
+
+// A value taken as it is: a quoted string like "0,1,2,3" (without the quotes; a
+// backslash escapes a quote or backslash in it), a bracketed list like [0,1,2,3], an
+// object like {"lr": 0.1}, or a value with escaped commas like a\,b
+fn literal(part: &str) -> Option<String> {
+    let mut chars = part.chars();
+    match (chars.next(), chars.next_back()) {
+        (Some(open @ ('"' | '\'')), Some(close)) if open == close && part.len() >= 2 => {
+            let mut value = String::new();
+            let mut chars = part[1..part.len() - 1].chars().peekable();
+            while let Some(c) = chars.next() {
+                match chars.peek() {
+                    Some(&next) if c == '\\' && (next == open || next == '\\') => {
+                        value.push(next);
+                        chars.next();
                     }
-                    i += step;
+                    _ => value.push(c),
                 }
-                continue;
             }
+            Some(value)
         }
+        (Some('['), Some(']')) => Some(part.to_string()),
+        (Some('{'), Some('}')) if part.contains(':') => Some(part.to_string()),
+        _ if part.contains("\\,") => Some(part.replace("\\,", ",")),
+        _ => None,
+    }
+}
 
-        // Try to parse as expression
-        match parse_expr(part, context) {
-            Ok(val) => {
-                if seen.insert(val.clone()) {
-                    results.push(val);
-                }
+// The placeholders like "{gpu}" or "{env(HOME)}" in a value, with their position: a name
+// in braces that is not part of a shell expansion like "${HOME}"
+fn placeholders(part: &str) -> Vec<(std::ops::Range<usize>, &str)> {
+    let mut found = Vec::new();
+    let mut from = 0;
+    while let Some(start) = part[from..].find('{').map(|i| from + i) {
+        from = start + 1;
+        let Some(end) = part[from..].find('}').map(|i| from + i) else {
+            break;
+        };
+        let name = &part[from..end];
+        let identifier = |name: &str| {
+            name.starts_with(|c: char| c.is_alphabetic() || c == '_')
+                && name.chars().all(|c| c.is_alphanumeric() || c == '_')
+        };
+        let valid = identifier(name) || env_builtin(name).is_some_and(identifier);
+        if valid && !part[..start].ends_with('$') {
+            found.push((start..end + 1, name));
+            from = end + 1;
+        }
+    }
+    found
+}
+
+// A value with its placeholders replaced by the values of the parameters of those names
+// (case-insensitive) or of builtins, taken as a string rather than an expression.
+// Placeholders of unknown names are kept. None if the value has no placeholders.
+fn interpolate(part: &str, context: &HashMap<String, String>) -> Option<String> {
+    let found = placeholders(part);
+    if found.is_empty() {
+        return None;
+    }
+    let mut value = String::new();
+    let mut last = 0;
+    for (range, name) in found {
+        value.push_str(&part[last..range.start]);
+        match context
+            .get(&name.to_uppercase())
+            .cloned()
+            .or_else(|| builtin(name))
+        {
+            Some(v) => value.push_str(&v),
+            None => value.push_str(&part[range.clone()]),
+        }
+        last = range.end;
+    }
+    value.push_str(&part[last..]);
+    Some(value)
+}
+
+// A value outside the grid, like a --tag value or the --name, with its builtin
+// placeholders replaced; other placeholders are kept
+pub fn expand_builtins(value: &str) -> String {
+    interpolate(value, &HashMap::new()).unwrap_or_else(|| value.to_string())
+}
+
+// The value of a builtin placeholder: "today" and "now", the UTC date and time at which
+// runexp started, like 2024-01-31 and 20240131-235959 (the same in every value and run),
+// and "env(NAME)", a variable of runexp's own environment (empty if it is not set)
+pub fn builtin(name: &str) -> Option<String> {
+    static START: OnceLock<DateTime> = OnceLock::new();
+    if let Some(variable) = env_builtin(name) {
+        return Some(std::env::var(variable).unwrap_or_default());
+    }
+    match name.to_lowercase().as_str() {
+        "today" => Some(START.get_or_init(DateTime::now).date()),
+        "now" => Some(START.get_or_init(DateTime::now).compact()),
+        _ => None,
+    }
+}
+
+// The variable name in "env(NAME)"
+fn env_builtin(name: &str) -> Option<&str> {
+    let prefix = name.get(..4).filter(|p| p.eq_ignore_ascii_case("env("))?;
+    name[prefix.len()..].strip_suffix(')')
+}
+
+// Whether a quote after `before` (the text of the current value or term so far) opens
+// a quoted string: at the start of a value, after "++" or as a function argument
+fn opens_quote(before: &str) -> bool {
+    let before = before.trim_end();
+    before.is_empty() || before.ends_with(['(', ',']) || before.ends_with("++")
+}
+
+// Split a string expression into the terms joined by "++"
+fn split_concat(expr: &str) -> Vec<&str> {
+    let mut terms = Vec::new();
+    let mut depth = 0;
+    let mut start = 0;
+    let mut quote = None;
+    let mut chars = expr.char_indices();
+    while let Some((i, c)) = chars.next() {
+        match (quote, c) {
+            (Some(_), '\\') => {
+                chars.next();
             }
-            Err(_) => {
-                // If parsing fails, treat as literal string
-                let val = part.to_string();
-                if seen.insert(val.clone()) {
-                    results.push(val);
-                }
+            (Some(q), _) if c == q => quote = None,
+            (Some(_), _) => {}
+            (None, '"' | '\'') if opens_quote(&expr[start..i]) => quote = Some(c),
+            (None, '(') => depth += 1,
+            (None, ')') => depth -= 1,
+            (None, '+') if depth == 0 && expr[i + 1..].starts_with('+') => {
+                terms.push(&expr[start..i]);
+                chars.next();
+                start = i + 2;
             }
+            _ => {}
         }
     }
+    terms.push(&expr[start..]);
+    terms
+}
 
-    Ok(results)
+// A call of a string function like "replace(x, "/", "_")": its name and arguments
+fn string_call(term: &str) -> Option<(&str, &str)> {
+    let (name, rest) = term.split_once('(')?;
+    let name = name.trim();
+    let args = rest.strip_suffix(')')?;
+    matches!(name, "upper" | "lower" | "replace").then_some((name, args))
 }
 
-fn parse_expr(expr: &str, context: &HashMap<String, String>) -> Result<String, String> {
-    let expr = expr.trim();
+fn is_string_expr(part: &str) -> bool {
+    split_concat(part).len() > 1 || string_call(part).is_some()
+}
 
-    // Try to parse as integer expression first
-    match parse_int_expr(expr, context) {
-        Ok(val) => Ok(val.to_string()),
-        Err(_) => {
-            // Not a numeric expression, return as-is
-            Ok(expr.to_string())
-        }
+// The value of a string expression: terms joined by "++", each a quoted string, a call
+// of upper, lower or replace, a parameter or a number expression. None if the value is
+// not a string expression or cannot be evaluated, so it is kept as written.
+fn string_expr(part: &str, context: &HashMap<String, String>) -> Option<String> {
+    if !is_string_expr(part) {
+        return None;
     }
+    concat(part, context)
 }
 
-fn parse_int_expr(expr: &str, context: &HashMap<String, String>) -> Result<i64, String> {
-    let expr = expr.trim();
+fn concat(expr: &str, context: &HashMap<String, String>) -> Option<String> {
+    let terms = split_concat(expr)
+        .into_iter()
+        .map(|term| string_term(term.trim(), context))
+        .collect::<Option<Vec<String>>>()?;
+    Some(terms.concat())
+}
 
-    // Handle addition (lowest precedence)
-    if expr.contains('+') {
-        let parts: Vec<&str> = expr.split('+').collect();
-        let mut sum = 0;
-        for part in parts {
-            sum += parse_mult_expr(part.trim(), context)?;
+fn string_term(term: &str, context: &HashMap<String, String>) -> Option<String> {
+    if term.starts_with(['"', '\'']) {
+        return literal(term);
+    }
+    if let Some((name, args)) = string_call(term) {
+        let args = split_top_level(args, ',')
+            .into_iter()
+            .map(|arg| concat(arg.trim(), context))
+            .collect::<Option<Vec<String>>>()?;
+        return match (name, &args[..]) {
+            ("upper", [value]) => Some(value.to_uppercase()),
+            ("lower", [value]) => Some(value.to_lowercase()),
+            ("replace", [value, from, to]) if !from.is_empty() => Some(value.replace(from, to)),
+            _ => None,
+        };
+    }
+    match context.get(&term.to_uppercase()) {
+        Some(value) => Some(value.clone()),
+        None => parse_expr(term, context).ok(),
+    }
+}
+
+// The parameters a string expression refers to, outside its quoted strings
+fn string_variables(part: &str, variables: &mut HashSet<String>) {
+    for term in split_concat(part) {
+        let term = term.trim();
+        if term.starts_with(['"', '\'']) {
+            continue;
+        }
+        match string_call(term) {
+            Some((_, args)) => {
+                for arg in split_top_level(args, ',') {
+                    string_variables(arg.trim(), variables);
+                }
+            }
+            None => extract_variables_from_term(term, variables),
         }
-        return Ok(sum);
     }
+}
+
+// Quote a value so it is taken as one literal value, for --no-split
+pub fn quote_literal(value: &str) -> String {
+    format!("\"{}\"", value.replace('\\', "\\\\").replace('"', "\\\""))
+}
 
-    parse_mult_expr(expr, context)
+// Split a sum into its terms, each with whether it is subtracted, e.g. "n-3*2+1" into
+// n, -3*2 and 1. A '-' at the start or right after another operator belongs to the term
+// (a negative number, as in "10:1:-2" or "n*-1")
+fn split_terms(expr: &str) -> Vec<(bool, &str)> {
+    let mut terms = Vec::new();
+    let mut negate = false;
+    let mut start = 0;
+    let mut prev = None;
+    let mut depth = 0;
+    for (i, c) in expr.char_indices() {
+        let binary = prev.is_some_and(|p| !"+-*/^(".contains(p));
+        match c {
+            '(' => depth += 1,
+            ')' => depth -= 1,
+            _ => {}
+        }
+        if depth == 0 && (c == '+' || (c == '-' && binary)) {
+            terms.push((negate, &expr[start..i]));
+            negate = c == '-';
+            start = i + 1;
+        }
+        if !c.is_whitespace() {
+            prev = Some(c);
+        }
+    }
+    terms.push((negate, &expr[start..]));
+    terms
 }
 
 fn parse_mult_expr(expr: &str, context: &HashMap<String, String>) -> Result<i64, String> {
@@ -301,7 +1809,10 @@ fn parse_exp_expr(expr: &str, context: &HashMap<String, String>) -> Result<i64,
         if parts.len() == 2 {
             let base = parse_atom_expr(parts[0].trim(), context)?;
             let exp = parse_exp_expr(parts[1].trim(), context)?; // Right associative
-            return Ok(base.pow(exp as u32));
+            return u32::try_from(exp)
+                .ok()
+                .and_then(|exp| base.checked_pow(exp))
+                .ok_or_else(|| format!("Not an integer power: {}", expr));
         }
     }
 
@@ -311,6 +1822,11 @@ fn parse_exp_expr(expr: &str, context: &HashMap<String, String>) -> Result<i64,
 fn parse_atom_expr(expr: &str, context: &HashMap<String, String>) -> Result<i64, String> {
     let expr = expr.trim();
 
+    // Handle negation (e.g., "-2", "-n")
+    if let Some(rest) = expr.strip_prefix('-') {
+        return Ok(-parse_atom_expr(rest, context)?);
+    }
+
     // Handle implicit multiplication (e.g., "2n", "32gpu")
     // Try to find where number ends and variable begins
     let mut num_end = 0;
@@ -346,32 +1862,197 @@ fn parse_atom_expr(expr: &str, context: &HashMap<String, String>) -> Result<i64,
 mod tests {
     use super::*;
 
+    #[test]
+    fn test_assign_seeds() {
+        let params = vec![("X".to_string(), "1,2,3".to_string())];
+        let mut combinations = evaluate_params(&params, &[], None).unwrap();
+        assign_seeds(&mut combinations, 7).unwrap();
+
+        let seeds: Vec<&String> = combinations.iter().map(|c| &c.params["SEED"]).collect();
+        assert_eq!(combinations[0].param_order, vec!["X", "SEED"]);
+        assert!(seeds[0] != seeds[1] && seeds[1] != seeds[2]);
+        assert_eq!(seeds[0], &run_seed(7, 0).to_string());
+        assert!(run_seed(7, 0) < 1 << 31);
+        // Another sweep seed gives other seeds
+        assert_ne!(run_seed(8, 0), run_seed(7, 0));
+
+        assert!(assign_seeds(&mut combinations, 7).is_err());
+    }
+
+    #[test]
+    fn test_select_combinations() {
+        let params = vec![
+            ("X".to_string(), "1,2".to_string()),
+            ("Y".to_string(), "a,b,c".to_string()),
+        ];
+        let combinations = evaluate_params(&params, &[], None).unwrap();
+        let selected = select_combinations(combinations.clone(), &[(4, 5), (0, 2)]).unwrap();
+        let values: Vec<String> = selected
+            .iter()
+            .map(|c| format!("{}{}", c.params["X"], c.params["Y"]))
+            .collect();
+        assert_eq!(values, ["1a", "1b", "2b"]);
+        assert_eq!(
+            select_combinations(combinations.clone(), &[(3, usize::MAX)])
+                .unwrap()
+                .len(),
+            3
+        );
+        assert!(select_combinations(combinations, &[(6, 7)]).is_err());
+    }
+
+    #[test]
+    fn test_drop_internal() {
+        let params = vec![
+            ("_N".to_string(), "1,2".to_string()),
+            ("GPU".to_string(), "_n".to_string()),
+            ("BS".to_string(), "32_n".to_string()),
+        ];
+        let mut combinations = evaluate_params(&params, &[], None).unwrap();
+        drop_internal(&mut combinations);
+        assert_eq!(combinations[1].param_order, ["GPU", "BS"]);
+        assert_eq!(combinations[1].params.len(), 2);
+        assert_eq!(combinations[1].params["BS"], "64");
+    }
+
+    #[test]
+    fn test_dedup_combinations() {
+        let params = vec![
+            ("_N".to_string(), "1:9".to_string()),
+            ("W".to_string(), "min(_n,4)".to_string()),
+            ("OPT".to_string(), "adam,sgd".to_string()),
+        ];
+        let mut combinations = evaluate_params(&params, &[], None).unwrap();
+        drop_internal(&mut combinations);
+        let combinations = dedup_combinations(combinations);
+        let values: Vec<String> = combinations
+            .iter()
+            .map(|c| format!("{}{}", c.params["W"], c.params["OPT"]))
+            .collect();
+        assert_eq!(
+            values,
+            [
+                "1adam", "1sgd", "2adam", "2sgd", "3adam", "3sgd", "4adam", "4sgd"
+            ]
+        );
+    }
+
+    #[test]
+    fn test_halving_rounds() {
+        assert_eq!(halving_budgets(1.0, 27.0, 3), ["1", "3", "9", "27"]);
+        assert_eq!(halving_budgets(1.0, 20.0, 3), ["1", "3", "9", "20"]);
+        assert_eq!(halving_budgets(0.25, 1.0, 2), ["0.25", "0.5", "1"]);
+        assert_eq!(halving_budgets(5.0, 5.0, 2), ["5"]);
+
+        let params = vec![("X".to_string(), "1,2".to_string())];
+        let combinations = evaluate_params(&params, &[], None).unwrap();
+        let budgets = halving_budgets(1.0, 4.0, 2);
+        let rounds = halving_rounds(combinations.clone(), "EPOCHS", &budgets).unwrap();
+        let runs: Vec<(&str, &str, &str)> = rounds
+            .iter()
+            .map(|c| {
+                (
+                    c.params["X"].as_str(),
+                    c.params["EPOCHS"].as_str(),
+                    c.params["ROUND"].as_str(),
+                )
+            })
+            .collect();
+        assert_eq!(
+            runs,
+            [
+                ("1", "1", "1"),
+                ("2", "1", "1"),
+                ("1", "2", "2"),
+                ("2", "2", "2"),
+                ("1", "4", "3"),
+                ("2", "4", "3")
+            ]
+        );
+        assert_eq!(rounds[0].param_order, ["X", "EPOCHS", "ROUND"]);
+        assert!(halving_rounds(combinations, "X", &budgets).is_err());
+    }
+
+    #[test]
+    fn test_repeat_combinations() {
+        let params = vec![("X".to_string(), "1,2".to_string())];
+        let combinations = evaluate_params(&params, &[], None).unwrap();
+        let mut repeated = repeat_combinations(combinations, 3, 1).unwrap();
+        let runs: Vec<(&str, &str)> = repeated
+            .iter()
+            .map(|c| (c.params["X"].as_str(), c.params["REP"].as_str()))
+            .collect();
+        assert_eq!(
+            runs,
+            [
+                ("1", "1"),
+                ("1", "2"),
+                ("1", "3"),
+                ("2", "1"),
+                ("2", "2"),
+                ("2", "3")
+            ]
+        );
+        // Seeds come after the repetitions, so every repetition gets its own
+        assign_seeds(&mut repeated, 0).unwrap();
+        assert_eq!(repeated[0].param_order, vec!["X", "REP", "SEED"]);
+        assert_ne!(repeated[0].params["SEED"], repeated[1].params["SEED"]);
+
+        // --repeats numbers them from 0, and adds REP even for a single run
+        let combinations = evaluate_params(&params, &[], None).unwrap();
+        let single = repeat_combinations(combinations, 1, 0).unwrap();
+        let runs: Vec<&str> = single.iter().map(|c| c.params["REP"].as_str()).collect();
+        assert_eq!(runs, ["0", "0"]);
+        assert_eq!(single[0].param_order, vec!["X", "REP"]);
+
+        assert!(repeat_combinations(repeated, 2, 0).is_err());
+    }
+
     #[test]
     fn test_combinations() {
         let params = vec![
             ("GPU".to_string(), "1,2,4".to_string()),
             ("BATCHSIZE".to_string(), "32,64".to_string()),
         ];
-        let combos = evaluate_params(&params).unwrap();
+        let combos = evaluate_params(&params, &[], None).unwrap();
         assert_eq!(combos.len(), 6); // 3 * 2
     }
 
+    #[test]
+    fn test_literal_values() {
+        let values = |value: &str| -> Vec<String> {
+            let params = vec![("D".to_string(), value.to_string())];
+            let combos = evaluate_params(&params, &[], None).unwrap();
+            combos.iter().map(|c| c.params["D"].clone()).collect()
+        };
+        assert_eq!(values(r#""0,1,2,3",4"#), ["0,1,2,3", "4"]);
+        assert_eq!(values(r#"'1:4', "a\"b""#), ["1:4", "a\"b"]);
+        assert_eq!(values("[0,1],[2,3]"), ["[0,1]", "[2,3]"]);
+        assert_eq!(values(r#"{"lr": 0.1, "b": 2}"#), [r#"{"lr": 0.1, "b": 2}"#]);
+        assert_eq!(values(r"a\,b,c"), ["a,b", "c"]);
+        assert_eq!(values("it's,x"), ["it's", "x"]);
+        assert_eq!(values(&quote_literal(r#"1,"2"\3"#)), [r#"1,"2"\3"#]);
+        assert!(extract_variables(r#""N+1",[N]"#).is_empty());
+    }
+
     #[test]
     fn test_ranges() {
         // Basic range
-        let combos = evaluate_params(&[("N".to_string(), "1:4".to_string())]).unwrap();
+        let combos = evaluate_params(&[("N".to_string(), "1:4".to_string())], &[], None).unwrap();
         assert_eq!(combos.len(), 3);
         assert_eq!(combos[0].params.get("N").unwrap(), "1");
         assert_eq!(combos[2].params.get("N").unwrap(), "3");
 
         // Positive step
-        let combos = evaluate_params(&[("N".to_string(), "1:10:2".to_string())]).unwrap();
+        let combos =
+            evaluate_params(&[("N".to_string(), "1:10:2".to_string())], &[], None).unwrap();
         assert_eq!(combos.len(), 5);
         assert_eq!(combos[0].params.get("N").unwrap(), "1");
         assert_eq!(combos[4].params.get("N").unwrap(), "9");
 
         // Negative step
-        let combos = evaluate_params(&[("N".to_string(), "10:1:-2".to_string())]).unwrap();
+        let combos =
+            evaluate_params(&[("N".to_string(), "10:1:-2".to_string())], &[], None).unwrap();
         assert_eq!(combos.len(), 5);
         assert_eq!(combos[0].params.get("N").unwrap(), "10");
         assert_eq!(combos[4].params.get("N").unwrap(), "2");
@@ -385,39 +2066,450 @@ mod tests {
             ("GPU".to_string(), "n".to_string()),
             ("BATCHSIZE".to_string(), "32n".to_string()),
         ];
-        let combos = evaluate_params(&params).unwrap();
+        let combos = evaluate_params(&params, &[], None).unwrap();
         assert_eq!(combos.len(), 2);
         assert_eq!(combos[0].params.get("BATCHSIZE").unwrap(), "32");
         assert_eq!(combos[1].params.get("BATCHSIZE").unwrap(), "64");
 
         // Operator precedence: n+3*2 = 2+6 = 8
-        let combos = evaluate_params(&[
-            ("N".to_string(), "2".to_string()),
-            ("VALUE".to_string(), "n+3*2".to_string()),
-        ])
+        let combos = evaluate_params(
+            &[
+                ("N".to_string(), "2".to_string()),
+                ("VALUE".to_string(), "n+3*2".to_string()),
+            ],
+            &[],
+            None,
+        )
         .unwrap();
         assert_eq!(combos[0].params.get("VALUE").unwrap(), "8");
 
         // Operator precedence: n+n^2 = 2+4 = 6
-        let combos = evaluate_params(&[
-            ("N".to_string(), "2".to_string()),
-            ("VALUE".to_string(), "n+n^2".to_string()),
-        ])
+        let combos = evaluate_params(
+            &[
+                ("N".to_string(), "2".to_string()),
+                ("VALUE".to_string(), "n+n^2".to_string()),
+            ],
+            &[],
+            None,
+        )
         .unwrap();
         assert_eq!(combos[0].params.get("VALUE").unwrap(), "6");
     }
 
+    #[test]
+    fn test_geometric_ranges() {
+        let values = |expr: &str| {
+            evaluate_params(&[("BATCHSIZE".to_string(), expr.to_string())], &[], None).map(
+                |combos| {
+                    combos
+                        .iter()
+                        .map(|c| c.params["BATCHSIZE"].clone())
+                        .collect::<Vec<_>>()
+                },
+            )
+        };
+        assert_eq!(
+            values("1:1024:*2").unwrap(),
+            ["1", "2", "4", "8", "16", "32", "64", "128", "256", "512"]
+        );
+        assert_eq!(values("1:1000:*10").unwrap(), ["1", "10", "100"]);
+        assert_eq!(values("3:30:*2").unwrap(), ["3", "6", "12", "24"]);
+        assert_eq!(values("1:0.1:*0.5").unwrap(), ["1", "0.5", "0.25", "0.125"]);
+        assert!(values("0:16:*2").is_err());
+        assert!(values("1:16:*1").is_err());
+        assert!(values("16:1:*2").is_err());
+    }
+
+    #[test]
+    fn test_log_ranges() {
+        let values = |expr: &str| {
+            evaluate_params(
+                &[
+                    ("N".to_string(), "4".to_string()),
+                    ("LR".to_string(), expr.to_string()),
+                ],
+                &[],
+                None,
+            )
+            .map(|combos| {
+                combos
+                    .iter()
+                    .map(|c| c.params["LR"].clone())
+                    .collect::<Vec<_>>()
+            })
+        };
+        assert_eq!(
+            values("log(1e-5:1e-1:5)").unwrap(),
+            ["0.00001", "0.0001", "0.001", "0.01", "0.1"]
+        );
+        assert_eq!(values("log(1:8:n)").unwrap(), ["1", "2", "4", "8"]);
+        assert_eq!(values("log(100:1:3),0").unwrap(), ["100", "10", "1", "0"]);
+        assert!(values("log(0:1:3)").is_err());
+        assert!(values("log(1:10:1)").is_err());
+        assert!(values("log(1:10)").is_err());
+    }
+
+    #[test]
+    fn test_float_ranges() {
+        let values = |expr: &str| {
+            evaluate_params(&[("LR".to_string(), expr.to_string())], &[], None).map(|combos| {
+                combos
+                    .iter()
+                    .map(|c| c.params["LR"].clone())
+                    .collect::<Vec<_>>()
+            })
+        };
+        assert_eq!(values("0.1:0.5:0.1").unwrap(), ["0.1", "0.2", "0.3", "0.4"]);
+        assert_eq!(values("0:1:0.25").unwrap(), ["0", "0.25", "0.5", "0.75"]);
+        assert_eq!(values("1:0:-0.5").unwrap(), ["1", "0.5"]);
+        assert_eq!(values("0.5:3").unwrap(), ["0.5", "1.5", "2.5"]);
+        assert_eq!(values("1:2:0.5,2:3").unwrap(), ["1", "1.5", "2"]);
+        assert!(values("0.5:0.1:0.1").is_err());
+        assert!(values("0:1:0.0").is_err());
+    }
+
+    #[test]
+    fn test_float_expressions() {
+        let values = |expr: &str| -> Vec<String> {
+            evaluate_params(
+                &[
+                    ("N".to_string(), "5,10,30".to_string()),
+                    ("LR".to_string(), "0.01".to_string()),
+                    ("VALUE".to_string(), expr.to_string()),
+                ],
+                &[],
+                None,
+            )
+            .unwrap()
+            .iter()
+            .map(|c| c.params["VALUE"].clone())
+            .collect()
+        };
+        // Whole results are written as integers
+        assert_eq!(values("n/10"), ["0.5", "1", "3"]);
+        assert_eq!(values("0.1n"), ["0.5", "1", "3"]);
+        assert_eq!(values("lr*n"), ["0.05", "0.1", "0.3"]);
+        assert_eq!(values("n/4*2+lr"), ["2.51", "5.01", "15.01"]);
+        assert_eq!(values("lr^2"), ["0.0001"; 3]);
+        assert_eq!(values("1/3"), ["0.333333333333"; 3]);
+        // Numbers and other strings stay as written
+        assert_eq!(values("0.10"), ["0.10"; 3]);
+        assert_eq!(values("1e-3"), ["1e-3"; 3]);
+        assert_eq!(values("data/train"), ["data/train"; 3]);
+        assert_eq!(values("n/0"), ["n/0"; 3]);
+    }
+
+    #[test]
+    fn test_values_from_file() {
+        let path = std::env::temp_dir().join(format!("runexp_values_{}.txt", std::process::id()));
+        std::fs::write(&path, "imagenet\n\n  cifar-10 \n1:3\nimagenet\n").unwrap();
+        let expr = format!("@{},mnist", path.display());
+        let combos = evaluate_params(&[("DATASET".to_string(), expr)], &[], None).unwrap();
+        let values: Vec<&str> = combos
+            .iter()
+            .map(|c| c.params["DATASET"].as_str())
+            .collect();
+        // Lines are not expressions or ranges, and duplicates are dropped
+        assert_eq!(values, ["imagenet", "cifar-10", "1:3", "mnist"]);
+
+        std::fs::write(&path, "\n").unwrap();
+        let expr = format!("@{}", path.display());
+        assert!(evaluate_params(&[("DATASET".to_string(), expr)], &[], None).is_err());
+        std::fs::remove_file(&path).unwrap();
+        let expr = format!("@{}", path.display());
+        assert!(evaluate_params(&[("DATASET".to_string(), expr)], &[], None).is_err());
+    }
+
+    #[test]
+    fn test_file_patterns() {
+        let dir = std::env::temp_dir().join(format!("runexp_configs_{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        for name in ["b.yaml", "a.yaml", "c.json"] {
+            std::fs::write(dir.join(name), "").unwrap();
+        }
+        let dir = dir.to_string_lossy().to_string();
+        let combos = evaluate_params(
+            &[("CONFIG".to_string(), format!("{}/*.yaml,base.yaml", dir))],
+            &[],
+            None,
+        )
+        .unwrap();
+        let values: Vec<&str> = combos.iter().map(|c| c.params["CONFIG"].as_str()).collect();
+        assert_eq!(
+            values,
+            [
+                format!("{}/a.yaml", dir),
+                format!("{}/b.yaml", dir),
+                "base.yaml".to_string()
+            ]
+        );
+        let pattern = format!("{}/*.toml", dir);
+        assert!(evaluate_params(&[("CONFIG".to_string(), pattern)], &[], None).is_err());
+        std::fs::remove_dir_all(&dir).unwrap();
+
+        // Products are not file patterns
+        assert!(is_file_pattern("configs/*.yaml"));
+        assert!(is_file_pattern("exp_?.json"));
+        assert!(!is_file_pattern("n*2"));
+        assert!(!is_file_pattern("0.1*n"));
+        assert!(!is_file_pattern("n*.5"));
+        let params = vec![
+            ("N".to_string(), "4".to_string()),
+            ("X".to_string(), "n/2*n".to_string()),
+        ];
+        let combos = evaluate_params(&params, &[], None).unwrap();
+        assert_eq!(combos[0].params["X"], "8");
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_values_from_command() {
+        let path = std::env::temp_dir().join(format!("runexp_cmd_{}.log", std::process::id()));
+        let command = format!(
+            "cmd:echo ran >> {}; printf 'a.pt\\nb,c.pt\\n\\na.pt\\n'",
+            path.display()
+        );
+        let params = vec![
+            ("X".to_string(), "1,2,3".to_string()),
+            ("CKPT".to_string(), command),
+        ];
+        let combos = evaluate_params(&params, &[], None).unwrap();
+        let values: Vec<&str> = combos.iter().map(|c| c.params["CKPT"].as_str()).collect();
+        assert_eq!(values, ["a.pt", "b,c.pt"].repeat(3));
+        // The command ran once, not once per combination
+        assert_eq!(std::fs::read_to_string(&path).unwrap(), "ran\n");
+        std::fs::remove_file(&path).unwrap();
+
+        let values =
+            |command: &str| evaluate_params(&[("V".to_string(), command.to_string())], &[], None);
+        assert!(values("cmd:false").is_err());
+        assert!(values("cmd:true").is_err());
+    }
+
+    #[test]
+    fn test_conditionals() {
+        let values = |expr: &str| {
+            evaluate_params(
+                &[
+                    ("N".to_string(), "2,8".to_string()),
+                    ("MODEL".to_string(), "resnet".to_string()),
+                    ("VALUE".to_string(), expr.to_string()),
+                ],
+                &[],
+                None,
+            )
+            .map(|combos| {
+                combos
+                    .iter()
+                    .map(|c| c.params["VALUE"].clone())
+                    .collect::<Vec<_>>()
+            })
+        };
+        assert_eq!(values("n>4 ? fp16 : fp32").unwrap(), ["fp32", "fp16"]);
+        assert_eq!(values("n<=2?2n:n/2").unwrap(), ["4", "4"]);
+        assert_eq!(values("model==resnet ? 1 : 0").unwrap(), ["1", "1"]);
+        assert_eq!(values("model!=vit ? yes : no").unwrap(), ["yes", "yes"]);
+        assert_eq!(values("n==2 ? a : n==8 ? b : c").unwrap(), ["a", "b"]);
+        assert_eq!(values("n>4 ? x : y,z").unwrap(), ["y", "z", "x", "z"]);
+        // Not conditionals
+        assert_eq!(values("why?").unwrap(), ["why?", "why?"]);
+        assert!(values("model>1 ? a : b").is_err());
+        assert!(values("n=2 ? a : b").is_err());
+    }
+
+    #[test]
+    fn test_functions() {
+        let values = |expr: &str| -> Vec<String> {
+            evaluate_params(
+                &[
+                    ("N".to_string(), "1,10".to_string()),
+                    ("VALUE".to_string(), expr.to_string()),
+                ],
+                &[],
+                None,
+            )
+            .unwrap()
+            .iter()
+            .map(|c| c.params["VALUE"].clone())
+            .collect()
+        };
+        assert_eq!(values("min(n, 8)"), ["1", "8"]);
+        assert_eq!(values("max(4, 2n)"), ["4", "20"]);
+        assert_eq!(values("max(1, n, 5)"), ["5", "10"]);
+        assert_eq!(values("abs(5-n)"), ["4", "5"]);
+        assert_eq!(values("floor(n/3)"), ["0", "3"]);
+        assert_eq!(values("ceil(n/3)"), ["1", "4"]);
+        assert_eq!(values("pow(2, n)"), ["2", "1024"]);
+        assert_eq!(values("2min(n, 4)+1"), ["3", "9"]);
+        assert_eq!(values("(n+1)*2"), ["4", "22"]);
+        // Several values, some of them calls
+        assert_eq!(values("min(n,2),7"), ["1", "7", "2", "7"]);
+        // Unknown functions and wrong arities are kept as written
+        assert_eq!(values("f(n)"), ["f(n)"; 2]);
+        assert_eq!(values("abs(1, 2)"), ["abs(1, 2)"; 2]);
+
+        // String operations
+        let strings = |expr: &str| -> Vec<String> {
+            evaluate_params(
+                &[
+                    // Defined first, so it is evaluated after the parameters it refers to
+                    ("VALUE".to_string(), expr.to_string()),
+                    ("MODEL".to_string(), "org/Bert,gpt".to_string()),
+                    ("N".to_string(), "2".to_string()),
+                ],
+                &[],
+                None,
+            )
+            .unwrap()
+            .iter()
+            .map(|c| c.params["VALUE"].clone())
+            .collect()
+        };
+        assert_eq!(strings("upper(model)"), ["ORG/BERT", "GPT"]);
+        assert_eq!(strings("lower(model)"), ["org/bert", "gpt"]);
+        assert_eq!(strings("replace(model, \"/\", \"_\")"), ["org_Bert", "gpt"]);
+        assert_eq!(
+            strings("model ++ \"_\" ++ n*2 ++ ',x'"),
+            ["org/Bert_4,x", "gpt_4,x"]
+        );
+        assert_eq!(
+            strings("upper(replace(model, \")\", \"\") ++ \"-\" ++ n)"),
+            ["ORG/BERT-2", "GPT-2"]
+        );
+        assert_eq!(strings("upper(model, n)"), ["upper(model, n)"; 2]);
+
+        // Function names are not parameter references
+        let combos = evaluate_params(
+            &[
+                ("X".to_string(), "min(y, 3)".to_string()),
+                ("Y".to_string(), "2,5".to_string()),
+                ("MIN".to_string(), "0".to_string()),
+            ],
+            &[],
+            None,
+        )
+        .unwrap();
+        let xs: Vec<&str> = combos.iter().map(|c| c.params["X"].as_str()).collect();
+        assert_eq!(xs, ["2", "3"]);
+    }
+
+    #[test]
+    fn test_subtraction() {
+        let values = |expr: &str| -> Vec<String> {
+            evaluate_params(
+                &[
+                    ("N".to_string(), "5,10".to_string()),
+                    ("VALUE".to_string(), expr.to_string()),
+                ],
+                &[],
+                None,
+            )
+            .unwrap()
+            .iter()
+            .map(|c| c.params["VALUE"].clone())
+            .collect()
+        };
+        assert_eq!(values("n-1"), ["4", "9"]);
+        assert_eq!(values("n-3*2"), ["-1", "4"]);
+        assert_eq!(values("20-n-1"), ["14", "9"]);
+        assert_eq!(values("n*-2"), ["-10", "-20"]);
+        assert_eq!(values("-n+1"), ["-4", "-9"]);
+        assert_eq!(values("1-n/10"), ["0.5", "0"]);
+        assert_eq!(values("2^-1"), ["0.5"; 2]);
+        assert_eq!(values("-3"), ["-3"; 2]);
+        // Dashes that are not subtractions stay as written
+        assert_eq!(values("resnet-50"), ["resnet-50"; 2]);
+        assert_eq!(values("2024-01-01"), ["2024-01-01"; 2]);
+        assert_eq!(values("1e-3"), ["1e-3"; 2]);
+        assert_eq!(values("-"), ["-"; 2]);
+
+        let combos = evaluate_params(
+            &[
+                ("N".to_string(), "4".to_string()),
+                ("X".to_string(), "n-1:n+2,10:0:-n".to_string()),
+            ],
+            &[],
+            None,
+        )
+        .unwrap();
+        let xs: Vec<&str> = combos.iter().map(|c| c.params["X"].as_str()).collect();
+        assert_eq!(xs, ["3", "4", "5", "10", "6", "2"]);
+    }
+
+    #[test]
+    fn test_interpolation() {
+        let params = vec![
+            ("GPU".to_string(), "1,2".to_string()),
+            (
+                "OUTDIR".to_string(),
+                "results/{gpu}g_{batchsize}b".to_string(),
+            ),
+            ("BATCHSIZE".to_string(), "32gpu".to_string()),
+            ("LOG".to_string(), "{outdir}/log_{Idx}.txt".to_string()),
+            ("CACHE".to_string(), "${HOME}/{unknown}".to_string()),
+        ];
+        let combos = evaluate_params(&params, &[], None).unwrap();
+        let values: Vec<(&str, &str)> = combos
+            .iter()
+            .map(|c| (c.params["OUTDIR"].as_str(), c.params["LOG"].as_str()))
+            .collect();
+        assert_eq!(
+            values,
+            [
+                ("results/1g_32b", "results/1g_32b/log_0.txt"),
+                ("results/2g_64b", "results/2g_64b/log_1.txt")
+            ]
+        );
+        assert_eq!(combos[0].params["CACHE"], "${HOME}/{unknown}");
+
+        // Builtins, unless a parameter has the name
+        let params = vec![
+            ("TAG".to_string(), "run_{today}_{Now}".to_string()),
+            (
+                "DIR".to_string(),
+                "{env(PATH)},{env(RUNEXP_TEST_UNSET)}".to_string(),
+            ),
+            ("TODAY".to_string(), "monday".to_string()),
+        ];
+        let combos = evaluate_params(&params, &[], None).unwrap();
+        let tag = &combos[0].params["TAG"];
+        assert_eq!(tag, &format!("run_monday_{}", builtin("now").unwrap()));
+        assert_eq!(combos[0].params["DIR"], std::env::var("PATH").unwrap());
+        assert_eq!(combos[1].params["DIR"], "");
+        let params = vec![("TAG".to_string(), "{today}".to_string())];
+        let combos = evaluate_params(&params, &[], None).unwrap();
+        assert_eq!(combos[0].params["TAG"].len(), "2024-01-31".len());
+
+        // Objects and quoted strings are not templates
+        let params = vec![
+            ("GPU".to_string(), "1".to_string()),
+            ("A".to_string(), "{\"gpu\": 1}".to_string()),
+            ("B".to_string(), "'{gpu}'".to_string()),
+        ];
+        let combos = evaluate_params(&params, &[], None).unwrap();
+        assert_eq!(combos[0].params["A"], "{\"gpu\": 1}");
+        assert_eq!(combos[0].params["B"], "{gpu}");
+    }
+
     #[test]
     fn test_literal_strings() {
         // Pure literals
-        let combos =
-            evaluate_params(&[("ROUTING".to_string(), "source,dest,both".to_string())]).unwrap();
+        let combos = evaluate_params(
+            &[("ROUTING".to_string(), "source,dest,both".to_string())],
+            &[],
+            None,
+        )
+        .unwrap();
         assert_eq!(combos.len(), 3);
         assert_eq!(combos[0].params.get("ROUTING").unwrap(), "source");
 
         // Mixed literals and numbers
-        let combos =
-            evaluate_params(&[("MODE".to_string(), "train,test,1,2".to_string())]).unwrap();
+        let combos = evaluate_params(
+            &[("MODE".to_string(), "train,test,1,2".to_string())],
+            &[],
+            None,
+        )
+        .unwrap();
         assert_eq!(combos.len(), 4);
         assert_eq!(combos[0].params.get("MODE").unwrap(), "train");
         assert_eq!(combos[2].params.get("MODE").unwrap(), "1");
@@ -431,7 +2523,7 @@ mod tests {
             ("BATCHSIZE".to_string(), "32,64".to_string()),
             ("LR".to_string(), "0.01".to_string()),
         ];
-        let combos = evaluate_params(&params).unwrap();
+        let combos = evaluate_params(&params, &[], None).unwrap();
 
         // Check that param_order matches input order
         assert_eq!(combos[0].param_order, vec!["GPU", "BATCHSIZE", "LR"]);
@@ -445,7 +2537,7 @@ mod tests {
             ("N".to_string(), "1,2".to_string()),
             ("GPU".to_string(), "n".to_string()), // Also refers to N
         ];
-        let combos = evaluate_params(&params).unwrap();
+        let combos = evaluate_params(&params, &[], None).unwrap();
 
         assert_eq!(combos.len(), 2);
 
@@ -463,6 +2555,52 @@ mod tests {
         assert_eq!(combos[0].param_order, vec!["BATCHSIZE", "N", "GPU"]);
     }
 
+    #[test]
+    fn test_idx() {
+        let params = vec![
+            ("PORT".to_string(), "8000+idx".to_string()),
+            ("N".to_string(), "1,2".to_string()),
+            ("LR".to_string(), "0.1,0.01".to_string()),
+            ("URL".to_string(), "port*n".to_string()),
+        ];
+        let combos = evaluate_params(&params, &[], None).unwrap();
+        let values: Vec<(usize, &str, &str)> = combos
+            .iter()
+            .map(|c| (c.index, c.params["PORT"].as_str(), c.params["URL"].as_str()))
+            .collect();
+        assert_eq!(
+            values,
+            [
+                (0, "8000", "8000"),
+                (1, "8001", "8001"),
+                (2, "8002", "16004"),
+                (3, "8003", "16006")
+            ]
+        );
+        let counts = count_values(&params, &[], None).unwrap();
+        assert_eq!(counts[0], ("PORT".to_string(), 1));
+
+        // A parameter named IDX is used instead
+        let params = vec![
+            ("IDX".to_string(), "5".to_string()),
+            ("PORT".to_string(), "8000+idx".to_string()),
+        ];
+        let combos = evaluate_params(&params, &[], None).unwrap();
+        assert_eq!(combos[0].params["PORT"], "8005");
+
+        let params = vec![
+            ("N".to_string(), "1,2".to_string()),
+            ("PORT".to_string(), "idx,idx+10".to_string()),
+        ];
+        assert!(evaluate_params(&params, &[], None).is_err());
+        let zip = vec![vec!["N".to_string(), "PORT".to_string()]];
+        let params = vec![
+            ("N".to_string(), "1,2".to_string()),
+            ("PORT".to_string(), "idx".to_string()),
+        ];
+        assert!(evaluate_params(&params, &zip, None).is_err());
+    }
+
     #[test]
     fn test_circular_dependency_detection() {
         // Test that circular dependencies are detected and reported
@@ -470,10 +2608,15 @@ mod tests {
             ("A".to_string(), "b".to_string()), // A depends on B
             ("B".to_string(), "a".to_string()), // B depends on A - circular!
         ];
-        let result = evaluate_params(&params);
+        let result = evaluate_params(&params, &[], None);
 
         assert!(result.is_err());
-        assert!(result.unwrap_err().contains("Circular dependency"));
+        assert!(
+            result
+                .unwrap_err()
+                .to_string()
+                .contains("Circular dependency")
+        );
     }
 
     #[test]
@@ -483,7 +2626,7 @@ mod tests {
             ("GPU".to_string(), "1,2".to_string()),
             ("BATCHSIZE".to_string(), "32,64".to_string()),
         ];
-        let combos = evaluate_params(&params).unwrap();
+        let combos = evaluate_params(&params, &[], None).unwrap();
 
         assert_eq!(combos.len(), 4);
 
@@ -510,7 +2653,7 @@ mod tests {
             ("B".to_string(), "2a".to_string()),  // B depends on A
             ("A".to_string(), "1,2".to_string()), // A has no dependencies
         ];
-        let combos = evaluate_params(&params).unwrap();
+        let combos = evaluate_params(&params, &[], None).unwrap();
 
         assert_eq!(combos.len(), 2);
 
@@ -532,7 +2675,8 @@ mod tests {
     fn test_concatenated_ranges() {
         // Test concatenating multiple ranges with comma
         // Example: 32:512:16,512:1024:32 should produce values from both ranges
-        let combos = evaluate_params(&[("DEPTH".to_string(), "1:4,10:13".to_string())]).unwrap();
+        let combos =
+            evaluate_params(&[("DEPTH".to_string(), "1:4,10:13".to_string())], &[], None).unwrap();
         assert_eq!(combos.len(), 6); // 1,2,3 + 10,11,12
         assert_eq!(combos[0].params.get("DEPTH").unwrap(), "1");
         assert_eq!(combos[1].params.get("DEPTH").unwrap(), "2");
@@ -542,8 +2686,12 @@ mod tests {
         assert_eq!(combos[5].params.get("DEPTH").unwrap(), "12");
 
         // Test with step
-        let combos =
-            evaluate_params(&[("DEPTH".to_string(), "1:5:2,10:15:2".to_string())]).unwrap();
+        let combos = evaluate_params(
+            &[("DEPTH".to_string(), "1:5:2,10:15:2".to_string())],
+            &[],
+            None,
+        )
+        .unwrap();
         assert_eq!(combos.len(), 5); // 1,3 + 10,12,14
         assert_eq!(combos[0].params.get("DEPTH").unwrap(), "1");
         assert_eq!(combos[1].params.get("DEPTH").unwrap(), "3");
@@ -556,7 +2704,8 @@ mod tests {
     fn test_duplicate_filtering() {
         // Test that duplicates are filtered while preserving order
         // Range 1:4 = 1,2,3 and adding 2,4 should result in 1,2,3,4 (no duplicate 2)
-        let combos = evaluate_params(&[("N".to_string(), "1:4,2,4".to_string())]).unwrap();
+        let combos =
+            evaluate_params(&[("N".to_string(), "1:4,2,4".to_string())], &[], None).unwrap();
         assert_eq!(combos.len(), 4); // 1,2,3,4 (2 appears only once)
         assert_eq!(combos[0].params.get("N").unwrap(), "1");
         assert_eq!(combos[1].params.get("N").unwrap(), "2");
@@ -568,7 +2717,8 @@ mod tests {
     fn test_duplicate_filtering_with_overlapping_ranges() {
         // Test filtering duplicates when ranges overlap
         // 1:5 = 1,2,3,4 and 3:7 = 3,4,5,6 -> should produce 1,2,3,4,5,6
-        let combos = evaluate_params(&[("N".to_string(), "1:5,3:7".to_string())]).unwrap();
+        let combos =
+            evaluate_params(&[("N".to_string(), "1:5,3:7".to_string())], &[], None).unwrap();
         assert_eq!(combos.len(), 6); // 1,2,3,4,5,6
         assert_eq!(combos[0].params.get("N").unwrap(), "1");
         assert_eq!(combos[1].params.get("N").unwrap(), "2");
@@ -581,11 +2731,296 @@ mod tests {
     #[test]
     fn test_duplicate_filtering_preserves_order() {
         // Test that duplicates are filtered but first occurrence order is preserved
-        let combos = evaluate_params(&[("N".to_string(), "5,3,1,3,5,7".to_string())]).unwrap();
+        let combos =
+            evaluate_params(&[("N".to_string(), "5,3,1,3,5,7".to_string())], &[], None).unwrap();
         assert_eq!(combos.len(), 4); // 5,3,1,7
         assert_eq!(combos[0].params.get("N").unwrap(), "5");
         assert_eq!(combos[1].params.get("N").unwrap(), "3");
         assert_eq!(combos[2].params.get("N").unwrap(), "1");
         assert_eq!(combos[3].params.get("N").unwrap(), "7");
     }
+
+    #[test]
+    fn test_constrain_combinations() {
+        let params = vec![
+            ("GPU".to_string(), "1,2,4".to_string()),
+            ("BATCHSIZE".to_string(), "128,256".to_string()),
+            ("MODEL".to_string(), "small,large".to_string()),
+        ];
+        let constrain = |constraints: &[&str]| {
+            let constraints: Vec<String> = constraints.iter().map(|c| c.to_string()).collect();
+            constrain_combinations(evaluate_params(&params, &[], None).unwrap(), &constraints)
+                .map(|combos| combos.len())
+        };
+        assert_eq!(constrain(&[]).unwrap(), 12);
+        assert_eq!(constrain(&["batchsize*gpu <= 512"]).unwrap(), 10);
+        assert_eq!(
+            constrain(&["batchsize*gpu <= 512", "model != large"]).unwrap(),
+            5
+        );
+        assert_eq!(constrain(&["gpu==2"]).unwrap(), 4);
+        assert!(constrain(&["model > 1"]).is_err());
+    }
+
+    #[test]
+    fn test_sampling() {
+        let params = vec![
+            ("MODEL".to_string(), "a,b".to_string()),
+            ("LR".to_string(), "loguniform(1e-4, 1e-1)".to_string()),
+            ("DROPOUT".to_string(), "uniform(0, 0.5)".to_string()),
+            ("SHIFT".to_string(), "normal(10, 1)".to_string()),
+        ];
+        let random = |count, seed| {
+            Some(Sampling {
+                count,
+                seed,
+                strategy: Strategy::Random,
+            })
+        };
+        let sample = |seed| evaluate_params(&params, &[], random(5, seed)).unwrap();
+        let combos = sample(0);
+        // Sampled parameters are drawn together and crossed with the others
+        assert_eq!(combos.len(), 10);
+        let value = |i: usize, name: &str| combos[i].params[name].parse::<f64>().unwrap();
+        for i in 0..10 {
+            assert!((1e-4..1e-1).contains(&value(i, "LR")));
+            assert!((0.0..0.5).contains(&value(i, "DROPOUT")));
+            assert!((0.0..20.0).contains(&value(i, "SHIFT")));
+        }
+        assert_eq!(combos[0].params["LR"], combos[5].params["LR"]);
+        assert_ne!(combos[0].params["LR"], combos[1].params["LR"]);
+        assert_ne!(combos[0].params["LR"], combos[0].params["DROPOUT"]);
+
+        // Reproducible for a seed
+        let params_of = |combos: Vec<Combination>| -> Vec<HashMap<String, String>> {
+            combos.into_iter().map(|c| c.params).collect()
+        };
+        assert_eq!(params_of(sample(0)), params_of(sample(0)));
+        assert_ne!(params_of(sample(0)), params_of(sample(1)));
+
+        assert_eq!(
+            count_values(&params, &[], random(5, 0)).unwrap(),
+            [
+                ("MODEL".to_string(), 2),
+                ("LR+DROPOUT+SHIFT".to_string(), 5)
+            ]
+        );
+        assert!(evaluate_params(&params, &[], None).is_err());
+        let bad = |value: &str| {
+            evaluate_params(&[("X".to_string(), value.to_string())], &[], random(2, 0)).is_err()
+        };
+        assert!(bad("uniform(1, 0)"));
+        assert!(bad("loguniform(0, 1)"));
+        assert!(bad("normal(0)"));
+        assert!(!bad("uniform(-1, 1)"));
+    }
+
+    #[test]
+    fn test_space_filling_sampling() {
+        let params = vec![
+            ("A".to_string(), "uniform(0, 1)".to_string()),
+            ("B".to_string(), "uniform(10, 20)".to_string()),
+            ("C".to_string(), "normal(0, 1)".to_string()),
+        ];
+        let sample = |strategy| {
+            let sampling = Sampling {
+                count: 8,
+                seed: 3,
+                strategy,
+            };
+            let combos = evaluate_params(&params, &[], Some(sampling)).unwrap();
+            let column = |name: &str, low: f64, width: f64| -> Vec<usize> {
+                let mut strata: Vec<usize> = combos
+                    .iter()
+                    .map(|c| {
+                        ((c.params[name].parse::<f64>().unwrap() - low) / width * 8.0) as usize
+                    })
+                    .collect();
+                strata.sort();
+                strata
+            };
+            (column("A", 0.0, 1.0), column("B", 10.0, 10.0))
+        };
+
+        // Every eighth of each range holds exactly one point
+        let all: Vec<usize> = (0..8).collect();
+        for strategy in [Strategy::Lhs, Strategy::Sobol] {
+            assert_eq!(sample(strategy), (all.clone(), all.clone()));
+        }
+
+        assert!((normal_quantile(0.975) - 1.959964).abs() < 1e-6);
+        assert!((normal_quantile(0.01) + 2.326348).abs() < 1e-6);
+        assert_eq!(normal_quantile(0.5), 0.0);
+        assert!(sobol(16, 4, 0).is_err());
+    }
+
+    #[test]
+    fn test_zip() {
+        let params = vec![
+            ("MODEL".to_string(), "a,b".to_string()),
+            ("SEED".to_string(), "1,2".to_string()),
+            ("CONFIG".to_string(), "a.yaml,b.yaml".to_string()),
+            ("BATCHSIZE".to_string(), "16seed,32seed".to_string()),
+        ];
+        let zip = vec![
+            vec!["MODEL".to_string(), "CONFIG".to_string()],
+            vec!["SEED".to_string(), "BATCHSIZE".to_string()],
+        ];
+        assert!(evaluate_params(&params, &zip, None).is_err()); // BATCHSIZE depends on SEED
+
+        let zip = vec![vec!["CONFIG".to_string(), "MODEL".to_string()]];
+        let combos = evaluate_params(&params[..3], &zip, None).unwrap();
+        let runs: Vec<(&str, &str, &str)> = combos
+            .iter()
+            .map(|c| {
+                let p = |name: &str| c.params[name].as_str();
+                (p("MODEL"), p("SEED"), p("CONFIG"))
+            })
+            .collect();
+        assert_eq!(
+            runs,
+            [
+                ("a", "1", "a.yaml"),
+                ("a", "2", "a.yaml"),
+                ("b", "1", "b.yaml"),
+                ("b", "2", "b.yaml")
+            ]
+        );
+        assert_eq!(combos[0].param_order, ["MODEL", "SEED", "CONFIG"]);
+        assert_eq!(
+            count_values(&params[..3], &zip, None).unwrap(),
+            [("MODEL+CONFIG".to_string(), 2), ("SEED".to_string(), 2)]
+        );
+
+        // A zipped parameter may depend on other parameters
+        let params = vec![
+            ("N".to_string(), "1,2".to_string()),
+            ("GPU".to_string(), "n,2n".to_string()),
+            ("NAME".to_string(), "x,y".to_string()),
+        ];
+        let zip = vec![vec!["GPU".to_string(), "NAME".to_string()]];
+        let combos = evaluate_params(&params, &zip, None).unwrap();
+        let runs: Vec<String> = combos
+            .iter()
+            .map(|c| format!("{}{}", c.params["NAME"], c.params["GPU"]))
+            .collect();
+        assert_eq!(runs, ["x1", "y2", "x2", "y4"]);
+
+        // Unequal lengths, unknown and repeated names
+        let params = vec![
+            ("A".to_string(), "1,2".to_string()),
+            ("B".to_string(), "1,2,3".to_string()),
+        ];
+        let zip = |names: &[&str]| vec![names.iter().map(|n| n.to_string()).collect()];
+        assert!(evaluate_params(&params, &zip(&["A", "B"]), None).is_err());
+        assert!(count_values(&params, &zip(&["A", "B"]), None).is_err());
+        assert!(evaluate_params(&params, &zip(&["A", "C"]), None).is_err());
+        assert!(evaluate_params(&params, &zip(&["A", "A"]), None).is_err());
+    }
+
+    #[test]
+    fn test_count_values() {
+        let params = vec![
+            ("GPU".to_string(), "1,2,4".to_string()),
+            ("BATCHSIZE".to_string(), "32gpu,64gpu".to_string()),
+            ("LR".to_string(), "1:8".to_string()),
+        ];
+        let counts = count_values(&params, &[], None).unwrap();
+        assert_eq!(
+            counts,
+            vec![
+                ("GPU".to_string(), 3),
+                ("BATCHSIZE".to_string(), 2),
+                ("LR".to_string(), 7)
+            ]
+        );
+    }
+
+    #[test]
+    fn test_skip_and_only_filters() {
+        let params = vec![
+            ("GPU".to_string(), "1,2,4".to_string()),
+            ("MODEL".to_string(), "resnet18,resnet50,vgg16".to_string()),
+        ];
+        let combos = evaluate_params(&params, &[], None).unwrap();
+        let skip = vec![vec![
+            ("GPU".to_string(), "4".to_string()),
+            ("MODEL".to_string(), "vgg16".to_string()),
+        ]];
+        let only = vec![vec![("MODEL".to_string(), "resnet*".to_string())]];
+
+        // Skip removes only the exact corner
+        let filtered = filter_combinations(combos.clone(), &skip, &[]).unwrap();
+        assert_eq!(filtered.len(), 8);
+
+        // Only keeps the matching slice, in original order
+        let filtered = filter_combinations(combos.clone(), &[], &only).unwrap();
+        assert_eq!(filtered.len(), 6);
+        assert_eq!(filtered[0].params.get("MODEL").unwrap(), "resnet18");
+        assert_eq!(filtered[1].params.get("MODEL").unwrap(), "resnet50");
+
+        // Unknown parameters are reported
+        let bad = vec![vec![("DEPTH".to_string(), "1".to_string())]];
+        assert!(filter_combinations(combos, &bad, &[]).is_err());
+    }
+
+    #[test]
+    fn test_shards_are_disjoint() {
+        let params = vec![
+            ("A".to_string(), "1:20".to_string()),
+            ("B".to_string(), "x,y,z".to_string()),
+        ];
+        let combinations = evaluate_params(&params, &[], None).unwrap();
+        let shards: Vec<Vec<Combination>> = (1..=4)
+            .map(|shard| shard_combinations(combinations.clone(), (shard, 4)))
+            .collect();
+        assert_eq!(shards.iter().map(|s| s.len()).sum::<usize>(), 57);
+        assert!(shards.iter().all(|s| !s.is_empty()));
+
+        // The split does not depend on the order of the parameters
+        let reordered = vec![params[1].clone(), params[0].clone()];
+        let mut shard: Vec<_> =
+            shard_combinations(evaluate_params(&reordered, &[], None).unwrap(), (2, 4))
+                .iter()
+                .map(|c| (c.params["A"].clone(), c.params["B"].clone()))
+                .collect();
+        let mut expected: Vec<_> = shards[1]
+            .iter()
+            .map(|c| (c.params["A"].clone(), c.params["B"].clone()))
+            .collect();
+        expected.sort();
+        shard.sort();
+        assert_eq!(shard, expected);
+    }
+
+    #[test]
+    fn test_sample_combinations() {
+        let params = vec![
+            ("A".to_string(), "1:50".to_string()),
+            ("B".to_string(), "x,y".to_string()),
+        ];
+        let combinations = evaluate_params(&params, &[], None).unwrap();
+        let sample = |count, seed| -> Vec<(String, String)> {
+            sample_combinations(combinations.clone(), (count, seed))
+                .iter()
+                .map(|c| (c.params["A"].clone(), c.params["B"].clone()))
+                .collect()
+        };
+        let small = sample(10, 0);
+        assert_eq!(small.len(), 10);
+        assert_eq!(small, sample(10, 0));
+        assert_ne!(small, sample(10, 1));
+        assert_eq!(sample(200, 0).len(), 98);
+
+        // In grid order, and a larger sample contains the smaller one
+        let position = |run: &(String, String)| {
+            combinations
+                .iter()
+                .position(|c| c.params["A"] == run.0 && c.params["B"] == run.1)
+                .unwrap()
+        };
+        assert!(small.windows(2).all(|w| position(&w[0]) < position(&w[1])));
+        let large = sample(30, 0);
+        assert!(small.iter().all(|run| large.contains(run)));
+    }
 }
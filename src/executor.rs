@@ -1,73 +1,549 @@
-use crate::evaluator::Combination;
-use crate::parser::Options;
-use std::collections::{BTreeMap, HashMap};
-use std::fs::{self, File, OpenOptions};
-use std::io::Write;
-use std::process::{Command, Stdio};
-use std::sync::atomic::{AtomicUsize, Ordering};
+use crate::aggregate::{aggregate_path, aggregate_runs, write_aggregate};
+use crate::artifact::collect_artifacts;
+use crate::backend::{
+    ExecutionBackend, IONICE_VARIABLE, Job, NICE_VARIABLE, ResourceUsage, STDIN_VARIABLE,
+};
+use crate::capture::OutputCapture;
+use crate::cost::CostExpr;
+use crate::csv::{escape_csv_field, read_csv, read_csv_header};
+use crate::error::Error;
+use crate::evaluator::{Combination, builtin, halving_budgets, random_rank};
+use crate::halving::Halving;
+use crate::hash::{sha256_file, sha256_hex};
+use crate::metadata::{
+    metadata_path, metadata_value, read_metadata, sweep_metadata, write_metadata,
+};
+use crate::optimize::{DEFAULT_BUDGET, Optimizer};
+use crate::parser::{MismatchAction, Options, Stage, flag_value};
+use crate::sink::{CsvSink, ResultSink};
+use crate::snapshot::{
+    append_snapshot, git_commit, last_snapshot, shell_quote, snapshot_drift, snapshot_path,
+    take_snapshot,
+};
+use crate::timestamp::DateTime;
+use crate::verify::InputVerifier;
+use std::collections::HashMap;
+use std::collections::hash_map::Entry;
+use std::fs;
+use std::io::{BufRead, IsTerminal, Write};
+use std::path::{Path, PathBuf};
 use std::sync::{Arc, Mutex};
 use std::thread;
-
-#[cfg(unix)]
-use std::os::unix::process::CommandExt;
-
-#[cfg(windows)]
-use std::os::windows::process::CommandExt;
+use std::time::{Duration, Instant};
 
 #[derive(Debug, Clone)]
 struct ExperimentResult {
+    name: String,
+    tags: HashMap<String, String>,
     params: HashMap<String, String>,
+    stages: Vec<StageResult>,
+    // Paths of the files collected with --collect
+    artifacts: Vec<String>,
+    // Path of the --log-dir files without the stream suffix, e.g. "logs/3f2a9c1b7e04"
+    log: String,
+    // "ok", "failed", "timeout", "stalled" (--stall-timeout), "oom-killed"
+    // (--memory-limit) or "aborted" (--abort-if), and the exit code of the last stage that ran
+    // (--record-status)
+    status: String,
+    exit_code: String,
+    // Wall-clock seconds from the start of the first stage to the end of the last
+    duration: Option<f64>,
+    // Summed over the stages that finished (--record-usage)
+    usage: Option<ResourceUsage>,
+    // When the first stage started, in ISO 8601 UTC (--record-meta)
+    started_at: String,
+}
+
+impl ExperimentResult {
+    fn new(
+        combo: &Combination,
+        options: &Options,
+        stages: Vec<StageResult>,
+        artifacts: Vec<String>,
+    ) -> Self {
+        ExperimentResult {
+            name: options.name.clone().unwrap_or_default(),
+            tags: options.tags.iter().cloned().collect(),
+            params: combo.params.clone(),
+            stages,
+            artifacts,
+            log: log_stem(options, combo),
+            status: "ok".to_string(),
+            exit_code: "0".to_string(),
+            duration: None,
+            usage: None,
+            started_at: String::new(),
+        }
+    }
+
+    // A run that failed; `finished` holds the results of the stages that succeeded
+    fn failed(
+        combo: &Combination,
+        options: &Options,
+        stage_count: usize,
+        mut finished: Vec<StageResult>,
+        artifacts: Vec<String>,
+        status: &str,
+        exit_code: Option<i32>,
+    ) -> Self {
+        finished.resize(stage_count, StageResult::default());
+        ExperimentResult {
+            status: status.to_string(),
+            exit_code: exit_code.map(|code| code.to_string()).unwrap_or_default(),
+            ..ExperimentResult::new(combo, options, finished, artifacts)
+        }
+    }
+
+    fn is_ok(&self) -> bool {
+        self.status == "ok"
+    }
+
+    // Succeeded with a value for every metric; --rerun-failed runs any other row again
+    fn is_complete(&self, options: &Options) -> bool {
+        self.is_ok()
+            && (0..self.stages.len()).all(|stage| {
+                options
+                    .metrics
+                    .iter()
+                    .all(|metric| !self.metric_value(stage, metric).is_empty())
+            })
+    }
+
+    // Value of a metric column: the first parsed label containing the metric name
+    fn metric_value(&self, stage: usize, metric: &str) -> &str {
+        let metric_lower = metric.to_lowercase();
+        self.stages[stage]
+            .metrics
+            .iter()
+            .find(|(label, _)| label.to_lowercase().contains(&metric_lower))
+            .map(|(_, v)| v.as_str())
+            .unwrap_or("")
+    }
+
+    fn to_run_result(
+        &self,
+        index: usize,
+        stages: &[Stage],
+        options: &Options,
+        resumed: bool,
+    ) -> RunResult {
+        let mut metrics = HashMap::new();
+        for stage in 0..stages.len() {
+            for metric in &options.metrics {
+                let column = Column::Metric(stage, metric.clone());
+                metrics.insert(
+                    column.header(stages),
+                    self.metric_value(stage, metric).to_string(),
+                );
+            }
+        }
+        RunResult {
+            index,
+            params: self.params.clone(),
+            metrics,
+            stdout: self.stages.iter().map(|s| s.stdout.clone()).collect(),
+            stderr: self.stages.iter().map(|s| s.stderr.clone()).collect(),
+            artifacts: self.artifacts.clone(),
+            resumed,
+            failed: !self.is_ok(),
+            duration_s: self.duration,
+            usage: self.usage,
+        }
+    }
+}
+
+// The result of one parameter combination, as returned by the library API
+#[derive(Debug, Clone)]
+pub struct RunResult {
+    // Position of the combination in the generated grid
+    pub index: usize,
+    pub params: HashMap<String, String>,
+    // Metric values keyed by their CSV column, e.g. "accuracy" or "eval.accuracy"
+    pub metrics: HashMap<String, String>,
+    // Captured output per stage; empty for resumed runs unless output is preserved
+    pub stdout: Vec<String>,
+    pub stderr: Vec<String>,
+    // Files moved into the artifact directory with --collect
+    pub artifacts: Vec<String>,
+    // Loaded from an existing results file instead of being run
+    pub resumed: bool,
+    // A failed run recorded with --record-status; it has only the metrics of the stages
+    // that finished, or those printed until --abort-if stopped it
+    pub failed: bool,
+    // Wall-clock time of the run; for resumed runs only with --record-time
+    pub duration_s: Option<f64>,
+    // Resources used by the run's commands, if the backend reports them; for resumed
+    // runs only with --record-usage
+    pub usage: Option<ResourceUsage>,
+}
+
+impl RunResult {
+    // Numeric value of a metric column, if present and parseable
+    pub fn metric(&self, column: &str) -> Option<f64> {
+        self.metrics.get(column)?.parse().ok()
+    }
+}
+
+// All results of a sweep in grid order, with counts for the completion summary
+#[derive(Debug, Clone, Default)]
+pub struct SweepResults {
+    pub runs: Vec<RunResult>,
+    pub new: usize,
+    pub skipped: usize,
+    pub failed: usize,
+    // Total --cost of the runs started by this invocation
+    pub cost: Option<f64>,
+}
+
+// Parsed metrics and captured output of one stage of an experiment
+#[derive(Debug, Clone, Default)]
+struct StageResult {
     metrics: HashMap<String, String>,
+    // The beginning and end of each stream (--max-output-bytes)
     stdout: String,
     stderr: String,
 }
 
-// Ensures progress messages print in sequential order during concurrent execution.
-struct OrderedOutput {
-    next_to_print: AtomicUsize,
-    pending: Mutex<BTreeMap<usize, String>>,
+// A column of the results CSV. The layout is derived from the options, so the same
+// list drives header writing, row writing and compatibility checks on resume.
+// Metric and output columns refer to a stage by index.
+#[derive(Debug, Clone, PartialEq)]
+enum Column {
+    Name,
+    Tag(String),
+    Param(String),
+    Metric(usize, String),
+    Status,
+    ExitCode,
+    Duration,
+    // One of USAGE_COLUMNS
+    Usage(&'static str),
+    // One of META_COLUMNS
+    Meta(&'static str),
+    Artifacts,
+    Log,
+    Stdout(usize),
+    Stderr(usize),
 }
 
-impl OrderedOutput {
-    fn new() -> Self {
-        OrderedOutput {
-            next_to_print: AtomicUsize::new(0),
-            pending: Mutex::new(BTreeMap::new()),
+impl Column {
+    // Columns of named stages are prefixed with the stage name, e.g. "eval.accuracy"
+    fn header(&self, stages: &[Stage]) -> String {
+        let prefixed = |stage: usize, name: &str| match stages[stage].name.as_str() {
+            "" => name.to_string(),
+            stage_name => format!("{}.{}", stage_name, name),
+        };
+        match self {
+            Column::Name => "name".to_string(),
+            Column::Tag(key) | Column::Param(key) => key.clone(),
+            Column::Metric(stage, name) => prefixed(*stage, name),
+            Column::Status => "status".to_string(),
+            Column::ExitCode => "exit_code".to_string(),
+            Column::Duration => "duration_s".to_string(),
+            Column::Usage(name) | Column::Meta(name) => name.to_string(),
+            Column::Artifacts => "artifacts".to_string(),
+            Column::Log => "log".to_string(),
+            Column::Stdout(stage) => prefixed(*stage, "stdout"),
+            Column::Stderr(stage) => prefixed(*stage, "stderr"),
         }
     }
+}
 
-    fn print(&self, idx: usize, message: String) {
-        let mut pending = self
-            .pending
-            .lock()
-            .unwrap_or_else(|poisoned| poisoned.into_inner());
-        pending.insert(idx, message);
-
-        // Print all consecutive messages starting from next_to_print.
-        // Messages arriving out of order are buffered and printed later.
-        loop {
-            let next = self.next_to_print.load(Ordering::SeqCst);
-            if let Some(msg) = pending.remove(&next) {
-                drop(pending); // Release lock before printing
-                print!("{}", msg);
-                let _ = std::io::stdout().flush();
-                self.next_to_print.fetch_add(1, Ordering::SeqCst);
-                pending = self
-                    .pending
-                    .lock()
-                    .unwrap_or_else(|poisoned| poisoned.into_inner());
+// Where execute_all takes the combinations to start from: the scheduled combinations in
+// order, or the proposals of --optimize, which depend on the results so far
+pub trait Planner<'a> {
+    // The next combination to start, given the results so far and the indices of the
+    // combinations running; None once no more are to be started
+    fn next(
+        &mut self,
+        results: &SweepResults,
+        running: &[usize],
+    ) -> Option<(usize, &'a Combination)>;
+
+    // How many combinations are still to be started, for progress messages
+    fn remaining(&self) -> usize;
+
+    // The number of combinations indices refer to, for "combination i/N" messages
+    fn total(&self) -> usize;
+
+    // Called once after the last run finished
+    fn finish(&mut self, _results: &SweepResults) -> Result<(), String> {
+        Ok(())
+    }
+}
+
+// The combinations in the order of `schedule`
+struct Scheduled<'a> {
+    order: std::vec::IntoIter<(usize, &'a Combination)>,
+    total: usize,
+}
+
+impl<'a> Planner<'a> for Scheduled<'a> {
+    fn next(&mut self, _: &SweepResults, _: &[usize]) -> Option<(usize, &'a Combination)> {
+        self.order.next()
+    }
+
+    fn remaining(&self) -> usize {
+        self.order.len()
+    }
+
+    fn total(&self) -> usize {
+        self.total
+    }
+}
+
+// --dry-run: print the environment and commands of every run instead of running them
+pub fn print_plan(
+    combinations: &[Combination],
+    stages: &[Stage],
+    options: &Options,
+) -> Result<(), Error> {
+    let assignment = |(name, value): (&String, &String)| format!("{}={}", name, shell_quote(value));
+    if !options.env.is_empty() {
+        let constants: Vec<String> = options
+            .env
+            .iter()
+            .map(|(k, v)| assignment((k, v)))
+            .collect();
+        println!("Every run gets: {}", constants.join(" "));
+    }
+    if let Some(path) = &options.env_file {
+        let names: Vec<&str> = options.file_env.iter().map(|(k, _)| k.as_str()).collect();
+        println!("Every run gets from {}: {}", path, names.join(" "));
+    }
+    if options.clean_env {
+        let passed: Vec<String> = passed_env(options).into_iter().map(|(k, _)| k).collect();
+        println!(
+            "Runs start with a clean environment, except: {}",
+            passed.join(" ")
+        );
+    }
+    if let Some(setup) = &options.setup {
+        println!("Setup: {}", setup);
+    }
+    let total = combinations.len();
+    let order = schedule(combinations, options)?;
+    let planned = order.len();
+    for (idx, expected_cost) in order {
+        let combo = &combinations[idx];
+        let commands = stages.iter().map(|stage| {
+            let command = display_command(&stage_command(stage, combo, options), options);
+            if stage.name.is_empty() {
+                command
             } else {
-                break;
+                format!("{}: {}", stage.name, command)
+            }
+        });
+        let params: Vec<String> = combo
+            .param_order
+            .iter()
+            .filter_map(|name| {
+                let value = combo.params.get(name)?;
+                Some(assignment((&env_name(name, options).to_string(), value)))
+            })
+            .collect();
+        let expected_cost = expected_cost
+            .map(|cost| format!(" (expected cost {})", cost))
+            .unwrap_or_default();
+        println!(
+            "Combination {}/{}: {}{}",
+            idx + 1,
+            total,
+            params.join(" "),
+            expected_cost
+        );
+        for command in commands {
+            println!("  {}", command);
+        }
+        if let Some(dir) = run_workdir(combo, options) {
+            println!("  (in {})", dir.display());
+        }
+        if let Some(template) = &options.stdin_file {
+            println!("  (stdin from {})", substitute_params(template, combo));
+        }
+    }
+    if let Some(teardown) = &options.teardown {
+        println!("Teardown: {}", teardown);
+    }
+    println!("Dry run: {} combinations, nothing was run", planned);
+    Ok(())
+}
+
+// The order in which combinations are started, as indices with their expected cost: grid
+// order, or with --expected-cost the cheapest first so results come in early (or with
+// --costliest-first the most expensive first, so the long runs are spread over the
+// slots rather than left for the end). Combinations of equal cost keep grid order. With
+// --limit N only the first N of them are started.
+fn schedule(
+    combinations: &[Combination],
+    options: &Options,
+) -> Result<Vec<(usize, Option<f64>)>, Error> {
+    let mut order = run_order(combinations, options)?;
+    if let Some(limit) = options.limit {
+        order.truncate(limit);
+    }
+    Ok(order)
+}
+
+fn run_order(
+    combinations: &[Combination],
+    options: &Options,
+) -> Result<Vec<(usize, Option<f64>)>, Error> {
+    let Some(expr) = &options.expected_cost else {
+        let mut order: Vec<usize> = (0..combinations.len()).collect();
+        if let Some(seed) = options.shuffle {
+            order.sort_by_cached_key(|&idx| random_rank(&combinations[idx], seed));
+        }
+        return Ok(order.into_iter().map(|idx| (idx, None)).collect());
+    };
+    let mut order = Vec::with_capacity(combinations.len());
+    for (idx, combo) in combinations.iter().enumerate() {
+        let cost = expr
+            .eval(&|name| {
+                combo
+                    .params
+                    .get(&name.to_uppercase())
+                    .and_then(|value| value.trim().parse().ok())
+            })
+            .map_err(|e| {
+                Error::usage(format!(
+                    "Cannot compute --expected-cost {} for combination {}: {}",
+                    expr.source(),
+                    idx + 1,
+                    e
+                ))
+            })?;
+        order.push((idx, cost));
+    }
+    order.sort_by(|(_, a), (_, b)| {
+        if options.costliest_first {
+            b.total_cmp(a)
+        } else {
+            a.total_cmp(b)
+        }
+    });
+    Ok(order
+        .into_iter()
+        .map(|(idx, cost)| (idx, Some(cost)))
+        .collect())
+}
+
+// Whether a command is `bash -c CONTENT PATH ARGS...` running the -f script
+fn is_script_command(command: &[String], options: &Options) -> bool {
+    options
+        .script
+        .as_ref()
+        .is_some_and(|script| command.len() > 3 && command[3] == script.path)
+}
+
+// The command of a stage for a combination, with "{name}" in its arguments replaced by
+// the value of parameter NAME. The content of a -f script is left alone. The flag of a
+// --flag:--name parameter that is on takes the place of an argument "{name}", or is
+// appended to the command if there is none; when off, it is left out.
+fn stage_command(stage: &Stage, combo: &Combination, options: &Options) -> Vec<String> {
+    let script = is_script_command(&stage.command, options);
+    let flag = |arg: &str| {
+        let name = arg.strip_prefix('{')?.strip_suffix('}')?.to_uppercase();
+        options.flags.iter().find(|(param, _)| *param == name)
+    };
+    let mut command = Vec::new();
+    let mut placed = Vec::new();
+    for (i, arg) in stage.command.iter().enumerate() {
+        if script && i == 2 {
+            command.push(arg.clone());
+        } else if let Some((param, name)) = flag(arg).filter(|_| !(script && i < 4)) {
+            if combo
+                .params
+                .get(param)
+                .is_some_and(|v| flag_value(v) == Some(true))
+            {
+                command.push(name.clone());
+            }
+            placed.push(param);
+        } else {
+            command.push(substitute_params(arg, combo));
+        }
+    }
+    for (param, name) in &options.flags {
+        if !placed.contains(&param)
+            && combo
+                .params
+                .get(param)
+                .is_some_and(|v| flag_value(v) == Some(true))
+        {
+            command.push(name.clone());
+        }
+    }
+    command
+}
+
+// Replace "{name}" by the value of the parameter of that name (case-insensitive), and
+// "{idx}" by the position of the combination and "{today}" and the like by the value of
+// the builtin, unless a parameter is named so. Unknown names and shell expansions like
+// "${NAME}" are kept as they are.
+fn substitute_params(arg: &str, combo: &Combination) -> String {
+    let lookup = |name: &str| match combo.params.get(&name.to_uppercase()) {
+        Some(value) => Some(value.clone()),
+        None if name.eq_ignore_ascii_case("idx") => Some(combo.index.to_string()),
+        None => builtin(name),
+    };
+    let mut result = String::with_capacity(arg.len());
+    let mut rest = arg;
+    while let Some(start) = rest.find('{') {
+        let (before, after) = rest.split_at(start);
+        result.push_str(before);
+        let value = after[1..]
+            .find('}')
+            .filter(|_| !result.ends_with('$'))
+            .and_then(|end| Some((end, lookup(&after[1..=end])?)));
+        match value {
+            Some((end, value)) => {
+                result.push_str(&value);
+                rest = &after[end + 2..];
+            }
+            None => {
+                result.push('{');
+                rest = &after[1..];
             }
         }
     }
+    result.push_str(rest);
+    result
+}
+
+// A command as it could be typed in a shell; a -f script is shown by its path rather
+// than its content
+fn display_command(command: &[String], options: &Options) -> String {
+    let command = match &options.script {
+        Some(script) if is_script_command(command, options) => {
+            let mut shown = vec!["bash".to_string(), script.path.clone()];
+            shown.extend(command[4..].iter().cloned());
+            shown
+        }
+        _ => command.to_vec(),
+    };
+    command
+        .iter()
+        .map(|arg| shell_quote(arg))
+        .collect::<Vec<_>>()
+        .join(" ")
 }
 
 pub fn execute_experiments(
     combinations: &[Combination],
-    command: &[String],
+    stages: &[Stage],
     options: &Options,
-) -> Result<(), String> {
+    backend: &Arc<dyn ExecutionBackend>,
+    extra_sinks: &[Arc<Mutex<dyn ResultSink>>],
+) -> Result<SweepResults, Error> {
+    // Refuse to start on changed inputs before touching any file
+    let mut verifier = InputVerifier::default();
+    verifier.verify(&options.verify_inputs)?;
+    let scheduled: Vec<(usize, &Combination)> = schedule(combinations, options)?
+        .into_iter()
+        .map(|(idx, _)| (idx, &combinations[idx]))
+        .collect();
+
     // Get expected parameter names from combinations (in input order)
     let expected_params: Vec<String> = if let Some(first_combo) = combinations.first() {
         first_combo.param_order.clone()
@@ -75,579 +551,1722 @@ pub fn execute_experiments(
         Vec::new()
     };
 
-    // Pre-compute lowercase metrics to avoid repeated allocations in the loop
-    let metric_columns_lower: Vec<String> =
-        options.metrics.iter().map(|m| m.to_lowercase()).collect();
+    check_tag_columns(&csv_columns(&expected_params, stages, options), stages)?;
 
     // Check if output file exists and load existing results for skip detection
-    let file_exists = std::path::Path::new(&options.output_file).exists();
+    let mut options = options.clone();
+    let mut file_exists = std::path::Path::new(&options.output_file).exists();
+    let mut migrated = false;
+    if file_exists {
+        let expected_headers: Vec<String> = csv_columns(&expected_params, stages, &options)
+            .iter()
+            .map(|c| c.header(stages))
+            .collect();
+        let found_headers = read_csv_header(&options.output_file).map_err(|message| Error::Io {
+            path: options.output_file.clone(),
+            message,
+        })?;
+        if found_headers != expected_headers {
+            let diff = describe_header_diff(&found_headers, &expected_headers);
+            eprintln!(
+                "The header of {} does not match this invocation:",
+                options.output_file
+            );
+            eprint!("{}", diff);
+            match choose_mismatch_action(options.on_mismatch)? {
+                MismatchAction::Migrate => {
+                    let backup = migrate_results_file(&options.output_file, &expected_headers)
+                        .map_err(|message| Error::Io {
+                            path: options.output_file.clone(),
+                            message,
+                        })?;
+                    println!(
+                        "Migrated {} to the new header (backup saved as {})",
+                        options.output_file, backup
+                    );
+                    migrated = true;
+                }
+                MismatchAction::New => {
+                    options.output_file = timestamped_filename(&options.output_file);
+                    file_exists = false;
+                    println!("Writing results to {}", options.output_file);
+                }
+                _ => {
+                    return Err(Error::Incompatible {
+                        path: options.output_file.clone(),
+                        message: "Existing result file is incompatible. Please use a different output file, remove the existing one, or pass --on-mismatch migrate/new.".to_string(),
+                    });
+                }
+            }
+        }
+    }
+    let options = &options;
     let existing_results = if file_exists {
-        match load_existing_results(
-            &options.output_file,
-            &expected_params,
-            &options.metrics,
-            options.preserve_output,
-            options.stdout_only,
-            options.stderr_only,
-        ) {
-            Ok(res) => res,
+        match load_existing_results(&options.output_file, &expected_params, stages, options) {
+            Ok(res) => ExistingResults::new(res, options),
             Err(e) => {
-                return Err(format!(
-                    "Existing result file is incompatible: {}. Please use a different output file or remove the existing one.",
-                    e
-                ));
+                return Err(Error::Incompatible {
+                    path: options.output_file.clone(),
+                    message: format!(
+                        "Existing result file is incompatible: {}. Please use a different output file or remove the existing one.",
+                        e
+                    ),
+                });
             }
         }
     } else {
-        Vec::new()
+        ExistingResults::default()
+    };
+
+    // Rows of the new runs should be comparable to the ones already in the file
+    let environment = take_snapshot(options);
+    if file_exists {
+        check_drift(stages, options, &environment)?;
+    }
+
+    // The results file is always the first sink; it is also what later invocations resume from
+    let mut sinks: Vec<Arc<Mutex<dyn ResultSink>>> = vec![Arc::new(Mutex::new(CsvSink::new(
+        &options.output_file,
+        file_exists,
+    )))];
+    sinks.extend(extra_sinks.iter().cloned());
+    let writer = RowWriter {
+        columns: csv_columns(&expected_params, stages, options),
+        sinks,
+        hostname: metadata_value(&environment, "hostname")
+            .unwrap_or_default()
+            .to_string(),
+        git_commit: if options.record_meta {
+            git_commit().unwrap_or_default()
+        } else {
+            String::new()
+        },
     };
+    let headers: Vec<String> = writer.columns.iter().map(|c| c.header(stages)).collect();
+    writer.each_sink(|sink| sink.write_header(&headers))?;
+
+    // If the file doesn't exist, write the sweep metadata next to it; a migrated file now
+    // belongs to this invocation, so its metadata is rewritten
+    let meta_path = metadata_path(&options.output_file);
+    if !file_exists || migrated {
+        let entries = sweep_metadata(&expected_params, &command_sha256(stages, options), options);
+        write_metadata(&meta_path, &entries).map_err(|message| Error::Io {
+            path: meta_path.clone(),
+            message,
+        })?;
+    }
+
+    let snapshot = snapshot_path(&options.output_file);
+    append_snapshot(&snapshot, &environment).map_err(|message| Error::Io {
+        path: snapshot.clone(),
+        message,
+    })?;
 
-    // If the file doesn't exist, write the header first
-    if !file_exists {
-        write_csv_header(&expected_params, &options.output_file, options)?;
+    if let Some(dir) = &options.log_dir {
+        fs::create_dir_all(dir).map_err(|e| Error::io(dir, e))?;
     }
 
-    // Convert combinations to indexed list for execution
-    let indexed_combos: Vec<(usize, &Combination)> = combinations.iter().enumerate().collect();
+    backend.prepare(stages, options)?;
 
-    // Execute experiments (sequentially or concurrently) with lazy checking
-    let (new_results_count, skipped_count, failed_count) = if options.concurrency <= 1 {
-        execute_sequential(
-            &indexed_combos,
-            combinations.len(),
-            command,
-            options,
-            &expected_params,
-            &metric_columns_lower,
-            &existing_results,
-        )
-    } else {
-        execute_concurrent(
-            &indexed_combos,
-            combinations.len(),
-            command,
-            options,
-            &expected_params,
-            &metric_columns_lower,
-            &existing_results,
-        )
+    if let Some(setup) = &options.setup {
+        run_hook("setup", setup, options)
+            .map_err(|e| Error::Other(format!("{}; not starting the sweep", e)))?;
+    }
+
+    let mut plan: Box<dyn Planner> = match (&options.optimize, &options.halving) {
+        (Some(_), _) => Box::new(Optimizer::new(combinations, options)?),
+        (None, Some((_, min, max))) => {
+            let rounds = halving_budgets(*min, *max, options.halving_eta).len();
+            Box::new(Halving::new(combinations, rounds, options))
+        }
+        (None, None) => Box::new(Scheduled {
+            order: scheduled.into_iter(),
+            total: combinations.len(),
+        }),
     };
+    let (mut results, aborted) = execute_all(
+        plan.as_mut(),
+        stages,
+        options,
+        &writer,
+        &existing_results,
+        backend.as_ref(),
+        &mut verifier,
+    );
+
+    if let Err(e) = plan.finish(&results) {
+        eprintln!("Warning: {}", e);
+    }
+    results.runs.sort_by_key(|run| run.index);
+    writer.each_sink(|sink| sink.finalize())?;
+
+    // --shuffle appends the rows in the order the runs finish; put them back in grid order
+    if options.shuffle.is_some()
+        && let Err(e) = sort_results_file(&options.output_file, &expected_params, combinations)
+    {
+        eprintln!("Warning: {}", e);
+    }
+
+    if options.repeat > 1 && !options.repeats {
+        let metrics: Vec<String> = writer
+            .columns
+            .iter()
+            .filter(|column| matches!(column, Column::Metric(..)))
+            .map(|column| column.header(stages))
+            .collect();
+        let path = aggregate_path(&options.output_file);
+        let rows = aggregate_runs(&results.runs, &expected_params, &metrics);
+        match write_aggregate(&path, &rows) {
+            Ok(()) => println!(
+                "Wrote the mean and standard deviation over {} repetitions to {}",
+                options.repeat, path
+            ),
+            Err(e) => eprintln!("Warning: {}", e),
+        }
+    }
 
+    // The teardown runs even if the sweep was aborted, since the setup did run
+    if let Some(teardown) = &options.teardown
+        && let Err(e) = run_hook("teardown", teardown, options)
+    {
+        eprintln!("Warning: {}", e);
+    }
+
+    // --optimize runs only its budget of the combinations, --halving only the survivors
+    // of each round and --limit only the first ones
+    let planned = match (&options.optimize, &options.halving) {
+        (Some(_), _) => options
+            .budget
+            .unwrap_or(DEFAULT_BUDGET)
+            .min(combinations.len()),
+        (None, Some(_)) => results.skipped + results.new + results.failed,
+        (None, None) => options.limit.unwrap_or(usize::MAX).min(combinations.len()),
+    };
     println!(
         "Completed {} out of {} combinations ({} skipped, {} new, {} failed)",
-        skipped_count + new_results_count,
-        combinations.len(),
-        skipped_count,
-        new_results_count,
-        failed_count
+        results.skipped + results.new,
+        planned,
+        results.skipped,
+        results.new,
+        results.failed
     );
+    if let Some(cost) = results.cost {
+        println!("Total cost: {:.4}", cost);
+    }
 
-    Ok(())
+    match aborted {
+        Some(error) => Err(error),
+        None => Ok(results),
+    }
+}
+
+// Run a --setup or --teardown command through bash in the directory of the results file,
+// with the environment of the runs (but no parameters) and the output shown as it is
+// written
+fn run_hook(kind: &str, command: &str, options: &Options) -> Result<(), String> {
+    println!("Running {}: {}", kind, command);
+    let dir = Path::new(&options.output_file)
+        .parent()
+        .filter(|dir| !dir.as_os_str().is_empty())
+        .unwrap_or(Path::new("."));
+    let mut hook = std::process::Command::new("bash");
+    if options.clean_env {
+        hook.env_clear();
+    }
+    let status = hook
+        .args(["-c", command])
+        .envs(
+            passed_env(options)
+                .iter()
+                .chain(&options.file_env)
+                .chain(&options.env)
+                .map(|(k, v)| (k, v)),
+        )
+        .current_dir(dir)
+        .status()
+        .map_err(|e| format!("Failed to run {} command: {}", kind, e))?;
+    if status.success() {
+        Ok(())
+    } else {
+        Err(format!("The {} command failed ({})", kind, status))
+    }
 }
 
-fn execute_sequential(
-    indexed_combos: &[(usize, &Combination)],
-    total_count: usize,
-    command: &[String],
+// Compare the commands, the script, the recorded input files and the environment with
+// those of the last start that wrote to the results file, so rows of another command are
+// not taken for done. Differences are an error unless --force is given.
+fn check_drift(
+    stages: &[Stage],
     options: &Options,
-    expected_params: &[String],
-    metric_columns_lower: &[String],
-    existing_results: &[ExperimentResult],
-) -> (usize, usize, usize) {
-    let mut new_results_count = 0;
-    let mut skipped_count = 0;
-    let mut failed_count = 0;
-
-    for (idx, combo) in indexed_combos {
-        // Check if combination already exists (lazy check)
-        if result_exists(existing_results, combo) {
-            println!(
-                "Skipping combination {}/{} (already exists)",
-                idx + 1,
-                total_count
-            );
-            skipped_count += 1;
+    environment: &[(String, String)],
+) -> Result<(), Error> {
+    let mut drift = Vec::new();
+    let recorded = read_metadata(&metadata_path(&options.output_file)).unwrap_or_default();
+    let short = |hash: &str| hash.get(..12).unwrap_or(hash).to_string();
+    let command = command_sha256(stages, options);
+    if let Some(recorded_hash) = metadata_value(&recorded, "command_sha256")
+        && *recorded_hash != command
+    {
+        drift.push(format!(
+            "command: sha256 {} -> {}",
+            short(recorded_hash),
+            short(&command)
+        ));
+    }
+    if let (Some(recorded_hash), Some(script)) =
+        (metadata_value(&recorded, "script_sha256"), &options.script)
+        && recorded_hash != script.sha256
+    {
+        drift.push(format!(
+            "{}: sha256 {} -> {}",
+            script.path,
+            short(recorded_hash),
+            short(&script.sha256)
+        ));
+    }
+    // Inputs of --verify-input and --track-input, hashed again as they are now
+    for (key, value) in &recorded {
+        let (Some(path), Some(recorded_hash)) =
+            (key.strip_prefix("input."), value.strip_prefix("sha256:"))
+        else {
             continue;
+        };
+        match sha256_file(path) {
+            Ok(hash) if hash == recorded_hash => {}
+            Ok(hash) => drift.push(format!(
+                "{}: sha256 {} -> {}",
+                path,
+                short(recorded_hash),
+                short(&hash)
+            )),
+            Err(e) => drift.push(format!("{}: {}", path, e)),
         }
+    }
+    if let Some(previous) = last_snapshot(&snapshot_path(&options.output_file)) {
+        drift.extend(snapshot_drift(&previous, environment));
+    }
+    if drift.is_empty() {
+        return Ok(());
+    }
 
-        println!("Running combination {}/{}", idx + 1, total_count);
+    let description = format!(
+        "The environment differs from the last run into {}:\n  {}",
+        options.output_file,
+        drift.join("\n  ")
+    );
+    if options.force {
+        eprintln!("Warning: {}", description);
+        return Ok(());
+    }
+    Err(Error::Incompatible {
+        path: options.output_file.clone(),
+        message: format!(
+            "{}\nPass --force to add rows anyway, or use a different output file.",
+            description
+        ),
+    })
+}
 
-        match execute_single(combo, command, options) {
-            Ok((metrics, stdout, stderr)) => {
-                let result = ExperimentResult {
-                    params: combo.params.clone(),
-                    metrics,
-                    stdout,
-                    stderr,
-                };
-                // Append result immediately after each successful run
-                if let Err(e) = append_result(
-                    &result,
-                    expected_params,
-                    &options.output_file,
-                    options,
-                    metric_columns_lower,
-                ) {
-                    eprintln!("Failed to write result: {}", e);
-                    failed_count += 1;
-                } else {
-                    new_results_count += 1;
-                }
-            }
-            Err(e) => {
-                eprintln!("Failed to run combination: {}", e);
-                failed_count += 1;
-            }
+// Summarize how two headers differ: columns added, removed, or the same columns reordered
+fn describe_header_diff(found: &[String], expected: &[String]) -> String {
+    let added: Vec<&str> = expected
+        .iter()
+        .filter(|c| !found.contains(c))
+        .map(|c| c.as_str())
+        .collect();
+    let removed: Vec<&str> = found
+        .iter()
+        .filter(|c| !expected.contains(c))
+        .map(|c| c.as_str())
+        .collect();
+
+    let mut diff = String::new();
+    if !added.is_empty() {
+        diff += &format!("  + added:     {}\n", added.join(", "));
+    }
+    if !removed.is_empty() {
+        diff += &format!("  - removed:   {}\n", removed.join(", "));
+    }
+
+    // Compare the order of the columns present in both headers
+    let common_found: Vec<&String> = found.iter().filter(|c| expected.contains(c)).collect();
+    let common_expected: Vec<&String> = expected.iter().filter(|c| found.contains(c)).collect();
+    if common_found != common_expected {
+        diff += &format!(
+            "  ~ reordered: {} -> {}\n",
+            common_found
+                .iter()
+                .map(|c| c.as_str())
+                .collect::<Vec<_>>()
+                .join(","),
+            common_expected
+                .iter()
+                .map(|c| c.as_str())
+                .collect::<Vec<_>>()
+                .join(",")
+        );
+    }
+
+    diff
+}
+
+// Resolve "ask" by prompting on an interactive terminal; otherwise abort
+fn choose_mismatch_action(action: MismatchAction) -> Result<MismatchAction, String> {
+    if action != MismatchAction::Ask {
+        return Ok(action);
+    }
+    if !std::io::stdin().is_terminal() {
+        return Ok(MismatchAction::Abort);
+    }
+
+    loop {
+        print!("[m]igrate the existing file, write a [n]ew file, or [a]bort? ");
+        let _ = std::io::stdout().flush();
+        let mut answer = String::new();
+        std::io::stdin()
+            .lock()
+            .read_line(&mut answer)
+            .map_err(|e| format!("Failed to read answer: {}", e))?;
+        match answer.trim() {
+            "m" | "migrate" => return Ok(MismatchAction::Migrate),
+            "n" | "new" => return Ok(MismatchAction::New),
+            "a" | "abort" | "" => return Ok(MismatchAction::Abort),
+            _ => continue,
         }
     }
+}
+
+// Rewrite a results file under a new header, mapping values by column name.
+// Columns that no longer exist are dropped and new columns are left empty.
+// Returns the path of the backup of the original file.
+fn migrate_results_file(filename: &str, new_headers: &[String]) -> Result<String, String> {
+    let records = read_csv(filename)?;
+    let old_headers = &records[0];
 
-    (new_results_count, skipped_count, failed_count)
+    let mut migrated = new_headers
+        .iter()
+        .map(|h| escape_csv_field(h))
+        .collect::<Vec<_>>()
+        .join(",");
+    migrated.push('\n');
+    for values in &records[1..] {
+        let row: Vec<String> = new_headers
+            .iter()
+            .map(|header| {
+                old_headers
+                    .iter()
+                    .position(|h| h == header)
+                    .and_then(|pos| values.get(pos))
+                    .map(|v| escape_csv_field(v))
+                    .unwrap_or_default()
+            })
+            .collect();
+        migrated += &row.join(",");
+        migrated.push('\n');
+    }
+
+    let backup = format!("{}.bak", filename);
+    fs::copy(filename, &backup).map_err(|e| format!("Failed to back up {}: {}", filename, e))?;
+    fs::write(filename, migrated).map_err(|e| format!("Failed to write {}: {}", filename, e))?;
+    Ok(backup)
 }
 
-fn execute_concurrent(
-    indexed_combos: &[(usize, &Combination)],
-    total_count: usize,
-    command: &[String],
-    options: &Options,
-    expected_params: &[String],
-    metric_columns_lower: &[String],
-    existing_results: &[ExperimentResult],
-) -> (usize, usize, usize) {
-    let new_results_count = Arc::new(AtomicUsize::new(0));
-    let skipped_count = Arc::new(AtomicUsize::new(0));
-    let failed_count = Arc::new(AtomicUsize::new(0));
-    let file_lock = Arc::new(Mutex::new(()));
-    let output_order = Arc::new(OrderedOutput::new());
-
-    // Use a work queue pattern: index into indexed_combos
-    let next_work_idx = Arc::new(AtomicUsize::new(0));
-
-    // Spawn worker threads
-    let mut handles = Vec::with_capacity(options.concurrency);
-
-    for _ in 0..options.concurrency {
-        let next_work_idx = Arc::clone(&next_work_idx);
-        let new_results_count = Arc::clone(&new_results_count);
-        let skipped_count = Arc::clone(&skipped_count);
-        let failed_count = Arc::clone(&failed_count);
-        let file_lock = Arc::clone(&file_lock);
-        let output_order = Arc::clone(&output_order);
-
-        // Clone data needed by each thread
-        let indexed_combos: Vec<(usize, Combination)> = indexed_combos
+// Sort the rows of a results file by the position of their combination in the grid. Rows
+// of combinations outside the grid (e.g. of an earlier, larger sweep) keep their order
+// at the end.
+fn sort_results_file(
+    filename: &str,
+    param_names: &[String],
+    combinations: &[Combination],
+) -> Result<(), String> {
+    let records = read_csv(filename)?;
+    let Some((header, rows)) = records.split_first() else {
+        return Ok(());
+    };
+    let columns: Vec<Option<usize>> = param_names
+        .iter()
+        .map(|name| header.iter().position(|h| h == name))
+        .collect();
+    let positions: HashMap<Vec<&str>, usize> = combinations
+        .iter()
+        .enumerate()
+        .map(|(idx, combo)| {
+            let values = param_names.iter().map(|n| combo.params[n].as_str());
+            (values.collect(), idx)
+        })
+        .collect();
+    let mut rows: Vec<&Vec<String>> = rows.iter().collect();
+    rows.sort_by_cached_key(|row| {
+        let values: Vec<&str> = columns
             .iter()
-            .map(|(idx, combo)| (*idx, (*combo).clone()))
+            .map(|c| c.and_then(|c| row.get(c)).map_or("", |v| v.as_str()))
             .collect();
-        let existing_results: Vec<ExperimentResult> = existing_results.to_vec();
-        let command = command.to_vec();
-        let options = options.clone();
-        let expected_params = expected_params.to_vec();
-        let metric_columns_lower = metric_columns_lower.to_vec();
-        let total = total_count;
-
-        let handle = thread::spawn(move || {
-            loop {
-                // Atomically get the next work item
-                let work_idx = next_work_idx.fetch_add(1, Ordering::SeqCst);
-                if work_idx >= indexed_combos.len() {
-                    break; // No more work
-                }
+        positions.get(&values).copied().unwrap_or(usize::MAX)
+    });
+
+    let mut sorted = String::new();
+    for row in std::iter::once(header).chain(rows) {
+        let fields: Vec<String> = row.iter().map(|v| escape_csv_field(v)).collect();
+        sorted += &fields.join(",");
+        sorted.push('\n');
+    }
+    fs::write(filename, sorted).map_err(|e| format!("Failed to write {}: {}", filename, e))
+}
+
+// "results.csv" -> "results-20240131-235959.csv"
+fn timestamped_filename(filename: &str) -> String {
+    let stamp = DateTime::now().compact();
+    let path = std::path::Path::new(filename);
+    match (path.file_stem(), path.extension()) {
+        (Some(stem), Some(ext)) => path
+            .with_file_name(format!(
+                "{}-{}.{}",
+                stem.to_string_lossy(),
+                stamp,
+                ext.to_string_lossy()
+            ))
+            .to_string_lossy()
+            .to_string(),
+        _ => format!("{}-{}", filename, stamp),
+    }
+}
+
+// A combination whose stages are being run
+struct ActiveRun<'a> {
+    idx: usize,
+    combo: &'a Combination,
+    // Stage currently running and the results of the stages before it
+    stage: usize,
+    finished: Vec<StageResult>,
+    current: Option<StageRun>,
+    started: Instant,
+    started_at: DateTime,
+    // Which of the --concurrency slots the run occupies, from 1
+    slot: usize,
+    // 1 for the first attempt, higher for --retries
+    attempt: usize,
+    // How the last stage that finished ended
+    exit_code: Option<i32>,
+    timed_out: bool,
+    stalled: bool,
+    oom_killed: bool,
+    aborted: bool,
+    // Resources used by the stages that finished
+    usage: Option<ResourceUsage>,
+    // The token of each --resource held by the run until it finishes, as
+    // (variable, token)
+    tokens: Vec<(String, String)>,
+}
 
-                let (idx, combo) = &indexed_combos[work_idx];
+// A failed run waiting to be attempted again
+struct PendingRetry<'a> {
+    idx: usize,
+    combo: &'a Combination,
+    attempt: usize,
+    ready: Instant,
+}
+
+// A launched stage command and the capture of its output
+struct StageRun {
+    job: Job,
+    stdout: OutputCapture,
+    stderr: OutputCapture,
+    started: Instant,
+    timed_out: bool,
+    // When the stage last printed something, and whether it was stopped for printing
+    // nothing for --stall-timeout
+    last_output: Instant,
+    stalled: bool,
+    // When the memory use was last compared to --memory-limit, and whether it exceeded it
+    memory_checked: Instant,
+    oom_killed: bool,
+    // The --abort-if or --stop-when condition the stage was stopped for
+    aborted: Option<String>,
+    reached: Option<String>,
+}
+
+impl StageRun {
+    // Pass output written so far to the captures; returns whether there was any
+    fn read_output(
+        &mut self,
+        options: &Options,
+        backend: &dyn ExecutionBackend,
+    ) -> Result<bool, String> {
+        let (stdout, stderr) = backend.read_output(&self.job)?;
+        self.stdout.feed(&stdout, options)?;
+        self.stderr.feed(&stderr, options)?;
+        let any = !stdout.is_empty() || !stderr.is_empty();
+        if any {
+            self.last_output = Instant::now();
+        }
+        Ok(any)
+    }
+
+    // Check the --abort-if and --stop-when conditions against the metrics parsed so far
+    // and the parameters; returns a message once one holds. Metrics of a named stage can
+    // be referred to with or without the stage prefix, e.g. "train.loss" or "loss".
+    fn check_conditions(
+        &mut self,
+        stage: &Stage,
+        combo: &Combination,
+        options: &Options,
+    ) -> Option<String> {
+        if self.aborted.is_some() || self.reached.is_some() {
+            return None;
+        }
+        let lookup = |name: &str| {
+            let prefix = format!("{}.", stage.name);
+            let metric = name.strip_prefix(&prefix).unwrap_or(name).to_lowercase();
+            combo
+                .params
+                .get(&name.to_uppercase())
+                .or_else(|| {
+                    // Values from stderr take precedence, as in the results
+                    [self.stderr.metrics(), self.stdout.metrics()]
+                        .into_iter()
+                        .find_map(|metrics| {
+                            metrics
+                                .iter()
+                                .find(|(label, _)| label.to_lowercase().contains(&metric))
+                                .map(|(_, value)| value)
+                        })
+                })
+                .and_then(|value| value.trim().parse().ok())
+        };
+        if let Some(condition) = options.abort_if.iter().find(|c| c.holds(&lookup)) {
+            self.aborted = Some(condition.source().to_string());
+            return Some(format!("{} (--abort-if)", condition.source()));
+        }
+        let condition = options.stop_when.iter().find(|c| c.holds(&lookup))?;
+        self.reached = Some(condition.source().to_string());
+        Some(format!("{} (--stop-when)", condition.source()))
+    }
+}
+
+enum Progress {
+    Waiting,
+    // Output was read or the next stage was started
+    Advanced,
+    Done(Result<Vec<StageResult>, String>),
+}
+
+// How often the memory use of a run is compared to --memory-limit
+const MEMORY_CHECK_INTERVAL: Duration = Duration::from_millis(250);
+
+// Shortest and longest pause between polls when no job has finished
+const MIN_POLL_INTERVAL: Duration = Duration::from_millis(1);
+const MAX_POLL_INTERVAL: Duration = Duration::from_millis(50);
+
+// Run the combinations with up to --concurrency of them in flight. Jobs are launched
+// and polled from this thread, which the local backend also reads their output on, so
+// many concurrent runs do not need a thread each.
+// Combinations are started in the order they are given (see schedule), which keeps
+// progress messages in order.
+// With --verify-each-run, no more combinations are started once an input changed, and
+// with --max-failures once that many runs failed in a row; the error is returned after
+// the runs in flight have finished.
+fn execute_all<'a>(
+    plan: &mut dyn Planner<'a>,
+    stages: &[Stage],
+    options: &Options,
+    writer: &RowWriter,
+    existing_results: &ExistingResults,
+    backend: &dyn ExecutionBackend,
+    verifier: &mut InputVerifier,
+) -> (SweepResults, Option<Error>) {
+    let total = plan.total();
+    let mut results = SweepResults::default();
+    let mut active: Vec<ActiveRun> = Vec::new();
+    let mut interval = MIN_POLL_INTERVAL;
+    let mut cost = 0.0;
+    let mut budget_reached = false;
+    let mut consecutive_failures = 0;
+    let mut aborted = None;
+    let mut retries: Vec<PendingRetry> = Vec::new();
+
+    loop {
+        // Start combinations until the concurrency limit is reached
+        while active.len() < options.concurrency.max(1) {
+            if let Some(budget) = options.cost_budget
+                && cost >= budget
+            {
+                if !budget_reached && plan.remaining() > 0 {
+                    println!(
+                        "Cost budget of {} reached; not starting the remaining {} combinations",
+                        budget,
+                        plan.remaining()
+                    );
+                }
+                budget_reached = true;
+                break;
+            }
+            if let Some(max) = options.max_failures
+                && consecutive_failures >= max
+                && aborted.is_none()
+                && (plan.remaining() > 0 || !retries.is_empty())
+            {
+                let remaining = plan.remaining() + retries.len();
+                eprintln!(
+                    "{} runs failed in a row; not starting the remaining {} combinations",
+                    consecutive_failures, remaining
+                );
+                aborted = Some(Error::Other(format!(
+                    "Stopped the sweep after {} consecutive failures (--max-failures)",
+                    consecutive_failures
+                )));
+            }
+            if aborted.is_some() {
+                break;
+            }
+            // Every run needs a free token of each --resource (and --gpus)
+            let Some(tokens) = free_tokens(&options.resources, &active) else {
+                break;
+            };
+            // Retries go first once their delay has passed
+            let (idx, combo, attempt) = if let Some(pos) = retries
+                .iter()
+                .position(|retry| retry.ready <= Instant::now())
+            {
+                let retry = retries.remove(pos);
+                (retry.idx, retry.combo, retry.attempt)
+            } else {
+                // Runs waiting for a retry count as running, since they may still finish
+                let running: Vec<usize> = active
+                    .iter()
+                    .map(|run| run.idx)
+                    .chain(retries.iter().map(|retry| retry.idx))
+                    .collect();
+                let Some((idx, combo)) = plan.next(&results, &running) else {
+                    break;
+                };
 
                 // Check if combination already exists (lazy check)
-                if result_exists(&existing_results, combo) {
-                    output_order.print(
-                        work_idx,
-                        format!(
-                            "Skipping combination {}/{} (already exists)\n",
+                if let Some(existing) = existing_results.find(combo, options)
+                    && (existing.is_complete(options) || !options.rerun_failed)
+                {
+                    if existing.is_complete(options) {
+                        println!(
+                            "Skipping combination {}/{} (already exists)",
                             idx + 1,
                             total
-                        ),
-                    );
-                    skipped_count.fetch_add(1, Ordering::SeqCst);
+                        );
+                    } else if existing.is_ok() {
+                        println!(
+                            "Skipping combination {}/{} (empty metrics; --rerun-failed runs it again)",
+                            idx + 1,
+                            total
+                        );
+                    } else {
+                        println!(
+                            "Skipping combination {}/{} (recorded as {}; --rerun-failed runs it again)",
+                            idx + 1,
+                            total,
+                            existing.status
+                        );
+                    }
+                    results
+                        .runs
+                        .push(existing.to_run_result(idx, stages, options, true));
+                    results.skipped += 1;
                     continue;
                 }
+                (idx, combo, 1)
+            };
+
+            if options.verify_each_run
+                && let Err(e) = verifier.verify(&options.verify_inputs)
+            {
+                eprintln!(
+                    "Input check failed; not starting combination {}/{} or any later one",
+                    idx + 1,
+                    total
+                );
+                aborted = Some(e);
+                break;
+            }
+
+            let slot = (1..)
+                .find(|slot| active.iter().all(|run| run.slot != *slot))
+                .unwrap_or(1);
+            println!(
+                "Running combination {}/{}{}{}{}",
+                idx + 1,
+                total,
+                attempt_label(attempt, options),
+                slot_label(slot, options),
+                tokens_label(&tokens)
+            );
+            let _ = std::io::stdout().flush();
+            match launch_stage(combo, idx, &stages[0], &tokens, options, backend) {
+                Ok(current) => active.push(ActiveRun {
+                    idx,
+                    combo,
+                    stage: 0,
+                    finished: Vec::new(),
+                    current: Some(current),
+                    started: Instant::now(),
+                    started_at: DateTime::now(),
+                    slot,
+                    attempt,
+                    exit_code: None,
+                    timed_out: false,
+                    stalled: false,
+                    oom_killed: false,
+                    aborted: false,
+                    usage: None,
+                    tokens,
+                }),
+                Err(e) => {
+                    eprintln!("Failed to run combination: {}", stage_error(&stages[0], e));
+                    finish_scratch(options, idx, false, total);
+                    match retry(idx, combo, attempt, total, options) {
+                        Some(retry) => retries.push(retry),
+                        None => {
+                            let mut result = ExperimentResult::failed(
+                                combo,
+                                options,
+                                stages.len(),
+                                Vec::new(),
+                                Vec::new(),
+                                "failed",
+                                None,
+                            );
+                            result.started_at = DateTime::now().iso();
+                            record_failure(result, idx, stages, options, writer, &mut results);
+                            consecutive_failures += 1;
+                        }
+                    }
+                }
+            }
+        }
+
+        if active.is_empty() && (retries.is_empty() || aborted.is_some() || budget_reached) {
+            break;
+        }
+
+        let mut progressed = false;
+        let mut i = 0;
+        while i < active.len() {
+            match advance(&mut active[i], stages, options, backend) {
+                Progress::Waiting => i += 1,
+                Progress::Advanced => {
+                    progressed = true;
+                    i += 1;
+                }
+                Progress::Done(outcome) => {
+                    progressed = true;
+                    let run = active.remove(i);
+                    let duration = run.started.elapsed();
+                    // Collected from failed runs too, so the next run does not pick them up
+                    let (outcome, artifacts) = match run_artifacts(options, run.combo) {
+                        Ok(artifacts) => (outcome, artifacts),
+                        Err(e) => (Err(e), Vec::new()),
+                    };
+                    // Runs finish out of order when several run at once
+                    if options.concurrency > 1 {
+                        println!(
+                            "Combination {}/{}{} {} after {:.1}s ({} running, {} waiting)",
+                            run.idx + 1,
+                            total,
+                            slot_label(run.slot, options),
+                            if outcome.is_ok() {
+                                "finished"
+                            } else {
+                                "failed"
+                            },
+                            duration.as_secs_f64(),
+                            active.len(),
+                            plan.remaining()
+                        );
+                    }
+                    let mut metrics = HashMap::new();
+                    match outcome {
+                        Ok(stage_results) => {
+                            finish_scratch(options, run.idx, false, total);
+                            let mut result =
+                                ExperimentResult::new(run.combo, options, stage_results, artifacts);
+                            result.duration = Some(duration.as_secs_f64());
+                            result.usage = run.usage;
+                            result.started_at = run.started_at.iso();
+                            let run_result = result.to_run_result(run.idx, stages, options, false);
+                            metrics = run_result.metrics.clone();
+                            consecutive_failures = 0;
+                            // Append result immediately after each successful run
+                            if let Err(e) = writer.write(&result) {
+                                eprintln!("Failed to write result: {}", e);
+                                results.failed += 1;
+                            } else {
+                                results.runs.push(run_result);
+                                results.new += 1;
+                            }
+                        }
+                        Err(e) => {
+                            eprintln!("Failed to run combination: {}", e);
+                            // An aborted run would most likely end the same way again
+                            let retry = if run.aborted {
+                                None
+                            } else {
+                                retry(run.idx, run.combo, run.attempt, total, options)
+                            };
+                            finish_scratch(options, run.idx, retry.is_none(), total);
+                            match retry {
+                                Some(retry) => retries.push(retry),
+                                None => {
+                                    let status = if run.aborted {
+                                        "aborted"
+                                    } else if run.timed_out {
+                                        "timeout"
+                                    } else if run.stalled {
+                                        "stalled"
+                                    } else if run.oom_killed {
+                                        "oom-killed"
+                                    } else {
+                                        "failed"
+                                    };
+                                    let mut result = ExperimentResult::failed(
+                                        run.combo,
+                                        options,
+                                        stages.len(),
+                                        run.finished,
+                                        artifacts,
+                                        status,
+                                        run.exit_code,
+                                    );
+                                    result.duration = Some(duration.as_secs_f64());
+                                    result.usage = run.usage;
+                                    result.started_at = run.started_at.iso();
+                                    record_failure(
+                                        result,
+                                        run.idx,
+                                        stages,
+                                        options,
+                                        writer,
+                                        &mut results,
+                                    );
+                                    consecutive_failures += 1;
+                                }
+                            }
+                        }
+                    }
+                    if let Some(expr) = &options.cost {
+                        match run_cost(expr, run.combo, &metrics, duration) {
+                            Ok(run_cost) => {
+                                cost += run_cost;
+                                print_cost(run.idx, total, run_cost, cost, options);
+                            }
+                            Err(e) => eprintln!(
+                                "Warning: cannot compute the cost of combination {}/{}: {}",
+                                run.idx + 1,
+                                total,
+                                e
+                            ),
+                        }
+                    }
+                }
+            }
+        }
+
+        // Poll often while jobs finish quickly and back off while they run long
+        if progressed {
+            interval = MIN_POLL_INTERVAL;
+        } else {
+            thread::sleep(interval);
+            interval = (interval * 2).min(MAX_POLL_INTERVAL);
+        }
+    }
+
+    // Runs whose retry was never started keep their failure
+    results.failed += retries.len();
+    results.cost = options.cost.is_some().then_some(cost);
+    (results, aborted)
+}
+
+// Count a run that failed for good, and with --record-status write its row
+fn record_failure(
+    result: ExperimentResult,
+    idx: usize,
+    stages: &[Stage],
+    options: &Options,
+    writer: &RowWriter,
+    results: &mut SweepResults,
+) {
+    results.failed += 1;
+    if !options.record_status {
+        return;
+    }
+    match writer.write(&result) {
+        Ok(()) => results
+            .runs
+            .push(result.to_run_result(idx, stages, options, false)),
+        Err(e) => eprintln!("Failed to write result: {}", e),
+    }
+}
+
+// Schedule another attempt of a failed run, unless it has used up its --retries
+fn retry<'a>(
+    idx: usize,
+    combo: &'a Combination,
+    attempt: usize,
+    total: usize,
+    options: &Options,
+) -> Option<PendingRetry<'a>> {
+    if attempt > options.retries {
+        return None;
+    }
+    let delay = options
+        .retry_delay
+        .saturating_mul(2u32.saturating_pow(attempt as u32 - 1));
+    println!(
+        "Retrying combination {}/{} in {:.1}s (attempt {}/{})",
+        idx + 1,
+        total,
+        delay.as_secs_f64(),
+        attempt + 1,
+        options.retries + 1
+    );
+    Some(PendingRetry {
+        idx,
+        combo,
+        attempt: attempt + 1,
+        ready: Instant::now() + delay,
+    })
+}
+
+// A token of every resource that no running run holds, or None if one of the resources
+// has no free token
+fn free_tokens(
+    resources: &[(String, Vec<String>)],
+    active: &[ActiveRun],
+) -> Option<Vec<(String, String)>> {
+    resources
+        .iter()
+        .map(|(name, tokens)| {
+            let token = tokens.iter().find(|token| {
+                active
+                    .iter()
+                    .all(|run| !run.tokens.iter().any(|(n, t)| n == name && t == *token))
+            })?;
+            Some((name.clone(), token.clone()))
+        })
+        .collect()
+}
+
+// " on GPU 1, PORT=8001" for the tokens of a run
+fn tokens_label(tokens: &[(String, String)]) -> String {
+    if tokens.is_empty() {
+        return String::new();
+    }
+    let tokens: Vec<String> = tokens
+        .iter()
+        .map(|(name, token)| match name.as_str() {
+            "CUDA_VISIBLE_DEVICES" => format!("GPU {}", token),
+            _ => format!("{}={}", name, token),
+        })
+        .collect();
+    format!(" on {}", tokens.join(", "))
+}
+
+// " (attempt 2/3)" for retries
+fn attempt_label(attempt: usize, options: &Options) -> String {
+    if attempt > 1 {
+        format!(" (attempt {}/{})", attempt, options.retries + 1)
+    } else {
+        String::new()
+    }
+}
+
+// " [slot 2/4]" when runs share the machine, so interleaved messages can be told apart
+fn slot_label(slot: usize, options: &Options) -> String {
+    if options.concurrency > 1 {
+        format!(" [slot {}/{}]", slot, options.concurrency)
+    } else {
+        String::new()
+    }
+}
+
+// Evaluate --cost for a finished run. Parameters can be written in any case; metrics
+// are referred to by their column and are missing when the run failed.
+fn run_cost(
+    expr: &CostExpr,
+    combo: &Combination,
+    metrics: &HashMap<String, String>,
+    duration: Duration,
+) -> Result<f64, String> {
+    expr.eval(&|name| {
+        if name == "duration_s" {
+            return Some(duration.as_secs_f64());
+        }
+        combo
+            .params
+            .get(&name.to_uppercase())
+            .or_else(|| metrics.get(name))
+            .and_then(|value| value.trim().parse().ok())
+    })
+}
+
+fn print_cost(idx: usize, total: usize, run_cost: f64, cost: f64, options: &Options) {
+    let budget = options
+        .cost_budget
+        .map(|budget| format!(" of {}", budget))
+        .unwrap_or_default();
+    println!(
+        "Cost of combination {}/{}: {:.4} (total {:.4}{})",
+        idx + 1,
+        total,
+        run_cost,
+        cost,
+        budget
+    );
+}
+
+// Check on the running stage of a combination: stop it when it exceeds --timeout, and
+// once it has finished, start the next stage or return the results of all stages.
+// Stops at the first failing stage.
+fn advance(
+    run: &mut ActiveRun,
+    stages: &[Stage],
+    options: &Options,
+    backend: &dyn ExecutionBackend,
+) -> Progress {
+    let stage = &stages[run.stage];
+    let Some(current) = &mut run.current else {
+        return Progress::Done(Err("No stage is running".to_string()));
+    };
+    let code = match backend.poll(&current.job) {
+        Ok(Some(code)) => code,
+        Ok(None) => {
+            if let Some(timeout) = options.timeout
+                && !current.timed_out
+                && current.started.elapsed() > timeout
+            {
+                current.timed_out = true;
+                if let Err(e) = backend.kill(&current.job) {
+                    return Progress::Done(Err(stage_error(stage, e)));
+                }
+            }
+            if let Some(stall_timeout) = options.stall_timeout
+                && !current.stalled
+                && current.last_output.elapsed() > stall_timeout
+            {
+                current.stalled = true;
+                if let Err(e) = backend.kill(&current.job) {
+                    return Progress::Done(Err(stage_error(stage, e)));
+                }
+            }
+            if let Some(limit) = options.memory_limit
+                && !current.oom_killed
+                && current.memory_checked.elapsed() >= MEMORY_CHECK_INTERVAL
+            {
+                current.memory_checked = Instant::now();
+                if backend
+                    .memory_kb(&current.job)
+                    .is_some_and(|kb| kb * 1024 > limit)
+                {
+                    current.oom_killed = true;
+                    if let Err(e) = backend.kill(&current.job) {
+                        return Progress::Done(Err(stage_error(stage, e)));
+                    }
+                }
+            }
+            return match current.read_output(options, backend) {
+                Ok(true) => {
+                    if let Some(message) = current.check_conditions(stage, run.combo, options) {
+                        println!("Stopping combination {}: {}", run.idx + 1, message);
+                        if let Err(e) = backend.kill(&current.job) {
+                            return Progress::Done(Err(stage_error(stage, e)));
+                        }
+                    }
+                    Progress::Advanced
+                }
+                Ok(false) => Progress::Waiting,
+                Err(e) => Progress::Done(Err(stage_error(stage, e))),
+            };
+        }
+        Err(e) => return Progress::Done(Err(stage_error(stage, e))),
+    };
+
+    let Some(current) = run.current.take() else {
+        return Progress::Done(Err("No stage is running".to_string()));
+    };
+    run.exit_code = code;
+    run.timed_out = current.timed_out;
+    run.stalled = current.stalled;
+    run.oom_killed = current.oom_killed;
+    if let Some(usage) = backend.usage(&current.job) {
+        run.usage = Some(run.usage.map_or(usage, |total| total.combine(usage)));
+    }
+    let aborted = current.aborted.clone();
+    match finish_stage(stage, options, code, current, backend) {
+        Ok(result) => run.finished.push(result),
+        Err(e) => return Progress::Done(Err(stage_error(stage, e))),
+    }
+    // The run fails, keeping the values printed until then
+    if let Some(condition) = aborted {
+        run.aborted = true;
+        return Progress::Done(Err(stage_error(
+            stage,
+            format!("Aborted because {} holds", condition),
+        )));
+    }
+
+    run.stage += 1;
+    if run.stage == stages.len() {
+        let finished = std::mem::take(&mut run.finished);
+        return Progress::Done(check_metrics(stages, options, &finished).map(|_| finished));
+    }
+    match launch_stage(
+        run.combo,
+        run.idx,
+        &stages[run.stage],
+        &run.tokens,
+        options,
+        backend,
+    ) {
+        Ok(current) => {
+            run.current = Some(current);
+            Progress::Advanced
+        }
+        Err(e) => Progress::Done(Err(stage_error(&stages[run.stage], e))),
+    }
+}
+
+fn stage_error(stage: &Stage, error: String) -> String {
+    if stage.name.is_empty() {
+        error
+    } else {
+        format!("Stage {} failed: {}", stage.name, error)
+    }
+}
+
+// If metrics are specified, check that all were found (in any stage)
+fn check_metrics(
+    stages: &[Stage],
+    options: &Options,
+    results: &[StageResult],
+) -> Result<(), String> {
+    let mut missing_metrics = Vec::new();
+    for metric in &options.metrics {
+        // Check if any metric label contains this metric
+        let found = results.iter().any(|r| {
+            r.metrics
+                .keys()
+                .any(|label| label.to_lowercase().contains(&metric.to_lowercase()))
+        });
+        if !found {
+            missing_metrics.push(metric.clone());
+        }
+    }
+
+    if !missing_metrics.is_empty() {
+        // Write the collected stdout and stderr to runexp's output so user can inspect
+        for (stage, result) in stages.iter().zip(results) {
+            print_captured_output(stage, result, options);
+        }
+        return Err(format!(
+            "Missing metrics in output: {}",
+            missing_metrics.join(", ")
+        ));
+    }
+
+    Ok(())
+}
+
+fn print_captured_output(stage: &Stage, result: &StageResult, options: &Options) {
+    // --verbose showed the output as it was written
+    if options.verbose {
+        return;
+    }
+    let prefix = if stage.name.is_empty() {
+        String::new()
+    } else {
+        format!("{} ", stage.name)
+    };
+    eprintln!("=== {}stdout ===", prefix);
+    eprint!("{}", result.stdout);
+    eprintln!("=== {}stderr ===", prefix);
+    eprint!("{}", result.stderr);
+}
+
+fn launch_stage(
+    combo: &Combination,
+    idx: usize,
+    stage: &Stage,
+    tokens: &[(String, String)],
+    options: &Options,
+    backend: &dyn ExecutionBackend,
+) -> Result<StageRun, String> {
+    // Environment variables: with --clean-env the variables passed through, then those of
+    // the --env-file, then fixed --env values, then the identity and scratch directory of
+    // the run, then the sweep parameters, then the resource tokens held by the run, then
+    // its priority and stdin file
+    let identity = [
+        ("RUNEXP_RUN_INDEX".to_string(), (idx + 1).to_string()),
+        ("RUNEXP_RUN_ID".to_string(), run_id(combo)),
+    ];
+    // Created by the first stage and shared by the later ones
+    let mut scratch = Vec::new();
+    if options.scratch {
+        let dir = scratch_dir(idx);
+        fs::create_dir_all(&dir)
+            .map_err(|e| format!("Failed to create {}: {}", dir.display(), e))?;
+        let dir = dir.to_string_lossy().to_string();
+        scratch.push(("RUNEXP_TMPDIR".to_string(), dir.clone()));
+        scratch.push(("TMPDIR".to_string(), dir));
+    }
+    let settings = [
+        (NICE_VARIABLE, &options.nice),
+        (IONICE_VARIABLE, &options.ionice),
+        (STDIN_VARIABLE, &options.stdin_file),
+    ]
+    .into_iter()
+    .filter_map(|(name, value)| {
+        let value = substitute_params(value.as_ref()?, combo);
+        Some((name.to_string(), value))
+    });
+    let env: Vec<(String, String)> = passed_env(options)
+        .into_iter()
+        .chain(options.file_env.iter().cloned())
+        .chain(options.env.iter().cloned())
+        .chain(identity)
+        .chain(scratch)
+        .chain(
+            combo
+                .params
+                .iter()
+                .map(|(k, v)| (env_name(k, options).to_string(), v.clone())),
+        )
+        .chain(tokens.iter().cloned())
+        .chain(settings)
+        .collect();
+
+    let mut stdout = OutputCapture::new(
+        log_path(options, combo, stage, "stdout").as_deref(),
+        !options.stderr_only,
+        options.max_output_bytes,
+    )?;
+    let mut stderr = OutputCapture::new(
+        log_path(options, combo, stage, "stderr").as_deref(),
+        !options.stdout_only,
+        options.max_output_bytes,
+    )?;
+    // With --verbose, output lines of the third combination are shown as "[3] line"
+    // (or "[3 train] line")
+    if options.verbose {
+        let prefix = if stage.name.is_empty() {
+            format!("[{}] ", idx + 1)
+        } else {
+            format!("[{} {}] ", idx + 1, stage.name)
+        };
+        stdout = stdout.echo(prefix.clone(), false);
+        stderr = stderr.echo(prefix, true);
+    }
+    let command = stage_command(stage, combo, options);
+    let job = match run_workdir(combo, options) {
+        Some(dir) => {
+            fs::create_dir_all(&dir)
+                .map_err(|e| format!("Failed to create {}: {}", dir.display(), e))?;
+            backend.launch_in(&command, &env, &dir)?
+        }
+        None => backend.launch(&command, &env)?,
+    };
+    Ok(StageRun {
+        job,
+        stdout,
+        stderr,
+        started: Instant::now(),
+        timed_out: false,
+        last_output: Instant::now(),
+        stalled: false,
+        memory_checked: Instant::now(),
+        oom_killed: false,
+        aborted: None,
+        reached: None,
+    })
+}
+
+// SHA-256 of the commands of all stages, recorded as command_sha256. The content of a -f
+// script is left out, as it has a script_sha256 of its own.
+fn command_sha256(stages: &[Stage], options: &Options) -> String {
+    let commands: Vec<(&String, Vec<&str>)> = stages
+        .iter()
+        .map(|stage| {
+            let script = is_script_command(&stage.command, options);
+            let args = stage
+                .command
+                .iter()
+                .enumerate()
+                .map(|(i, arg)| if script && i == 2 { "" } else { arg.as_str() })
+                .collect();
+            (&stage.name, args)
+        })
+        .collect();
+    sha256_hex(format!("{:?}", commands).as_bytes())
+}
+
+// RUNEXP_RUN_ID: the first 12 hex digits of the SHA-256 of the parameter values, so a
+// combination keeps its id when the grid around it changes
+fn run_id(combo: &Combination) -> String {
+    let mut params: Vec<(&String, &String)> = combo.params.iter().collect();
+    params.sort();
+    // Debug formatting quotes the values, so no two combinations give the same text
+    sha256_hex(format!("{:?}", params).as_bytes())[..12].to_string()
+}
+
+// Variables of runexp's own environment that runs get with --clean-env: PATH and HOME,
+// so commands and their configuration are found, and those named by --pass-env
+fn passed_env(options: &Options) -> Vec<(String, String)> {
+    if !options.clean_env {
+        return Vec::new();
+    }
+    ["PATH", "HOME"]
+        .iter()
+        .copied()
+        .chain(options.pass_env.iter().map(String::as_str))
+        .filter_map(|name| Some((name.to_string(), std::env::var(name).ok()?)))
+        .collect()
+}
 
-                output_order.print(
-                    work_idx,
-                    format!("Running combination {}/{}\n", idx + 1, total),
-                );
+// The environment variable of a parameter: its name, or the one given as --name@VAR
+fn env_name<'a>(param: &'a str, options: &'a Options) -> &'a str {
+    options
+        .env_names
+        .iter()
+        .find(|(name, _)| name == param)
+        .map_or(param, |(_, var)| var.as_str())
+}
 
-                match execute_single(combo, &command, &options) {
-                    Ok((metrics, stdout, stderr)) => {
-                        let result = ExperimentResult {
-                            params: combo.params.clone(),
-                            metrics,
-                            stdout,
-                            stderr,
-                        };
-                        // Lock when writing to the file to prevent corruption
-                        let _guard = file_lock
-                            .lock()
-                            .unwrap_or_else(|poisoned| poisoned.into_inner());
-                        if let Err(e) = append_result(
-                            &result,
-                            &expected_params,
-                            &options.output_file,
-                            &options,
-                            &metric_columns_lower,
-                        ) {
-                            eprintln!("Failed to write result: {}", e);
-                            failed_count.fetch_add(1, Ordering::SeqCst);
-                        } else {
-                            new_results_count.fetch_add(1, Ordering::SeqCst);
-                        }
-                    }
-                    Err(e) => {
-                        eprintln!("Failed to run combination: {}", e);
-                        failed_count.fetch_add(1, Ordering::SeqCst);
-                    }
-                }
-            }
-        });
-        handles.push(handle);
-    }
+// The --workdir of a combination, with its parameter values filled in
+fn run_workdir(combo: &Combination, options: &Options) -> Option<PathBuf> {
+    let template = options.workdir.as_ref()?;
+    Some(PathBuf::from(substitute_params(template, combo)))
+}
 
-    // Wait for all threads to complete, handling panics properly
-    for handle in handles {
-        if let Err(e) = handle.join() {
-            eprintln!("Worker thread panicked: {:?}", e);
-            failed_count.fetch_add(1, Ordering::SeqCst);
-        }
+// The name of a run's own files and directories: its run id, after the --name of the
+// sweep if it has one, e.g. "ablation-v2-3f2a9c1b7e04"
+fn run_file_name(options: &Options, combo: &Combination) -> String {
+    match &options.name {
+        Some(name) => format!("{}-{}", name, run_id(combo)),
+        None => run_id(combo),
     }
+}
 
-    (
-        new_results_count.load(Ordering::SeqCst),
-        skipped_count.load(Ordering::SeqCst),
-        failed_count.load(Ordering::SeqCst),
-    )
+// Move (or with --collect-copy, copy) the files matching --collect to
+// <artifact_dir>/<run file name>/. They are looked for in the run's --workdir if it has one.
+fn run_artifacts(options: &Options, combo: &Combination) -> Result<Vec<String>, String> {
+    if options.collect.is_empty() {
+        return Ok(Vec::new());
+    }
+    let dest = Path::new(&options.artifact_dir).join(run_file_name(options, combo));
+    let base = run_workdir(combo, options).unwrap_or_else(|| PathBuf::from("."));
+    let collected = collect_artifacts(&base, &options.collect, &dest, options.collect_copy)?;
+    Ok(collected
+        .iter()
+        .map(|path| path.to_string_lossy().to_string())
+        .collect())
 }
 
-fn execute_single(
-    combo: &Combination,
-    command: &[String],
+// Full output of a run in --log-dir, e.g. "3f2a9c1b7e04.stdout" or
+// "3f2a9c1b7e04.eval.stdout" for a named stage, named like its artifact directory so the
+// files of a combination stay the same when the grid around it changes
+fn log_path(
     options: &Options,
-) -> Result<(HashMap<String, String>, String, String), String> {
-    // Check if command is stdin (heredoc style) or regular command
-    let (cmd, args) = if command.is_empty() {
-        return Err("No command specified".to_string());
+    combo: &Combination,
+    stage: &Stage,
+    stream: &str,
+) -> Option<PathBuf> {
+    let dir = options.log_dir.as_ref()?;
+    let file = if stage.name.is_empty() {
+        format!("{}.{}", run_file_name(options, combo), stream)
     } else {
-        (&command[0], &command[1..])
+        format!(
+            "{}.{}.{}",
+            run_file_name(options, combo),
+            stage.name,
+            stream
+        )
     };
+    Some(Path::new(dir).join(file))
+}
 
-    // Set up the command
-    let mut child = Command::new(cmd);
-    child.args(args);
-
-    // Set environment variables
-    for (name, value) in &combo.params {
-        child.env(name, value);
-    }
-
-    // Capture stdout and stderr
-    child.stdout(Stdio::piped());
-    child.stderr(Stdio::piped());
+// The --scratch directory of a run, e.g. /tmp/runexp-4242-3 for the third combination;
+// the process id keeps concurrent sweeps apart
+fn scratch_dir(idx: usize) -> PathBuf {
+    std::env::temp_dir().join(format!("runexp-{}-{}", std::process::id(), idx + 1))
+}
 
-    // On Unix systems, create a new process group for the child process
-    // so it receives signals (e.g., SIGINT) independently.
-    #[cfg(unix)]
-    {
-        child.process_group(0);
+// Remove the --scratch directory of a run that finished, or keep it for a look at what a
+// failed run left behind
+fn finish_scratch(options: &Options, idx: usize, failed: bool, total: usize) {
+    if !options.scratch {
+        return;
     }
-
-    // On Windows MSVC, explicitly use default creation flags so child shares
-    // parent's console and receives Ctrl-C events.
-    #[cfg(all(windows, target_env = "msvc"))]
+    let dir = scratch_dir(idx);
+    if failed && options.keep_failed_scratch {
+        if dir.exists() {
+            println!(
+                "Kept the scratch directory of combination {}/{}: {}",
+                idx + 1,
+                total,
+                dir.display()
+            );
+        }
+    } else if let Err(e) = fs::remove_dir_all(&dir)
+        && e.kind() != std::io::ErrorKind::NotFound
     {
-        child.creation_flags(0);
+        eprintln!("Warning: failed to remove {}: {}", dir.display(), e);
     }
+}
 
-    // On MSYS2/MinGW, use CREATE_NEW_PROCESS_GROUP for proper Ctrl-C handling.
-    #[cfg(all(windows, target_env = "gnu"))]
-    {
-        const CREATE_NEW_PROCESS_GROUP: u32 = 0x00000200;
-        child.creation_flags(CREATE_NEW_PROCESS_GROUP);
-    }
+// The value of the log column: the common part of the paths of the run's log files
+fn log_stem(options: &Options, combo: &Combination) -> String {
+    options
+        .log_dir
+        .as_ref()
+        .map(|dir| {
+            Path::new(dir)
+                .join(run_file_name(options, combo))
+                .to_string_lossy()
+                .to_string()
+        })
+        .unwrap_or_default()
+}
 
-    // Execute
-    let output = child
-        .output()
-        .map_err(|e| format!("Failed to execute command: {}", e))?;
+// Read the rest of the output of a finished stage, check its exit status and collect
+// the metrics parsed from stdout and stderr
+fn finish_stage(
+    stage: &Stage,
+    options: &Options,
+    code: Option<i32>,
+    mut current: StageRun,
+    backend: &dyn ExecutionBackend,
+) -> Result<StageResult, String> {
+    while current.read_output(options, backend)? {}
+    let (stdout, stderr) = backend.collect(current.job)?;
+    current.stdout.feed(stdout.as_bytes(), options)?;
+    current.stderr.feed(stderr.as_bytes(), options)?;
+
+    let (stdout, mut metrics) = current.stdout.finish(options);
+    let (stderr, stderr_metrics) = current.stderr.finish(options);
+    // Values from stderr take precedence, as they did when both streams were parsed as one text
+    metrics.extend(stderr_metrics);
+    let result = StageResult {
+        metrics,
+        stdout,
+        stderr,
+    };
 
-    let stdout = String::from_utf8_lossy(&output.stdout).to_string();
-    let stderr = String::from_utf8_lossy(&output.stderr).to_string();
+    // Stopped on purpose; advance tells an aborted run apart
+    if current.aborted.is_some() || current.reached.is_some() {
+        return Ok(result);
+    }
 
-    // Check exit status
-    if !output.status.success() {
+    if current.timed_out {
         // Write the collected stdout and stderr to runexp's output so user can inspect
-        eprintln!("=== stdout ===");
-        eprint!("{}", stdout);
-        eprintln!("=== stderr ===");
-        eprint!("{}", stderr);
+        print_captured_output(stage, &result, options);
+        let timeout = options.timeout.unwrap_or_default();
+        return Err(format!("Timed out after {}s", timeout.as_secs_f64()));
+    }
+
+    if current.oom_killed {
+        print_captured_output(stage, &result, options);
+        let limit = options.memory_limit.unwrap_or_default();
         return Err(format!(
-            "Command failed with exit code: {:?}",
-            output.status.code()
+            "Used more memory than --memory-limit ({} kB) allows; stopped",
+            limit / 1024
         ));
     }
 
-    // Parse output based on options
-    let mut parsed = HashMap::new();
-
-    if options.stdout_only {
-        parse_output(&stdout, &mut parsed, &options.metrics);
-    } else if options.stderr_only {
-        parse_output(&stderr, &mut parsed, &options.metrics);
-    } else {
-        // Parse both stdout and stderr by default
-        // Add newline delimiter to prevent joining last line of stdout with first line of stderr
-        let combined = format!("{}\n{}", stdout, stderr);
-        parse_output(&combined, &mut parsed, &options.metrics);
+    if current.stalled {
+        print_captured_output(stage, &result, options);
+        let stall_timeout = options.stall_timeout.unwrap_or_default();
+        return Err(format!(
+            "No output for {} minutes; stopped as hanging",
+            stall_timeout.as_secs_f64() / 60.0
+        ));
     }
 
-    // If metrics are specified, check that all were found
-    if !options.metrics.is_empty() {
-        let mut missing_metrics = Vec::new();
-        for metric in &options.metrics {
-            // Check if any metric label contains this metric
-            let found = parsed
-                .keys()
-                .any(|label| label.to_lowercase().contains(&metric.to_lowercase()));
-            if !found {
-                missing_metrics.push(metric.clone());
-            }
-        }
-
-        if !missing_metrics.is_empty() {
-            // Write the collected stdout and stderr to runexp's output so user can inspect
-            eprintln!("=== stdout ===");
-            eprint!("{}", stdout);
-            eprintln!("=== stderr ===");
-            eprint!("{}", stderr);
-            return Err(format!(
-                "Missing metrics in output: {}",
-                missing_metrics.join(", ")
-            ));
-        }
+    // Check exit status
+    if code != Some(0) {
+        // Write the collected stdout and stderr to runexp's output so user can inspect
+        print_captured_output(stage, &result, options);
+        return Err(format!("Command failed with exit code: {:?}", code));
     }
 
-    Ok((parsed, stdout, stderr))
+    Ok(result)
 }
 
-fn parse_output(text: &str, results: &mut HashMap<String, String>, metrics: &[String]) {
-    // Split by \n and \r to handle all line endings (including \r\n which produces empty strings)
-    let lines: Vec<&str> = text.split(['\n', '\r']).collect();
+// Turns experiment results into rows for the sinks
+#[derive(Clone)]
+struct RowWriter {
+    columns: Vec<Column>,
+    sinks: Vec<Arc<Mutex<dyn ResultSink>>>,
+    // Values of the META_COLUMNS shared by all runs of this invocation
+    hostname: String,
+    git_commit: String,
+}
 
-    for line in lines {
-        let line = line.trim();
-        if line.is_empty() {
-            continue;
+impl RowWriter {
+    fn each_sink(
+        &self,
+        mut f: impl FnMut(&mut dyn ResultSink) -> Result<(), String>,
+    ) -> Result<(), String> {
+        for sink in &self.sinks {
+            let mut sink = sink.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+            f(&mut *sink)?;
         }
+        Ok(())
+    }
 
-        extract_numbers_from_line(line, results, metrics);
+    fn write(&self, result: &ExperimentResult) -> Result<(), String> {
+        let artifacts = result.artifacts.join(ARTIFACT_SEPARATOR);
+        let duration = result
+            .duration
+            .map(|secs| format!("{:.3}", secs))
+            .unwrap_or_default();
+        let usage = usage_values(result.usage);
+        let values: Vec<String> = self
+            .columns
+            .iter()
+            .map(|column| {
+                let val = match column {
+                    Column::Name => result.name.as_str(),
+                    Column::Tag(key) => result.tags.get(key).map(|s| s.as_str()).unwrap_or(""),
+                    Column::Param(name) => {
+                        result.params.get(name).map(|s| s.as_str()).unwrap_or("")
+                    }
+                    Column::Metric(stage, metric) => result.metric_value(*stage, metric),
+                    Column::Status => result.status.as_str(),
+                    Column::ExitCode => result.exit_code.as_str(),
+                    Column::Duration => duration.as_str(),
+                    Column::Usage(name) => usage.get(name).map_or("", |v| v.as_str()),
+                    Column::Meta("started_at") => result.started_at.as_str(),
+                    Column::Meta("hostname") => self.hostname.as_str(),
+                    Column::Meta(_) => self.git_commit.as_str(),
+                    Column::Artifacts => artifacts.as_str(),
+                    Column::Log => result.log.as_str(),
+                    Column::Stdout(stage) => result.stages[*stage].stdout.as_str(),
+                    Column::Stderr(stage) => result.stages[*stage].stderr.as_str(),
+                };
+                val.to_string()
+            })
+            .collect();
+        self.each_sink(|sink| sink.append_row(&values))
     }
 }
 
-// Extract numbers from a line, using preceding text as labels.
-// Numbers following alphanumeric chars (e.g., "F1") are skipped to avoid false matches.
-fn extract_numbers_from_line(
-    line: &str,
-    results: &mut HashMap<String, String>,
-    metrics: &[String],
-) {
-    let mut search_start = 0;
-    let mut i = 0;
-    let chars: Vec<char> = line.chars().collect();
-
-    while i < chars.len() {
-        // A number must not be preceded by alphanumeric (to avoid parsing "F1" as "1")
-        let is_num_start = (chars[i].is_ascii_digit()
-            || (chars[i] == '.' && i + 1 < chars.len() && chars[i + 1].is_ascii_digit()))
-            && (i == 0 || !chars[i - 1].is_alphanumeric());
-
-        if is_num_start {
-            let num_start = i;
-            let mut num_end = i;
-            let mut has_dot = chars[i] == '.';
-
-            if has_dot {
-                num_end = i + 1;
-                i += 1;
-            }
-
-            // Collect digits and at most one decimal point
-            while i < chars.len() {
-                if chars[i].is_ascii_digit() {
-                    num_end = i + 1;
-                    i += 1;
-                } else if chars[i] == '.'
-                    && !has_dot
-                    && i + 1 < chars.len()
-                    && chars[i + 1].is_ascii_digit()
-                {
-                    has_dot = true;
-                    num_end = i + 1;
-                    i += 1;
-                } else {
-                    break;
-                }
-            }
+// Separates the paths in the artifacts column
+const ARTIFACT_SEPARATOR: &str = ";";
 
-            let num_str: String = chars[num_start..num_end].iter().collect();
+// Columns added by --record-usage
+const USAGE_COLUMNS: [&str; 3] = ["max_rss_kb", "user_cpu_s", "sys_cpu_s"];
 
-            if num_str.parse::<f64>().is_ok() {
-                let label: String = chars[search_start..num_start].iter().collect();
-                let label = if label.is_empty() {
-                    "value".to_string()
-                } else {
-                    label
-                };
+// Columns added by --record-meta: when the run started, and the host and git commit of
+// the working directory runexp was started on
+const META_COLUMNS: [&str; 3] = ["started_at", "hostname", "git_commit"];
 
-                if should_keep_label(&label, metrics) {
-                    results.insert(label, num_str);
-                }
-            }
+fn usage_values(usage: Option<ResourceUsage>) -> HashMap<&'static str, String> {
+    let Some(usage) = usage else {
+        return HashMap::new();
+    };
+    HashMap::from([
+        ("max_rss_kb", usage.max_rss_kb.to_string()),
+        ("user_cpu_s", format!("{:.3}", usage.user_cpu_s)),
+        ("sys_cpu_s", format!("{:.3}", usage.sys_cpu_s)),
+    ])
+}
 
-            search_start = num_end;
-        } else {
-            i += 1;
-        }
+// Set the field of a usage column read back from a results file
+fn parse_usage(mut usage: ResourceUsage, column: &str, value: &str) -> Option<ResourceUsage> {
+    match column {
+        "max_rss_kb" => usage.max_rss_kb = value.parse().ok()?,
+        "user_cpu_s" => usage.user_cpu_s = value.parse().ok()?,
+        _ => usage.sys_cpu_s = value.parse().ok()?,
     }
+    Some(usage)
 }
 
-fn should_keep_label(label: &str, metrics: &[String]) -> bool {
-    if metrics.is_empty() {
-        return true;
+// Tags are named freely, so one could repeat the name of a parameter, a metric or another
+// column, which would leave the file with two columns of that name
+fn check_tag_columns(columns: &[Column], stages: &[Stage]) -> Result<(), Error> {
+    let headers: Vec<String> = columns.iter().map(|c| c.header(stages)).collect();
+    for column in columns {
+        if let Column::Tag(key) = column
+            && headers.iter().filter(|header| *header == key).count() > 1
+        {
+            return Err(Error::usage(format!(
+                "--tag {} has the name of another column of the results",
+                key
+            )));
+        }
     }
-
-    metrics
-        .iter()
-        .any(|m| label.to_lowercase().contains(&m.to_lowercase()))
+    Ok(())
 }
 
-fn write_csv_header(
-    param_names: &[String],
-    filename: &str,
-    options: &Options,
-) -> Result<(), String> {
-    let mut file =
-        File::create(filename).map_err(|e| format!("Failed to create results file: {}", e))?;
-
-    let headers = build_csv_headers(
-        param_names,
-        &options.metrics,
-        options.preserve_output,
-        options.stdout_only,
-        options.stderr_only,
-    );
+fn csv_columns(param_names: &[String], stages: &[Stage], options: &Options) -> Vec<Column> {
+    let mut columns = Vec::new();
 
-    let header_csv = headers
-        .iter()
-        .map(|h| escape_csv_field(h))
-        .collect::<Vec<_>>()
-        .join(",");
-    writeln!(file, "{}", header_csv).map_err(|e| format!("Failed to write to file: {}", e))?;
+    if options.name.is_some() {
+        columns.push(Column::Name);
+    }
 
-    Ok(())
-}
+    columns.extend(options.tags.iter().map(|(key, _)| Column::Tag(key.clone())));
 
-fn append_result(
-    result: &ExperimentResult,
-    param_names: &[String],
-    filename: &str,
-    options: &Options,
-    metric_columns_lower: &[String],
-) -> Result<(), String> {
-    let mut file = OpenOptions::new()
-        .append(true)
-        .open(filename)
-        .map_err(|e| format!("Failed to open results file for appending: {}", e))?;
+    columns.extend(param_names.iter().cloned().map(Column::Param));
 
-    let mut values: Vec<String> = Vec::new();
+    for stage in 0..stages.len() {
+        for metric in &options.metrics {
+            columns.push(Column::Metric(stage, metric.clone()));
+        }
+    }
 
-    // Add parameter values
-    for name in param_names {
-        let val = result.params.get(name).map(|s| s.as_str()).unwrap_or("");
-        values.push(escape_csv_field(val));
+    if options.record_status {
+        columns.push(Column::Status);
+        columns.push(Column::ExitCode);
     }
 
-    // Add metric values (find matching metric for each metric name)
-    for metric_lower in metric_columns_lower {
-        let val = result
-            .metrics
-            .iter()
-            .find(|(label, _)| label.to_lowercase().contains(metric_lower))
-            .map(|(_, v)| v.as_str())
-            .unwrap_or("");
-        values.push(escape_csv_field(val));
+    if options.record_time {
+        columns.push(Column::Duration);
     }
 
-    // Add stdout/stderr only if preserve_output is enabled
-    if options.preserve_output {
-        if options.stdout_only {
-            values.push(escape_csv_field(&result.stdout));
-        } else if options.stderr_only {
-            values.push(escape_csv_field(&result.stderr));
-        } else {
-            values.push(escape_csv_field(&result.stdout));
-            values.push(escape_csv_field(&result.stderr));
-        }
+    if options.record_usage {
+        columns.extend(USAGE_COLUMNS.iter().map(|name| Column::Usage(name)));
     }
 
-    writeln!(file, "{}", values.join(","))
-        .map_err(|e| format!("Failed to write to file: {}", e))?;
+    if options.record_meta {
+        columns.extend(META_COLUMNS.iter().map(|name| Column::Meta(name)));
+    }
 
-    Ok(())
-}
+    if !options.collect.is_empty() {
+        columns.push(Column::Artifacts);
+    }
 
-// Escape CSV field according to RFC 4180
-fn escape_csv_field(field: &str) -> String {
-    // If field contains comma, quote, or newline, it needs to be quoted
-    if field.contains(',') || field.contains('"') || field.contains('\n') || field.contains('\r') {
-        // Escape quotes by doubling them
-        let escaped = field.replace('"', "\"\"");
-        format!("\"{}\"", escaped)
-    } else {
-        field.to_string()
+    if options.log_dir.is_some() {
+        columns.push(Column::Log);
     }
-}
 
-fn build_csv_headers(
-    param_names: &[String],
-    metrics: &[String],
-    preserve_output: bool,
-    stdout_only: bool,
-    stderr_only: bool,
-) -> Vec<String> {
-    let mut headers = param_names.to_vec();
-    headers.extend_from_slice(metrics);
-
-    if preserve_output {
-        if stdout_only {
-            headers.push("stdout".to_string());
-        } else if stderr_only {
-            headers.push("stderr".to_string());
-        } else {
-            headers.push("stdout".to_string());
-            headers.push("stderr".to_string());
+    if options.preserve_output {
+        for stage in 0..stages.len() {
+            if options.stdout_only {
+                columns.push(Column::Stdout(stage));
+            } else if options.stderr_only {
+                columns.push(Column::Stderr(stage));
+            } else {
+                columns.push(Column::Stdout(stage));
+                columns.push(Column::Stderr(stage));
+            }
         }
     }
 
-    headers
+    columns
 }
 
 fn load_existing_results(
     filename: &str,
     expected_params: &[String],
-    expected_metrics: &[String],
-    preserve_output: bool,
-    stdout_only: bool,
-    stderr_only: bool,
+    stages: &[Stage],
+    options: &Options,
 ) -> Result<Vec<ExperimentResult>, String> {
-    let contents =
-        fs::read_to_string(filename).map_err(|_| format!("Could not read file: {}", filename))?;
-
-    let records = parse_csv(&contents)?;
+    let records = read_csv(filename)?;
 
     if records.is_empty() {
         return Err("Empty results file".to_string());
@@ -655,14 +2274,9 @@ fn load_existing_results(
 
     let column_names = &records[0];
 
-    // Build expected header using the shared helper function
-    let expected_headers = build_csv_headers(
-        expected_params,
-        expected_metrics,
-        preserve_output,
-        stdout_only,
-        stderr_only,
-    );
+    // Build expected header from the same column layout used for writing
+    let columns = csv_columns(expected_params, stages, options);
+    let expected_headers: Vec<String> = columns.iter().map(|c| c.header(stages)).collect();
 
     // Compare headers
     if column_names != &expected_headers {
@@ -674,185 +2288,144 @@ fn load_existing_results(
         ));
     }
 
-    let num_params = expected_params.len();
-    let num_metrics = expected_metrics.len();
-    let data_columns_end = num_params + num_metrics;
-
     // Parse the results
     let mut results = Vec::new();
 
     for values in &records[1..] {
-        if values.len() != column_names.len() {
+        if values.len() != columns.len() {
             continue;
         }
 
-        let mut params = HashMap::new();
-        let mut metrics = HashMap::new();
-        let mut stdout = String::new();
-        let mut stderr = String::new();
-
-        for (idx, (name, value)) in column_names.iter().zip(values.iter()).enumerate() {
-            if name == "stdout" {
-                stdout = value.clone();
-            } else if name == "stderr" {
-                stderr = value.clone();
-            } else if idx < num_params {
-                // It's a parameter
-                params.insert(name.to_string(), value.to_string());
-            } else if idx < data_columns_end {
-                // It's a metric - store with metric name as key
-                metrics.insert(name.to_string(), value.to_string());
+        let mut result = ExperimentResult {
+            name: String::new(),
+            tags: HashMap::new(),
+            params: HashMap::new(),
+            stages: vec![StageResult::default(); stages.len()],
+            artifacts: Vec::new(),
+            log: String::new(),
+            status: "ok".to_string(),
+            exit_code: String::new(),
+            duration: None,
+            usage: None,
+            started_at: String::new(),
+        };
+
+        for (column, value) in columns.iter().zip(values.iter()) {
+            match column {
+                Column::Name => result.name = value.clone(),
+                Column::Tag(key) => {
+                    result.tags.insert(key.clone(), value.clone());
+                }
+                Column::Param(name) => {
+                    result.params.insert(name.clone(), value.clone());
+                }
+                // Metrics are stored with the metric name as key
+                Column::Metric(stage, name) => {
+                    result.stages[*stage]
+                        .metrics
+                        .insert(name.clone(), value.clone());
+                }
+                Column::Status => result.status = value.clone(),
+                Column::ExitCode => result.exit_code = value.clone(),
+                Column::Duration => result.duration = value.parse().ok(),
+                Column::Usage(name) => {
+                    if let Some(usage) = parse_usage(result.usage.unwrap_or_default(), name, value)
+                    {
+                        result.usage = Some(usage);
+                    }
+                }
+                Column::Artifacts => {
+                    result.artifacts = value
+                        .split(ARTIFACT_SEPARATOR)
+                        .filter(|path| !path.is_empty())
+                        .map(String::from)
+                        .collect();
+                }
+                Column::Meta("started_at") => result.started_at = value.clone(),
+                // Shared by the rows of an invocation; only new rows are written
+                Column::Meta(_) => {}
+                Column::Log => result.log = value.clone(),
+                Column::Stdout(stage) => result.stages[*stage].stdout = value.clone(),
+                Column::Stderr(stage) => result.stages[*stage].stderr = value.clone(),
             }
         }
 
-        results.push(ExperimentResult {
-            params,
-            metrics,
-            stdout,
-            stderr,
-        });
+        results.push(result);
     }
 
     Ok(results)
 }
 
-// Parse entire CSV content handling multi-line fields
-fn parse_csv(content: &str) -> Result<Vec<Vec<String>>, String> {
-    let mut records = Vec::new();
-    let mut current_record = Vec::new();
-    let mut current_field = String::new();
-    let mut in_quotes = false;
-    let mut chars = content.chars().peekable();
-
-    while let Some(c) = chars.next() {
-        if in_quotes {
-            if c == '"' {
-                // Check if it's an escaped quote (doubled)
-                if chars.peek() == Some(&'"') {
-                    current_field.push('"');
-                    chars.next();
-                } else {
-                    in_quotes = false;
+// Identifies a row for resuming: sweep name, tag values and parameter values, sorted by key
+type ResultKey = (String, Vec<(String, String)>, Vec<(String, String)>);
+
+fn result_key<'a>(
+    name: &str,
+    tags: impl Iterator<Item = (&'a String, &'a String)>,
+    params: &HashMap<String, String>,
+) -> ResultKey {
+    let sorted = |pairs: Vec<(&String, &String)>| {
+        let mut pairs: Vec<(String, String)> = pairs
+            .into_iter()
+            .map(|(k, v)| (k.clone(), v.clone()))
+            .collect();
+        pairs.sort();
+        pairs
+    };
+    (
+        name.to_string(),
+        sorted(tags.collect()),
+        sorted(params.iter().collect()),
+    )
+}
+
+// Results loaded from an existing results file, indexed so that checking whether a
+// combination is done takes constant time even for very large files
+#[derive(Default)]
+struct ExistingResults {
+    results: Vec<ExperimentResult>,
+    index: HashMap<ResultKey, usize>,
+}
+
+impl ExistingResults {
+    fn new(results: Vec<ExperimentResult>, options: &Options) -> Self {
+        let mut index = HashMap::with_capacity(results.len());
+        for (i, r) in results.iter().enumerate() {
+            // The first of duplicate rows wins, except that a recorded failure or a row
+            // with empty metrics is replaced by a later attempt (--rerun-failed)
+            match index.entry(result_key(&r.name, r.tags.iter(), &r.params)) {
+                Entry::Vacant(entry) => {
+                    entry.insert(i);
+                }
+                Entry::Occupied(mut entry) => {
+                    if !results[*entry.get()].is_complete(options) {
+                        entry.insert(i);
+                    }
                 }
-            } else {
-                current_field.push(c);
-            }
-        } else if c == '"' {
-            in_quotes = true;
-        } else if c == ',' {
-            current_record.push(std::mem::take(&mut current_field));
-        } else if c == '\n' {
-            current_record.push(std::mem::take(&mut current_field));
-            if current_record.iter().any(|s| !s.is_empty()) {
-                records.push(std::mem::take(&mut current_record));
-            } else {
-                current_record.clear();
             }
-        } else if c != '\r' {
-            current_field.push(c);
         }
+        ExistingResults { results, index }
     }
 
-    // Handle last record (file may not end with newline)
-    if !current_field.is_empty() || !current_record.is_empty() {
-        current_record.push(current_field);
-        if current_record.iter().any(|s| !s.is_empty()) {
-            records.push(current_record);
-        }
+    // A combination counts as done when a row has the same parameters, sweep name and tag values
+    fn find(&self, combo: &Combination, options: &Options) -> Option<&ExperimentResult> {
+        let name = options.name.as_deref().unwrap_or("");
+        let tags = options.tags.iter().map(|(k, v)| (k, v));
+        let key = result_key(name, tags, &combo.params);
+        self.index.get(&key).map(|&i| &self.results[i])
     }
-
-    Ok(records)
-}
-
-fn result_exists(existing: &[ExperimentResult], combo: &Combination) -> bool {
-    existing.iter().any(|r| r.params == combo.params)
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use std::fs::File;
 
-    #[test]
-    fn test_parse_output_formats() {
-        let metrics: Vec<String> = vec![];
-        let mut results = HashMap::new();
-
-        // Basic colon-space format
-        parse_output("accuracy: 0.95", &mut results, &metrics);
-        assert_eq!(results.get("accuracy: "), Some(&"0.95".to_string()));
-
-        // No space after colon
-        parse_output("time:2.3ms", &mut results, &metrics);
-        assert_eq!(results.get("time:"), Some(&"2.3".to_string()));
-
-        // With units
-        parse_output("latency: 4.5us", &mut results, &metrics);
-        assert_eq!(results.get("latency: "), Some(&"4.5".to_string()));
-
-        // Equals sign
-        parse_output("result=42", &mut results, &metrics);
-        assert_eq!(results.get("result="), Some(&"42".to_string()));
-
-        // Space-separated
-        parse_output("count(items) 99", &mut results, &metrics);
-        assert_eq!(results.get("count(items) "), Some(&"99".to_string()));
-    }
-
-    #[test]
-    fn test_parse_output_special_cases() {
-        let metrics: Vec<String> = vec![];
-
-        // Multiple appearances - keep last value (carriage return case)
-        let mut results = HashMap::new();
-        parse_output(
-            "progress: 10\rprogress: 50\rprogress: 100",
-            &mut results,
-            &metrics,
-        );
-        assert_eq!(results.get("progress: "), Some(&"100".to_string()));
-
-        // Multiple values with same label - keep last (newline case)
-        let mut results = HashMap::new();
-        parse_output("score: 10\nscore: 20\nscore: 30", &mut results, &metrics);
-        assert_eq!(results.get("score: "), Some(&"30".to_string()));
-
-        // Complex line with multiple numbers
-        let mut results = HashMap::new();
-        parse_output(
-            "simulated 73us in 2.8s, 6000 events resolved",
-            &mut results,
-            &metrics,
-        );
-        assert_eq!(results.get("simulated "), Some(&"73".to_string()));
-        assert_eq!(results.get("us in "), Some(&"2.8".to_string()));
-        assert_eq!(results.get("s, "), Some(&"6000".to_string()));
-    }
-
-    #[test]
-    fn test_parse_output_labels_preserved() {
-        let mut results = HashMap::new();
-        let metrics: Vec<String> = vec![];
-
-        parse_output(
-            "Test-Accuracy: 0.95\ntrain_loss: 1.234\nF1-Score (macro): 0.88",
-            &mut results,
-            &metrics,
-        );
-
-        assert_eq!(results.get("Test-Accuracy: "), Some(&"0.95".to_string()));
-        assert_eq!(results.get("train_loss: "), Some(&"1.234".to_string()));
-        assert_eq!(results.get("F1-Score (macro): "), Some(&"0.88".to_string()));
-    }
-
-    #[test]
-    fn test_parse_output_metric_filtering() {
-        let mut results = HashMap::new();
-        let metrics = vec!["accuracy".to_string()];
-
-        parse_output("accuracy: 0.95\nloss: 1.234", &mut results, &metrics);
-
-        assert_eq!(results.get("accuracy: "), Some(&"0.95".to_string()));
-        assert_eq!(results.get("loss: "), None);
+    fn single_stage() -> Vec<Stage> {
+        vec![Stage {
+            name: String::new(),
+            command: vec!["true".to_string()],
+        }]
     }
 
     #[test]
@@ -871,13 +2444,16 @@ mod tests {
         let expected_params = vec!["BATCHSIZE".to_string(), "GPU".to_string()];
         let expected_metrics = vec!["accuracy".to_string()];
 
+        let options = Options {
+            metrics: expected_metrics,
+            preserve_output: true,
+            ..Options::default()
+        };
         let result = load_existing_results(
             temp_path.to_str().unwrap(),
             &expected_params,
-            &expected_metrics,
-            true,  // preserve_output
-            false, // stdout_only
-            false, // stderr_only
+            &single_stage(),
+            &options,
         );
 
         // Clean up
@@ -907,13 +2483,16 @@ mod tests {
         let expected_params = vec!["BATCHSIZE".to_string(), "GPU".to_string(), "LR".to_string()];
         let expected_metrics: Vec<String> = vec![];
 
+        let options = Options {
+            metrics: expected_metrics,
+            preserve_output: true,
+            ..Options::default()
+        };
         let result = load_existing_results(
             temp_path.to_str().unwrap(),
             &expected_params,
-            &expected_metrics,
-            true,  // preserve_output
-            false, // stdout_only
-            false, // stderr_only
+            &single_stage(),
+            &options,
         );
 
         // Clean up
@@ -940,13 +2519,16 @@ mod tests {
         // Expect different metrics
         let expected_metrics = vec!["loss".to_string()];
 
+        let options = Options {
+            metrics: expected_metrics,
+            preserve_output: true,
+            ..Options::default()
+        };
         let result = load_existing_results(
             temp_path.to_str().unwrap(),
             &expected_params,
-            &expected_metrics,
-            true,  // preserve_output
-            false, // stdout_only
-            false, // stderr_only
+            &single_stage(),
+            &options,
         );
 
         // Clean up
@@ -973,13 +2555,16 @@ mod tests {
         let expected_metrics = vec!["accuracy".to_string()];
 
         // Try to load WITHOUT preserve_output (should fail)
+        let options = Options {
+            metrics: expected_metrics,
+            preserve_output: false,
+            ..Options::default()
+        };
         let result = load_existing_results(
             temp_path.to_str().unwrap(),
             &expected_params,
-            &expected_metrics,
-            false, // preserve_output = false but file has output columns
-            false, // stdout_only
-            false, // stderr_only
+            &single_stage(),
+            &options,
         );
 
         // Clean up
@@ -1006,13 +2591,16 @@ mod tests {
         let expected_metrics = vec!["accuracy".to_string()];
 
         // Load WITHOUT preserve_output (should succeed)
+        let options = Options {
+            metrics: expected_metrics,
+            preserve_output: false,
+            ..Options::default()
+        };
         let result = load_existing_results(
             temp_path.to_str().unwrap(),
             &expected_params,
-            &expected_metrics,
-            false, // preserve_output = false and file has no output columns
-            false, // stdout_only
-            false, // stderr_only
+            &single_stage(),
+            &options,
         );
 
         // Clean up
@@ -1024,8 +2612,298 @@ mod tests {
         assert_eq!(results[0].params.get("BATCHSIZE"), Some(&"32".to_string()));
         assert_eq!(results[0].params.get("GPU"), Some(&"1".to_string()));
         assert_eq!(
-            results[0].metrics.get("accuracy"),
+            results[0].stages[0].metrics.get("accuracy"),
             Some(&"0.95".to_string())
         );
     }
+
+    #[test]
+    fn test_load_existing_results_with_name_column() {
+        use std::io::Write;
+
+        let temp_dir = std::env::temp_dir();
+        let temp_path = temp_dir.join("test_runexp_name_column.csv");
+        {
+            let mut file = File::create(&temp_path).unwrap();
+            writeln!(file, "name,GPU,accuracy").unwrap();
+            writeln!(file, "ablation-v2,1,0.95").unwrap();
+        }
+
+        let expected_params = vec!["GPU".to_string()];
+        let options = Options {
+            metrics: vec!["accuracy".to_string()],
+            name: Some("ablation-v2".to_string()),
+            ..Options::default()
+        };
+        let result = load_existing_results(
+            temp_path.to_str().unwrap(),
+            &expected_params,
+            &single_stage(),
+            &options,
+        );
+
+        // Without --name the header has no name column and must not match
+        let unnamed = Options {
+            name: None,
+            ..options.clone()
+        };
+        let mismatch = load_existing_results(
+            temp_path.to_str().unwrap(),
+            &expected_params,
+            &single_stage(),
+            &unnamed,
+        );
+
+        // Clean up
+        let _ = fs::remove_file(&temp_path);
+
+        let results = result.unwrap();
+        assert_eq!(results[0].name, "ablation-v2");
+        assert_eq!(results[0].params.get("GPU"), Some(&"1".to_string()));
+        assert!(mismatch.unwrap_err().contains("Header mismatch"));
+    }
+
+    #[test]
+    fn test_stage_columns_prefixed() {
+        let stages = vec![
+            Stage {
+                name: "train".to_string(),
+                command: vec!["true".to_string()],
+            },
+            Stage {
+                name: "eval".to_string(),
+                command: vec!["true".to_string()],
+            },
+        ];
+        let options = Options {
+            metrics: vec!["loss".to_string(), "accuracy".to_string()],
+            preserve_output: true,
+            stdout_only: true,
+            ..Options::default()
+        };
+
+        let headers: Vec<String> = csv_columns(&["LR".to_string()], &stages, &options)
+            .iter()
+            .map(|c| c.header(&stages))
+            .collect();
+
+        assert_eq!(
+            headers,
+            vec![
+                "LR",
+                "train.loss",
+                "train.accuracy",
+                "eval.loss",
+                "eval.accuracy",
+                "train.stdout",
+                "eval.stdout"
+            ]
+        );
+    }
+
+    #[test]
+    fn test_existing_results_match_tags() {
+        let combo = Combination {
+            params: HashMap::from([("GPU".to_string(), "1".to_string())]),
+            param_order: vec!["GPU".to_string()],
+            index: 0,
+        };
+        let options = Options {
+            tags: vec![("codever".to_string(), "abc123".to_string())],
+            ..Options::default()
+        };
+        let existing = ExistingResults::new(
+            vec![ExperimentResult::new(
+                &combo,
+                &options,
+                Vec::new(),
+                Vec::new(),
+            )],
+            &options,
+        );
+
+        assert!(existing.find(&combo, &options).is_some());
+
+        // Same parameters produced by another code version must run again
+        let newer = Options {
+            tags: vec![("codever".to_string(), "def456".to_string())],
+            ..Options::default()
+        };
+        assert!(existing.find(&combo, &newer).is_none());
+
+        // Other parameter values are not done either
+        let other = Combination {
+            params: HashMap::from([("GPU".to_string(), "2".to_string())]),
+            param_order: vec!["GPU".to_string()],
+            index: 0,
+        };
+        assert!(existing.find(&other, &options).is_none());
+    }
+
+    #[test]
+    fn test_tag_column_clashes() {
+        let stages = vec![Stage {
+            name: String::new(),
+            command: vec!["true".to_string()],
+        }];
+        let params = ["GPU".to_string()];
+        let tagged = |key: &str| Options {
+            metrics: vec!["loss".to_string()],
+            tags: vec![(key.to_string(), "x".to_string())],
+            ..Options::default()
+        };
+        for key in ["GPU", "loss"] {
+            let columns = csv_columns(&params, &stages, &tagged(key));
+            assert!(check_tag_columns(&columns, &stages).is_err());
+        }
+        let columns = csv_columns(&params, &stages, &tagged("codever"));
+        assert!(check_tag_columns(&columns, &stages).is_ok());
+    }
+
+    #[test]
+    fn test_describe_header_diff() {
+        let found: Vec<String> = ["GPU", "BATCHSIZE", "loss"].map(String::from).to_vec();
+        let expected: Vec<String> = ["BATCHSIZE", "GPU", "accuracy"].map(String::from).to_vec();
+
+        let diff = describe_header_diff(&found, &expected);
+        assert!(diff.contains("added:     accuracy"));
+        assert!(diff.contains("removed:   loss"));
+        assert!(diff.contains("reordered: GPU,BATCHSIZE -> BATCHSIZE,GPU"));
+    }
+
+    #[test]
+    fn test_migrate_results_file() {
+        use std::io::Write;
+
+        let temp_path = std::env::temp_dir().join("test_runexp_migrate.csv");
+        {
+            let mut file = File::create(&temp_path).unwrap();
+            writeln!(file, "GPU,loss,stdout").unwrap();
+            writeln!(file, "1,0.5,\"a,b\"").unwrap();
+        }
+
+        let filename = temp_path.to_str().unwrap();
+        let new_headers: Vec<String> = ["stdout", "GPU", "accuracy"].map(String::from).to_vec();
+        let backup = migrate_results_file(filename, &new_headers).unwrap();
+        let migrated = fs::read_to_string(&temp_path).unwrap();
+
+        // Clean up
+        let _ = fs::remove_file(&temp_path);
+        let _ = fs::remove_file(&backup);
+
+        assert_eq!(migrated, "stdout,GPU,accuracy\n\"a,b\",1,\n");
+    }
+
+    #[test]
+    fn test_display_command() {
+        let stage = Stage {
+            name: String::new(),
+            command: ["bash", "-c", "echo $LR", "train.sh", "--epochs", "a b"]
+                .map(String::from)
+                .to_vec(),
+        };
+        let mut options = Options::default();
+        assert_eq!(
+            display_command(&stage.command, &options),
+            "bash -c 'echo $LR' train.sh --epochs 'a b'"
+        );
+        // A -f script is shown by its path
+        options.script = Some(crate::parser::ScriptFile {
+            path: "train.sh".to_string(),
+            sha256: String::new(),
+        });
+        assert_eq!(
+            display_command(&stage.command, &options),
+            "bash train.sh --epochs 'a b'"
+        );
+    }
+
+    #[test]
+    fn test_run_id() {
+        let combo = |pairs: &[(&str, &str)]| Combination {
+            params: pairs
+                .iter()
+                .map(|(k, v)| (k.to_string(), v.to_string()))
+                .collect(),
+            param_order: pairs.iter().map(|(k, _)| k.to_string()).collect(),
+            index: 0,
+        };
+        let id = run_id(&combo(&[("LR", "0.1"), ("GPU", "1")]));
+        assert_eq!(id.len(), 12);
+        assert_eq!(id, run_id(&combo(&[("GPU", "1"), ("LR", "0.1")])));
+        assert_ne!(id, run_id(&combo(&[("LR", "0.1"), ("GPU", "2")])));
+        // Values are not confused across parameters
+        assert_ne!(
+            run_id(&combo(&[("A", "1"), ("B", "")])),
+            run_id(&combo(&[("A", "1\nB=")]))
+        );
+    }
+
+    #[test]
+    fn test_flag_params() {
+        let combo = |pairs: &[(&str, &str)]| Combination {
+            params: pairs
+                .iter()
+                .map(|(k, v)| (k.to_string(), v.to_string()))
+                .collect(),
+            param_order: pairs.iter().map(|(k, _)| k.to_string()).collect(),
+            index: 0,
+        };
+        let stage = Stage {
+            name: String::new(),
+            command: ["python", "train.py", "{amp}", "data"]
+                .map(String::from)
+                .to_vec(),
+        };
+        let options = Options {
+            flags: vec![
+                ("AMP".to_string(), "--use-amp".to_string()),
+                ("V".to_string(), "-v".to_string()),
+            ],
+            ..Options::default()
+        };
+        let on = combo(&[("AMP", "on"), ("V", "on")]);
+        assert_eq!(
+            stage_command(&stage, &on, &options),
+            ["python", "train.py", "--use-amp", "data", "-v"]
+        );
+        let off = combo(&[("AMP", "off"), ("V", "off")]);
+        assert_eq!(
+            stage_command(&stage, &off, &options),
+            ["python", "train.py", "data"]
+        );
+    }
+
+    #[test]
+    fn test_substitute_params() {
+        let combo = Combination {
+            params: HashMap::from([
+                ("LR".to_string(), "0.1".to_string()),
+                ("BATCH_SIZE".to_string(), "32".to_string()),
+            ]),
+            param_order: vec!["LR".to_string(), "BATCH_SIZE".to_string()],
+            index: 7,
+        };
+        assert_eq!(
+            substitute_params("--learning-rate={lr}", &combo),
+            "--learning-rate=0.1"
+        );
+        assert_eq!(
+            substitute_params("run_{LR}_{batch_size}_{idx}.log", &combo),
+            "run_0.1_32_7.log"
+        );
+        assert_eq!(
+            substitute_params("{today}/{env(PATH)}", &combo),
+            format!(
+                "{}/{}",
+                builtin("today").unwrap(),
+                std::env::var("PATH").unwrap()
+            )
+        );
+        // Unknown names, shell expansions and unclosed braces stay
+        assert_eq!(
+            substitute_params("{} {x} ${LR} {a,b} {lr", &combo),
+            "{} {x} ${LR} {a,b} {lr"
+        );
+    }
 }
@@ -0,0 +1,165 @@
+// C ABI used by the Python bindings (python/runexp.py, loaded with ctypes). A sweep is
+// described by the same arguments as the command line; tables are returned as CSV text
+// so they can be read with Python's csv module.
+
+use crate::csv::escape_csv_field;
+use crate::{Error, Sweep, parse_args};
+use std::ffi::{CStr, CString, c_char, c_int};
+
+fn sweep_from_args(argc: c_int, argv: *const *const c_char) -> Result<Sweep, Error> {
+    let args = (0..argc.max(0) as usize)
+        .map(|i| {
+            // SAFETY: the caller passes argc valid NUL-terminated strings
+            let arg = unsafe { CStr::from_ptr(*argv.add(i)) };
+            arg.to_str()
+                .map(|s| s.to_string())
+                .map_err(|_| Error::usage("Arguments must be valid UTF-8"))
+        })
+        .collect::<Result<Vec<_>, _>>()?;
+    let (params, stages, options) = parse_args(&args)?;
+    if params.is_empty() {
+        return Err(Error::usage("No parameters specified"));
+    }
+    Ok(Sweep::from_parts(params, stages, options))
+}
+
+fn csv_table(header: &[String], rows: &[Vec<String>]) -> String {
+    let line = |values: &[String]| {
+        values
+            .iter()
+            .map(|v| escape_csv_field(v))
+            .collect::<Vec<_>>()
+            .join(",")
+            + "\n"
+    };
+    let mut table = line(header);
+    for row in rows {
+        table += &line(row);
+    }
+    table
+}
+
+// Store the result (or the error message) in *out and return 0 on success, 1 on error
+fn respond(result: Result<String, Error>, out: *mut *mut c_char) -> c_int {
+    let (code, text) = match result {
+        Ok(text) => (0, text),
+        Err(e) => (1, e.to_string()),
+    };
+    let text = CString::new(text.replace('\0', "")).unwrap_or_default();
+    // SAFETY: the caller passes a valid pointer to write the result to
+    unsafe { *out = text.into_raw() };
+    code
+}
+
+/// Write the combinations of a sweep as CSV to `*out`; returns 0 on success, or 1 with
+/// an error message in `*out`. Free the string with `runexp_free`.
+///
+/// # Safety
+/// `argv` must point to `argc` NUL-terminated strings and `out` must be writable.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn runexp_combinations(
+    argc: c_int,
+    argv: *const *const c_char,
+    out: *mut *mut c_char,
+) -> c_int {
+    let result = sweep_from_args(argc, argv).and_then(|sweep| {
+        let header: Vec<String> = sweep.param_names().iter().map(|s| s.to_string()).collect();
+        let rows: Vec<Vec<String>> = sweep
+            .combinations()?
+            .into_iter()
+            .map(|combo| combo.into_iter().map(|(_, value)| value).collect())
+            .collect();
+        Ok(csv_table(&header, &rows))
+    });
+    respond(result, out)
+}
+
+/// Run a sweep and write its results as CSV to `*out`: the parameters, the metric
+/// columns and a `resumed` column. Returns like `runexp_combinations`.
+///
+/// # Safety
+/// `argv` must point to `argc` NUL-terminated strings and `out` must be writable.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn runexp_run(
+    argc: c_int,
+    argv: *const *const c_char,
+    out: *mut *mut c_char,
+) -> c_int {
+    let result = sweep_from_args(argc, argv).and_then(|sweep| {
+        let results = sweep.run()?;
+        let params: Vec<String> = sweep.param_names().iter().map(|s| s.to_string()).collect();
+        let mut metrics: Vec<String> = results
+            .runs
+            .first()
+            .map(|run| run.metrics.keys().cloned().collect())
+            .unwrap_or_default();
+        metrics.sort();
+
+        let header: Vec<String> = params
+            .iter()
+            .chain(&metrics)
+            .cloned()
+            .chain(["resumed".to_string()])
+            .collect();
+        let rows: Vec<Vec<String>> = results
+            .runs
+            .iter()
+            .map(|run| {
+                params
+                    .iter()
+                    .map(|p| run.params.get(p).cloned().unwrap_or_default())
+                    .chain(metrics.iter().map(|m| run.metrics[m].clone()))
+                    .chain([run.resumed.to_string()])
+                    .collect()
+            })
+            .collect();
+        Ok(csv_table(&header, &rows))
+    });
+    respond(result, out)
+}
+
+/// Free a string returned by this library.
+///
+/// # Safety
+/// `s` must be a string returned through `out` by this library, or null.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn runexp_free(s: *mut c_char) {
+    if !s.is_null() {
+        // SAFETY: the string was created by CString::into_raw in respond
+        drop(unsafe { CString::from_raw(s) });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn call(
+        f: unsafe extern "C" fn(c_int, *const *const c_char, *mut *mut c_char) -> c_int,
+        args: &[&str],
+    ) -> (c_int, String) {
+        let args: Vec<CString> = args.iter().map(|a| CString::new(*a).unwrap()).collect();
+        let argv: Vec<*const c_char> = args.iter().map(|a| a.as_ptr()).collect();
+        let mut out = std::ptr::null_mut();
+        unsafe {
+            let code = f(argv.len() as c_int, argv.as_ptr(), &mut out);
+            let text = CStr::from_ptr(out).to_string_lossy().to_string();
+            runexp_free(out);
+            (code, text)
+        }
+    }
+
+    #[test]
+    fn test_ffi_combinations() {
+        let (code, table) = call(
+            runexp_combinations,
+            &["--a", "1,2", "--b", "A*10", "--skip", "A=2", "true"],
+        );
+        assert_eq!(code, 0);
+        assert_eq!(table, "A,B\n1,10\n");
+
+        let (code, message) = call(runexp_combinations, &["--a", "1:0:0", "true"]);
+        assert_eq!(code, 1);
+        assert!(message.contains("step"));
+    }
+}
@@ -0,0 +1,79 @@
+use std::fs;
+use std::path::{Path, PathBuf};
+
+// Minimal glob matching supporting `*` (any run of characters) and `?` (any single character).
+pub fn glob_match(pattern: &str, text: &str) -> bool {
+    let pattern: Vec<char> = pattern.chars().collect();
+    let text: Vec<char> = text.chars().collect();
+
+    let (mut p, mut t) = (0, 0);
+    // Position of the last `*` in the pattern and the text position it was tried at
+    let mut backtrack: Option<(usize, usize)> = None;
+
+    while t < text.len() {
+        if p < pattern.len() && (pattern[p] == '?' || pattern[p] == text[t]) {
+            p += 1;
+            t += 1;
+        } else if p < pattern.len() && pattern[p] == '*' {
+            backtrack = Some((p, t));
+            p += 1;
+        } else if let Some((star_p, star_t)) = backtrack {
+            // Let the last `*` absorb one more character and retry
+            p = star_p + 1;
+            t = star_t + 1;
+            backtrack = Some((star_p, star_t + 1));
+        } else {
+            return false;
+        }
+    }
+
+    pattern[p..].iter().all(|&c| c == '*')
+}
+
+// Paths under `base` matching a relative pattern such as "plots/*.png", returned relative
+// to `base` in sorted order. Wildcards match within one path component, and names
+// starting with a dot only match a component that starts with a dot too.
+pub fn glob_paths(base: &Path, pattern: &str) -> Vec<PathBuf> {
+    let mut paths = vec![PathBuf::new()];
+    for component in pattern.split('/').filter(|c| !c.is_empty() && *c != ".") {
+        let mut next = Vec::new();
+        for path in &paths {
+            if !component.contains(['*', '?']) {
+                next.push(path.join(component));
+                continue;
+            }
+            let Ok(entries) = fs::read_dir(base.join(path)) else {
+                continue;
+            };
+            let mut names: Vec<String> = entries
+                .filter_map(|entry| entry.ok())
+                .map(|entry| entry.file_name().to_string_lossy().to_string())
+                .filter(|name| !name.starts_with('.') || component.starts_with('.'))
+                .filter(|name| glob_match(component, name))
+                .collect();
+            names.sort();
+            next.extend(names.into_iter().map(|name| path.join(name)));
+        }
+        paths = next;
+    }
+    paths.retain(|path| !path.as_os_str().is_empty() && base.join(path).exists());
+    paths
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_glob_match() {
+        assert!(glob_match("resnet*", "resnet50"));
+        assert!(glob_match("resnet*", "resnet"));
+        assert!(!glob_match("resnet*", "vgg16"));
+        assert!(glob_match("*net?0", "resnet50"));
+        assert!(glob_match("4", "4"));
+        assert!(!glob_match("4", "40"));
+        assert!(glob_match("*", ""));
+        assert!(glob_match("a*b*c", "aXXbYYc"));
+        assert!(!glob_match("a*b*c", "aXXbYY"));
+    }
+}
@@ -0,0 +1,305 @@
+// Gzip compression (RFC 1951/1952) for archives written by `runexp bundle`.
+// Implemented here to keep the zero-dependency design: LZ77 matching over hash chains,
+// encoded with the fixed Huffman code. Input is compressed as it is written, so large
+// log files do not need to fit in memory.
+
+use std::io::{self, Write};
+
+const WINDOW: usize = 32768;
+const MIN_MATCH: usize = 3;
+const MAX_MATCH: usize = 258;
+// Candidates examined per position; more find longer matches but take longer
+const MAX_CHAIN: usize = 64;
+// Input compressed per block
+const BLOCK: usize = 65536;
+const HASH_BITS: u32 = 15;
+
+const LENGTH_BASE: [u16; 29] = [
+    3, 4, 5, 6, 7, 8, 9, 10, 11, 13, 15, 17, 19, 23, 27, 31, 35, 43, 51, 59, 67, 83, 99, 115, 131,
+    163, 195, 227, 258,
+];
+const LENGTH_EXTRA: [u8; 29] = [
+    0, 0, 0, 0, 0, 0, 0, 0, 1, 1, 1, 1, 2, 2, 2, 2, 3, 3, 3, 3, 4, 4, 4, 4, 5, 5, 5, 5, 0,
+];
+const DIST_BASE: [u16; 30] = [
+    1, 2, 3, 4, 5, 7, 9, 13, 17, 25, 33, 49, 65, 97, 129, 193, 257, 385, 513, 769, 1025, 1537,
+    2049, 3073, 4097, 6145, 8193, 12289, 16385, 24577,
+];
+const DIST_EXTRA: [u8; 30] = [
+    0, 0, 0, 0, 1, 1, 2, 2, 3, 3, 4, 4, 5, 5, 6, 6, 7, 7, 8, 8, 9, 9, 10, 10, 11, 11, 12, 12, 13,
+    13,
+];
+
+const CRC_TABLE: [u32; 256] = {
+    let mut table = [0u32; 256];
+    let mut n = 0;
+    while n < 256 {
+        let mut c = n as u32;
+        let mut k = 0;
+        while k < 8 {
+            c = if c & 1 != 0 {
+                0xEDB88320 ^ (c >> 1)
+            } else {
+                c >> 1
+            };
+            k += 1;
+        }
+        table[n] = c;
+        n += 1;
+    }
+    table
+};
+
+fn crc32_update(crc: u32, data: &[u8]) -> u32 {
+    let mut c = !crc;
+    for &byte in data {
+        c = CRC_TABLE[((c ^ byte as u32) & 0xFF) as usize] ^ (c >> 8);
+    }
+    !c
+}
+
+// Writes bits least significant first, as deflate requires
+struct BitWriter<W: Write> {
+    out: W,
+    bits: u64,
+    count: u32,
+}
+
+impl<W: Write> BitWriter<W> {
+    fn write(&mut self, value: u32, count: u32) -> io::Result<()> {
+        self.bits |= (value as u64) << self.count;
+        self.count += count;
+        while self.count >= 8 {
+            self.out.write_all(&[self.bits as u8])?;
+            self.bits >>= 8;
+            self.count -= 8;
+        }
+        Ok(())
+    }
+
+    // Huffman codes are defined most significant bit first
+    fn write_code(&mut self, code: u32, count: u32) -> io::Result<()> {
+        self.write(code.reverse_bits() >> (32 - count), count)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        if self.count > 0 {
+            self.out.write_all(&[self.bits as u8])?;
+            self.bits = 0;
+            self.count = 0;
+        }
+        Ok(())
+    }
+}
+
+pub struct GzipWriter<W: Write> {
+    bits: BitWriter<W>,
+    // Up to WINDOW bytes already compressed, followed by input not yet compressed
+    buffer: Vec<u8>,
+    // Stream position of buffer[0]
+    base: usize,
+    // Index in buffer of the first byte not yet compressed
+    pending: usize,
+    // Most recent position + 1 of each hash, and the previous position with the same hash
+    head: Vec<usize>,
+    prev: Vec<usize>,
+    crc: u32,
+    size: u64,
+}
+
+impl<W: Write> GzipWriter<W> {
+    pub fn new(mut out: W) -> io::Result<Self> {
+        // Magic, deflate, no flags, no modification time, no extra flags, unknown OS
+        out.write_all(&[0x1f, 0x8b, 8, 0, 0, 0, 0, 0, 0, 255])?;
+        Ok(GzipWriter {
+            bits: BitWriter {
+                out,
+                bits: 0,
+                count: 0,
+            },
+            buffer: Vec::new(),
+            base: 0,
+            pending: 0,
+            head: vec![0; 1 << HASH_BITS],
+            prev: vec![0; WINDOW],
+            crc: 0,
+            size: 0,
+        })
+    }
+
+    pub fn finish(mut self) -> io::Result<W> {
+        self.compress(self.buffer.len(), true)?;
+        self.bits.flush()?;
+        let mut out = self.bits.out;
+        out.write_all(&self.crc.to_le_bytes())?;
+        out.write_all(&(self.size as u32).to_le_bytes())?;
+        out.flush()?;
+        Ok(out)
+    }
+
+    fn hash(&self, i: usize) -> usize {
+        let b = &self.buffer[i..i + MIN_MATCH];
+        let h = (b[0] as u32) << 16 | (b[1] as u32) << 8 | b[2] as u32;
+        (h.wrapping_mul(2654435761) >> (32 - HASH_BITS)) as usize
+    }
+
+    fn insert(&mut self, i: usize) {
+        if i + MIN_MATCH <= self.buffer.len() {
+            let h = self.hash(i);
+            let pos = self.base + i;
+            self.prev[pos % WINDOW] = self.head[h];
+            self.head[h] = pos + 1;
+        }
+    }
+
+    // Longest earlier match for the data at buffer[i..end] as (length, distance)
+    fn find_match(&self, i: usize, end: usize) -> (usize, usize) {
+        let max_len = MAX_MATCH.min(end - i);
+        if max_len < MIN_MATCH {
+            return (0, 0);
+        }
+        let pos = self.base + i;
+        let mut best = (0, 0);
+        let mut candidate = self.head[self.hash(i)];
+        for _ in 0..MAX_CHAIN {
+            if candidate == 0 || pos - (candidate - 1) > WINDOW || candidate - 1 < self.base {
+                break;
+            }
+            let j = candidate - 1 - self.base;
+            let len = self.buffer[j..]
+                .iter()
+                .zip(&self.buffer[i..i + max_len])
+                .take_while(|(a, b)| a == b)
+                .count();
+            if len > best.0 {
+                best = (len, i - j);
+                if len == max_len {
+                    break;
+                }
+            }
+            candidate = self.prev[(candidate - 1) % WINDOW];
+        }
+        if best.0 >= MIN_MATCH { best } else { (0, 0) }
+    }
+
+    // Compress buffer[pending..end] as one fixed Huffman block
+    fn compress(&mut self, end: usize, last: bool) -> io::Result<()> {
+        self.bits.write(last as u32, 1)?;
+        self.bits.write(1, 2)?;
+        let mut i = self.pending;
+        while i < end {
+            let (len, dist) = self.find_match(i, end);
+            if len > 0 {
+                self.write_match(len, dist)?;
+                for k in i..i + len {
+                    self.insert(k);
+                }
+                i += len;
+            } else {
+                self.write_literal(self.buffer[i] as u32)?;
+                self.insert(i);
+                i += 1;
+            }
+        }
+        self.write_literal(256)?;
+        self.pending = end;
+
+        // Keep only the window for later matches
+        if self.pending > WINDOW {
+            let drop = self.pending - WINDOW;
+            self.buffer.drain(..drop);
+            self.base += drop;
+            self.pending -= drop;
+        }
+        Ok(())
+    }
+
+    fn write_literal(&mut self, value: u32) -> io::Result<()> {
+        match value {
+            0..=143 => self.bits.write_code(0x30 + value, 8),
+            144..=255 => self.bits.write_code(0x190 + value - 144, 9),
+            256..=279 => self.bits.write_code(value - 256, 7),
+            _ => self.bits.write_code(0xC0 + value - 280, 8),
+        }
+    }
+
+    fn write_match(&mut self, len: usize, dist: usize) -> io::Result<()> {
+        let code = LENGTH_BASE
+            .iter()
+            .rposition(|&b| b as usize <= len)
+            .unwrap();
+        self.write_literal(257 + code as u32)?;
+        self.bits.write(
+            (len - LENGTH_BASE[code] as usize) as u32,
+            LENGTH_EXTRA[code] as u32,
+        )?;
+        let code = DIST_BASE.iter().rposition(|&b| b as usize <= dist).unwrap();
+        self.bits.write_code(code as u32, 5)?;
+        self.bits.write(
+            (dist - DIST_BASE[code] as usize) as u32,
+            DIST_EXTRA[code] as u32,
+        )
+    }
+}
+
+impl<W: Write> Write for GzipWriter<W> {
+    fn write(&mut self, data: &[u8]) -> io::Result<usize> {
+        self.crc = crc32_update(self.crc, data);
+        self.size += data.len() as u64;
+        self.buffer.extend_from_slice(data);
+        // Leave MAX_MATCH bytes so matches can extend into the next block
+        while self.buffer.len() - self.pending >= BLOCK + MAX_MATCH {
+            self.compress(self.pending + BLOCK, false)?;
+        }
+        Ok(data.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_crc32() {
+        assert_eq!(crc32_update(0, b"123456789"), 0xCBF43926);
+        assert_eq!(crc32_update(crc32_update(0, b"1234"), b"56789"), 0xCBF43926);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_gzip_round_trip() {
+        use std::process::{Command, Stdio};
+
+        let mut data = Vec::new();
+        for i in 0..20000 {
+            data.extend_from_slice(
+                format!("epoch {} loss: {:.4}\n", i % 100, 1.0 / (i + 1) as f64).as_bytes(),
+            );
+        }
+        data.extend((0..100000u32).map(|i| (i.wrapping_mul(2654435761) >> 24) as u8));
+
+        let mut writer = GzipWriter::new(Vec::new()).unwrap();
+        for chunk in data.chunks(7777) {
+            writer.write_all(chunk).unwrap();
+        }
+        let compressed = writer.finish().unwrap();
+        assert!(compressed.len() < data.len());
+
+        let mut gzip = Command::new("gzip")
+            .arg("-dc")
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .spawn()
+            .unwrap();
+        let mut stdin = gzip.stdin.take().unwrap();
+        let writer = std::thread::spawn(move || stdin.write_all(&compressed));
+        let output = gzip.wait_with_output().unwrap();
+        writer.join().unwrap().unwrap();
+        assert!(output.status.success());
+        assert!(output.stdout == data);
+    }
+}
@@ -0,0 +1,210 @@
+use crate::evaluator::Combination;
+use crate::executor::{Planner, SweepResults};
+use crate::parser::Options;
+use std::collections::VecDeque;
+
+// With --halving NAME=MIN:MAX, the combinations are run in rounds of increasing budget
+// (successive halving): every combination first runs with NAME=MIN, then only the best
+// 1/eta of them by --halving-metric run again with eta times the budget, and so on until
+// the budget reaches MAX. Each round's runs are rows of their own in the results file,
+// with the budget and the round number (ROUND) as parameters, so a resumed sweep ranks
+// the same runs and keeps the same survivors.
+
+pub struct Halving<'a> {
+    // The combinations of all rounds, round after round (see halving_rounds)
+    pool: &'a [Combination],
+    rounds: usize,
+    eta: usize,
+    metric: String,
+    maximize: bool,
+    // The current round (from 0), its combinations still to start (as positions within a
+    // round) and those started
+    round: usize,
+    queue: VecDeque<usize>,
+    started: Vec<usize>,
+}
+
+impl<'a> Halving<'a> {
+    pub fn new(pool: &'a [Combination], rounds: usize, options: &Options) -> Self {
+        let per_round = pool.len() / rounds.max(1);
+        Halving {
+            pool,
+            rounds,
+            eta: options.halving_eta,
+            metric: options.halving_metric.clone().unwrap_or_default(),
+            maximize: options.maximize,
+            round: 0,
+            queue: (0..per_round).collect(),
+            started: Vec::new(),
+        }
+    }
+
+    fn per_round(&self) -> usize {
+        self.pool.len() / self.rounds.max(1)
+    }
+
+    // The best 1/eta (at least one) of the runs of the current round, in grid order. Runs
+    // that failed or printed no value for the metric rank last.
+    fn survivors(&self, results: &SweepResults) -> Vec<usize> {
+        let offset = self.round * self.per_round();
+        let mut ranked: Vec<(usize, Option<f64>)> = self
+            .started
+            .iter()
+            .map(|&position| {
+                let value = results
+                    .runs
+                    .iter()
+                    .find(|run| run.index == offset + position && !run.failed)
+                    .and_then(|run| run.metric(&self.metric))
+                    .filter(|v| v.is_finite());
+                (position, value)
+            })
+            .collect();
+        ranked.sort_by(|(_, a), (_, b)| match (a, b) {
+            (Some(a), Some(b)) if self.maximize => b.total_cmp(a),
+            (Some(a), Some(b)) => a.total_cmp(b),
+            (Some(_), None) => std::cmp::Ordering::Less,
+            (None, Some(_)) => std::cmp::Ordering::Greater,
+            (None, None) => std::cmp::Ordering::Equal,
+        });
+        let keep = (ranked.len() / self.eta).max(1);
+        let mut survivors: Vec<usize> = ranked
+            .into_iter()
+            .take(keep)
+            .filter(|(_, value)| value.is_some())
+            .map(|(position, _)| position)
+            .collect();
+        survivors.sort_unstable();
+        survivors
+    }
+}
+
+// The number of runs of successive halving over `combinations` in the given number of
+// rounds, if every round has a value for all its runs
+pub fn halving_runs(combinations: usize, rounds: usize, eta: usize) -> usize {
+    let mut runs = combinations;
+    let mut total = 0;
+    for _ in 0..rounds {
+        total += runs;
+        runs = (runs / eta).max(1);
+    }
+    total
+}
+
+impl<'a> Planner<'a> for Halving<'a> {
+    fn next(
+        &mut self,
+        results: &SweepResults,
+        running: &[usize],
+    ) -> Option<(usize, &'a Combination)> {
+        if self.queue.is_empty() {
+            // The next round starts once every run of this one has finished
+            if !running.is_empty() || self.round + 1 >= self.rounds {
+                return None;
+            }
+            let survivors = self.survivors(results);
+            if survivors.is_empty() {
+                println!(
+                    "No run of round {} printed {}; not starting round {}",
+                    self.round + 1,
+                    self.metric,
+                    self.round + 2
+                );
+                self.rounds = self.round + 1;
+                return None;
+            }
+            self.round += 1;
+            let first = &self.pool[self.round * self.per_round() + survivors[0]];
+            let budget = first.param_order[first.param_order.len() - 2].clone();
+            println!(
+                "Round {}/{}: running the best {} of {} combinations by {} with {}={}",
+                self.round + 1,
+                self.rounds,
+                survivors.len(),
+                self.started.len(),
+                self.metric,
+                budget,
+                first.params[&budget]
+            );
+            self.queue = survivors.into();
+            self.started.clear();
+        }
+        let position = self.queue.pop_front()?;
+        self.started.push(position);
+        let idx = self.round * self.per_round() + position;
+        Some((idx, &self.pool[idx]))
+    }
+
+    fn remaining(&self) -> usize {
+        let this_round = self.queue.len() + self.started.len();
+        let later = self.rounds - self.round - 1;
+        self.queue.len() + halving_runs(this_round, later + 1, self.eta) - this_round
+    }
+
+    fn total(&self) -> usize {
+        self.pool.len()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::evaluator::{evaluate_params, halving_budgets, halving_rounds};
+    use crate::executor::RunResult;
+
+    #[test]
+    fn test_halving_keeps_the_best() {
+        let params = vec![("X".to_string(), "1:10".to_string())];
+        let combinations = evaluate_params(&params, &[], None).unwrap();
+        let budgets = halving_budgets(1.0, 9.0, 3);
+        let pool = halving_rounds(combinations, "EPOCHS", &budgets).unwrap();
+        let options = Options {
+            halving_metric: Some("loss".to_string()),
+            halving_eta: 3,
+            ..Options::default()
+        };
+        let mut halving = Halving::new(&pool, budgets.len(), &options);
+        assert_eq!(halving.remaining(), 9 + 3 + 1);
+
+        // The loss is smallest for X=4, and the run of X=5 fails in every round
+        let mut results = SweepResults::default();
+        let mut runs = Vec::new();
+        while let Some((idx, combo)) = halving.next(&results, &[]) {
+            let x: f64 = combo.params["X"].parse().unwrap();
+            let epochs: f64 = combo.params["EPOCHS"].parse().unwrap();
+            runs.push((combo.params["X"].clone(), combo.params["EPOCHS"].clone()));
+            if x == 5.0 {
+                continue;
+            }
+            results.runs.push(RunResult {
+                index: idx,
+                params: combo.params.clone(),
+                metrics: [("loss".to_string(), ((x - 4.5).abs() / epochs).to_string())].into(),
+                stdout: Vec::new(),
+                stderr: Vec::new(),
+                artifacts: Vec::new(),
+                resumed: false,
+                failed: false,
+                duration_s: None,
+                usage: None,
+            });
+        }
+        let round = |epochs: &str| -> Vec<&str> {
+            runs.iter()
+                .filter(|(_, e)| e == epochs)
+                .map(|(x, _)| x.as_str())
+                .collect()
+        };
+        assert_eq!(round("1").len(), 9);
+        assert_eq!(round("3"), ["3", "4", "6"]);
+        assert_eq!(round("9"), ["4"]);
+        assert_eq!(halving.remaining(), 0);
+
+        // A round waits for the runs in flight
+        let mut halving = Halving::new(&pool, budgets.len(), &options);
+        for _ in 0..9 {
+            halving.next(&SweepResults::default(), &[]);
+        }
+        assert!(halving.next(&results, &[8]).is_none());
+    }
+}
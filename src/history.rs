@@ -0,0 +1,403 @@
+use crate::diff::is_metric_column;
+use crate::error::Error;
+use crate::executor::SweepResults;
+use crate::metadata::{format_metadata, metadata_value, parse_metadata};
+use crate::parser::Options;
+use crate::snapshot::invocation;
+use crate::timestamp::DateTime;
+use std::collections::BTreeMap;
+use std::fs::{self, OpenOptions};
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::time::Instant;
+
+// Every sweep started from the command line is recorded in a local history, so the
+// command that produced a results file can be found (and run again) later. The history
+// is a file of `key=value` blocks like the environment snapshot. A sweep appends one
+// block when it starts and another with the same id when it ends; the later values win,
+// so a sweep that was interrupted keeps the status "running".
+
+// Metrics whose name contains one of these are better when lower
+const LOWER_IS_BETTER: [&str; 5] = ["loss", "error", "latency", "time", "perplexity"];
+
+// $RUNEXP_HISTORY (empty to disable), else $XDG_DATA_HOME/runexp/history, else
+// ~/.local/share/runexp/history
+pub fn history_path() -> Option<PathBuf> {
+    if let Ok(path) = std::env::var("RUNEXP_HISTORY") {
+        return (!path.is_empty()).then(|| PathBuf::from(path));
+    }
+    let base = std::env::var("XDG_DATA_HOME")
+        .map(PathBuf::from)
+        .or_else(|_| {
+            std::env::var("HOME").map(|home| PathBuf::from(home).join(".local").join("share"))
+        })
+        .or_else(|_| {
+            std::env::var("USERPROFILE")
+                .map(|home| PathBuf::from(home).join(".local").join("share"))
+        })
+        .ok()?;
+    Some(base.join("runexp").join("history"))
+}
+
+// The history entry of a sweep in progress
+pub struct HistoryRecord {
+    path: PathBuf,
+    id: u64,
+    started: Instant,
+    metrics: Vec<String>,
+}
+
+impl HistoryRecord {
+    // Record the start of a sweep. The history is a convenience, so failing to write it
+    // only prints a warning.
+    pub fn start(options: &Options) -> Option<Self> {
+        let path = history_path()?;
+        let cwd = std::env::current_dir().ok()?;
+        let id = read_history(&path).last().map_or(1, |entry| entry.id + 1);
+        let mut entries = vec![
+            ("id".to_string(), id.to_string()),
+            ("started".to_string(), DateTime::now().iso()),
+            ("status".to_string(), "running".to_string()),
+            ("cwd".to_string(), cwd.to_string_lossy().to_string()),
+            ("output".to_string(), options.output_file.clone()),
+            ("command".to_string(), invocation(options)),
+        ];
+        // The arguments one per entry, so they can be passed on exactly when re-running
+        for (i, arg) in options.invocation.iter().enumerate() {
+            entries.push((format!("arg.{}", i), arg.clone()));
+        }
+        if let Err(e) = append_block(&path, &entries) {
+            eprintln!("Warning: {}", e);
+            return None;
+        }
+        Some(HistoryRecord {
+            path,
+            id,
+            started: Instant::now(),
+            metrics: options.metrics.clone(),
+        })
+    }
+
+    // Record how the sweep ended
+    pub fn finish(self, result: &Result<SweepResults, Error>) {
+        let mut entries = vec![
+            ("id".to_string(), self.id.to_string()),
+            (
+                "duration_s".to_string(),
+                format!("{:.1}", self.started.elapsed().as_secs_f64()),
+            ),
+        ];
+        match result {
+            Ok(results) => {
+                let status = if results.failed > 0 {
+                    "failed"
+                } else {
+                    "completed"
+                };
+                entries.push(("status".to_string(), status.to_string()));
+                entries.push((
+                    "runs".to_string(),
+                    format!(
+                        "{} new, {} skipped, {} failed",
+                        results.new, results.skipped, results.failed
+                    ),
+                ));
+                if let Some(best) = best_result(results, &self.metrics) {
+                    entries.push(("best".to_string(), best));
+                }
+            }
+            Err(e) => {
+                entries.push(("status".to_string(), "error".to_string()));
+                entries.push(("error".to_string(), e.to_string()));
+            }
+        }
+        if let Err(e) = append_block(&self.path, &entries) {
+            eprintln!("Warning: {}", e);
+        }
+    }
+}
+
+// A sweep in the history with all recorded values
+#[derive(Debug, Clone, PartialEq)]
+pub struct HistoryEntry {
+    pub id: u64,
+    pub values: Vec<(String, String)>,
+}
+
+impl HistoryEntry {
+    pub fn get(&self, key: &str) -> Option<&str> {
+        metadata_value(&self.values, key)
+    }
+
+    // The arguments after "runexp"
+    pub fn args(&self) -> Vec<String> {
+        (0..)
+            .map_while(|i| self.get(&format!("arg.{}", i)).map(String::from))
+            .collect()
+    }
+}
+
+// All sweeps in the history, oldest first; a missing history is empty
+pub fn read_history(path: &Path) -> Vec<HistoryEntry> {
+    let Ok(content) = fs::read_to_string(path) else {
+        return Vec::new();
+    };
+    let mut entries: BTreeMap<u64, Vec<(String, String)>> = BTreeMap::new();
+    for block in content.split("\n\n") {
+        let values = parse_metadata(block);
+        let Some(id) = metadata_value(&values, "id").and_then(|id| id.parse().ok()) else {
+            continue;
+        };
+        let merged = entries.entry(id).or_default();
+        for (key, value) in values {
+            match merged.iter_mut().find(|(k, _)| *k == key) {
+                Some(entry) => entry.1 = value,
+                None => merged.push((key, value)),
+            }
+        }
+    }
+    entries
+        .into_iter()
+        .map(|(id, values)| HistoryEntry { id, values })
+        .collect()
+}
+
+fn append_block(path: &Path, entries: &[(String, String)]) -> Result<(), String> {
+    let error = |e: std::io::Error| format!("Failed to write history {}: {}", path.display(), e);
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent).map_err(error)?;
+    }
+    let mut file = OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(path)
+        .map_err(error)?;
+    // One write per block, so concurrent sweeps do not interleave their lines
+    file.write_all(format!("\n{}", format_metadata(entries)).as_bytes())
+        .map_err(error)
+}
+
+// The run with the best value of the first metric, e.g. "accuracy=0.93 (GPU=2, LR=0.1)"
+fn best_result(results: &SweepResults, metrics: &[String]) -> Option<String> {
+    let metric = metrics.first()?;
+    let lower = lower_is_better(metric);
+    let (run, column, value) = results
+        .runs
+        .iter()
+        .filter_map(|run| {
+            let column = run
+                .metrics
+                .keys()
+                .filter(|column| is_metric_column(column, std::slice::from_ref(metric)))
+                .min()?;
+            Some((run, column, run.metric(column)?))
+        })
+        .filter(|(_, _, value)| !value.is_nan())
+        .min_by(|a, b| {
+            let order = a.2.total_cmp(&b.2);
+            if lower { order } else { order.reverse() }
+        })?;
+    let mut params: Vec<String> = run
+        .params
+        .iter()
+        .map(|(name, value)| format!("{}={}", name, value))
+        .collect();
+    params.sort();
+    Some(format!(
+        "{}={} ({})",
+        column,
+        run.metrics
+            .get(column)
+            .map_or(value.to_string(), String::from),
+        params.join(", ")
+    ))
+}
+
+// Whether smaller values of a metric are better, judging by its name
+pub fn lower_is_better(metric: &str) -> bool {
+    LOWER_IS_BETTER
+        .iter()
+        .any(|word| metric.to_lowercase().contains(word))
+}
+
+// `runexp history [-n N]`: list the most recent sweeps
+pub fn run_history(args: &[String]) -> Result<(), Error> {
+    let mut limit = 20;
+    let mut i = 0;
+    while i < args.len() {
+        match args[i].as_str() {
+            "-n" => {
+                limit = args
+                    .get(i + 1)
+                    .and_then(|n| n.parse().ok())
+                    .ok_or_else(|| Error::usage("-n requires a number"))?;
+                i += 2;
+            }
+            "--all" => {
+                limit = usize::MAX;
+                i += 1;
+            }
+            arg => return Err(Error::usage(format!("Unexpected argument: {}", arg))),
+        }
+    }
+
+    let path = history_path()
+        .ok_or_else(|| Error::Other("No history location (set RUNEXP_HISTORY or HOME)".into()))?;
+    let entries = read_history(&path);
+    if entries.is_empty() {
+        println!("No sweeps recorded in {}", path.display());
+        return Ok(());
+    }
+    for entry in entries.iter().skip(entries.len().saturating_sub(limit)) {
+        let field = |key: &str| entry.get(key).unwrap_or("-").to_string();
+        let line = format!(
+            "{:>4}  {}  {:<9}  {:>8}  {}  {}",
+            entry.id,
+            field("started"),
+            field("status"),
+            entry
+                .get("duration_s")
+                .and_then(|d| d.parse().ok())
+                .map_or("-".to_string(), format_duration),
+            field("output"),
+            entry.get("best").unwrap_or("")
+        );
+        println!("{}", line.trim_end());
+        println!("      {}", field("command"));
+    }
+    Ok(())
+}
+
+// `runexp show ID [--rerun]`: print everything recorded about a sweep, or run it again
+// in the directory it was started from
+pub fn run_show(args: &[String]) -> Result<(), Error> {
+    let mut id = None;
+    let mut rerun = false;
+    for arg in args {
+        match arg.as_str() {
+            "--rerun" => rerun = true,
+            _ if id.is_none() => {
+                id = Some(
+                    arg.parse::<u64>()
+                        .map_err(|_| Error::usage(format!("Invalid history id: {}", arg)))?,
+                )
+            }
+            _ => return Err(Error::usage(format!("Unexpected argument: {}", arg))),
+        }
+    }
+    let id = id.ok_or_else(|| Error::usage("Usage: runexp show ID [--rerun]"))?;
+    let path = history_path()
+        .ok_or_else(|| Error::Other("No history location (set RUNEXP_HISTORY or HOME)".into()))?;
+    let entry = read_history(&path)
+        .into_iter()
+        .find(|entry| entry.id == id)
+        .ok_or_else(|| Error::usage(format!("No sweep {} in {}", id, path.display())))?;
+
+    if !rerun {
+        for (key, value) in &entry.values {
+            if !key.starts_with("arg.") {
+                println!("{}: {}", key, value);
+            }
+        }
+        return Ok(());
+    }
+
+    let cwd = entry
+        .get("cwd")
+        .ok_or_else(|| Error::Other("The sweep has no recorded directory".into()))?;
+    println!(
+        "Re-running in {}: {}",
+        cwd,
+        entry.get("command").unwrap_or("")
+    );
+    let exe = std::env::current_exe().map_err(|e| format!("Failed to locate runexp: {}", e))?;
+    let status = std::process::Command::new(exe)
+        .args(entry.args())
+        .current_dir(cwd)
+        .status()
+        .map_err(|e| format!("Failed to start runexp in {}: {}", cwd, e))?;
+    if !status.success() {
+        std::process::exit(status.code().unwrap_or(1));
+    }
+    Ok(())
+}
+
+// e.g. "45s", "3m12s", "2h05m"
+fn format_duration(secs: f64) -> String {
+    let secs = secs.round() as u64;
+    if secs < 60 {
+        format!("{}s", secs)
+    } else if secs < 3600 {
+        format!("{}m{:02}s", secs / 60, secs % 60)
+    } else {
+        format!("{}h{:02}m", secs / 3600, secs % 3600 / 60)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::executor::RunResult;
+    use std::collections::HashMap;
+
+    #[test]
+    fn test_history_merges_blocks() {
+        let path = std::env::temp_dir().join("runexp_test_history");
+        let _ = fs::remove_file(&path);
+        let block = |pairs: &[(&str, &str)]| -> Vec<(String, String)> {
+            pairs
+                .iter()
+                .map(|(k, v)| (k.to_string(), v.to_string()))
+                .collect()
+        };
+        append_block(
+            &path,
+            &block(&[
+                ("id", "1"),
+                ("status", "running"),
+                ("arg.0", "--x"),
+                ("arg.1", "a\nb"),
+            ]),
+        )
+        .unwrap();
+        append_block(&path, &block(&[("id", "2"), ("status", "running")])).unwrap();
+        append_block(&path, &block(&[("id", "1"), ("status", "completed")])).unwrap();
+
+        let entries = read_history(&path);
+        assert_eq!(entries.len(), 2);
+        assert_eq!(entries[0].get("status"), Some("completed"));
+        assert_eq!(entries[0].args(), vec!["--x", "a\nb"]);
+        // Interrupted sweeps stay "running"
+        assert_eq!(entries[1].get("status"), Some("running"));
+        let _ = fs::remove_file(&path);
+    }
+
+    #[test]
+    fn test_best_result() {
+        let run = |x: &str, loss: &str| RunResult {
+            index: 0,
+            params: HashMap::from([("X".to_string(), x.to_string())]),
+            metrics: HashMap::from([("train.loss".to_string(), loss.to_string())]),
+            stdout: Vec::new(),
+            stderr: Vec::new(),
+            artifacts: Vec::new(),
+            resumed: false,
+            failed: false,
+            duration_s: None,
+            usage: None,
+        };
+        let results = SweepResults {
+            runs: vec![run("1", "0.5"), run("2", "0.25"), run("3", "")],
+            new: 3,
+            skipped: 0,
+            failed: 1,
+            cost: None,
+        };
+        assert_eq!(
+            best_result(&results, &["loss".to_string()]),
+            Some("train.loss=0.25 (X=2)".to_string())
+        );
+        assert_eq!(best_result(&results, &["accuracy".to_string()]), None);
+        assert_eq!(format_duration(192.4), "3m12s");
+    }
+}
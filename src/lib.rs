@@ -0,0 +1,1628 @@
+// runexp as a library: describe a sweep in code and get typed results back in-process.
+//
+//     let results = runexp::Sweep::new()
+//         .param("GPU", "1,2,4")
+//         .param("BATCHSIZE", "32,64")
+//         .command(["python", "train.py"])
+//         .metrics(["accuracy"])
+//         .output("results.csv")
+//         .run()?;
+//     for run in &results.runs {
+//         println!("{:?} -> {:?}", run.params, run.metric("accuracy"));
+//     }
+//
+// Results are still recorded in the CSV file, so a sweep resumes exactly like the CLI.
+
+mod aggregate;
+mod artifact;
+mod backend;
+mod bundle;
+mod capture;
+mod condition;
+mod config;
+mod cost;
+mod csv;
+mod diff;
+mod error;
+mod evaluator;
+mod executor;
+mod ffi;
+mod glob;
+mod gzip;
+mod halving;
+mod hash;
+mod history;
+mod merge;
+mod metadata;
+mod metrics;
+mod optimize;
+mod parser;
+mod refine;
+mod regex;
+mod report;
+mod sink;
+mod snapshot;
+mod tar;
+mod timestamp;
+mod verify;
+mod watch;
+
+use evaluator::{
+    Combination, assign_seeds, constrain_combinations, count_values, dedup_combinations,
+    drop_internal, evaluate_params, filter_combinations, halving_budgets, halving_rounds,
+    is_internal, repeat_combinations, sample_combinations, select_combinations, shard_combinations,
+};
+use executor::{execute_experiments, print_plan};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+pub use backend::{ExecutionBackend, Job, LocalBackend, ResourceUsage};
+pub use bundle::run_bundle;
+pub use condition::Condition;
+pub use cost::CostExpr;
+pub use diff::run_diff;
+pub use error::Error;
+pub use executor::{RunResult, SweepResults};
+pub use history::{HistoryRecord, run_history, run_show};
+pub use merge::run_merge;
+pub use metrics::{
+    HeuristicParser, JsonParser, LogfmtParser, MetricParser, RegexParser, parser_from_spec,
+};
+pub use parser::{MismatchAction, Options, ScriptFile, Stage, Strategy, json_errors, parse_args};
+pub use refine::run_refine;
+pub use report::run_report;
+pub use sink::{CsvSink, ResultSink};
+
+// A parameter sweep: parameters, the command (or stages) run for every combination, and options
+#[derive(Clone)]
+pub struct Sweep {
+    params: Vec<(String, String)>,
+    stages: Vec<Stage>,
+    options: Options,
+    backend: Arc<dyn ExecutionBackend>,
+    sinks: Vec<Arc<Mutex<dyn ResultSink>>>,
+}
+
+impl Default for Sweep {
+    fn default() -> Self {
+        Sweep {
+            params: Vec::new(),
+            stages: Vec::new(),
+            options: Options::default(),
+            backend: Arc::new(LocalBackend::new()),
+            sinks: Vec::new(),
+        }
+    }
+}
+
+impl Sweep {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    // A sweep as produced by parse_args
+    pub fn from_parts(params: Vec<(String, String)>, stages: Vec<Stage>, options: Options) -> Self {
+        Sweep {
+            params,
+            stages,
+            options,
+            ..Self::default()
+        }
+    }
+
+    // Add a parameter. The value uses the CLI syntax: "1,2,4", "1:8:2", "2*BATCH", ...
+    pub fn param(mut self, name: impl Into<String>, value: impl Into<String>) -> Self {
+        self.params.push((name.into(), value.into()));
+        self
+    }
+
+    // The command run for every combination, replacing any previous command or stages
+    pub fn command<I, S>(mut self, command: I) -> Self
+    where
+        I: IntoIterator<Item = S>,
+        S: Into<String>,
+    {
+        self.stages = vec![Stage {
+            name: String::new(),
+            command: command.into_iter().map(Into::into).collect(),
+        }];
+        self
+    }
+
+    // A bash script run for every combination, like the heredoc form of the CLI
+    pub fn script(self, script: impl Into<String>) -> Self {
+        self.command(["bash".to_string(), "-c".to_string(), script.into()])
+    }
+
+    // Append a named stage running a bash command, like --stage NAME CMD.
+    // Stages replace a plain command set earlier.
+    pub fn stage(mut self, name: impl Into<String>, command: impl Into<String>) -> Self {
+        self.stages.retain(|stage| !stage.name.is_empty());
+        self.stages.push(Stage {
+            name: name.into(),
+            command: vec!["bash".to_string(), "-c".to_string(), command.into()],
+        });
+        self
+    }
+
+    pub fn metrics<I, S>(mut self, metrics: I) -> Self
+    where
+        I: IntoIterator<Item = S>,
+        S: Into<String>,
+    {
+        self.options.metrics = metrics.into_iter().map(Into::into).collect();
+        self
+    }
+
+    pub fn output(mut self, path: impl Into<String>) -> Self {
+        self.options.output_file = path.into();
+        self
+    }
+
+    pub fn concurrency(mut self, concurrency: usize) -> Self {
+        self.options.concurrency = concurrency.max(1);
+        self
+    }
+
+    // Export a deterministic SEED to every run and record it as a column (--sweep-seed)
+    pub fn auto_seed(mut self, sweep_seed: u64) -> Self {
+        self.options.auto_seed = Some(sweep_seed);
+        self
+    }
+
+    // Stop and fail runs whose stage takes longer than this
+    pub fn timeout(mut self, timeout: Duration) -> Self {
+        self.options.timeout = Some(timeout);
+        self
+    }
+
+    // Attempt failed runs up to `retries` more times, waiting `delay` before the first
+    // retry and doubling it for each further one
+    pub fn retries(mut self, retries: usize, delay: Duration) -> Self {
+        self.options.retries = retries;
+        self.options.retry_delay = delay;
+        self
+    }
+
+    pub fn preserve_output(mut self, preserve: bool) -> Self {
+        self.options.preserve_output = preserve;
+        self
+    }
+
+    pub fn env(mut self, name: impl Into<String>, value: impl Into<String>) -> Self {
+        self.options.env.push((name.into(), value.into()));
+        self
+    }
+
+    pub fn tag(mut self, key: impl Into<String>, value: impl Into<String>) -> Self {
+        self.options.tags.push((key.into(), value.into()));
+        self
+    }
+
+    // Run the commands through another backend instead of as local processes
+    pub fn backend(mut self, backend: impl ExecutionBackend + 'static) -> Self {
+        self.backend = Arc::new(backend);
+        self
+    }
+
+    // Also send every new result to this sink, in addition to the results file
+    pub fn sink(mut self, sink: impl ResultSink + 'static) -> Self {
+        self.sinks.push(Arc::new(Mutex::new(sink)));
+        self
+    }
+
+    // Add a metric parser; without any, the heuristic parser is used
+    pub fn parser(mut self, parser: impl MetricParser + 'static) -> Self {
+        self.options.parsers.push(Arc::new(parser));
+        self
+    }
+
+    // Any other setting, e.g. sweep.with_options(|o| o.on_mismatch = MismatchAction::New)
+    pub fn with_options(mut self, f: impl FnOnce(&mut Options)) -> Self {
+        f(&mut self.options);
+        self
+    }
+
+    // The combinations the sweep would run (after --skip/--only, --sample, --shard and merging
+    // duplicates), in grid order, with each parameter's value in definition order; with
+    // --halving, those of every round, of which only the survivors of the previous round are run
+    pub fn combinations(&self) -> Result<Vec<Vec<(String, String)>>, Error> {
+        let combinations = self.expand(false)?;
+        Ok(combinations
+            .into_iter()
+            .map(|combo| {
+                combo
+                    .param_order
+                    .iter()
+                    .map(|name| (name.clone(), combo.params[name].clone()))
+                    .collect()
+            })
+            .collect())
+    }
+
+    // Evaluate the grid and take it through --repeat(s), --auto-seed, --only indices, --where,
+    // --skip/--only, --sample, --shard, merging duplicates and the --halving rounds. With
+    // `report`, the steps that change the grid say so, as run() shows them.
+    fn expand(&self, report: bool) -> Result<Vec<Combination>, Error> {
+        let options = &self.options;
+        let note = |message: String| {
+            if report {
+                println!("{}", message);
+            }
+        };
+
+        let mut combinations = evaluate_params(&self.params, &options.zip, options.sampling())?;
+        if let Some((count, first)) = options.repetitions() {
+            combinations = repeat_combinations(combinations, count, first)?;
+        }
+        if let Some(seed) = options.auto_seed {
+            assign_seeds(&mut combinations, seed)?;
+        }
+
+        note(format!(
+            "Generated {} parameter combinations",
+            combinations.len()
+        ));
+
+        // Keep the combinations chosen by their position with --only 3,7 or --only 0:100
+        if !options.only_indices.is_empty() {
+            let total = combinations.len();
+            combinations = select_combinations(combinations, &options.only_indices)?;
+            note(format!(
+                "Selected {} of {} combinations by index",
+                combinations.len(),
+                total
+            ));
+        }
+
+        // Apply --where constraints and --skip/--only filters to the generated grid
+        if !options.constraints.is_empty() || !options.skip.is_empty() || !options.only.is_empty() {
+            let total = combinations.len();
+            let constrained = constrain_combinations(combinations, &options.constraints)?;
+            combinations = filter_combinations(constrained, &options.skip, &options.only)?;
+            note(format!(
+                "Filtered out {} combinations ({} remaining)",
+                total - combinations.len(),
+                combinations.len()
+            ));
+        }
+
+        if let Some(count) = options.sample {
+            let total = combinations.len();
+            combinations = sample_combinations(combinations, (count, options.sample_seed));
+            note(format!(
+                "Sampled {} of {} combinations (seed {})",
+                combinations.len(),
+                total,
+                options.sample_seed
+            ));
+        }
+
+        if let Some((shard, count)) = options.shard {
+            let total = combinations.len();
+            combinations = shard_combinations(combinations, (shard, count));
+            note(format!(
+                "Shard {}/{}: running {} of {} combinations",
+                shard,
+                count,
+                combinations.len(),
+                total
+            ));
+        }
+
+        // Internal parameters (--_n) have done their part, and combinations that differed
+        // only in them run once
+        drop_internal(&mut combinations);
+        if options.dedup {
+            let total = combinations.len();
+            combinations = dedup_combinations(combinations);
+            if combinations.len() < total {
+                note(format!(
+                    "Merged {} duplicate combinations ({} remaining)",
+                    total - combinations.len(),
+                    combinations.len()
+                ));
+            }
+        }
+
+        if let Some(seed) = options.shuffle {
+            note(format!(
+                "Running the combinations in random order (seed {})",
+                seed
+            ));
+        }
+        if let Some(limit) = options.limit.filter(|&limit| limit < combinations.len()) {
+            note(format!(
+                "Running only the first {} of {} combinations (--limit)",
+                limit,
+                combinations.len()
+            ));
+        }
+
+        if let Some(metric) = &options.optimize {
+            note(format!(
+                "Searching for the {} {} in up to {} of these combinations",
+                if options.maximize {
+                    "largest"
+                } else {
+                    "smallest"
+                },
+                metric,
+                options
+                    .budget
+                    .unwrap_or(optimize::DEFAULT_BUDGET)
+                    .min(combinations.len())
+            ));
+        }
+
+        // With --halving, every combination is run in the first round and some of them in
+        // the next ones
+        if let Some((name, min, max)) = &options.halving {
+            let budgets = halving_budgets(*min, *max, options.halving_eta);
+            note(format!(
+                "Successive halving in {} rounds with {}={}, keeping the best 1/{} by {} after each",
+                budgets.len(),
+                name,
+                budgets.join(","),
+                options.halving_eta,
+                options.halving_metric.as_deref().unwrap_or_default()
+            ));
+            combinations = halving_rounds(combinations, name, &budgets)?;
+        }
+        Ok(combinations)
+    }
+
+    // Names of the parameters in definition order, followed by REP with --repeat(s), SEED
+    // with --auto-seed and the budget parameter and ROUND with --halving
+    pub fn param_names(&self) -> Vec<&str> {
+        let mut names: Vec<&str> = self
+            .params
+            .iter()
+            .map(|(name, _)| name.as_str())
+            .filter(|name| !is_internal(name))
+            .collect();
+        if self.options.repetitions().is_some() {
+            names.push("REP");
+        }
+        if self.options.auto_seed.is_some() {
+            names.push("SEED");
+        }
+        if let Some((name, _, _)) = &self.options.halving {
+            names.push(name);
+            names.push("ROUND");
+        }
+        names
+    }
+
+    // Expand the grid, run every combination not already in the results file and
+    // return the results of the whole grid
+    pub fn run(&self) -> Result<SweepResults, Error> {
+        let options = &self.options;
+        if self.params.is_empty() {
+            return Err(Error::usage("No parameters specified"));
+        }
+        if self.stages.is_empty() {
+            return Err(Error::usage("No command specified"));
+        }
+
+        // Preview the size of the sweep before expanding it
+        let mut counts = count_values(&self.params, &options.zip, options.sampling())?;
+        if let Some((count, _)) = options.repetitions() {
+            counts.push(("REP".to_string(), count));
+        }
+        let total = counts
+            .iter()
+            .fold(1usize, |acc, (_, count)| acc.saturating_mul(*count));
+        let breakdown = counts
+            .iter()
+            .enumerate()
+            .map(|(idx, (name, count))| {
+                if idx == 0 {
+                    format!("{}: {} values", name, count)
+                } else {
+                    format!("{}: {}", name, count)
+                }
+            })
+            .collect::<Vec<_>>()
+            .join(" × ");
+        println!("{} = {} runs", breakdown, total);
+
+        // Only the combinations chosen by index or sampled are run, --optimize runs only
+        // its budget and --limit only the first ones
+        let runs = match options.only_indices.is_empty() {
+            true => total,
+            false => options
+                .only_indices
+                .iter()
+                .map(|&(start, end)| end.min(total).saturating_sub(start))
+                .sum::<usize>()
+                .min(total),
+        };
+        let runs = match options.sample {
+            Some(count) => count.min(runs),
+            None => runs,
+        };
+        let runs = match &options.optimize {
+            Some(_) => options.budget.unwrap_or(optimize::DEFAULT_BUDGET).min(runs),
+            None => options.limit.unwrap_or(usize::MAX).min(runs),
+        };
+        let budgets = options
+            .halving
+            .as_ref()
+            .map(|(_, min, max)| halving_budgets(*min, *max, options.halving_eta));
+        let runs = match &budgets {
+            Some(budgets) => halving::halving_runs(runs, budgets.len(), options.halving_eta),
+            None => runs,
+        };
+        if runs > options.max_combinations && !options.yes {
+            return Err(Error::Other(format!(
+                "{} runs exceed the limit of {} (use --yes to start anyway, or raise --max-combinations)",
+                runs, options.max_combinations
+            )));
+        }
+
+        let combinations = self.expand(true)?;
+
+        if options.dry_run {
+            print_plan(&combinations, &self.stages, options)?;
+            return Ok(SweepResults::default());
+        }
+
+        execute_experiments(
+            &combinations,
+            &self.stages,
+            options,
+            &self.backend,
+            &self.sinks,
+        )
+    }
+
+    // Run the sweep, then run it again each time one of the --watch files changes, until
+    // interrupted. Each round starts from scratch, with the previous results moved to
+    // <output>.prev, unless the results are kept (--watch-invalidate none). A script
+    // given with -f is read again, so edits to it take effect.
+    pub fn watch(mut self) -> Result<(), Error> {
+        let files = self.options.watch.clone();
+        if files.is_empty() {
+            return Err(Error::usage("No files to watch"));
+        }
+        let mut states = watch::file_states(&files);
+        // Mistakes in the sweep itself cannot be fixed by editing the watched files
+        self.run()?;
+        // Kept results are meant to mix with rows of the edited files
+        if self.options.watch_keep_results {
+            self.options.force = true;
+        }
+
+        loop {
+            println!("Watching {} for changes (Ctrl-C to stop)", files.join(", "));
+            let changed = watch::wait_for_change(&files, &mut states);
+            println!("\nChanged: {}", changed.join(", "));
+
+            if let Err(e) = self.reload_script() {
+                eprintln!("Error: {}", e);
+                continue;
+            }
+            if !self.options.watch_keep_results {
+                match watch::invalidate_results(&self.options.output_file) {
+                    Ok(Some(previous)) => println!("Previous results moved to {}", previous),
+                    Ok(None) => {}
+                    Err(e) => {
+                        eprintln!("Error: {}", e);
+                        continue;
+                    }
+                }
+            }
+            if let Err(e) = self.run() {
+                eprintln!("Error: {}", e);
+            }
+        }
+    }
+
+    // Replace the content of the -f script in the command with the file's current content
+    fn reload_script(&mut self) -> Result<(), Error> {
+        let Some(script) = &mut self.options.script else {
+            return Ok(());
+        };
+        let content =
+            std::fs::read_to_string(&script.path).map_err(|e| Error::io(&script.path, e))?;
+        script.sha256 = hash::sha256_hex(content.as_bytes());
+        if let Some(stage) = self.stages.first_mut()
+            && stage.command.len() > 3
+            && stage.command[3] == script.path
+        {
+            stage.command[2] = content;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    #[cfg(unix)]
+    use std::time::Instant;
+
+    // A results file of the temporary directory, removed with its .meta and .snapshot
+    // files both now and when the returned guard drops
+    fn temp_output(name: &str) -> (String, TempOutput) {
+        let output = std::env::temp_dir().join(name);
+        let output = TempOutput(output.to_string_lossy().to_string());
+        output.remove();
+        (output.0.clone(), output)
+    }
+
+    struct TempOutput(String);
+
+    impl TempOutput {
+        fn remove(&self) {
+            let _ = std::fs::remove_file(&self.0);
+            let _ = std::fs::remove_file(format!("{}.meta", self.0));
+            let _ = std::fs::remove_file(format!("{}.snapshot", self.0));
+        }
+    }
+
+    impl Drop for TempOutput {
+        fn drop(&mut self) {
+            self.remove();
+        }
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_sweep_returns_results() {
+        let (output, _output) = temp_output("runexp_test_sweep_builder.csv");
+
+        let sweep = Sweep::new()
+            .param("X", "1,2")
+            .param("Y", "10*X")
+            .script("echo \"value: $Y\"")
+            .metrics(["value"])
+            .output(output.clone());
+
+        let results = sweep.run().unwrap();
+        assert_eq!(results.new, 2);
+        assert_eq!(results.runs.len(), 2);
+        assert_eq!(results.runs[0].params["X"], "1");
+        assert_eq!(results.runs[1].metric("value"), Some(20.0));
+        assert!(!results.runs[0].resumed);
+
+        // A second run resumes from the results file
+        let results = sweep.run().unwrap();
+        assert_eq!(results.skipped, 2);
+        assert!(results.runs.iter().all(|run| run.resumed));
+        assert_eq!(results.runs[0].metric("value"), Some(10.0));
+    }
+
+    #[test]
+    fn test_sweep_builtins_example() {
+        // The examples of the readme, as a dry run
+        let args: Vec<String> = [
+            "--run-label",
+            "run_{today}",
+            "--tag",
+            "date={today}",
+            "--dry-run",
+            "echo",
+            "{run_label}",
+        ]
+        .iter()
+        .map(|s| s.to_string())
+        .collect();
+        let (params, stages, options) = parse_args(&args).unwrap();
+        let today = evaluator::builtin("today").unwrap();
+        assert_eq!(options.tags, [("date".to_string(), today.clone())]);
+        let sweep = Sweep::from_parts(params, stages, options);
+        assert_eq!(
+            sweep.combinations().unwrap(),
+            vec![vec![("RUN_LABEL".to_string(), format!("run_{}", today))]]
+        );
+        assert_eq!(sweep.run().unwrap().runs.len(), 0);
+    }
+
+    #[test]
+    fn test_sweep_string_operations_example() {
+        // The example of the readme, as a dry run
+        let args: Vec<String> = [
+            "--model",
+            "ResNet,ViT",
+            "--n",
+            "2",
+            "--run-label",
+            "lower(model) ++ \"_\" ++ 2n",
+            "--dry-run",
+            "echo",
+            "{run_label}",
+        ]
+        .iter()
+        .map(|s| s.to_string())
+        .collect();
+        let (params, stages, options) = parse_args(&args).unwrap();
+        let sweep = Sweep::from_parts(params, stages, options);
+        let labels: Vec<String> = sweep
+            .combinations()
+            .unwrap()
+            .into_iter()
+            .map(|combo| combo[2].1.clone())
+            .collect();
+        assert_eq!(labels, ["resnet_4", "vit_4"]);
+        assert_eq!(sweep.run().unwrap().runs.len(), 0);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_sweep_timeout_and_concurrency() {
+        let (output, _output) = temp_output("runexp_test_sweep_timeout.csv");
+
+        // All runs are in flight at once; the ones sleeping past the timeout are stopped
+        let started = Instant::now();
+        let results = Sweep::new()
+            .param("T", "0,0.01,0.02,0.03,0.04,0.05,0.06,0.07,5,6")
+            .script("sleep $T; echo \"value: 1\"")
+            .metrics(["value"])
+            .output(output.clone())
+            .concurrency(10)
+            .timeout(Duration::from_millis(500))
+            .run()
+            .unwrap();
+        assert_eq!(results.new, 8);
+        assert_eq!(results.failed, 2);
+        assert!(started.elapsed() < Duration::from_secs(4));
+    }
+
+    #[cfg(target_os = "linux")]
+    #[test]
+    fn test_sweep_threads_do_not_grow_with_concurrency() {
+        const CONCURRENCY: &str = "RUNEXP_TEST_CONCURRENCY";
+        // Counted in a process of its own, where no other tests start threads
+        if let Ok(concurrency) = std::env::var(CONCURRENCY) {
+            let concurrency: usize = concurrency.parse().unwrap();
+            let (output, _output) =
+                temp_output(&format!("runexp_test_threads_{}.csv", concurrency));
+
+            let done = std::sync::Arc::new(std::sync::atomic::AtomicBool::new(false));
+            let sampler = {
+                let done = done.clone();
+                std::thread::spawn(move || {
+                    let mut peak = 0;
+                    while !done.load(std::sync::atomic::Ordering::SeqCst) {
+                        peak = peak.max(std::fs::read_dir("/proc/self/task").unwrap().count());
+                        std::thread::sleep(Duration::from_millis(5));
+                    }
+                    peak
+                })
+            };
+            let results = Sweep::new()
+                .param("N", format!("1:{}", concurrency + 1))
+                .script("echo out; echo err >&2; sleep 0.5")
+                .output(output.clone())
+                .concurrency(concurrency)
+                .run()
+                .unwrap();
+            done.store(true, std::sync::atomic::Ordering::SeqCst);
+            assert_eq!(results.new, concurrency);
+            println!("peak threads: {}", sampler.join().unwrap());
+
+            return;
+        }
+
+        let peak_threads = |concurrency: usize| -> usize {
+            let output = std::process::Command::new(std::env::current_exe().unwrap())
+                .args([
+                    "--exact",
+                    "tests::test_sweep_threads_do_not_grow_with_concurrency",
+                    "--nocapture",
+                    "--test-threads=1",
+                ])
+                .env(CONCURRENCY, concurrency.to_string())
+                .output()
+                .unwrap();
+            let stdout = String::from_utf8_lossy(&output.stdout);
+            assert!(output.status.success(), "{}", stdout);
+            stdout
+                .lines()
+                .find_map(|line| line.strip_prefix("peak threads: "))
+                .unwrap()
+                .parse()
+                .unwrap()
+        };
+        // Well above the number of cores, so runs cannot each have had a thread
+        let cores = std::thread::available_parallelism().map_or(1, |n| n.get());
+        let few = peak_threads(2);
+        let many = peak_threads((4 * cores).clamp(32, 512));
+        assert!(
+            many <= few + 1,
+            "{} threads for 2 runs, {} for many",
+            few,
+            many
+        );
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_sweep_retries() {
+        let dir = std::env::temp_dir().join("runexp_test_retries");
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(&dir).unwrap();
+        let output = dir.join("results.csv").to_string_lossy().to_string();
+
+        // Every run fails on its first attempt; X=3 always fails
+        let results = Sweep::new()
+            .param("X", "1,2,3")
+            .script(
+                "f=$DIR/attempted.$X; if [ -e $f ] && [ $X != 3 ]; then echo \"value: $X\"; \
+                 else touch $f; exit 1; fi",
+            )
+            .env("DIR", dir.to_string_lossy())
+            .metrics(["value"])
+            .output(output)
+            .concurrency(2)
+            .retries(1, Duration::ZERO)
+            .run()
+            .unwrap();
+        assert_eq!(results.new, 2);
+        assert_eq!(results.failed, 1);
+
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_sweep_record_status() {
+        let dir = std::env::temp_dir().join("runexp_test_record_status");
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(&dir).unwrap();
+        let output = dir.join("results.csv").to_string_lossy().to_string();
+
+        // X=2 fails until the "fixed" file exists
+        let sweep = |rerun_failed: bool| {
+            Sweep::new()
+                .param("X", "1,2")
+                .script("if [ $X = 2 ] && [ ! -e $DIR/fixed ]; then exit 3; fi; echo \"value: $X\"")
+                .env("DIR", dir.to_string_lossy())
+                .metrics(["value"])
+                .output(output.clone())
+                .with_options(|options| {
+                    options.record_status = true;
+                    options.rerun_failed = rerun_failed;
+                })
+                .run()
+                .unwrap()
+        };
+        let results = sweep(false);
+        assert_eq!((results.new, results.failed), (1, 1));
+        assert!(results.runs[1].failed);
+        let content = std::fs::read_to_string(&output).unwrap();
+        assert_eq!(content, "X,value,status,exit_code\n1,1,ok,0\n2,,failed,3\n");
+
+        // The recorded failure counts as done until --rerun-failed
+        std::fs::write(dir.join("fixed"), "").unwrap();
+        let results = sweep(false);
+        assert_eq!((results.skipped, results.new), (2, 0));
+        let results = sweep(true);
+        assert_eq!((results.skipped, results.new), (1, 1));
+        let results = sweep(true);
+        assert_eq!(results.skipped, 2);
+        assert!(results.runs.iter().all(|run| !run.failed));
+
+        // A row with an empty metric cell is incomplete too
+        let content = std::fs::read_to_string(&output).unwrap();
+        std::fs::write(&output, content.replace("1,1,ok,0", "1,,ok,0")).unwrap();
+        assert_eq!(sweep(false).skipped, 2);
+        let results = sweep(true);
+        assert_eq!((results.skipped, results.new), (1, 1));
+        assert_eq!(results.runs[0].metrics["value"], "1");
+
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_sweep_stall_timeout() {
+        let (output, _output) = temp_output("runexp_test_sweep_stall.csv");
+
+        // X=2 hangs after its first line; X=1 keeps printing until it is done
+        let started = Instant::now();
+        let results = Sweep::new()
+            .param("X", "1,2")
+            .script(
+                "for i in 1 2 3 4 5 6; do echo \"step: $i\"; [ $X = 2 ] && sleep 10; sleep 0.1; done",
+            )
+            .metrics(["step"])
+            .output(output.clone())
+            .with_options(|options| {
+                options.record_status = true;
+                options.stall_timeout = Some(Duration::from_millis(400));
+            })
+            .run()
+            .unwrap();
+        assert!(started.elapsed() < Duration::from_secs(5));
+        assert_eq!((results.new, results.failed), (1, 1));
+        let content = std::fs::read_to_string(&output).unwrap();
+        assert_eq!(content, "X,step,status,exit_code\n1,6,ok,0\n2,,stalled,\n");
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_sweep_resume_checks_command_and_inputs() {
+        let (output, _output) = temp_output("runexp_test_sweep_command_hash.csv");
+        let input = format!("{}.input", output);
+        std::fs::write(&input, "v1").unwrap();
+        let sweep = |command: &str, force: bool| {
+            let checksum = crate::hash::sha256_file(&input).unwrap();
+            Sweep::new()
+                .param("X", "1,2")
+                .command(["sh", "-c", command])
+                .metrics(["value"])
+                .output(output.clone())
+                .with_options(|options| {
+                    options.track_inputs = vec![(input.clone(), checksum)];
+                    options.force = force;
+                })
+                .run()
+        };
+
+        assert_eq!(sweep("echo value: $X", false).unwrap().new, 2);
+        assert_eq!(sweep("echo value: $X", false).unwrap().skipped, 2);
+        // Another command, or a changed input, does not resume from the same rows
+        let error = sweep("echo value: $((X*2))", false).unwrap_err();
+        assert!(matches!(error, Error::Incompatible { .. }));
+        assert!(error.to_string().contains("command: sha256"));
+        std::fs::write(&input, "v2").unwrap();
+        let error = sweep("echo value: $X", false).unwrap_err();
+        assert!(error.to_string().contains(&format!("{}: sha256", input)));
+        assert_eq!(sweep("echo value: $X", true).unwrap().skipped, 2);
+
+        let _ = std::fs::remove_file(&input);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_sweep_expected_cost_order() {
+        let (output, _output) = temp_output("runexp_test_sweep_expected_cost.csv");
+        let sweep = |costliest_first: bool, expr: &str| {
+            let _ = std::fs::remove_file(&output);
+            let expr = CostExpr::parse(expr).unwrap();
+            Sweep::new()
+                .param("X", "2,3,1")
+                .param("MODE", "a,b")
+                .command(["sh", "-c", "echo value: $X"])
+                .metrics(["value"])
+                .output(output.clone())
+                .with_options(|options| {
+                    options.expected_cost = Some(expr);
+                    options.costliest_first = costliest_first;
+                })
+                .run()
+        };
+
+        // Rows are written in the order the runs finish; ties keep grid order
+        sweep(false, "X").unwrap();
+        let content = std::fs::read_to_string(&output).unwrap();
+        assert_eq!(
+            content,
+            "X,MODE,value\n1,a,1\n1,b,1\n2,a,2\n2,b,2\n3,a,3\n3,b,3\n"
+        );
+        sweep(true, "X*X").unwrap();
+        let content = std::fs::read_to_string(&output).unwrap();
+        assert_eq!(
+            content,
+            "X,MODE,value\n3,a,3\n3,b,3\n2,a,2\n2,b,2\n1,a,1\n1,b,1\n"
+        );
+        // MODE is not a number
+        let error = sweep(false, "X*MODE").unwrap_err();
+        assert!(matches!(error, Error::Usage { .. }));
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_sweep_stdin_file() {
+        let dir = std::env::temp_dir().join("runexp_test_stdin_file");
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(&dir).unwrap();
+        std::fs::write(dir.join("1.txt"), "10\n").unwrap();
+        std::fs::write(dir.join("2.txt"), "20\n").unwrap();
+        let output = dir.join("results.csv").to_string_lossy().to_string();
+
+        // There is no input for N=3
+        let results = Sweep::new()
+            .param("N", "1,2,3")
+            .script("read v; echo \"value: $v\"")
+            .metrics(["value"])
+            .output(output.clone())
+            .with_options(|options| {
+                options.stdin_file = Some(format!("{}/{{n}}.txt", dir.display()));
+            })
+            .run()
+            .unwrap();
+        assert_eq!((results.new, results.failed), (2, 1));
+        let content = std::fs::read_to_string(&output).unwrap();
+        assert_eq!(content, "N,value\n1,10\n2,20\n");
+
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_sweep_scratch() {
+        let (output, _output) = temp_output("runexp_test_sweep_scratch.csv");
+
+        // X=2 fails after writing to its directory
+        let results = Sweep::new()
+            .param("X", "1,2")
+            .script(
+                "[ \"$TMPDIR\" = \"$RUNEXP_TMPDIR\" ] || exit 3; echo $X > \"$TMPDIR/x\"; \
+                 [ $X = 2 ] && exit 1; echo \"value: $(cat \"$TMPDIR/x\")\"",
+            )
+            .metrics(["value"])
+            .output(output.clone())
+            .with_options(|options| {
+                options.scratch = true;
+                options.keep_failed_scratch = true;
+            })
+            .run()
+            .unwrap();
+        assert_eq!((results.new, results.failed), (1, 1));
+        assert_eq!(results.runs[0].metrics["value"], "1");
+        let scratch =
+            |n: usize| std::env::temp_dir().join(format!("runexp-{}-{}", std::process::id(), n));
+        assert!(!scratch(1).exists());
+        assert_eq!(
+            std::fs::read_to_string(scratch(2).join("x")).unwrap(),
+            "2\n"
+        );
+
+        let _ = std::fs::remove_dir_all(scratch(2));
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_sweep_max_failures() {
+        let (output, _output) = temp_output("runexp_test_sweep_max_failures.csv");
+
+        // Only X=2 succeeds, which resets the count; X=3 and X=4 fail in a row
+        let error = Sweep::new()
+            .param("X", "1,2,3,4,5,6")
+            .script("[ $X = 2 ] || exit 1; echo \"value: $X\"")
+            .metrics(["value"])
+            .output(output.clone())
+            .with_options(|options| {
+                options.record_status = true;
+                options.max_failures = Some(2);
+            })
+            .run()
+            .unwrap_err();
+        assert!(matches!(error, Error::Other(_)));
+        let content = std::fs::read_to_string(&output).unwrap();
+        assert_eq!(
+            content,
+            "X,value,status,exit_code\n1,,failed,1\n2,2,ok,0\n3,,failed,1\n4,,failed,1\n"
+        );
+    }
+
+    #[cfg(target_os = "linux")]
+    #[test]
+    fn test_sweep_memory_limit() {
+        let (output, _output) = temp_output("runexp_test_sweep_memory.csv");
+
+        // In X=2, tail holds a 200 MB line in memory until the input ends
+        let started = Instant::now();
+        let results = Sweep::new()
+            .param("X", "1,2")
+            .script(
+                "if [ $X = 2 ]; then { head -c 200000000 /dev/zero; sleep 10; } | tail >/dev/null; fi; \
+                 echo \"value: $X\"",
+            )
+            .metrics(["value"])
+            .output(output.clone())
+            .with_options(|options| {
+                options.record_status = true;
+                options.memory_limit = Some(50 << 20);
+            })
+            .run()
+            .unwrap();
+        assert!(started.elapsed() < Duration::from_secs(8));
+        assert_eq!((results.new, results.failed), (1, 1));
+        let content = std::fs::read_to_string(&output).unwrap();
+        assert_eq!(
+            content,
+            "X,value,status,exit_code\n1,1,ok,0\n2,,oom-killed,\n"
+        );
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_sweep_abort_if_and_stop_when() {
+        let (output, _output) = temp_output("runexp_test_sweep_abort.csv");
+
+        // X=1 diverges, X=2 reaches its goal; each would print for 5 seconds
+        let started = Instant::now();
+        let results = Sweep::new()
+            .param("X", "1,2")
+            .script(
+                "i=0; while [ $i -lt 100 ]; do i=$((i+1)); \
+                 echo \"loss: $(((2-X)*i*5))\"; echo \"step: $i\"; sleep 0.05; done",
+            )
+            .metrics(["loss", "step"])
+            .output(output.clone())
+            .with_options(|options| {
+                options.record_status = true;
+                options.retries = 1;
+                options.abort_if = vec![Condition::parse("loss > 10").unwrap()];
+                options.stop_when = vec![Condition::parse("step>=5").unwrap()];
+            })
+            .run()
+            .unwrap();
+        assert!(started.elapsed() < Duration::from_secs(3));
+        assert_eq!((results.new, results.failed), (1, 1));
+
+        // The aborted run is not retried and keeps the values printed until then
+        let aborted = &results.runs[0];
+        assert!(aborted.failed);
+        assert!(aborted.metric("loss").unwrap() > 10.0);
+        let content = std::fs::read_to_string(&output).unwrap();
+        assert!(content.lines().nth(1).unwrap().ends_with(",aborted,"));
+        let reached = &results.runs[1];
+        assert!(!reached.failed);
+        assert!(reached.metric("step").unwrap() >= 5.0);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_sweep_log_dir() {
+        let dir = std::env::temp_dir().join("runexp_test_log_dir");
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(&dir).unwrap();
+        let output = dir.join("results.csv").to_string_lossy().to_string();
+        let logs = dir.join("logs").to_string_lossy().to_string();
+
+        let sweep = |values: &str, output: &str| {
+            Sweep::new()
+                .param("X", values)
+                .script("echo \"value: $X\"; echo progress $X >&2")
+                .metrics(["value"])
+                .output(output)
+                .with_options(|options| {
+                    options.log_dir = Some(logs.clone());
+                    options.name = Some("logged".to_string());
+                })
+                .run()
+                .unwrap()
+        };
+        assert_eq!(sweep("1,2", &output).new, 2);
+        let records = csv::read_csv(&output).unwrap();
+        assert_eq!(records[0], ["name", "X", "value", "log"]);
+        // The files are named by the sweep's name and the run id
+        let stem = &records[2][3];
+        let id = stem.strip_prefix(&format!("{}/logged-", logs)).unwrap();
+        assert_eq!(id.len(), 12);
+        assert_ne!(stem, &records[1][3]);
+        let stderr = std::fs::read_to_string(format!("{}.stderr", stem)).unwrap();
+        assert_eq!(stderr, "progress 2\n");
+
+        // and keep their names in another grid
+        let other = dir.join("other.csv").to_string_lossy().to_string();
+        sweep("0,2", &other);
+        assert_eq!(&csv::read_csv(&other).unwrap()[2][3], stem);
+
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_sweep_workdir() {
+        let dir = std::env::temp_dir().join("runexp_test_workdir");
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(&dir).unwrap();
+        let output = dir.join("results.csv").to_string_lossy().to_string();
+
+        // Every run writes the same file name into a directory of its own, from which
+        // --collect picks it up
+        let results = Sweep::new()
+            .param("X", "1,2")
+            .script("echo $X > out.txt; echo \"value: $X\"")
+            .metrics(["value"])
+            .output(output.clone())
+            .with_options(|options| {
+                options.workdir = Some(format!("{}/runs/x{{x}}", dir.display()));
+                options.collect = vec!["out.txt".to_string()];
+                options.collect_copy = true;
+                options.artifact_dir = dir.join("artifacts").to_string_lossy().to_string();
+                options.name = Some("wd".to_string());
+            })
+            .run()
+            .unwrap();
+        assert_eq!(results.new, 2);
+        for x in ["1", "2"] {
+            let written = std::fs::read_to_string(dir.join(format!("runs/x{}/out.txt", x)));
+            assert_eq!(written.unwrap(), format!("{}\n", x));
+        }
+        // Collected into a directory named by the sweep's name and the run id
+        let artifact = &results.runs[1].artifacts[0];
+        let run_dir = std::path::Path::new(artifact).parent().unwrap();
+        assert_eq!(run_dir.parent().unwrap(), dir.join("artifacts"));
+        let run_dir = run_dir.file_name().unwrap().to_string_lossy().to_string();
+        assert_eq!(run_dir.strip_prefix("wd-").unwrap().len(), 12);
+        assert_eq!(std::fs::read_to_string(artifact).unwrap(), "2\n");
+
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_sweep_setup_and_teardown() {
+        let dir = std::env::temp_dir().join("runexp_test_setup");
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(&dir).unwrap();
+        let output = dir.join("results.csv").to_string_lossy().to_string();
+
+        // The hooks run in the directory of the results file, the runs in between
+        let sweep = |setup: &str| {
+            let setup = setup.to_string();
+            Sweep::new()
+                .param("X", "1,2")
+                .script("[ -e $DIR/server ] && echo \"value: $X\"")
+                .env("DIR", dir.to_string_lossy())
+                .metrics(["value"])
+                .output(output.clone())
+                .with_options(move |options| {
+                    options.setup = Some(setup);
+                    options.teardown = Some("rm server && touch stopped".to_string());
+                })
+                .run()
+        };
+        // A failing setup stops the sweep before any run, without a teardown
+        assert!(sweep("exit 1").is_err());
+        assert!(!dir.join("stopped").exists());
+        sweep("touch server").unwrap();
+        assert!(dir.join("stopped").exists());
+        assert!(!dir.join("server").exists());
+        let content = std::fs::read_to_string(&output).unwrap();
+        assert_eq!(content, "X,value\n1,1\n2,2\n");
+
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_sweep_optimize() {
+        let dir = std::env::temp_dir().join("runexp_test_optimize");
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(&dir).unwrap();
+        let output = dir.join("results.csv").to_string_lossy().to_string();
+
+        let sweep = || {
+            Sweep::new()
+                .param("X", "0:40")
+                .param("MODE", "a,b")
+                .script("echo \"score: $(( (X-25)*(X-25) ))\"")
+                .metrics(["score"])
+                .output(output.clone())
+                .concurrency(2)
+                .with_options(|options| {
+                    options.optimize = Some("score".to_string());
+                    options.budget = Some(12);
+                })
+                .run()
+                .unwrap()
+        };
+        let results = sweep();
+        assert_eq!(results.new, 12);
+        let best = results.runs.iter().filter_map(|run| run.metric("score"));
+        assert!(best.fold(f64::INFINITY, f64::min) <= 16.0);
+        assert!(dir.join("results.csv.opt").exists());
+
+        // Resuming replays the proposals from the state file instead of starting over
+        let results = sweep();
+        assert_eq!((results.skipped, results.new), (12, 0));
+
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_sweep_shuffle() {
+        let dir = std::env::temp_dir().join("runexp_test_shuffle");
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(&dir).unwrap();
+        let output = dir.join("results.csv").to_string_lossy().to_string();
+
+        let results = Sweep::new()
+            .param("X", "1:9")
+            .script("echo $X >> $DIR/order && echo \"value: $X\"")
+            .env("DIR", dir.to_string_lossy())
+            .metrics(["value"])
+            .output(output.clone())
+            .with_options(|options| options.shuffle = Some(3))
+            .run()
+            .unwrap();
+        assert_eq!(results.new, 8);
+
+        // The runs start out of order, but the results file is in grid order
+        let order = std::fs::read_to_string(dir.join("order")).unwrap();
+        assert_ne!(order, "1\n2\n3\n4\n5\n6\n7\n8\n");
+        let content = std::fs::read_to_string(&output).unwrap();
+        assert_eq!(content, "X,value\n1,1\n2,2\n3,3\n4,4\n5,5\n6,6\n7,7\n8,8\n");
+
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_sweep_limit() {
+        let dir = std::env::temp_dir().join("runexp_test_limit");
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(&dir).unwrap();
+        let output = dir.join("results.csv").to_string_lossy().to_string();
+
+        let sweep = |limit: Option<usize>| {
+            Sweep::new()
+                .param("X", "1:9")
+                .script("echo \"value: $X\"")
+                .metrics(["value"])
+                .output(output.clone())
+                .with_options(move |options| {
+                    options.shuffle = Some(3);
+                    options.limit = limit;
+                })
+                .run()
+                .unwrap()
+        };
+        let results = sweep(Some(3));
+        assert_eq!(results.new, 3);
+        let first: Vec<usize> = results.runs.iter().map(|run| run.index).collect();
+
+        // The full sweep starts with the same combinations and skips them
+        let results = sweep(None);
+        assert_eq!((results.skipped, results.new), (3, 5));
+        let skipped: Vec<usize> = results
+            .runs
+            .iter()
+            .filter(|run| run.resumed)
+            .map(|run| run.index)
+            .collect();
+        assert_eq!(skipped, first);
+
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_sweep_halving() {
+        let dir = std::env::temp_dir().join("runexp_test_halving");
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(&dir).unwrap();
+        let output = dir.join("results.csv").to_string_lossy().to_string();
+
+        let sweep = || {
+            Sweep::new()
+                .param("X", "1:7")
+                .script("echo \"loss: $(( (X-4)*(X-4)*100/EPOCHS ))\"")
+                .metrics(["loss"])
+                .output(output.clone())
+                .concurrency(2)
+                .with_options(|options| {
+                    options.halving = Some(("EPOCHS".to_string(), 1.0, 4.0));
+                    options.halving_metric = Some("loss".to_string());
+                    options.halving_eta = 2;
+                })
+                .run()
+                .unwrap()
+        };
+        let results = sweep();
+        assert_eq!(results.new, 6 + 3 + 1);
+        let content = std::fs::read_to_string(&output).unwrap();
+        assert!(content.starts_with("X,EPOCHS,ROUND,loss\n"));
+        let mut later: Vec<&str> = content.lines().filter(|l| !l.contains(",1,1,")).collect();
+        later.sort_unstable();
+        assert_eq!(
+            later,
+            [
+                "3,2,2,50",
+                "4,2,2,0",
+                "4,4,3,0",
+                "5,2,2,50",
+                "X,EPOCHS,ROUND,loss"
+            ]
+        );
+
+        let results = sweep();
+        assert_eq!((results.skipped, results.new), (10, 0));
+
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_sweep_repeat() {
+        let dir = std::env::temp_dir().join("runexp_test_repeat");
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(&dir).unwrap();
+        let output = dir.join("results.csv").to_string_lossy().to_string();
+
+        let sweep = Sweep::new()
+            .param("X", "1,2")
+            .script("echo \"value: $((X * 10 + REP))\"")
+            .metrics(["value"])
+            .output(output.clone())
+            .with_options(|options| options.repeat = 3);
+        assert_eq!(sweep.param_names(), ["X", "REP"]);
+        let results = sweep.run().unwrap();
+        assert_eq!(results.new, 6);
+        let content = std::fs::read_to_string(&output).unwrap();
+        assert!(content.starts_with("X,REP,value\n1,1,11\n1,2,12\n"));
+        let summary = std::fs::read_to_string(dir.join("results.agg.csv")).unwrap();
+        assert_eq!(
+            summary,
+            "X,value_mean,value_std,value_n\n1,12,1,3\n2,22,1,3\n"
+        );
+
+        // --repeats numbers the runs from 0 and writes no summary
+        std::fs::remove_dir_all(&dir).unwrap();
+        std::fs::create_dir_all(&dir).unwrap();
+        let sweep = sweep.with_options(|options| options.repeats = true);
+        assert_eq!(sweep.param_names(), ["X", "REP"]);
+        sweep.run().unwrap();
+        let content = std::fs::read_to_string(&output).unwrap();
+        assert!(content.starts_with("X,REP,value\n1,0,10\n1,1,11\n1,2,12\n2,0,20\n"));
+        assert!(!dir.join("results.agg.csv").exists());
+
+        // REP is there for a single run too
+        std::fs::remove_dir_all(&dir).unwrap();
+        std::fs::create_dir_all(&dir).unwrap();
+        let sweep = sweep.with_options(|options| options.repeat = 1);
+        assert_eq!(sweep.param_names(), ["X", "REP"]);
+        sweep.run().unwrap();
+        let content = std::fs::read_to_string(&output).unwrap();
+        assert_eq!(content, "X,REP,value\n1,0,10\n2,0,20\n");
+
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_sweep_migrate_rewrites_metadata() {
+        let dir = std::env::temp_dir().join("runexp_test_migrate_meta");
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(&dir).unwrap();
+        let output = dir.join("r.csv").to_string_lossy().to_string();
+        let run = |extra: &[&str]| {
+            let mut args = vec![
+                "--output",
+                &output,
+                "--on-mismatch",
+                "migrate",
+                "--x",
+                "1,2",
+            ];
+            args.extend_from_slice(extra);
+            args.push("true");
+            let args: Vec<String> = args.iter().map(|s| s.to_string()).collect();
+            let (params, stages, options) = parse_args(&args).unwrap();
+            Sweep::from_parts(params, stages, options).run().unwrap();
+            metadata::read_metadata(&metadata::metadata_path(&output)).unwrap()
+        };
+
+        let meta = run(&[]);
+        assert_eq!(metadata::metadata_value(&meta, "params"), Some("X"));
+        // Adding a parameter migrates the file, and the metadata follows
+        let meta = run(&["--y", "3"]);
+        assert_eq!(metadata::metadata_value(&meta, "params"), Some("X,Y"));
+        assert_eq!(metadata::metadata_value(&meta, "arg.6"), Some("--y"));
+        assert_eq!(metadata::metadata_value(&meta, "arg.7"), Some("3"));
+        let content = std::fs::read_to_string(&output).unwrap();
+        assert!(content.starts_with("X,Y\n"));
+
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_sweep_record_time_and_usage() {
+        let (output, _output) = temp_output("runexp_test_record_time.csv");
+
+        let sweep = Sweep::new()
+            .param("T", "0.2")
+            .script("sleep $T; echo \"value: 1\"")
+            .metrics(["value"])
+            .output(output.clone())
+            .with_options(|options| {
+                options.record_time = true;
+                options.record_usage = true;
+            });
+        let run = sweep.run().unwrap().runs.remove(0);
+        let duration = run.duration_s.unwrap();
+        assert!((0.2..5.0).contains(&duration));
+        assert!(run.usage.unwrap().max_rss_kb > 0);
+        let header = std::fs::read_to_string(&output).unwrap();
+        assert!(header.starts_with("T,value,duration_s,max_rss_kb,user_cpu_s,sys_cpu_s\n"));
+
+        // Resumed runs report the recorded time
+        let results = sweep.run().unwrap();
+        assert!(results.runs[0].resumed);
+        assert!((results.runs[0].duration_s.unwrap() - duration).abs() < 0.001);
+        assert_eq!(
+            results.runs[0].usage.map(|u| u.max_rss_kb),
+            run.usage.map(|u| u.max_rss_kb)
+        );
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_sweep_resource_tokens() {
+        let (output, _output) = temp_output("runexp_test_resource_tokens.csv");
+
+        // Three slots but two GPUs and two ports: no more than two runs at a time, each
+        // holding a GPU and a port no other running run has
+        let dir = std::env::temp_dir().join("runexp_test_resource_tokens");
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(&dir).unwrap();
+        let results = Sweep::new()
+            .param("X", "1,2,3,4")
+            .script(
+                "for f in $DIR/gpu$CUDA_VISIBLE_DEVICES $DIR/port$PORT; do \
+                 [ -e $f ] && exit 1; touch $f; done; sleep 0.1; \
+                 rm $DIR/gpu$CUDA_VISIBLE_DEVICES $DIR/port$PORT; \
+                 echo \"gpu: $CUDA_VISIBLE_DEVICES\"; echo \"port: $PORT\"",
+            )
+            .env("DIR", dir.to_string_lossy())
+            .metrics(["gpu", "port"])
+            .output(output.clone())
+            .concurrency(3)
+            .with_options(|options| {
+                options.resources = vec![
+                    (
+                        "CUDA_VISIBLE_DEVICES".to_string(),
+                        vec!["0".into(), "1".into()],
+                    ),
+                    ("PORT".to_string(), vec!["8000".into(), "8001".into()]),
+                ]
+            })
+            .run()
+            .unwrap();
+        assert_eq!(results.new, 4);
+        for (metric, tokens) in [
+            ("gpu", ["0", "0", "1", "1"]),
+            ("port", ["8000", "8000", "8001", "8001"]),
+        ] {
+            let mut values: Vec<_> = results
+                .runs
+                .iter()
+                .map(|r| r.metrics[metric].clone())
+                .collect();
+            values.sort();
+            assert_eq!(values, tokens);
+        }
+
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+
+    // Pretends to run the command and reports the product of the parameters
+    struct FakeBackend;
+
+    impl ExecutionBackend for FakeBackend {
+        fn launch(&self, _command: &[String], env: &[(String, String)]) -> Result<Job, String> {
+            let product = env
+                .iter()
+                .filter(|(name, _)| !name.starts_with("RUNEXP_"))
+                .map(|(_, v)| v.parse::<u64>().unwrap())
+                .product();
+            Ok(Job { id: product })
+        }
+
+        fn wait(&self, _job: &Job) -> Result<Option<i32>, String> {
+            Ok(Some(0))
+        }
+
+        fn collect(&self, job: Job) -> Result<(String, String), String> {
+            Ok((format!("product: {}\n", job.id), String::new()))
+        }
+    }
+
+    #[test]
+    fn test_sweep_record_meta() {
+        let (output, _output) = temp_output("runexp_test_record_meta.csv");
+
+        Sweep::new()
+            .param("A", "2,3")
+            .command(["true"])
+            .metrics(["product"])
+            .output(output.clone())
+            .backend(FakeBackend)
+            .with_options(|options| options.record_meta = true)
+            .run()
+            .unwrap();
+        let records = crate::csv::read_csv(&output).unwrap();
+        assert_eq!(
+            records[0],
+            ["A", "product", "started_at", "hostname", "git_commit"]
+        );
+        for row in &records[1..] {
+            // e.g. 2024-01-31T23:59:59Z
+            assert_eq!(row[2].len(), 20);
+            assert!(row[2].ends_with('Z'));
+            assert_eq!(row[3], records[1][3]);
+            assert_eq!(row[4], crate::snapshot::git_commit().unwrap_or_default());
+        }
+    }
+
+    #[test]
+    fn test_sweep_custom_backend() {
+        let (output, _output) = temp_output("runexp_test_sweep_backend.csv");
+
+        let results = Sweep::new()
+            .param("A", "2,3")
+            .param("B", "5")
+            .command(["not-a-real-command"])
+            .metrics(["product"])
+            .output(output.clone())
+            .concurrency(2)
+            .backend(FakeBackend)
+            .run()
+            .unwrap();
+        let products: Vec<_> = results.runs.iter().map(|r| r.metric("product")).collect();
+        assert_eq!(products, vec![Some(10.0), Some(15.0)]);
+    }
+
+    #[test]
+    fn test_sweep_cost_budget() {
+        let (output, _output) = temp_output("runexp_test_sweep_cost.csv");
+
+        // Costs 1, 4, 9, ...: the budget is reached after the second run
+        let results = Sweep::new()
+            .param("A", "1,2,3,4")
+            .param("B", "5")
+            .command(["not-a-real-command"])
+            .metrics(["product"])
+            .output(output.clone())
+            .backend(FakeBackend)
+            .with_options(|o| {
+                o.cost = Some(CostExpr::parse("a * product / B + 0 * duration_s").unwrap());
+                o.cost_budget = Some(5.0);
+            })
+            .run()
+            .unwrap();
+        assert_eq!(results.new, 2);
+        assert_eq!(results.cost, Some(5.0));
+    }
+
+    // Keeps every row it receives
+    struct MemorySink(Arc<Mutex<Vec<Vec<String>>>>);
+
+    impl ResultSink for MemorySink {
+        fn write_header(&mut self, columns: &[String]) -> Result<(), String> {
+            self.0.lock().unwrap().push(columns.to_vec());
+            Ok(())
+        }
+
+        fn append_row(&mut self, values: &[String]) -> Result<(), String> {
+            self.0.lock().unwrap().push(values.to_vec());
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn test_sweep_custom_sink() {
+        let (output, _output) = temp_output("runexp_test_sweep_sink.csv");
+
+        let rows = Arc::new(Mutex::new(Vec::new()));
+        Sweep::new()
+            .param("A", "2")
+            .param("B", "5,7")
+            .command(["not-a-real-command"])
+            .metrics(["product"])
+            .output(output.clone())
+            .backend(FakeBackend)
+            .sink(MemorySink(Arc::clone(&rows)))
+            .run()
+            .unwrap();
+        assert_eq!(
+            *rows.lock().unwrap(),
+            vec![
+                vec!["A".to_string(), "B".to_string(), "product".to_string()],
+                vec!["2".to_string(), "5".to_string(), "10".to_string()],
+                vec!["2".to_string(), "7".to_string(), "14".to_string()],
+            ]
+        );
+    }
+}
@@ -1,12 +1,10 @@
+use runexp::{
+    Error, HistoryRecord, Sweep, json_errors, parse_args, run_bundle, run_diff, run_history,
+    run_merge, run_refine, run_report, run_show,
+};
 use std::env;
 
-mod evaluator;
-mod executor;
-mod parser;
-
-use evaluator::evaluate_params;
-use executor::execute_experiments;
-use parser::parse_args;
+type Subcommand = fn(&[String]) -> Result<(), Error>;
 
 fn main() {
     let args: Vec<String> = env::args().skip(1).collect();
@@ -16,56 +14,118 @@ fn main() {
         return;
     }
 
+    // Subcommands operating on existing result files
+    let subcommand: Option<Subcommand> = match args[0].as_str() {
+        "report" => Some(run_report),
+        "bundle" => Some(run_bundle),
+        "diff" => Some(run_diff),
+        "merge" => Some(run_merge),
+        "history" => Some(run_history),
+        "show" => Some(run_show),
+        "refine" => Some(run_refine),
+        _ => None,
+    };
+    if let Some(subcommand) = subcommand {
+        let (args, json_errors) = subcommand_args(&args[1..]);
+        if let Err(e) = subcommand(&args) {
+            exit_with(e, json_errors);
+        }
+        return;
+    }
+
     // Check for help flag
     if args.contains(&"--help".to_string()) || args.contains(&"-h".to_string()) {
         print_usage();
         return;
     }
 
+    let json_errors = json_errors(&args);
+
     // Parse command line arguments
-    let (params, command, options) = match parse_args(&args) {
+    let (params, stages, options) = match parse_args(&args) {
         Ok(result) => result,
-        Err(e) => {
-            if e == "HELP_REQUESTED" {
-                print_usage();
-                return;
-            }
-            eprintln!("Error: {}", e);
-            eprintln!("Use --help or -h for usage information");
-            std::process::exit(1);
+        Err(Error::HelpRequested) => {
+            print_usage();
+            return;
         }
+        Err(e) => exit_with(e, json_errors),
     };
 
     // Validate that at least one of --metrics or --preserve-output is specified
     if options.metrics.is_empty() && !options.preserve_output {
-        eprintln!("Error: At least one of --metrics or --preserve-output must be specified");
-        eprintln!("       (Otherwise no meaningful output would be generated)");
-        eprintln!("Use --help or -h for usage information");
-        std::process::exit(1);
+        // Exiting skips destructors, so drop the options first to remove a script file
+        drop(options);
+        exit_with(
+            Error::usage(
+                "At least one of --metrics or --preserve-output must be specified \
+                 (otherwise no meaningful output would be generated)",
+            ),
+            json_errors,
+        );
     }
 
-    if params.is_empty() {
-        eprintln!("Error: No parameters specified");
-        eprintln!("Use --help or -h for usage information");
-        std::process::exit(1);
-    }
-
-    // Evaluate parameter combinations
-    let combinations = match evaluate_params(&params) {
-        Ok(combos) => combos,
-        Err(e) => {
-            eprintln!("Error evaluating parameters: {}", e);
-            std::process::exit(1);
+    let watch = !options.watch.is_empty();
+    let history = if watch || options.dry_run {
+        None
+    } else {
+        HistoryRecord::start(&options)
+    };
+    let sweep = Sweep::from_parts(params, stages, options);
+    let result = if watch {
+        sweep.watch()
+    } else {
+        let result = sweep.run();
+        if let Some(history) = history {
+            history.finish(&result);
         }
+        drop(sweep);
+        result.map(|_| ())
     };
+    if let Err(e) = result {
+        exit_with(e, json_errors);
+    }
+}
 
-    println!("Generated {} parameter combinations", combinations.len());
+// The arguments of a subcommand without --error-format, which all subcommands accept, and
+// whether errors are printed as JSON
+fn subcommand_args(args: &[String]) -> (Vec<String>, bool) {
+    let mut rest = Vec::new();
+    let mut format = None;
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        if arg == "--error-format" {
+            format = Some(args.next().cloned().unwrap_or_default());
+        } else if let Some(value) = arg.strip_prefix("--error-format=") {
+            format = Some(value.to_string());
+        } else {
+            rest.push(arg.clone());
+        }
+    }
+    match format.as_deref() {
+        None => (rest, json_errors(&[])),
+        Some("json") => (rest, true),
+        Some("text") => (rest, false),
+        Some(format) => exit_with(
+            Error::usage(format!(
+                "Invalid --error-format value: {} (expected text or json)",
+                format
+            )),
+            false,
+        ),
+    }
+}
 
-    // Execute experiments
-    if let Err(e) = execute_experiments(&combinations, &command, &options) {
-        eprintln!("Error executing experiments: {}", e);
-        std::process::exit(1);
+// Report an error and exit with the code of its kind
+fn exit_with(error: Error, json: bool) -> ! {
+    if json {
+        eprintln!("{}", error.to_json());
+    } else {
+        eprintln!("Error: {}", error);
+        if let Error::Usage { .. } = error {
+            eprintln!("Use --help or -h for usage information");
+        }
     }
+    std::process::exit(error.exit_code());
 }
 
 fn print_usage() {
@@ -73,14 +133,189 @@ fn print_usage() {
     println!();
     println!("Usage: runexp [OPTIONS] --param1 value1 --param2 value2 ... COMMAND [ARGS...]");
     println!("       runexp [OPTIONS] --param1 value1 --param2 value2 ... < script.sh");
+    println!("       runexp [OPTIONS] --param1 value1 --param2 value2 ... -f script.sh [ARGS...]");
+    println!("       runexp report [--html] [FILE]");
+    println!("       runexp bundle ARCHIVE.tar.gz [FILE] [--log-dir DIR]");
+    println!("       runexp diff OLD.csv NEW.csv [--metrics M,...] [--threshold PCT]");
+    println!("       runexp merge OUTPUT.csv INPUT.csv...");
+    println!("       runexp history [-n N | --all]");
+    println!("       runexp show ID [--rerun]");
+    println!("       runexp refine [FILE] --metric M [--around-best] [--factor N]");
     println!();
     println!("Options:");
     println!("  --stdout               Parse output only from stdout");
     println!("  --stderr               Parse output only from stderr");
     println!("  -m, --metrics m1,m2    Filter results by metrics (comma-separated)");
     println!("  -p, --preserve-output  Include stdout/stderr columns in the result CSV");
+    println!(
+        "  --max-output-bytes N   Keep at most N bytes of each stream, head and tail (default: 65536)"
+    );
+    println!(
+        "  --verbose              Show the output of runs live, prefixed with the combination number"
+    );
+    println!("  --pty                  Run commands with their output on a pseudo-terminal (Unix)");
     println!("  -o, --output FILE      Output file (default: results.csv)");
     println!("  -c, --concurrency N    Run up to N experiments in parallel (default: 1)");
+    println!("  --timeout SECONDS      Stop a run whose stage takes longer and count it as failed");
+    println!(
+        "  --stall-timeout MIN    Stop a run that prints nothing for MIN minutes and count it as failed"
+    );
+    println!(
+        "  --memory-limit SIZE    Stop a run using more memory, e.g. 16G, and count it as failed (Linux)"
+    );
+    println!(
+        "  --rlimit NAME=VALUE    Set a resource limit for every run, e.g. nofile=4096 (repeatable)"
+    );
+    println!(
+        "  --abort-if COND        Stop a run and count it as failed once COND holds, e.g. \"loss>100\""
+    );
+    println!(
+        "  --stop-when COND       Stop a run and count it as done once COND holds, e.g. \"acc>=0.99\""
+    );
+    println!(
+        "  --gpus ID,...          Give every run a free GPU from the list as CUDA_VISIBLE_DEVICES"
+    );
+    println!("  --resource NAME=T,...  Give every run a free token from the list as NAME");
+    println!("  --cpus-per-run N       Pin every run to N cores no other running run uses (Linux)");
+    println!("  --nice N               Run commands with niceness N, e.g. 10 or {{nice}} (Unix)");
+    println!(
+        "  --ionice CLASS[:N]     Run commands with I/O priority idle or best-effort:N (Linux)"
+    );
+    println!("  --retries N            Attempt a failed run up to N more times (default: 0)");
+    println!(
+        "  --retry-delay SECONDS  Wait before the first retry, doubling per retry (default: 5)"
+    );
+    println!("  --max-failures N       Stop the sweep after N runs in a row failed");
+    println!("  --record-status        Add status and exit_code columns and record failed runs");
+    println!(
+        "  --rerun-failed         Run combinations recorded as failed or with empty metrics again"
+    );
+    println!(
+        "  --record-time          Add a duration_s column with the wall-clock time of every run"
+    );
+    println!(
+        "  --record-usage         Add max_rss_kb, user_cpu_s and sys_cpu_s columns for every run"
+    );
+    println!(
+        "  --record-meta          Add started_at, hostname and git_commit columns for every run"
+    );
+    println!("  --log-dir DIR          Save the full stdout/stderr of every run in DIR");
+    println!(
+        "  --repeat N             Run every combination N times and write mean/std to *.agg.csv"
+    );
+    println!("  --repeats N            Run every combination N times, numbered by REP from 0");
+    println!("  --auto-seed            Export a deterministic SEED to every run and record it");
+    println!(
+        "  --sweep-seed N         Seed the SEED values are derived from (implies --auto-seed)"
+    );
+    println!("  --snapshot CMD         Also record the output of CMD in the environment snapshot");
+    println!("  --watch FILE,...       Re-run the sweep whenever one of the files changes");
+    println!(
+        "  --watch-invalidate M   On a change, re-run all combinations (all) or only missing (none)"
+    );
+    println!("  --collect GLOB,...     Move matching files into ARTIFACT_DIR/N/ after each run");
+    println!("  --collect-copy         Copy the files matched by --collect instead of moving them");
+    println!(
+        "  --workdir TEMPLATE     Run every combination in its own directory, e.g. runs/{{lr}}_{{batch}}"
+    );
+    println!("  --stdin-file TEMPLATE  Feed every run a file on stdin, e.g. inputs/{{n}}.txt");
+    println!(
+        "  --scratch              Give every run a temporary directory as RUNEXP_TMPDIR and TMPDIR"
+    );
+    println!("  --keep-failed-scratch  Keep the --scratch directory of runs that failed");
+    println!(
+        "  --clean-env            Start runs with only PATH, HOME, --env values and parameters"
+    );
+    println!("  --pass-env VAR,...     Also pass these variables to runs (implies --clean-env)");
+    println!("  --setup CMD            Run a shell command once before the first combination");
+    println!("  --teardown CMD         Run a shell command once after the last combination");
+    println!("  --artifact-dir DIR     Directory for collected files (default: artifacts)");
+    println!(
+        "  --verify-input F=SHA   Abort unless file F has checksum SHA (sha256:HEX, repeatable)"
+    );
+    println!("  --verify-each-run      Check --verify-input files before every run too");
+    println!(
+        "  --track-input FILE     Record the checksum of FILE and refuse to resume once it changed"
+    );
+    println!("  --cost EXPR            Cost of a run, e.g. \"GPU*duration_s/3600*2.5\"");
+    println!("  --cost-budget X        Start no more runs once the total cost reaches X");
+    println!(
+        "  --expected-cost EXPR   Start the combinations cheapest first by EXPR of their parameters"
+    );
+    println!(
+        "  --costliest-first      With --expected-cost, start the most expensive combinations first"
+    );
+    println!(
+        "  --shuffle [SEED]       Start the combinations in a random order; the results stay in grid order"
+    );
+    println!("  --limit N              Run only the first N combinations, e.g. to try out a sweep");
+    println!(
+        "  --dry-run              Print the environment and command of every run, run nothing"
+    );
+    println!(
+        "  --force                Resume even if the command, inputs or environment changed since the last start"
+    );
+    println!("  --max-combinations N   Refuse to start sweeps larger than N runs (default: 10000)");
+    println!("  --yes                  Start even if the sweep exceeds --max-combinations");
+    println!("  --stage NAME CMD       Run CMD as a named stage; stages run in order (repeatable)");
+    println!(
+        "  --name NAME            Name the sweep: adds a name column, output defaults to NAME.csv"
+    );
+    println!("  --env KEY=VALUE        Export a fixed variable to every run (repeatable)");
+    println!("  --env-file FILE        Export the KEY=VALUE lines of FILE to every run");
+    println!("  --tag KEY=VALUE        Add a constant KEY column to every row (repeatable)");
+    println!(
+        "  --on-mismatch ACTION   If the output file has another header: ask, migrate, new or abort"
+    );
+    println!(
+        "  -f, --file SCRIPT      Run a script file with bash; args from the first non-option word on go to it"
+    );
+    println!("  --shell CMD            Run a command read from stdin with CMD (default: bash)");
+    println!("  --describe NAME=TEXT   Describe a parameter in the sweep metadata and reports");
+    println!(
+        "  --no-split NAME,...    Take the values of these parameters as one value, commas and all"
+    );
+    println!(
+        "  --preserve-case        Export parameters under their names as written, like batchSize"
+    );
+    println!("  --no-dedup             Run combinations with the same parameter values every time");
+    println!("  --parser NAME          Metric parser: heuristic, json, logfmt, regex:PATTERN");
+    println!("  --samples N            Draw N random points for parameters like uniform(A,B)");
+    println!(
+        "  --strategy NAME        Spread the --samples points: random (default), lhs or sobol"
+    );
+    println!("  --sample N             Run only N randomly chosen combinations of the grid");
+    println!(
+        "  --optimize METRIC      Search for the smallest METRIC instead of running the whole grid"
+    );
+    println!("  --maximize             With --optimize or --halving, look for the largest value");
+    println!("  --budget N             With --optimize, run at most N combinations (default: 20)");
+    println!(
+        "  --halving NAME=MIN:MAX Successive halving over the budget parameter NAME, from MIN to MAX"
+    );
+    println!("  --halving-metric M     The metric that decides which runs continue with --halving");
+    println!(
+        "  --halving-eta N        Keep the best 1/N of the runs after each round, with N times the budget (default: 3)"
+    );
+    println!("  --sample-seed N        Seed of the random points and --sample (default: 0)");
+    println!(
+        "  --where COND           Run only combinations where COND holds, e.g. \"bs*gpu <= 512\" (repeatable)"
+    );
+    println!(
+        "  --zip NAME,NAME,...    Vary these parameters together instead of crossing them (repeatable)"
+    );
+    println!(
+        "  --preset N=L:{{K=V}}     Sweep over presets L of N that set parameters K together (see readme)"
+    );
+    println!("  --skip NAME=PAT,...    Skip combinations matching all conditions (repeatable)");
+    println!("  --exclude NAME=PAT,... Same as --skip");
+    println!("  --only NAME=PAT,...    Run only combinations matching all conditions (repeatable)");
+    println!(
+        "  --only 3,7 or A:B      Run only the combinations at these positions of the grid (from 0)"
+    );
+    println!("  --shard K/N            Run only the K-th of N disjoint parts of the sweep");
+    println!("  --profile NAME         Apply options from [profile.NAME] in the config file");
+    println!("  --error-format FORMAT  Print errors as text (default) or json");
     println!("  -h, --help             Show this help message");
     println!();
     println!("Parameters:");
@@ -89,16 +324,58 @@ fn print_usage() {
     println!("  Parameter names are converted to uppercase environment variables");
     println!("  Dashes and underscores in names are converted to underscores");
     println!("  Example: --batch-size becomes BATCH_SIZE, --gpu becomes GPU, -n becomes N");
+    println!(
+        "  Options are lowercase; write parameters in uppercase (-P, --M) to avoid collisions"
+    );
+    println!("  Aliases for long options: RUNEXP_ALIASES=\"conc=concurrency,out=output\"");
+    println!();
+    println!("Environment:");
+    println!("  Any option can be set as RUNEXP_<OPTION>, e.g. RUNEXP_OUTPUT, RUNEXP_CONCURRENCY");
+    println!("  Command-line options take precedence over environment and config profiles");
     println!();
     println!("Values can contain:");
     println!("  - Comma-separated lists: 1,2,4");
     println!("  - Ranges: 1:4 (expands to 1,2,3)");
+    println!("    - Decimal bounds and steps: 0.1:0.5:0.1");
+    println!("    - Multiplicative steps: 1:1024:*2 (1,2,4,...,512)");
+    println!("    - Log-spaced: log(1e-5:1e-1:5) (5 points, both ends included)");
     println!("  - Expressions referencing other parameters:");
     println!("    - Variables: n");
     println!("    - Addition: n+1, 2+n");
+    println!("    - Subtraction and negative numbers: n-1, -n");
     println!("    - Multiplication: 2n, n*n");
+    println!("    - Division and decimals: n/10, 0.1n, lr*2");
     println!("    - Exponentiation: n^2");
+    println!("    - Functions: min(n, 8), max, abs, floor, ceil, pow");
+    println!("    - Conditionals: \"n>4 ? fp16 : fp32\"");
+    println!("    - The grid position, from 0: 8000+idx");
+    println!(
+        "    - String operations: upper(x), lower(x), replace(x, \"/\", \"_\"), x ++ \"_\" ++ y"
+    );
+    println!("  - Values from a file, one per line: @values.txt");
+    println!("  - Output lines of a command: cmd:ls checkpoints/*.pt");
+    println!("  - Files matching a pattern: 'configs/*.yaml'");
+    println!("  - Templates filled from the same combination: \"runs/{{model}}_{{n}}\"");
+    println!("    - Builtins: {{today}}, {{now}}, {{env(NAME)}}");
     println!("  - Literal strings");
+    println!("  - Values with commas kept whole: '\"0,1\"', '[64,128]', 'a\\,b'");
+    println!();
+    println!("Subcommands:");
+    println!("  report [--html] [FILE]  Print a Markdown (or HTML) report of a results file");
+    println!("  bundle ARCHIVE [FILE]   Pack a results file with its metadata, snapshots, script");
+    println!("                          and logs into a .tar.gz (or .tar) archive");
+    println!("  diff OLD NEW            Match rows by parameters and report metric changes above");
+    println!("                          --threshold PCT (default 5%); --abs-threshold X also");
+    println!("                          requires an absolute change; --maximize/--minimize M,...");
+    println!("                          mark changes as improvements or regressions");
+    println!("  merge OUTPUT INPUT...   Combine results files, e.g. of shards, into one file");
+    println!("  history [-n N | --all]  List the last 20 sweeps, or the last N or all of them");
+    println!("  show ID [--rerun]       Print everything recorded about a sweep, or run it again");
+    println!(
+        "  refine [FILE]           Run a finer grid around the best combination by --metric M"
+    );
+    println!("                          (--around-best), with --factor N - 1 points towards each");
+    println!("                          neighbouring value (default: 2), into the same file");
     println!();
     println!("Examples:");
     println!("  # Filter results by metrics");
@@ -113,9 +390,19 @@ fn print_usage() {
     println!("  python evaluate.py");
     println!("  EOF");
     println!();
+    println!("  # Train then evaluate, collecting metrics from each stage");
+    println!(
+        "  runexp --metrics loss,accuracy --lr 0.1,0.01 --stage train \"python train.py\" --stage eval \"python eval.py\""
+    );
+    println!();
     println!("  # Preserve stdout/stderr in the output CSV");
     println!("  runexp --preserve-output --gpu 1,2 --batchsize 32 python train.py");
     println!();
+    println!("  # Skip a known-bad corner and restrict to one model family");
+    println!(
+        "  runexp --metrics accuracy --gpu 1,2,4 --batchsize 32,64 --skip GPU=4,BATCHSIZE=64 --only \"MODEL=resnet*\" python train.py"
+    );
+    println!();
     println!("  # Specify output file");
     println!(
         "  runexp --output my_results.csv --metrics accuracy --gpu 1,2 --batchsize 32 python train.py"
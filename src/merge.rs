@@ -0,0 +1,222 @@
+use crate::csv::{escape_csv_field, read_csv};
+use crate::diff::{is_metric_column, is_output_column, is_run_column};
+use crate::error::Error;
+use crate::metadata::{metadata_path, metadata_value, read_metadata, write_metadata};
+use crate::snapshot::snapshot_path;
+use std::collections::HashSet;
+use std::fs;
+use std::path::Path;
+
+// `runexp merge OUTPUT INPUT...`: combine results files, typically those of a sweep run
+// in shards (--shard K/N) on several machines, into one file with the metadata of the
+// first input and the environment snapshots of all of them.
+pub fn run_merge(args: &[String]) -> Result<(), Error> {
+    if let Some(arg) = args.iter().find(|arg| arg.starts_with('-')) {
+        return Err(Error::usage(format!("Unexpected argument: {}", arg)));
+    }
+    let [output, inputs @ ..] = args else {
+        return Err(Error::usage("Usage: runexp merge OUTPUT.csv INPUT.csv..."));
+    };
+    if inputs.is_empty() {
+        return Err(Error::usage("Usage: runexp merge OUTPUT.csv INPUT.csv..."));
+    }
+    if Path::new(output).exists() {
+        return Err(Error::Io {
+            path: output.clone(),
+            message: format!("{} already exists; choose another output file", output),
+        });
+    }
+
+    let mut files = Vec::new();
+    for input in inputs {
+        let records = read_csv(input).map_err(|message| Error::Io {
+            path: input.clone(),
+            message,
+        })?;
+        if records.is_empty() {
+            return Err(Error::Incompatible {
+                path: input.clone(),
+                message: format!("Empty results file: {}", input),
+            });
+        }
+        files.push((input.as_str(), records));
+    }
+    let metadata: Vec<Vec<(String, String)>> = inputs
+        .iter()
+        .map(|input| read_metadata(&metadata_path(input)).unwrap_or_default())
+        .collect();
+    warn_metadata_differences(inputs, &metadata);
+
+    let merged = merge_records(&files, metadata_value(&metadata[0], "metrics"))?;
+    let content: String = merged
+        .iter()
+        .map(|row| {
+            let fields: Vec<String> = row.iter().map(|v| escape_csv_field(v)).collect();
+            format!("{}\n", fields.join(","))
+        })
+        .collect();
+    fs::write(output, content).map_err(|e| Error::io(output, e))?;
+
+    if !metadata[0].is_empty() {
+        let entries: Vec<(String, String)> = metadata[0]
+            .iter()
+            .filter(|(key, _)| key != "shard")
+            .cloned()
+            .collect();
+        let path = metadata_path(output);
+        write_metadata(&path, &entries).map_err(|message| Error::Io { path, message })?;
+    }
+    let snapshots: Vec<String> = inputs
+        .iter()
+        .filter_map(|input| fs::read_to_string(snapshot_path(input)).ok())
+        .collect();
+    if !snapshots.is_empty() {
+        let path = snapshot_path(output);
+        fs::write(&path, snapshots.join("\n")).map_err(|e| Error::io(&path, e))?;
+    }
+
+    println!(
+        "Merged {} rows from {} files into {}",
+        merged.len() - 1,
+        inputs.len(),
+        output
+    );
+    Ok(())
+}
+
+// Rows of all files under the header of the first. Rows identifying the same run as an
+// earlier one (same values in all but the measured, output and status columns) are dropped.
+// Without the list of metrics, only identical rows are dropped.
+fn merge_records(
+    files: &[(&str, Vec<Vec<String>>)],
+    metrics: Option<&str>,
+) -> Result<Vec<Vec<String>>, Error> {
+    let header = files[0].1[0].clone();
+    let metrics: Vec<String> = metrics
+        .map(|m| m.split(',').map(String::from).collect())
+        .unwrap_or_default();
+    let key_columns: Vec<usize> = (0..header.len())
+        .filter(|&i| {
+            let column = &header[i];
+            metrics.is_empty()
+                || !(is_metric_column(column, &metrics)
+                    || is_output_column(column)
+                    || is_run_column(column))
+        })
+        .collect();
+
+    let mut merged = vec![header.clone()];
+    let mut seen = HashSet::new();
+    let mut duplicates = 0;
+    for (file, records) in files {
+        let columns = &records[0];
+        let mut sorted_columns = columns.clone();
+        sorted_columns.sort();
+        let mut sorted_header = header.clone();
+        sorted_header.sort();
+        if sorted_columns != sorted_header {
+            return Err(Error::Incompatible {
+                path: file.to_string(),
+                message: format!(
+                    "{} has other columns than {}:\n  {}\n  {}",
+                    file,
+                    files[0].0,
+                    columns.join(","),
+                    header.join(",")
+                ),
+            });
+        }
+        // Position of each output column in this file
+        let mapping: Vec<usize> = header
+            .iter()
+            .map(|h| columns.iter().position(|c| c == h).unwrap_or(0))
+            .collect();
+
+        for record in &records[1..] {
+            let row: Vec<String> = mapping
+                .iter()
+                .map(|&i| record.get(i).cloned().unwrap_or_default())
+                .collect();
+            let key: Vec<&String> = key_columns.iter().map(|&i| &row[i]).collect();
+            if seen.insert(format!("{:?}", key)) {
+                merged.push(row);
+            } else {
+                duplicates += 1;
+            }
+        }
+    }
+    if duplicates > 0 {
+        eprintln!(
+            "Warning: dropped {} rows of runs already present in an earlier file",
+            duplicates
+        );
+    }
+    Ok(merged)
+}
+
+// Shards of one sweep should share their parameters, metrics, command and script; also point
+// out shards that are missing
+fn warn_metadata_differences(inputs: &[String], metadata: &[Vec<(String, String)>]) {
+    for key in ["params", "metrics", "command_sha256", "script_sha256"] {
+        let first = metadata_value(&metadata[0], key);
+        for (input, entries) in inputs.iter().zip(metadata).skip(1) {
+            if metadata_value(entries, key) != first {
+                eprintln!(
+                    "Warning: {} differs between {} and {}",
+                    key, inputs[0], input
+                );
+            }
+        }
+    }
+
+    let shards: Vec<(usize, usize)> = metadata
+        .iter()
+        .filter_map(|entries| {
+            let (k, n) = metadata_value(entries, "shard")?.split_once('/')?;
+            Some((k.parse().ok()?, n.parse().ok()?))
+        })
+        .collect();
+    if let Some(&(_, count)) = shards.first() {
+        let missing: Vec<String> = (1..=count)
+            .filter(|k| !shards.contains(&(*k, count)))
+            .map(|k| format!("{}/{}", k, count))
+            .collect();
+        if !missing.is_empty() {
+            eprintln!("Warning: missing shards {}", missing.join(", "));
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn records(rows: &[&str]) -> Vec<Vec<String>> {
+        rows.iter()
+            .map(|row| row.split(',').map(String::from).collect())
+            .collect()
+    }
+
+    #[test]
+    fn test_merge_records() {
+        let first = records(&["X,Y,loss", "1,a,0.5", "2,a,0.4"]);
+        // Columns in another order, and a run that is also in the first file
+        let second = records(&["loss,Y,X", "0.3,b,1", "0.9,a,2"]);
+        let files = vec![("a.csv", first), ("b.csv", second)];
+
+        let merged = merge_records(&files, Some("loss")).unwrap();
+        assert_eq!(
+            merged,
+            records(&["X,Y,loss", "1,a,0.5", "2,a,0.4", "1,b,0.3"])
+        );
+
+        // Without metadata only identical rows count as duplicates
+        assert_eq!(merge_records(&files, None).unwrap().len(), 5);
+
+        let other = vec![
+            ("a.csv", records(&["X,loss", "1,0.5"])),
+            ("c.csv", records(&["X,acc", "1,0.5"])),
+        ];
+        assert!(merge_records(&other, Some("loss")).is_err());
+    }
+}
@@ -0,0 +1,224 @@
+use crate::optimize::DEFAULT_BUDGET;
+use crate::parser::Options;
+use std::fs;
+
+// Sweep metadata is stored next to the results file as `<output>.meta`,
+// one `key=value` entry per line. Backslashes and newlines in values are escaped.
+pub fn metadata_path(output_file: &str) -> String {
+    format!("{}.meta", output_file)
+}
+
+// Collect the metadata describing this invocation
+pub fn sweep_metadata(
+    param_names: &[String],
+    command_sha256: &str,
+    options: &Options,
+) -> Vec<(String, String)> {
+    let mut entries = vec![
+        ("params".to_string(), param_names.join(",")),
+        ("metrics".to_string(), options.metrics.join(",")),
+        ("command_sha256".to_string(), command_sha256.to_string()),
+    ];
+
+    if let Some(script) = &options.script {
+        entries.push(("script".to_string(), script.path.clone()));
+        entries.push(("script_sha256".to_string(), script.sha256.clone()));
+    }
+
+    if let Some(dir) = &options.log_dir {
+        entries.push(("log_dir".to_string(), dir.clone()));
+    }
+
+    if let Some((shard, count)) = options.shard {
+        entries.push(("shard".to_string(), format!("{}/{}", shard, count)));
+    }
+
+    if let Some(seed) = options.auto_seed {
+        entries.push(("sweep_seed".to_string(), seed.to_string()));
+    }
+
+    if let Some(sampling) = options.sampling() {
+        entries.push(("samples".to_string(), sampling.count.to_string()));
+        entries.push(("sample_seed".to_string(), sampling.seed.to_string()));
+        entries.push(("strategy".to_string(), sampling.strategy.name().to_string()));
+    }
+
+    if let Some(count) = options.sample {
+        entries.push(("sample".to_string(), count.to_string()));
+        if options.samples.is_none() {
+            entries.push(("sample_seed".to_string(), options.sample_seed.to_string()));
+        }
+    }
+
+    if let Some(metric) = &options.optimize {
+        entries.push(("optimize".to_string(), metric.clone()));
+        let goal = if options.maximize {
+            "maximize"
+        } else {
+            "minimize"
+        };
+        entries.push(("goal".to_string(), goal.to_string()));
+        let budget = options.budget.unwrap_or(DEFAULT_BUDGET);
+        entries.push(("budget".to_string(), budget.to_string()));
+    }
+
+    if let Some((name, min, max)) = &options.halving {
+        entries.push(("halving".to_string(), format!("{}={}:{}", name, min, max)));
+        if let Some(metric) = &options.halving_metric {
+            entries.push(("halving_metric".to_string(), metric.clone()));
+        }
+        let goal = if options.maximize {
+            "maximize"
+        } else {
+            "minimize"
+        };
+        entries.push(("goal".to_string(), goal.to_string()));
+        entries.push(("halving_eta".to_string(), options.halving_eta.to_string()));
+    }
+
+    if options.repeats {
+        entries.push(("repeats".to_string(), options.repeat.to_string()));
+    } else if options.repeat > 1 {
+        entries.push(("repeat".to_string(), options.repeat.to_string()));
+    }
+
+    for (path, checksum) in options.verify_inputs.iter().chain(&options.track_inputs) {
+        entries.push((format!("input.{}", path), format!("sha256:{}", checksum)));
+    }
+
+    if !options.collect.is_empty() {
+        entries.push(("collect".to_string(), options.collect.join(",")));
+        entries.push(("artifact_dir".to_string(), options.artifact_dir.clone()));
+    }
+
+    for (key, conditions) in [
+        ("abort_if", &options.abort_if),
+        ("stop_when", &options.stop_when),
+    ] {
+        if !conditions.is_empty() {
+            let sources: Vec<&str> = conditions.iter().map(|c| c.source()).collect();
+            entries.push((key.to_string(), sources.join(",")));
+        }
+    }
+
+    if let Some(seed) = options.shuffle {
+        entries.push(("shuffle".to_string(), seed.to_string()));
+    }
+
+    if let Some(cost) = &options.cost {
+        entries.push(("cost".to_string(), cost.source().to_string()));
+    }
+
+    if let Some(budget) = options.cost_budget {
+        entries.push(("cost_budget".to_string(), budget.to_string()));
+    }
+
+    for (key, value) in &options.env {
+        entries.push((format!("env.{}", key), value.clone()));
+    }
+
+    for (name, value) in &options.rlimits {
+        let value = value.map_or("unlimited".to_string(), |v| v.to_string());
+        entries.push((format!("rlimit.{}", name), value));
+    }
+
+    if let Some(nice) = &options.nice {
+        entries.push(("nice".to_string(), nice.clone()));
+    }
+    if let Some(ionice) = &options.ionice {
+        entries.push(("ionice".to_string(), ionice.clone()));
+    }
+
+    if let Some(path) = &options.env_file {
+        entries.push(("env_file".to_string(), path.clone()));
+    }
+
+    // The command line one argument per entry, for `runexp refine`
+    for (i, arg) in options.invocation.iter().enumerate() {
+        entries.push((format!("arg.{}", i), arg.clone()));
+    }
+
+    for (param, description) in &options.descriptions {
+        entries.push((format!("describe.{}", param), description.clone()));
+    }
+
+    entries
+}
+
+pub fn format_metadata(entries: &[(String, String)]) -> String {
+    entries
+        .iter()
+        .map(|(key, value)| {
+            let value = value.replace('\\', "\\\\").replace('\n', "\\n");
+            format!("{}={}\n", key, value)
+        })
+        .collect()
+}
+
+pub fn write_metadata(path: &str, entries: &[(String, String)]) -> Result<(), String> {
+    fs::write(path, format_metadata(entries))
+        .map_err(|e| format!("Failed to write metadata file {}: {}", path, e))
+}
+
+pub fn read_metadata(path: &str) -> Result<Vec<(String, String)>, String> {
+    let content = fs::read_to_string(path)
+        .map_err(|e| format!("Failed to read metadata file {}: {}", path, e))?;
+
+    Ok(parse_metadata(&content))
+}
+
+pub fn parse_metadata(content: &str) -> Vec<(String, String)> {
+    content
+        .lines()
+        .filter_map(|line| line.split_once('='))
+        .map(|(key, value)| (key.to_string(), unescape(value)))
+        .collect()
+}
+
+// Look up a single metadata entry
+pub fn metadata_value<'a>(entries: &'a [(String, String)], key: &str) -> Option<&'a str> {
+    entries
+        .iter()
+        .find(|(k, _)| k == key)
+        .map(|(_, v)| v.as_str())
+}
+
+fn unescape(value: &str) -> String {
+    let mut result = String::new();
+    let mut chars = value.chars();
+    while let Some(c) = chars.next() {
+        if c == '\\' {
+            match chars.next() {
+                Some('n') => result.push('\n'),
+                Some(other) => result.push(other),
+                None => result.push('\\'),
+            }
+        } else {
+            result.push(c);
+        }
+    }
+    result
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_metadata_roundtrip() {
+        let temp_path = std::env::temp_dir().join("test_runexp_metadata.meta");
+        let path = temp_path.to_str().unwrap();
+        let entries = vec![
+            ("script".to_string(), "train.sh".to_string()),
+            ("note".to_string(), "line1\nline2 with \\ and =".to_string()),
+        ];
+
+        write_metadata(path, &entries).unwrap();
+        let loaded = read_metadata(path);
+
+        // Clean up
+        let _ = fs::remove_file(&temp_path);
+
+        assert_eq!(loaded.unwrap(), entries);
+    }
+}
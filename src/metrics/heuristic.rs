@@ -0,0 +1,79 @@
+use super::{MetricParser, lines};
+use std::collections::HashMap;
+
+// The default parser: every number in the output, labelled with the text since the
+// previous number, e.g. "accuracy: 0.95" gives ("accuracy: ", "0.95").
+pub struct HeuristicParser;
+
+impl MetricParser for HeuristicParser {
+    fn name(&self) -> &str {
+        "heuristic"
+    }
+
+    fn parse(&self, text: &str, results: &mut HashMap<String, String>) {
+        for line in lines(text) {
+            extract_numbers_from_line(line, results);
+        }
+    }
+}
+
+// Extract numbers from a line, using preceding text as labels.
+// Numbers following alphanumeric chars (e.g., "F1") are skipped to avoid false matches.
+fn extract_numbers_from_line(line: &str, results: &mut HashMap<String, String>) {
+    let mut search_start = 0;
+    let mut i = 0;
+    let chars: Vec<char> = line.chars().collect();
+
+    while i < chars.len() {
+        // A number must not be preceded by alphanumeric (to avoid parsing "F1" as "1")
+        let is_num_start = (chars[i].is_ascii_digit()
+            || (chars[i] == '.' && i + 1 < chars.len() && chars[i + 1].is_ascii_digit()))
+            && (i == 0 || !chars[i - 1].is_alphanumeric());
+
+        if is_num_start {
+            let num_start = i;
+            let mut num_end = i;
+            let mut has_dot = chars[i] == '.';
+
+            if has_dot {
+                num_end = i + 1;
+                i += 1;
+            }
+
+            // Collect digits and at most one decimal point
+            while i < chars.len() {
+                if chars[i].is_ascii_digit() {
+                    num_end = i + 1;
+                    i += 1;
+                } else if chars[i] == '.'
+                    && !has_dot
+                    && i + 1 < chars.len()
+                    && chars[i + 1].is_ascii_digit()
+                {
+                    has_dot = true;
+                    num_end = i + 1;
+                    i += 1;
+                } else {
+                    break;
+                }
+            }
+
+            let num_str: String = chars[num_start..num_end].iter().collect();
+
+            if num_str.parse::<f64>().is_ok() {
+                let label: String = chars[search_start..num_start].iter().collect();
+                let label = if label.is_empty() {
+                    "value".to_string()
+                } else {
+                    label
+                };
+
+                results.insert(label, num_str);
+            }
+
+            search_start = num_end;
+        } else {
+            i += 1;
+        }
+    }
+}
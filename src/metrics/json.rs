@@ -0,0 +1,173 @@
+use super::{MetricParser, lines};
+use std::collections::HashMap;
+
+// Lines holding a JSON object, e.g. {"epoch": 3, "eval": {"loss": 0.25}}. Numbers are
+// labelled with their key path ("epoch", "eval.loss"); other lines are ignored.
+pub struct JsonParser;
+
+impl MetricParser for JsonParser {
+    fn name(&self) -> &str {
+        "json"
+    }
+
+    fn parse(&self, text: &str, results: &mut HashMap<String, String>) {
+        for line in lines(text).filter(|line| line.starts_with('{')) {
+            let mut reader = Reader {
+                chars: line.chars().collect(),
+                pos: 0,
+            };
+            let mut found = Vec::new();
+            // Lines that are not valid JSON are ignored as a whole
+            if reader.value("", &mut found).is_some() {
+                results.extend(found);
+            }
+        }
+    }
+}
+
+// A JSON reader that records numbers by key path instead of building a tree
+struct Reader {
+    chars: Vec<char>,
+    pos: usize,
+}
+
+impl Reader {
+    fn skip_whitespace(&mut self) {
+        while self.chars.get(self.pos).is_some_and(|c| c.is_whitespace()) {
+            self.pos += 1;
+        }
+    }
+
+    fn eat(&mut self, c: char) -> bool {
+        self.skip_whitespace();
+        if self.chars.get(self.pos) == Some(&c) {
+            self.pos += 1;
+            true
+        } else {
+            false
+        }
+    }
+
+    fn value(&mut self, path: &str, found: &mut Vec<(String, String)>) -> Option<()> {
+        let join = |key: &str| {
+            if path.is_empty() {
+                key.to_string()
+            } else {
+                format!("{}.{}", path, key)
+            }
+        };
+
+        self.skip_whitespace();
+        match self.chars.get(self.pos)? {
+            '{' => {
+                self.pos += 1;
+                if self.eat('}') {
+                    return Some(());
+                }
+                loop {
+                    self.skip_whitespace();
+                    let key = self.string()?;
+                    if !self.eat(':') {
+                        return None;
+                    }
+                    self.value(&join(&key), found)?;
+                    if self.eat('}') {
+                        return Some(());
+                    }
+                    if !self.eat(',') {
+                        return None;
+                    }
+                }
+            }
+            '[' => {
+                self.pos += 1;
+                if self.eat(']') {
+                    return Some(());
+                }
+                let mut index = 0;
+                loop {
+                    self.value(&join(&index.to_string()), found)?;
+                    index += 1;
+                    if self.eat(']') {
+                        return Some(());
+                    }
+                    if !self.eat(',') {
+                        return None;
+                    }
+                }
+            }
+            '"' => self.string().map(|_| ()),
+            _ => {
+                let start = self.pos;
+                while self
+                    .chars
+                    .get(self.pos)
+                    .is_some_and(|c| c.is_ascii_alphanumeric() || "+-.".contains(*c))
+                {
+                    self.pos += 1;
+                }
+                let literal: String = self.chars[start..self.pos].iter().collect();
+                match literal.as_str() {
+                    "true" | "false" | "null" => Some(()),
+                    number => {
+                        number.parse::<f64>().ok()?;
+                        found.push((path.to_string(), number.to_string()));
+                        Some(())
+                    }
+                }
+            }
+        }
+    }
+
+    fn string(&mut self) -> Option<String> {
+        if self.chars.get(self.pos) != Some(&'"') {
+            return None;
+        }
+        self.pos += 1;
+        let mut s = String::new();
+        loop {
+            let c = *self.chars.get(self.pos)?;
+            self.pos += 1;
+            match c {
+                '"' => return Some(s),
+                '\\' => {
+                    let escaped = *self.chars.get(self.pos)?;
+                    self.pos += 1;
+                    match escaped {
+                        'n' => s.push('\n'),
+                        't' => s.push('\t'),
+                        'r' => s.push('\r'),
+                        'u' => {
+                            let hex: String =
+                                self.chars.get(self.pos..self.pos + 4)?.iter().collect();
+                            self.pos += 4;
+                            s.push(char::from_u32(u32::from_str_radix(&hex, 16).ok()?)?);
+                        }
+                        other => s.push(other),
+                    }
+                }
+                c => s.push(c),
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_json_parser() {
+        let mut results = HashMap::new();
+        JsonParser.parse(
+            "loading...\n{\"epoch\": 3, \"eval\": {\"loss\": 0.25, \"name\": \"v\\\"al\"}, \"acc\": [0.5, 1e-3], \"ok\": true}\n{\"broken\": 1",
+            &mut results,
+        );
+        assert_eq!(results.get("epoch"), Some(&"3".to_string()));
+        assert_eq!(results.get("eval.loss"), Some(&"0.25".to_string()));
+        assert_eq!(results.get("acc.1"), Some(&"1e-3".to_string()));
+        assert_eq!(results.get("eval.name"), None);
+        assert_eq!(results.get("ok"), None);
+        assert_eq!(results.get("broken"), None);
+    }
+}
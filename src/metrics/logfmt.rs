@@ -0,0 +1,56 @@
+use super::{MetricParser, lines};
+use std::collections::HashMap;
+
+// key=value pairs separated by spaces, e.g. `step=10 loss=0.25 msg="epoch done"`.
+// Pairs whose value is a number are kept, labelled with the key.
+pub struct LogfmtParser;
+
+impl MetricParser for LogfmtParser {
+    fn name(&self) -> &str {
+        "logfmt"
+    }
+
+    fn parse(&self, text: &str, results: &mut HashMap<String, String>) {
+        for line in lines(text) {
+            let mut rest = line;
+            while let Some((key, after)) = rest.split_once('=') {
+                // The key is the last word before '='
+                let key = key.rsplit(' ').next().unwrap_or("").trim();
+                let (value, after) = if let Some(quoted) = after.strip_prefix('"') {
+                    match quoted.find('"') {
+                        Some(end) => (&quoted[..end], &quoted[end + 1..]),
+                        None => (quoted, ""),
+                    }
+                } else {
+                    match after.find(' ') {
+                        Some(end) => (&after[..end], &after[end..]),
+                        None => (after, ""),
+                    }
+                };
+                if !key.is_empty() && value.parse::<f64>().is_ok() {
+                    results.insert(key.to_string(), value.to_string());
+                }
+                rest = after;
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_logfmt_parser() {
+        let mut results = HashMap::new();
+        LogfmtParser.parse(
+            "level=info step=10 loss=0.5 msg=\"a=1 b\" lr=1e-4\nstep=11 loss=0.25",
+            &mut results,
+        );
+        assert_eq!(results.get("step"), Some(&"11".to_string()));
+        assert_eq!(results.get("loss"), Some(&"0.25".to_string()));
+        assert_eq!(results.get("lr"), Some(&"1e-4".to_string()));
+        assert_eq!(results.get("level"), None);
+        assert_eq!(results.get("a"), None);
+    }
+}
@@ -0,0 +1,196 @@
+// Extraction of metric values from command output. Each output format is a MetricParser
+// in its own module; an invocation selects one or more of them with --parser.
+
+mod heuristic;
+mod json;
+mod logfmt;
+mod regex;
+
+use std::collections::HashMap;
+use std::fmt;
+use std::sync::Arc;
+
+pub use heuristic::HeuristicParser;
+pub use json::JsonParser;
+pub use logfmt::LogfmtParser;
+pub use regex::RegexParser;
+
+// Finds labelled values in the output of a command. Labels are matched against the
+// requested metric names afterwards, by case-insensitive substring.
+pub trait MetricParser: Send + Sync {
+    fn name(&self) -> &str;
+
+    // Insert every (label, value) found in `text`; later values overwrite earlier ones
+    fn parse(&self, text: &str, results: &mut HashMap<String, String>);
+}
+
+impl fmt::Debug for dyn MetricParser {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.name())
+    }
+}
+
+type ParserConstructor = fn(Option<&str>) -> Result<Arc<dyn MetricParser>, String>;
+
+// Built-in parsers by name. Arguments follow a colon, e.g. "regex:loss=(?<loss>[0-9.]+)"
+const PARSERS: &[(&str, ParserConstructor)] = &[
+    ("heuristic", |_| Ok(Arc::new(HeuristicParser))),
+    ("json", |_| Ok(Arc::new(JsonParser))),
+    ("logfmt", |_| Ok(Arc::new(LogfmtParser))),
+    ("regex", |arg| {
+        let pattern = arg.ok_or("The regex parser needs a pattern: regex:PATTERN")?;
+        Ok(Arc::new(RegexParser::new(pattern)?))
+    }),
+];
+
+pub fn parser_from_spec(spec: &str) -> Result<Arc<dyn MetricParser>, String> {
+    let (name, arg) = match spec.split_once(':') {
+        Some((name, arg)) => (name, Some(arg)),
+        None => (spec, None),
+    };
+    let (_, constructor) = PARSERS.iter().find(|(n, _)| *n == name).ok_or_else(|| {
+        let names: Vec<&str> = PARSERS.iter().map(|(n, _)| *n).collect();
+        format!(
+            "Unknown parser '{}' (expected one of: {})",
+            name,
+            names.join(", ")
+        )
+    })?;
+    constructor(arg)
+}
+
+// Non-empty trimmed lines, treating \r as a line break so progress bars keep only their last value
+fn lines(text: &str) -> impl Iterator<Item = &str> {
+    text.split(['\n', '\r'])
+        .map(|line| line.trim())
+        .filter(|line| !line.is_empty())
+}
+
+// Run the parsers in order (the heuristic one if none are given) and keep the labels
+// matching the requested metrics
+pub fn parse_output(
+    text: &str,
+    results: &mut HashMap<String, String>,
+    metrics: &[String],
+    parsers: &[Arc<dyn MetricParser>],
+) {
+    let mut found = HashMap::new();
+    if parsers.is_empty() {
+        HeuristicParser.parse(text, &mut found);
+    }
+    for parser in parsers {
+        parser.parse(text, &mut found);
+    }
+
+    results.extend(
+        found
+            .into_iter()
+            .filter(|(label, _)| should_keep_label(label, metrics)),
+    );
+}
+
+fn should_keep_label(label: &str, metrics: &[String]) -> bool {
+    if metrics.is_empty() {
+        return true;
+    }
+
+    metrics
+        .iter()
+        .any(|m| label.to_lowercase().contains(&m.to_lowercase()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_output_formats() {
+        let metrics: Vec<String> = vec![];
+        let mut results = HashMap::new();
+
+        // Basic colon-space format
+        parse_output("accuracy: 0.95", &mut results, &metrics, &[]);
+        assert_eq!(results.get("accuracy: "), Some(&"0.95".to_string()));
+
+        // No space after colon
+        parse_output("time:2.3ms", &mut results, &metrics, &[]);
+        assert_eq!(results.get("time:"), Some(&"2.3".to_string()));
+
+        // With units
+        parse_output("latency: 4.5us", &mut results, &metrics, &[]);
+        assert_eq!(results.get("latency: "), Some(&"4.5".to_string()));
+
+        // Equals sign
+        parse_output("result=42", &mut results, &metrics, &[]);
+        assert_eq!(results.get("result="), Some(&"42".to_string()));
+
+        // Space-separated
+        parse_output("count(items) 99", &mut results, &metrics, &[]);
+        assert_eq!(results.get("count(items) "), Some(&"99".to_string()));
+    }
+
+    #[test]
+    fn test_parse_output_special_cases() {
+        let metrics: Vec<String> = vec![];
+
+        // Multiple appearances - keep last value (carriage return case)
+        let mut results = HashMap::new();
+        parse_output(
+            "progress: 10\rprogress: 50\rprogress: 100",
+            &mut results,
+            &metrics,
+            &[],
+        );
+        assert_eq!(results.get("progress: "), Some(&"100".to_string()));
+
+        // Multiple values with same label - keep last (newline case)
+        let mut results = HashMap::new();
+        parse_output(
+            "score: 10\nscore: 20\nscore: 30",
+            &mut results,
+            &metrics,
+            &[],
+        );
+        assert_eq!(results.get("score: "), Some(&"30".to_string()));
+
+        // Complex line with multiple numbers
+        let mut results = HashMap::new();
+        parse_output(
+            "simulated 73us in 2.8s, 6000 events resolved",
+            &mut results,
+            &metrics,
+            &[],
+        );
+        assert_eq!(results.get("simulated "), Some(&"73".to_string()));
+        assert_eq!(results.get("us in "), Some(&"2.8".to_string()));
+        assert_eq!(results.get("s, "), Some(&"6000".to_string()));
+    }
+
+    #[test]
+    fn test_parse_output_labels_preserved() {
+        let mut results = HashMap::new();
+        let metrics: Vec<String> = vec![];
+
+        parse_output(
+            "Test-Accuracy: 0.95\ntrain_loss: 1.234\nF1-Score (macro): 0.88",
+            &mut results,
+            &metrics,
+            &[],
+        );
+
+        assert_eq!(results.get("Test-Accuracy: "), Some(&"0.95".to_string()));
+        assert_eq!(results.get("train_loss: "), Some(&"1.234".to_string()));
+        assert_eq!(results.get("F1-Score (macro): "), Some(&"0.88".to_string()));
+    }
+
+    #[test]
+    fn test_parse_output_metric_filtering() {
+        let mut results = HashMap::new();
+        let metrics = vec!["accuracy".to_string()];
+
+        parse_output("accuracy: 0.95\nloss: 1.234", &mut results, &metrics, &[]);
+
+        assert_eq!(results.get("accuracy: "), Some(&"0.95".to_string()));
+        assert_eq!(results.get("loss: "), None);
+    }
+}
@@ -0,0 +1,54 @@
+use super::{MetricParser, lines};
+use crate::regex::Regex;
+use std::collections::HashMap;
+
+// A user pattern whose named groups are the labels, e.g. `loss=(?<loss>[0-9.]+)`.
+// Matched against every line; the last matching line wins.
+pub struct RegexParser {
+    pattern: Regex,
+}
+
+impl RegexParser {
+    pub fn new(pattern: &str) -> Result<Self, String> {
+        let pattern = Regex::new(pattern)?;
+        if pattern.group_names().is_empty() {
+            return Err(
+                "The regex parser needs at least one named group, e.g. (?<loss>[0-9.]+)"
+                    .to_string(),
+            );
+        }
+        Ok(RegexParser { pattern })
+    }
+}
+
+impl MetricParser for RegexParser {
+    fn name(&self) -> &str {
+        "regex"
+    }
+
+    fn parse(&self, text: &str, results: &mut HashMap<String, String>) {
+        for line in lines(text) {
+            for (name, value) in self.pattern.captures(line).unwrap_or_default() {
+                if let Some(value) = value {
+                    results.insert(name, value);
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_regex_parser() {
+        let parser = RegexParser::new(r"Epoch (?<epoch>\d+): val (?<val_loss>[\d.]+)").unwrap();
+        let mut results = HashMap::new();
+        parser.parse("Epoch 1: val 0.9\nEpoch 2: val 0.7\ndone", &mut results);
+        assert_eq!(results.get("epoch"), Some(&"2".to_string()));
+        assert_eq!(results.get("val_loss"), Some(&"0.7".to_string()));
+
+        assert!(RegexParser::new(r"loss (\d+)").is_err());
+    }
+}
@@ -0,0 +1,559 @@
+use crate::error::Error;
+use crate::evaluator::{Combination, combination_key};
+use crate::executor::{Planner, SweepResults};
+use crate::hash::{sha256, sha256_hex};
+use crate::metadata::{metadata_value, read_metadata, write_metadata};
+use crate::parser::Options;
+use std::collections::VecDeque;
+use std::path::Path;
+
+// With --optimize METRIC, runexp does not run every combination of the grid. It proposes
+// up to --budget of them, each one based on the results so far: a Gaussian process is
+// fitted to the metric values seen, and the combination with the largest expected
+// improvement over the best value is started next. The first few proposals are random.
+// The proposals and their values are kept in <output>.opt, so an interrupted search
+// resumes where it stopped instead of starting over.
+
+// Points drawn for distributions like "uniform(0,1)" when --optimize is used without
+// --samples; the search chooses among them
+pub const CANDIDATE_SAMPLES: usize = 256;
+
+// Runs proposed without --budget
+pub const DEFAULT_BUDGET: usize = 20;
+
+// Random proposals before the Gaussian process is used
+const INITIAL_PROPOSALS: usize = 5;
+
+// Noise variance of the (standardized) metric values
+const NOISE: f64 = 1e-4;
+
+pub fn state_path(output_file: &str) -> String {
+    format!("{}.opt", output_file)
+}
+
+pub struct Optimizer<'a> {
+    pool: &'a [Combination],
+    // The parameters of every combination, scaled to [0, 1]
+    features: Vec<Vec<f64>>,
+    // Pool indices in a random order, for the first proposals
+    random_order: Vec<usize>,
+    metric: String,
+    maximize: bool,
+    budget: usize,
+    path: String,
+    pool_sha256: String,
+    proposed: Vec<usize>,
+    // Proposals read from the state file, started again first (runs that finished are
+    // then found in the results file)
+    replay: VecDeque<usize>,
+    // The metric value of every proposal that finished with one
+    values: Vec<(usize, f64)>,
+}
+
+impl<'a> Optimizer<'a> {
+    pub fn new(pool: &'a [Combination], options: &Options) -> Result<Self, Error> {
+        let metric = options.optimize.clone().unwrap_or_default();
+        let keys: String = pool.iter().map(combination_key).collect();
+        let mut optimizer = Optimizer {
+            pool,
+            features: features(pool),
+            random_order: random_order(pool, options.sample_seed),
+            metric,
+            maximize: options.maximize,
+            budget: options.budget.unwrap_or(DEFAULT_BUDGET).min(pool.len()),
+            path: state_path(&options.output_file),
+            pool_sha256: sha256_hex(keys.as_bytes()),
+            proposed: Vec::new(),
+            replay: VecDeque::new(),
+            values: Vec::new(),
+        };
+        if Path::new(&optimizer.path).exists() {
+            optimizer.resume().map_err(|message| Error::Incompatible {
+                path: optimizer.path.clone(),
+                message,
+            })?;
+        }
+        Ok(optimizer)
+    }
+
+    // Take the proposals of an earlier invocation from the state file
+    fn resume(&mut self) -> Result<(), String> {
+        let entries = read_metadata(&self.path)?;
+        let goal = if self.maximize {
+            "maximize"
+        } else {
+            "minimize"
+        };
+        if metadata_value(&entries, "metric") != Some(&self.metric)
+            || metadata_value(&entries, "goal") != Some(goal)
+        {
+            return Err(format!(
+                "The search was started to {} {}; use another output file",
+                metadata_value(&entries, "goal").unwrap_or("optimize"),
+                metadata_value(&entries, "metric").unwrap_or_default()
+            ));
+        }
+        if metadata_value(&entries, "pool_sha256") != Some(&self.pool_sha256) {
+            return Err(
+                "The combinations to choose from changed since the search started; use another output file"
+                    .to_string(),
+            );
+        }
+        for (key, value) in &entries {
+            if key == "run"
+                && let Some(idx) = value.split(',').next().and_then(|idx| idx.parse().ok())
+                && idx < self.pool.len()
+                && !self.replay.contains(&idx)
+            {
+                self.replay.push_back(idx);
+            }
+        }
+        println!(
+            "Resuming the search with {} earlier proposals from {}",
+            self.replay.len(),
+            self.path
+        );
+        Ok(())
+    }
+
+    fn save(&self) -> Result<(), String> {
+        let goal = if self.maximize {
+            "maximize"
+        } else {
+            "minimize"
+        };
+        let mut entries = vec![
+            ("metric".to_string(), self.metric.clone()),
+            ("goal".to_string(), goal.to_string()),
+            ("budget".to_string(), self.budget.to_string()),
+            ("pool_sha256".to_string(), self.pool_sha256.clone()),
+        ];
+        for idx in &self.proposed {
+            let value = self
+                .values
+                .iter()
+                .find(|(i, _)| i == idx)
+                .map(|(_, v)| v.to_string())
+                .unwrap_or_default();
+            entries.push(("run".to_string(), format!("{},{}", idx, value)));
+        }
+        write_metadata(&self.path, &entries)
+    }
+
+    // Pick up the metric values of the runs that finished
+    fn observe(&mut self, results: &SweepResults) {
+        for run in &results.runs {
+            if let Some(value) = run.metric(&self.metric).filter(|v| v.is_finite())
+                && self.proposed.contains(&run.index)
+                && !self.values.iter().any(|(i, _)| *i == run.index)
+            {
+                self.values.push((run.index, value));
+            }
+        }
+    }
+
+    // The candidate with the largest expected improvement, with the runs in flight
+    // counted as if they ended with the mean value so far, so concurrent proposals
+    // spread out
+    fn best_candidate(&self, running: &[usize]) -> Option<usize> {
+        let sign = if self.maximize { -1.0 } else { 1.0 };
+        let mut x: Vec<&[f64]> = Vec::new();
+        let mut y: Vec<f64> = Vec::new();
+        for &(idx, value) in &self.values {
+            x.push(&self.features[idx]);
+            y.push(sign * value);
+        }
+        let mean = y.iter().sum::<f64>() / y.len() as f64;
+        for &idx in running {
+            x.push(&self.features[idx]);
+            y.push(mean);
+        }
+        let best = self
+            .values
+            .iter()
+            .map(|(_, v)| sign * v)
+            .fold(f64::INFINITY, f64::min);
+        let gp = GaussianProcess::fit(&x, &y)?;
+        let mut chosen: Option<(usize, f64)> = None;
+        for &idx in &self.random_order {
+            if self.proposed.contains(&idx) {
+                continue;
+            }
+            let (mu, sigma) = gp.predict(&self.features[idx]);
+            let improvement = expected_improvement(best, mu, sigma);
+            if chosen.is_none_or(|(_, ei)| improvement > ei) {
+                chosen = Some((idx, improvement));
+            }
+        }
+        chosen.map(|(idx, _)| idx)
+    }
+
+    fn propose(&mut self, idx: usize) -> Option<(usize, &'a Combination)> {
+        self.proposed.push(idx);
+        if let Err(e) = self.save() {
+            eprintln!("Warning: {}", e);
+        }
+        Some((idx, &self.pool[idx]))
+    }
+}
+
+impl<'a> Planner<'a> for Optimizer<'a> {
+    fn next(
+        &mut self,
+        results: &SweepResults,
+        running: &[usize],
+    ) -> Option<(usize, &'a Combination)> {
+        self.observe(results);
+        if let Some(idx) = self.replay.pop_front() {
+            return self.propose(idx);
+        }
+        if self.proposed.len() >= self.budget {
+            return None;
+        }
+        let idx = match self.values.len() {
+            n if n < INITIAL_PROPOSALS => None,
+            _ => self.best_candidate(running),
+        }
+        .or_else(|| {
+            self.random_order
+                .iter()
+                .copied()
+                .find(|idx| !self.proposed.contains(idx))
+        })?;
+        self.propose(idx)
+    }
+
+    fn remaining(&self) -> usize {
+        self.replay
+            .len()
+            .max(self.budget.saturating_sub(self.proposed.len()))
+    }
+
+    fn total(&self) -> usize {
+        self.pool.len()
+    }
+
+    fn finish(&mut self, results: &SweepResults) -> Result<(), String> {
+        self.observe(results);
+        self.save()?;
+        let best = self.values.iter().max_by(|(_, a), (_, b)| {
+            if self.maximize {
+                a.total_cmp(b)
+            } else {
+                b.total_cmp(a)
+            }
+        });
+        if let Some(&(idx, value)) = best {
+            let combo = &self.pool[idx];
+            let params: Vec<String> = combo
+                .param_order
+                .iter()
+                .map(|name| format!("{}={}", name, combo.params[name]))
+                .collect();
+            println!(
+                "Best {} after {} runs: {} ({})",
+                self.metric,
+                self.values.len(),
+                value,
+                params.join(" ")
+            );
+        }
+        Ok(())
+    }
+}
+
+// Every parameter that differs between combinations scaled to [0, 1]: numbers linearly,
+// or logarithmically when they are positive and span two orders of magnitude or more,
+// and other values as one coordinate per value, so all of them are equally far apart
+fn features(pool: &[Combination]) -> Vec<Vec<f64>> {
+    let mut features = vec![Vec::new(); pool.len()];
+    let Some(first) = pool.first() else {
+        return features;
+    };
+    for name in &first.param_order {
+        let values: Vec<&str> = pool.iter().map(|c| c.params[name].as_str()).collect();
+        let mut distinct: Vec<&str> = values.clone();
+        distinct.sort_unstable();
+        distinct.dedup();
+        if distinct.len() < 2 {
+            continue;
+        }
+        let numbers: Option<Vec<f64>> = values
+            .iter()
+            .map(|v| v.trim().parse::<f64>().ok().filter(|v| v.is_finite()))
+            .collect();
+        match numbers {
+            Some(mut numbers) => {
+                let min = numbers.iter().copied().fold(f64::INFINITY, f64::min);
+                let max = numbers.iter().copied().fold(f64::NEG_INFINITY, f64::max);
+                let (min, max) = if min > 0.0 && max / min >= 100.0 {
+                    numbers.iter_mut().for_each(|v| *v = v.ln());
+                    (min.ln(), max.ln())
+                } else {
+                    (min, max)
+                };
+                for (row, number) in features.iter_mut().zip(numbers) {
+                    row.push((number - min) / (max - min));
+                }
+            }
+            None => {
+                for (row, value) in features.iter_mut().zip(&values) {
+                    row.extend(distinct.iter().map(|d| {
+                        if d == value {
+                            std::f64::consts::FRAC_1_SQRT_2
+                        } else {
+                            0.0
+                        }
+                    }));
+                }
+            }
+        }
+    }
+    features
+}
+
+// Pool indices ordered by a hash of the seed and the parameter values
+fn random_order(pool: &[Combination], seed: u64) -> Vec<usize> {
+    let mut ranked: Vec<(u64, usize)> = pool
+        .iter()
+        .enumerate()
+        .map(|(idx, combo)| {
+            let key = format!("{}\noptimize\n{}", seed, combination_key(combo));
+            let digest = sha256(key.as_bytes());
+            (u64::from_be_bytes(digest[..8].try_into().unwrap()), idx)
+        })
+        .collect();
+    ranked.sort_unstable();
+    ranked.into_iter().map(|(_, idx)| idx).collect()
+}
+
+// A Gaussian process with a squared exponential kernel on standardized values. The length
+// scale is the one of a few candidates under which the values are most likely.
+struct GaussianProcess {
+    x: Vec<Vec<f64>>,
+    // Lower triangular Cholesky factor of the kernel matrix, and its inverse times y
+    factor: Vec<Vec<f64>>,
+    alpha: Vec<f64>,
+    length: f64,
+    mean: f64,
+    scale: f64,
+}
+
+impl GaussianProcess {
+    fn fit(x: &[&[f64]], y: &[f64]) -> Option<Self> {
+        let n = y.len();
+        let mean = y.iter().sum::<f64>() / n as f64;
+        let variance = y.iter().map(|v| (v - mean).powi(2)).sum::<f64>() / n as f64;
+        let scale = if variance > 0.0 { variance.sqrt() } else { 1.0 };
+        let y: Vec<f64> = y.iter().map(|v| (v - mean) / scale).collect();
+        let dims = x.first().map_or(1, |row| row.len().max(1)) as f64;
+
+        let mut best: Option<(f64, GaussianProcess)> = None;
+        for relative in [0.05, 0.1, 0.2, 0.4, 0.8] {
+            let length = relative * dims.sqrt();
+            let mut matrix: Vec<Vec<f64>> = x
+                .iter()
+                .map(|a| x.iter().map(|b| kernel(a, b, length)).collect())
+                .collect();
+            for (i, row) in matrix.iter_mut().enumerate() {
+                row[i] += NOISE;
+            }
+            let Some(factor) = cholesky(&matrix) else {
+                continue;
+            };
+            let alpha = solve_transposed(&factor, &solve_lower(&factor, &y));
+            let likelihood = -0.5 * y.iter().zip(&alpha).map(|(a, b)| a * b).sum::<f64>()
+                - (0..n).map(|i| factor[i][i].ln()).sum::<f64>();
+            if best.as_ref().is_none_or(|(l, _)| likelihood > *l) {
+                let gp = GaussianProcess {
+                    x: x.iter().map(|row| row.to_vec()).collect(),
+                    factor,
+                    alpha,
+                    length,
+                    mean,
+                    scale,
+                };
+                best = Some((likelihood, gp));
+            }
+        }
+        best.map(|(_, gp)| gp)
+    }
+
+    // Mean and standard deviation of the value at `point`
+    fn predict(&self, point: &[f64]) -> (f64, f64) {
+        let k: Vec<f64> = self
+            .x
+            .iter()
+            .map(|row| kernel(row, point, self.length))
+            .collect();
+        let mu = k.iter().zip(&self.alpha).map(|(a, b)| a * b).sum::<f64>();
+        let v = solve_lower(&self.factor, &k);
+        let variance = (1.0 - v.iter().map(|x| x * x).sum::<f64>()).max(0.0);
+        (self.mean + self.scale * mu, self.scale * variance.sqrt())
+    }
+}
+
+fn kernel(a: &[f64], b: &[f64], length: f64) -> f64 {
+    let distance: f64 = a.iter().zip(b).map(|(x, y)| (x - y).powi(2)).sum();
+    (-distance / (2.0 * length * length)).exp()
+}
+
+fn cholesky(matrix: &[Vec<f64>]) -> Option<Vec<Vec<f64>>> {
+    let n = matrix.len();
+    let mut factor = vec![vec![0.0; n]; n];
+    for i in 0..n {
+        for j in 0..=i {
+            let sum: f64 = (0..j).map(|k| factor[i][k] * factor[j][k]).sum();
+            if i == j {
+                let d = matrix[i][i] - sum;
+                if d <= 0.0 {
+                    return None;
+                }
+                factor[i][i] = d.sqrt();
+            } else {
+                factor[i][j] = (matrix[i][j] - sum) / factor[j][j];
+            }
+        }
+    }
+    Some(factor)
+}
+
+// Solve L x = b for lower triangular L
+fn solve_lower(factor: &[Vec<f64>], b: &[f64]) -> Vec<f64> {
+    let mut x = vec![0.0; b.len()];
+    for i in 0..b.len() {
+        let sum: f64 = (0..i).map(|k| factor[i][k] * x[k]).sum();
+        x[i] = (b[i] - sum) / factor[i][i];
+    }
+    x
+}
+
+// Solve L^T x = b for lower triangular L
+fn solve_transposed(factor: &[Vec<f64>], b: &[f64]) -> Vec<f64> {
+    let n = b.len();
+    let mut x = vec![0.0; n];
+    for i in (0..n).rev() {
+        let sum: f64 = (i + 1..n).map(|k| factor[k][i] * x[k]).sum();
+        x[i] = (b[i] - sum) / factor[i][i];
+    }
+    x
+}
+
+// Expected amount by which a value with mean `mu` and standard deviation `sigma` falls
+// below `best`
+fn expected_improvement(best: f64, mu: f64, sigma: f64) -> f64 {
+    if sigma <= 0.0 {
+        return (best - mu).max(0.0);
+    }
+    let z = (best - mu) / sigma;
+    let density = (-0.5 * z * z).exp() / (2.0 * std::f64::consts::PI).sqrt();
+    (best - mu) * normal_cdf(z) + sigma * density
+}
+
+// The standard normal distribution function, from the complementary error function
+// approximation of Numerical Recipes (relative error below 1.2e-7)
+fn normal_cdf(z: f64) -> f64 {
+    let x = z.abs() / std::f64::consts::SQRT_2;
+    let t = 1.0 / (1.0 + 0.5 * x);
+    let erfc = t
+        * (-x * x - 1.26551223
+            + t * (1.00002368
+                + t * (0.37409196
+                    + t * (0.09678418
+                        + t * (-0.18628806
+                            + t * (0.27886807
+                                + t * (-1.13520398
+                                    + t * (1.48851587 + t * (-0.82215223 + t * 0.17087277)))))))))
+            .exp();
+    if z >= 0.0 {
+        1.0 - 0.5 * erfc
+    } else {
+        0.5 * erfc
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::evaluator::evaluate_params;
+
+    #[test]
+    fn test_normal_cdf() {
+        assert!((normal_cdf(0.0) - 0.5).abs() < 1e-7);
+        assert!((normal_cdf(1.959964) - 0.975).abs() < 1e-6);
+        assert!((normal_cdf(-2.326348) - 0.01).abs() < 1e-6);
+    }
+
+    #[test]
+    fn test_features() {
+        let params = vec![
+            ("LR".to_string(), "0.0001,0.001,0.01".to_string()),
+            ("MODEL".to_string(), "a,b".to_string()),
+            ("FIXED".to_string(), "1".to_string()),
+        ];
+        let pool = evaluate_params(&params, &[], None).unwrap();
+        let features = features(&pool);
+        // LR on a log scale, MODEL as one coordinate per value, FIXED left out
+        assert_eq!(features[0].len(), 3);
+        assert!((features[2][0] - 0.5).abs() < 1e-12);
+        assert_eq!(features[5][0], 1.0);
+        assert_eq!(features[0][1..], [std::f64::consts::FRAC_1_SQRT_2, 0.0]);
+    }
+
+    #[test]
+    fn test_search_finds_the_optimum() {
+        // The metric peaks at X=63 of 0..100; the search should get close with 15 runs
+        let params = vec![("X".to_string(), "0:100".to_string())];
+        let pool = evaluate_params(&params, &[], None).unwrap();
+        let options = Options {
+            optimize: Some("score".to_string()),
+            maximize: true,
+            budget: Some(15),
+            output_file: std::env::temp_dir()
+                .join(format!("runexp_test_optimize_{}.csv", std::process::id()))
+                .to_string_lossy()
+                .to_string(),
+            ..Options::default()
+        };
+        let mut optimizer = Optimizer::new(&pool, &options).unwrap();
+        let mut results = SweepResults::default();
+        while let Some((idx, combo)) = optimizer.next(&results, &[]) {
+            let x: f64 = combo.params["X"].parse().unwrap();
+            results.runs.push(crate::executor::RunResult {
+                index: idx,
+                params: combo.params.clone(),
+                metrics: [("score".to_string(), (-(x - 63.0).powi(2)).to_string())].into(),
+                stdout: Vec::new(),
+                stderr: Vec::new(),
+                artifacts: Vec::new(),
+                resumed: false,
+                failed: false,
+                duration_s: None,
+                usage: None,
+            });
+        }
+        assert_eq!(results.runs.len(), 15);
+        let best = results
+            .runs
+            .iter()
+            .map(|run| run.metric("score").unwrap())
+            .fold(f64::NEG_INFINITY, f64::max);
+        assert!(best >= -4.0, "best score {}", best);
+
+        // The state file replays the proposals
+        let proposed: Vec<usize> = results.runs.iter().map(|run| run.index).collect();
+        let mut resumed = Optimizer::new(&pool, &options).unwrap();
+        let replayed: Vec<usize> = (0..15)
+            .map(|_| resumed.next(&results, &[]).unwrap().0)
+            .collect();
+        assert_eq!(replayed, proposed);
+        assert!(resumed.next(&results, &[]).is_none());
+
+        let other = Options {
+            maximize: false,
+            ..options.clone()
+        };
+        assert!(Optimizer::new(&pool, &other).is_err());
+        let _ = std::fs::remove_file(state_path(&options.output_file));
+    }
+}
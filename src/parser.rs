@@ -1,4 +1,73 @@
-use std::io::{self, Read};
+use crate::backend::{CPUS_VARIABLE, allowed_cpus, parse_ionice, parse_nice};
+use crate::capture::OUTPUT_BYTES;
+use crate::condition::{Condition, split_comparison};
+use crate::config::{Value, config_path, load_profile};
+use crate::cost::CostExpr;
+use crate::error::Error;
+use crate::evaluator::{Sampling, expand_builtins, quote_literal};
+use crate::hash::{sha256_file, sha256_hex};
+use crate::metrics::{MetricParser, parser_from_spec};
+use crate::optimize::CANDIDATE_SAMPLES;
+use crate::verify::parse_input_check;
+use std::fs;
+use std::io::{self, Read, Write};
+use std::path::Path;
+use std::sync::Arc;
+use std::time::Duration;
+
+// A command script loaded with -f/--file
+#[derive(Debug, Clone)]
+pub struct ScriptFile {
+    pub path: String,
+    pub sha256: String,
+}
+
+// A file written for the sweep, removed with the last copy of its options
+#[derive(Debug)]
+pub struct TempFile(pub std::path::PathBuf);
+
+impl Drop for TempFile {
+    fn drop(&mut self) {
+        let _ = fs::remove_file(&self.0);
+    }
+}
+
+// One command executed per combination. A plain command is a single unnamed stage;
+// --stage defines named stages that run sequentially with their own outputs and metrics.
+#[derive(Debug, Clone)]
+pub struct Stage {
+    pub name: String,
+    pub command: Vec<String>,
+}
+
+// What to do when an existing results file has a different header
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum MismatchAction {
+    Ask,
+    Migrate,
+    New,
+    Abort,
+}
+
+// How the random points of distributions like "uniform(0,1)" are placed (--strategy):
+// independent draws, a Latin hypercube (each parameter's range split into as many
+// strata as there are points, one point per stratum) or a scrambled Sobol sequence
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Strategy {
+    Random,
+    Lhs,
+    Sobol,
+}
+
+impl Strategy {
+    pub fn name(self) -> &'static str {
+        match self {
+            Strategy::Random => "random",
+            Strategy::Lhs => "lhs",
+            Strategy::Sobol => "sobol",
+        }
+    }
+}
 
 #[derive(Debug, Clone)]
 pub struct Options {
@@ -7,7 +76,167 @@ pub struct Options {
     pub metrics: Vec<String>,
     pub output_file: String,
     pub preserve_output: bool,
+    // Bytes kept of each output stream of a stage, its beginning and its end
+    pub max_output_bytes: usize,
     pub concurrency: usize,
+    // Runs whose stage takes longer are stopped and counted as failed
+    pub timeout: Option<Duration>,
+    // A stage that prints nothing for this long is stopped as hanging
+    pub stall_timeout: Option<Duration>,
+    // Bytes of memory a run may use, its own processes together
+    pub memory_limit: Option<u64>,
+    // Resource limits set for every command (--rlimit), as (name, value); None is unlimited
+    pub rlimits: Vec<(String, Option<u64>)>,
+    // Scheduling priority of the commands (--nice, --ionice); may refer to parameters like
+    // "{nice}" and is then checked per run
+    pub nice: Option<String>,
+    pub ionice: Option<String>,
+    // Conditions on the metrics printed so far that stop a running stage: --abort-if
+    // fails the run, --stop-when ends the stage as if it had finished
+    pub abort_if: Vec<Condition>,
+    pub stop_when: Vec<Condition>,
+    // Failed runs are attempted up to `retries` more times, waiting `retry_delay`
+    // before the first retry and twice as long before each further one
+    pub retries: usize,
+    pub retry_delay: Duration,
+    // After this many runs in a row failed (retries used up), no further ones are started
+    pub max_failures: Option<usize>,
+    // Write failed runs with status and exit_code columns; such rows, and rows with
+    // empty metric cells, count as done on resume unless rerun_failed is set
+    pub record_status: bool,
+    pub rerun_failed: bool,
+    // Add a duration_s column with the wall-clock time of every run
+    pub record_time: bool,
+    // Add columns with the peak memory and CPU time of every run
+    pub record_usage: bool,
+    // Add columns with the start time, host and git commit of every run
+    pub record_meta: bool,
+    // Echo the output of runs to the terminal while they run
+    pub verbose: bool,
+    // Exclusive resources as (variable, tokens): every run holds one token of each,
+    // exported as the variable, and waits until one is free. --gpus adds
+    // CUDA_VISIBLE_DEVICES.
+    pub resources: Vec<(String, Vec<String>)>,
+    // Directory receiving the full output of every run
+    pub log_dir: Option<String>,
+    // Sweep seed when --auto-seed adds a SEED parameter
+    pub auto_seed: Option<u64>,
+    // Runs per combination; above 1 a REP parameter numbers them and their metrics are
+    // summarized in <output>.agg.csv
+    pub repeat: usize,
+    // With --repeats N instead, REP counts from 0 and is also added for N = 1, and the
+    // repetitions are not summarized
+    pub repeats: bool,
+    // Commands whose output is recorded in the environment snapshot, e.g. "python3 --version"
+    pub snapshot_commands: Vec<String>,
+    // The command line arguments after "runexp", recorded in the snapshot
+    pub invocation: Vec<String>,
+    // Files whose changes re-run the sweep (Sweep::watch)
+    pub watch: Vec<String>,
+    // Keep the results of earlier rounds instead of re-running every combination
+    pub watch_keep_results: bool,
+    // Resume even when the script or environment differs from the last start
+    pub force: bool,
+    // Print the planned runs instead of running them
+    pub dry_run: bool,
+    // Files moved into <artifact_dir>/<run id>/ after each run, as relative glob patterns;
+    // copied instead if collect_copy is set
+    pub collect: Vec<String>,
+    pub collect_copy: bool,
+    // Directory each run is started in, with "{param}" filled in; created as needed
+    pub workdir: Option<String>,
+    // Give every run an empty temporary directory (RUNEXP_TMPDIR and TMPDIR), removed
+    // when it finishes unless it failed and keep_failed_scratch is set
+    pub scratch: bool,
+    pub keep_failed_scratch: bool,
+    // File each run reads on stdin, with "{param}" filled in
+    pub stdin_file: Option<String>,
+    // Start runs without runexp's environment, except PATH, HOME and the pass_env
+    // variables (--pass-env implies --clean-env)
+    pub clean_env: bool,
+    pub pass_env: Vec<String>,
+    // Shell commands run once before the first and after the last combination
+    pub setup: Option<String>,
+    pub teardown: Option<String>,
+    pub artifact_dir: String,
+    // Input files and their expected SHA-256, checked before the sweep (and each run)
+    pub verify_inputs: Vec<(String, String)>,
+    // Input files whose SHA-256 is recorded, so resuming after they changed is noticed
+    pub track_inputs: Vec<(String, String)>,
+    pub verify_each_run: bool,
+    // Cost of each run, and the total at which no more runs are started
+    pub cost: Option<CostExpr>,
+    pub cost_budget: Option<f64>,
+    // Expected cost of each run from its parameters; runs are started cheapest first, or
+    // most expensive first with costliest_first
+    pub expected_cost: Option<CostExpr>,
+    pub costliest_first: bool,
+    // Start the combinations in a random order drawn with this seed (--shuffle [SEED]);
+    // the results file is sorted back into grid order at the end
+    pub shuffle: Option<u64>,
+    // Start only the first N combinations of that order (--limit), e.g. to try out a sweep
+    pub limit: Option<usize>,
+    // Random points drawn for parameters with a distribution (--samples), the seed they
+    // are drawn with (--sample-seed) and how they are spread (--strategy)
+    pub samples: Option<usize>,
+    pub sample_seed: u64,
+    pub strategy: Strategy,
+    // Run only this many randomly chosen combinations of the grid (--sample), chosen with
+    // sample_seed
+    pub sample: Option<usize>,
+    // Search for the best value of this metric column instead of running the whole grid
+    // (--optimize): up to `budget` (default 20) combinations are proposed one by one from
+    // the results so far, for the largest value with maximize and the smallest otherwise
+    pub optimize: Option<String>,
+    pub maximize: bool,
+    pub budget: Option<usize>,
+    // Run the combinations in rounds of successive halving (--halving NAME=MIN:MAX): all
+    // of them with the budget parameter NAME=MIN, then the best 1/halving_eta of them by
+    // halving_metric (largest with maximize) with eta times the budget, up to MAX
+    pub halving: Option<(String, f64, f64)>,
+    pub halving_metric: Option<String>,
+    pub halving_eta: usize,
+    // Conditions every combination must satisfy, e.g. "batchsize*gpu <= 512" (--where)
+    pub constraints: Vec<String>,
+    // Groups of parameters that vary together instead of being crossed (--zip)
+    pub zip: Vec<Vec<String>>,
+    pub skip: Vec<Vec<(String, String)>>,
+    pub only: Vec<Vec<(String, String)>>,
+    // Positions in the generated grid to run (--only 3,7,12 or --only 0:100), as ranges
+    // with the end excluded
+    pub only_indices: Vec<(usize, usize)>,
+    // Run only shard K of N (1-based), for splitting a sweep across machines
+    pub shard: Option<(usize, usize)>,
+    pub name: Option<String>,
+    pub script: Option<ScriptFile>,
+    // The script read from stdin for a --shell ending in "-"
+    pub script_file: Option<Arc<TempFile>>,
+    // Commands write their output to a pseudo-terminal, for tools that need one
+    pub pty: bool,
+    // Shell or interpreter for a command read from stdin, "bash" if not given
+    pub shell: Option<String>,
+    pub env: Vec<(String, String)>,
+    // Variables read from --env-file; exported before the --env values, but unlike them
+    // never printed or recorded in the metadata, since they may be secrets
+    pub env_file: Option<String>,
+    pub file_env: Vec<(String, String)>,
+    pub tags: Vec<(String, String)>,
+    pub on_mismatch: MismatchAction,
+    pub max_combinations: usize,
+    pub yes: bool,
+    pub descriptions: Vec<(String, String)>,
+    // Environment variables of parameters named like --batch-size@BS, by parameter
+    pub env_names: Vec<(String, String)>,
+    // Export parameters under their names as written (--preserve-case) rather than uppercase
+    pub preserve_case: bool,
+    // Run combinations with the same parameter values once (unless --no-dedup)
+    pub dedup: bool,
+    // Parameters that add a flag to the command when on (--flag:--use-amp), by parameter
+    pub flags: Vec<(String, String)>,
+    // Parameters whose value is one value even if it contains commas (--no-split)
+    pub no_split: Vec<String>,
+    // Metric parsers applied in order; empty means the heuristic parser
+    pub parsers: Vec<Arc<dyn MetricParser>>,
 }
 
 impl Default for Options {
@@ -18,19 +247,752 @@ impl Default for Options {
             metrics: Vec::new(),
             output_file: "results.csv".to_string(),
             preserve_output: false,
+            max_output_bytes: OUTPUT_BYTES,
             concurrency: 1,
+            timeout: None,
+            stall_timeout: None,
+            memory_limit: None,
+            rlimits: Vec::new(),
+            nice: None,
+            ionice: None,
+            abort_if: Vec::new(),
+            stop_when: Vec::new(),
+            retries: 0,
+            retry_delay: Duration::from_secs(5),
+            max_failures: None,
+            record_status: false,
+            rerun_failed: false,
+            record_time: false,
+            record_usage: false,
+            record_meta: false,
+            verbose: false,
+            resources: Vec::new(),
+            log_dir: None,
+            auto_seed: None,
+            repeat: 1,
+            repeats: false,
+            snapshot_commands: vec!["python3 --version".to_string()],
+            invocation: Vec::new(),
+            watch: Vec::new(),
+            watch_keep_results: false,
+            force: false,
+            dry_run: false,
+            collect: Vec::new(),
+            collect_copy: false,
+            workdir: None,
+            stdin_file: None,
+            scratch: false,
+            keep_failed_scratch: false,
+            clean_env: false,
+            pass_env: Vec::new(),
+            setup: None,
+            teardown: None,
+            artifact_dir: "artifacts".to_string(),
+            verify_inputs: Vec::new(),
+            track_inputs: Vec::new(),
+            verify_each_run: false,
+            cost: None,
+            cost_budget: None,
+            expected_cost: None,
+            costliest_first: false,
+            shuffle: None,
+            limit: None,
+            samples: None,
+            sample_seed: 0,
+            strategy: Strategy::Random,
+            sample: None,
+            optimize: None,
+            maximize: false,
+            budget: None,
+            halving: None,
+            halving_metric: None,
+            halving_eta: 3,
+            constraints: Vec::new(),
+            zip: Vec::new(),
+            skip: Vec::new(),
+            only: Vec::new(),
+            only_indices: Vec::new(),
+            shard: None,
+            name: None,
+            script: None,
+            script_file: None,
+            pty: false,
+            shell: None,
+            env: Vec::new(),
+            env_file: None,
+            file_env: Vec::new(),
+            tags: Vec::new(),
+            on_mismatch: MismatchAction::Ask,
+            max_combinations: 10000,
+            yes: false,
+            descriptions: Vec::new(),
+            no_split: Vec::new(),
+            env_names: Vec::new(),
+            preserve_case: false,
+            dedup: true,
+            flags: Vec::new(),
+            parsers: Vec::new(),
+        }
+    }
+}
+
+impl Options {
+    // How random values are drawn, if --samples is given; --optimize draws candidates to
+    // choose from without it
+    pub(crate) fn sampling(&self) -> Option<Sampling> {
+        let default = self.optimize.as_ref().map(|_| CANDIDATE_SAMPLES);
+        self.samples.or(default).map(|count| Sampling {
+            count,
+            seed: self.sample_seed,
+            strategy: self.strategy,
+        })
+    }
+
+    // The number of runs of every combination and the REP of the first one, if a REP
+    // parameter numbers them (--repeat N from 1, --repeats N from 0)
+    pub(crate) fn repetitions(&self) -> Option<(usize, usize)> {
+        if self.repeats {
+            Some((self.repeat, 0))
+        } else {
+            (self.repeat > 1).then_some((self.repeat, 1))
+        }
+    }
+}
+
+// An option understood by runexp. The table is used to resolve aliases and to tell
+// options apart from parameters; each option's handling lives in parse_args.
+struct OptionSpec {
+    long: &'static str,
+    short: Option<&'static str>,
+    // Number of arguments following the option (0 for flags)
+    values: usize,
+}
+
+const fn opt(long: &'static str, short: Option<&'static str>, values: usize) -> OptionSpec {
+    OptionSpec {
+        long,
+        short,
+        values,
+    }
+}
+
+const OPTIONS: &[OptionSpec] = &[
+    opt("stdout", None, 0),
+    opt("stderr", None, 0),
+    opt("metrics", Some("m"), 1),
+    opt("output", Some("o"), 1),
+    opt("preserve-output", Some("p"), 0),
+    opt("max-output-bytes", None, 1),
+    opt("verbose", None, 0),
+    opt("concurrency", Some("c"), 1),
+    opt("timeout", None, 1),
+    opt("stall-timeout", None, 1),
+    opt("memory-limit", None, 1),
+    opt("rlimit", None, 1),
+    opt("nice", None, 1),
+    opt("ionice", None, 1),
+    opt("abort-if", None, 1),
+    opt("stop-when", None, 1),
+    opt("retries", None, 1),
+    opt("retry-delay", None, 1),
+    opt("max-failures", None, 1),
+    opt("record-status", None, 0),
+    opt("rerun-failed", None, 0),
+    opt("record-time", None, 0),
+    opt("record-usage", None, 0),
+    opt("record-meta", None, 0),
+    opt("gpus", None, 1),
+    opt("resource", None, 1),
+    opt("cpus-per-run", None, 1),
+    opt("log-dir", None, 1),
+    opt("auto-seed", None, 0),
+    opt("repeat", None, 1),
+    opt("repeats", None, 1),
+    opt("sweep-seed", None, 1),
+    opt("snapshot", None, 1),
+    opt("watch", None, 1),
+    opt("watch-invalidate", None, 1),
+    opt("force", None, 0),
+    opt("dry-run", None, 0),
+    opt("collect", None, 1),
+    opt("collect-copy", None, 0),
+    opt("workdir", None, 1),
+    opt("stdin-file", None, 1),
+    opt("scratch", None, 0),
+    opt("keep-failed-scratch", None, 0),
+    opt("clean-env", None, 0),
+    opt("pass-env", None, 1),
+    opt("setup", None, 1),
+    opt("teardown", None, 1),
+    opt("artifact-dir", None, 1),
+    opt("verify-input", None, 1),
+    opt("track-input", None, 1),
+    opt("verify-each-run", None, 0),
+    opt("cost", None, 1),
+    opt("cost-budget", None, 1),
+    opt("expected-cost", None, 1),
+    opt("costliest-first", None, 0),
+    opt("shuffle", None, 0),
+    opt("limit", None, 1),
+    opt("name", None, 1),
+    opt("file", Some("f"), 1),
+    opt("shell", None, 1),
+    opt("pty", None, 0),
+    opt("env", None, 1),
+    opt("env-file", None, 1),
+    opt("tag", None, 1),
+    opt("on-mismatch", None, 1),
+    opt("max-combinations", None, 1),
+    opt("yes", None, 0),
+    opt("stage", None, 2),
+    opt("describe", None, 1),
+    opt("no-split", None, 1),
+    opt("preserve-case", None, 0),
+    opt("no-dedup", None, 0),
+    opt("parser", None, 1),
+    opt("samples", None, 1),
+    opt("sample-seed", None, 1),
+    opt("sample", None, 1),
+    opt("strategy", None, 1),
+    opt("optimize", None, 1),
+    opt("maximize", None, 0),
+    opt("budget", None, 1),
+    opt("halving", None, 1),
+    opt("halving-metric", None, 1),
+    opt("halving-eta", None, 1),
+    opt("where", None, 1),
+    opt("zip", None, 1),
+    opt("preset", None, 1),
+    opt("skip", None, 1),
+    opt("exclude", None, 1),
+    opt("only", None, 1),
+    opt("shard", None, 1),
+    opt("profile", None, 1),
+    opt("error-format", None, 1),
+    opt("help", Some("h"), 0),
+];
+
+fn find_option(arg: &str) -> Option<&'static OptionSpec> {
+    let name = arg.split_once('=').map_or(arg, |(name, _)| name);
+    if let Some(long) = name.strip_prefix("--") {
+        OPTIONS.iter().find(|o| o.long == long)
+    } else if let Some(short) = name.strip_prefix('-') {
+        OPTIONS.iter().find(|o| o.short == Some(short))
+    } else {
+        None
+    }
+}
+
+// Parse user-defined aliases for long options, e.g. "conc=concurrency,out=output"
+pub fn parse_aliases(spec: &str) -> Result<Vec<(String, String)>, String> {
+    spec.split(',')
+        .map(|s| s.trim())
+        .filter(|s| !s.is_empty())
+        .map(|entry| {
+            let (alias, target) = entry
+                .split_once('=')
+                .ok_or_else(|| format!("Invalid alias (expected ALIAS=OPTION): {}", entry))?;
+            let (alias, target) = (
+                alias.trim_start_matches('-'),
+                target.trim_start_matches('-'),
+            );
+            if !OPTIONS.iter().any(|o| o.long == target) {
+                return Err(format!(
+                    "Alias {} refers to unknown option --{}",
+                    alias, target
+                ));
+            }
+            if alias.is_empty() || OPTIONS.iter().any(|o| o.long == alias) {
+                return Err(format!("Invalid alias name: {}", alias));
+            }
+            Ok((alias.to_string(), target.to_string()))
+        })
+        .collect()
+}
+
+// Rewrite aliased options ("--conc 4", "--conc=4") to their canonical long names.
+// Only the option section before the command is rewritten; values are left alone.
+fn expand_aliases(args: &[String], aliases: &[(String, String)]) -> Vec<String> {
+    let mut expanded = args.to_vec();
+    let mut i = 0;
+
+    while i < expanded.len() && expanded[i].starts_with('-') {
+        let arg = &expanded[i];
+        let (name, value) = match arg.split_once('=') {
+            Some((name, value)) => (name, Some(value)),
+            None => (arg.as_str(), None),
+        };
+        if let Some(stripped) = name.strip_prefix("--")
+            && let Some((_, target)) = aliases.iter().find(|(alias, _)| alias == stripped)
+        {
+            expanded[i] = match value {
+                Some(value) => format!("--{}={}", target, value),
+                None => format!("--{}", target),
+            };
         }
+
+        i += 1 + following_values(&expanded, i);
     }
+
+    expanded
 }
 
-pub type ParseResult = Result<(Vec<(String, String)>, Vec<String>, Options), String>;
+// Number of arguments consumed after the option or parameter name at args[i]
+fn following_values(args: &[String], i: usize) -> usize {
+    let arg = &args[i];
+    if arg == "--shuffle" {
+        return args
+            .get(i + 1)
+            .map(|s| s.as_str())
+            .filter(is_seed)
+            .map_or(0, |_| 1);
+    }
+    // --preset takes every following NAME=LABEL:{...} entry
+    if arg == "--preset" || arg.starts_with("--preset=") {
+        let first = if arg == "--preset" { i + 2 } else { i + 1 };
+        let entries = args
+            .iter()
+            .skip(first)
+            .take_while(|arg| parse_preset(arg).is_some())
+            .count();
+        return first - i - 1 + entries;
+    }
+    match find_option(arg) {
+        Some(spec) if arg.contains('=') => spec.values.saturating_sub(1),
+        Some(spec) => spec.values,
+        None if arg.contains('=') => 0,
+        None => 1,
+    }
+}
+
+// Find the value of an option in the option section before the command
+fn find_option_value(args: &[String], long: &str) -> Option<String> {
+    let mut i = 0;
+    while i < args.len() && args[i].starts_with('-') {
+        if let Some(value) = args[i].strip_prefix(long).and_then(|v| v.strip_prefix('=')) {
+            return Some(value.to_string());
+        }
+        if args[i] == long {
+            return args.get(i + 1).cloned();
+        }
+        i += 1 + following_values(args, i);
+    }
+    None
+}
+
+// Whether errors should be printed as JSON (--error-format json or RUNEXP_ERROR_FORMAT=json)
+pub fn json_errors(args: &[String]) -> bool {
+    let format = find_option_value(args, "--error-format")
+        .or_else(|| std::env::var(option_env_var("error-format")).ok());
+    format.as_deref() == Some("json")
+}
+
+// Environment variable overriding an option, e.g. RUNEXP_MAX_COMBINATIONS for --max-combinations
+fn option_env_var(long: &str) -> String {
+    format!("RUNEXP_{}", long.to_uppercase().replace('-', "_"))
+}
+
+// Collect options set through RUNEXP_* environment variables. Flags accept
+// 1/true/yes and 0/false/no; options taking a value are passed through as-is.
+fn env_args() -> Result<Vec<String>, String> {
+    let mut args = Vec::new();
+    for spec in OPTIONS.iter().filter(|o| o.values <= 1 && o.long != "help") {
+        let var = option_env_var(spec.long);
+        let Ok(value) = std::env::var(&var) else {
+            continue;
+        };
+        if spec.values == 1 {
+            args.push(format!("--{}={}", spec.long, value));
+            continue;
+        }
+        match value.to_lowercase().as_str() {
+            "1" | "true" | "yes" => args.push(format!("--{}", spec.long)),
+            "0" | "false" | "no" | "" => {}
+            _ => return Err(format!("Invalid value for {}: {}", var, value)),
+        }
+    }
+    Ok(args)
+}
+
+// Turn the entries of a config profile into option arguments. Without --profile,
+// the "default" profile is used if it exists.
+fn profile_args(profile: Option<&str>) -> Result<Vec<String>, String> {
+    let name = profile.unwrap_or("default");
+    let Some(entries) = load_profile(name)? else {
+        if profile.is_some() {
+            let path = config_path().map(|p| p.display().to_string());
+            return Err(format!(
+                "Profile {} not found in {}",
+                name,
+                path.unwrap_or_else(|| "the config file".to_string())
+            ));
+        }
+        return Ok(Vec::new());
+    };
+
+    let mut args = Vec::new();
+    for (key, value) in entries {
+        let spec = OPTIONS
+            .iter()
+            .find(|o| o.long == key && o.values <= 1 && !matches!(o.long, "profile" | "help"))
+            .ok_or_else(|| format!("Unknown option in profile {}: {}", name, key))?;
+        match (value, spec.values) {
+            (Value::Bool(true), 0) => args.push(format!("--{}", key)),
+            (Value::Bool(false), 0) => {}
+            (Value::String(value), 1) => args.push(format!("--{}={}", key, value)),
+            (Value::Array(values), 1) => {
+                args.extend(values.iter().map(|value| format!("--{}={}", key, value)))
+            }
+            _ => {
+                return Err(format!(
+                    "Invalid value for {} in profile {} (expected {})",
+                    key,
+                    name,
+                    if spec.values == 0 {
+                        "true or false"
+                    } else {
+                        "a string, number or array"
+                    }
+                ));
+            }
+        }
+    }
+    Ok(args)
+}
+
+pub type ParseResult = Result<(Vec<(String, String)>, Vec<Stage>, Options), Error>;
+
+// Marks -h/--help inside parse_options
+const HELP_REQUESTED: &str = "HELP_REQUESTED";
 
 pub fn parse_args(args: &[String]) -> ParseResult {
-    let mut params = Vec::new();
-    let mut options = Options::default();
+    let invocation = args.to_vec();
+    let aliases = parse_aliases(&std::env::var("RUNEXP_ALIASES").unwrap_or_default())
+        .map_err(|e| Error::usage(format!("RUNEXP_ALIASES: {}", e)))?;
+    let args = expand_aliases(args, &aliases);
+    let given = args.len();
+
+    // Later arguments take precedence: config profile < RUNEXP_* variables < command line
+    let mut overrides = env_args().map_err(Error::usage)?;
+    overrides.extend(args);
+    let mut full_args = profile_args(find_option_value(&overrides, "--profile").as_deref())
+        .map_err(Error::usage)?;
+    full_args.extend(overrides);
+    let args = &full_args;
+
+    // Positions in errors count from the first command line argument
+    let offset = args.len() - given;
+    let mut parsed = Parsed::default();
+    let mut current = 0;
+    let i = match parse_options(args, &mut current, &mut parsed) {
+        Ok(i) => i,
+        Err(message) if message == HELP_REQUESTED => return Err(Error::HelpRequested),
+        Err(message) => {
+            return Err(Error::Usage {
+                message,
+                arg: current.checked_sub(offset).map(|pos| pos + 1),
+            });
+        }
+    };
+    let Parsed {
+        mut params,
+        spelled,
+        presets,
+        mut options,
+        output_given,
+        script_path,
+        stages,
+    } = parsed;
+
+    options.invocation = invocation;
+
+    if options.stdout_only && options.stderr_only {
+        return Err(Error::usage("Cannot specify both --stdout and --stderr"));
+    }
+
+    if options.cost_budget.is_some() && options.cost.is_none() {
+        return Err(Error::usage("--cost-budget requires --cost"));
+    }
+
+    if options.costliest_first && options.expected_cost.is_none() {
+        return Err(Error::usage("--costliest-first requires --expected-cost"));
+    }
+
+    if options.shuffle.is_some()
+        && (options.expected_cost.is_some()
+            || options.optimize.is_some()
+            || options.halving.is_some())
+    {
+        return Err(Error::usage(
+            "--shuffle cannot be combined with --expected-cost, --optimize or --halving, which choose the order of the runs themselves",
+        ));
+    }
+
+    if options.limit.is_some() && (options.optimize.is_some() || options.halving.is_some()) {
+        return Err(Error::usage(
+            "--limit cannot be combined with --optimize or --halving (use --budget with --optimize)",
+        ));
+    }
+
+    if let Some(metric) = &options.optimize {
+        // The metric column, possibly prefixed with a stage name like "eval.accuracy"
+        let name = metric.rsplit_once('.').map_or(metric.as_str(), |(_, m)| m);
+        if !options.metrics.iter().any(|m| m == name) {
+            return Err(Error::usage(format!(
+                "--optimize {} must be one of the --metrics",
+                metric
+            )));
+        }
+        if options.expected_cost.is_some() {
+            return Err(Error::usage(
+                "--optimize chooses the order of the runs itself; it cannot be combined with --expected-cost",
+            ));
+        }
+    } else if options.budget.is_some() {
+        return Err(Error::usage("--budget requires --optimize"));
+    }
+
+    if options.halving.is_some() {
+        let Some(metric) = &options.halving_metric else {
+            return Err(Error::usage("--halving requires --halving-metric"));
+        };
+        let name = metric.rsplit_once('.').map_or(metric.as_str(), |(_, m)| m);
+        if !options.metrics.iter().any(|m| m == name) {
+            return Err(Error::usage(format!(
+                "--halving-metric {} must be one of the --metrics",
+                metric
+            )));
+        }
+        if options.optimize.is_some() || options.expected_cost.is_some() {
+            return Err(Error::usage(
+                "--halving chooses the order of the runs itself; it cannot be combined with --optimize or --expected-cost",
+            ));
+        }
+    } else if options.halving_metric.is_some() {
+        return Err(Error::usage("--halving-metric requires --halving"));
+    }
+
+    if options.maximize && options.optimize.is_none() && options.halving.is_none() {
+        return Err(Error::usage("--maximize requires --optimize or --halving"));
+    }
+
+    if options.strategy != Strategy::Random && options.samples.is_none() {
+        return Err(Error::usage("--strategy requires --samples"));
+    }
+
+    if options.keep_failed_scratch && !options.scratch {
+        return Err(Error::usage("--keep-failed-scratch requires --scratch"));
+    }
+
+    if options.collect_copy && options.collect.is_empty() {
+        return Err(Error::usage("--collect-copy requires --collect"));
+    }
+
+    // Presets become parameters zipped with their members
+    if !presets.is_empty() {
+        for group in preset_params(&presets).map_err(Error::usage)? {
+            if let Some((name, _)) = group
+                .iter()
+                .find(|(name, _)| params.iter().any(|(param, _)| param == name))
+            {
+                return Err(Error::usage(format!(
+                    "Parameter {} is set by --preset and given as a parameter",
+                    name
+                )));
+            }
+            options
+                .zip
+                .push(group.iter().map(|(name, _)| name.clone()).collect());
+            params.extend(group);
+        }
+    }
+
+    // With --preserve-case, parameters are exported under their names as written
+    if options.preserve_case {
+        for (name, written) in spelled {
+            if written != name && !options.env_names.iter().any(|(param, _)| *param == name) {
+                options.env_names.push((name, written));
+            }
+        }
+    }
+
+    // The environment variable of each parameter, which may be renamed with --name@VAR
+    let exported: Vec<&str> = params
+        .iter()
+        .map(|(name, _)| {
+            options
+                .env_names
+                .iter()
+                .find(|(param, _)| param == name)
+                .map_or(name.as_str(), |(_, var)| var.as_str())
+        })
+        .collect();
+    if let Some((i, var)) = exported
+        .iter()
+        .enumerate()
+        .find(|(i, var)| exported[..*i].contains(var))
+    {
+        return Err(Error::usage(format!(
+            "Parameter {} is exported as {}, like another parameter",
+            params[i].0, var
+        )));
+    }
+
+    if let Some((key, _)) = options
+        .env
+        .iter()
+        .find(|(key, _)| exported.contains(&key.as_str()))
+    {
+        return Err(Error::usage(format!(
+            "--env {} conflicts with a parameter of the same name",
+            key
+        )));
+    }
+
+    if let Some((param, _)) = options
+        .descriptions
+        .iter()
+        .find(|(param, _)| !params.iter().any(|(name, _)| name == param))
+    {
+        return Err(Error::usage(format!(
+            "--describe refers to unknown parameter: {}",
+            param
+        )));
+    }
+
+    // The values of a --flag parameter switch its flag on or off
+    for (name, flag) in &options.flags {
+        let (_, value) = params
+            .iter()
+            .find(|(param, _)| param == name)
+            .expect("flag parameters are parameters");
+        if let Some(value) = value.split(',').find(|v| flag_value(v).is_none()) {
+            return Err(Error::usage(format!(
+                "Invalid value for flag {}: {} (expected on/off, true/false, yes/no or 1/0)",
+                flag, value
+            )));
+        }
+    }
+
+    // Parameters whose value is taken as one literal value, commas and all
+    for name in &options.no_split {
+        let (_, value) = params
+            .iter_mut()
+            .find(|(param, _)| param == name)
+            .ok_or_else(|| {
+                Error::usage(format!("--no-split refers to unknown parameter: {}", name))
+            })?;
+        *value = quote_literal(value);
+    }
+
+    // Tags and the name may be dated or taken from the environment, e.g. --tag date={today}
+    for (_, value) in &mut options.tags {
+        *value = expand_builtins(value);
+    }
+    options.name = options.name.map(|name| expand_builtins(&name));
+
+    // A named sweep writes to <name>.csv unless an output file is given explicitly
+    if let Some(name) = &options.name
+        && !output_given
+    {
+        options.output_file = format!("{}.csv", name);
+    }
+
+    let mut command = args[i..].to_vec();
+
+    if options.shell.is_some()
+        && (!stages.is_empty() || script_path.is_some() || !command.is_empty())
+    {
+        return Err(Error::usage(
+            "--shell applies only to a command read from stdin",
+        ));
+    }
+
+    if !stages.is_empty() {
+        if script_path.is_some() || !command.is_empty() {
+            return Err(Error::usage(
+                "Cannot combine --stage with a command or script file",
+            ));
+        }
+        return Ok((params, stages, options));
+    }
+
+    if let Some(path) = script_path {
+        // Run the script content through bash; remaining arguments become its positional args.
+        // The content is read once so edits during the sweep don't affect later runs.
+        let content = fs::read_to_string(&path).map_err(|e| Error::io(&path, e))?;
+        options.script = Some(ScriptFile {
+            path: path.clone(),
+            sha256: sha256_hex(content.as_bytes()),
+        });
+        let mut script_command = vec!["bash".to_string(), "-c".to_string(), content, path];
+        script_command.append(&mut command);
+        command = script_command;
+    } else if command.is_empty() {
+        // If no command provided, read from stdin (for heredoc usage)
+        let mut stdin_content = String::new();
+        if let Err(e) = io::stdin().read_to_string(&mut stdin_content) {
+            return Err(Error::io("<stdin>", e));
+        }
+
+        if !stdin_content.trim().is_empty() {
+            let (shell, file) =
+                shell_command(options.shell.as_deref(), stdin_content).map_err(Error::usage)?;
+            command = shell;
+            options.script_file = file.map(Arc::new);
+        } else {
+            return Err(Error::usage("No command specified and no input from stdin"));
+        }
+    }
+
+    let stage = Stage {
+        name: String::new(),
+        command,
+    };
+    Ok((params, vec![stage], options))
+}
+
+// One entry of --preset, like model=small:{layers=2,dim=128}: the value of the preset
+// parameter and the values it sets
+struct Preset {
+    name: String,
+    label: String,
+    members: Vec<(String, String)>,
+}
+
+// Everything parse_options collects before the command
+#[derive(Default)]
+struct Parsed {
+    params: Vec<(String, String)>,
+    // Each parameter's name as written, for --preserve-case
+    spelled: Vec<(String, String)>,
+    presets: Vec<Preset>,
+    options: Options,
+    output_given: bool,
+    script_path: Option<String>,
+    stages: Vec<Stage>,
+}
+
+// Parse options and parameters up to the command and return the index of the command.
+// `current` is kept at the argument being parsed, so errors can point at it.
+fn parse_options(
+    args: &[String],
+    current: &mut usize,
+    parsed: &mut Parsed,
+) -> Result<usize, String> {
+    let Parsed {
+        params,
+        spelled,
+        presets,
+        options,
+        output_given,
+        script_path,
+        stages,
+    } = parsed;
     let mut i = 0;
 
     while i < args.len() {
+        *current = i;
         let arg = &args[i];
 
         if arg == "--stdout" {
@@ -39,88 +1001,607 @@ pub fn parse_args(args: &[String]) -> ParseResult {
         } else if arg == "--stderr" {
             options.stderr_only = true;
             i += 1;
-        } else if arg == "--metrics"
-            || arg == "-m"
-            || arg.starts_with("--metrics=")
-            || arg.starts_with("-m=")
-        {
-            let metrics_value = if let Some(value) = arg.strip_prefix("--metrics=") {
-                value.to_string()
-            } else if let Some(value) = arg.strip_prefix("-m=") {
-                value.to_string()
-            } else {
-                i += 1;
-                if i >= args.len() {
-                    return Err("--metrics/-m requires an argument".to_string());
-                }
-                args[i].clone()
-            };
+        } else if let Some(metrics_value) = take_value(args, &mut i, "--metrics", Some("-m"))? {
             options.metrics = metrics_value
                 .split(',')
                 .map(|s| s.trim().to_string())
                 .collect();
-            i += 1;
-        } else if arg == "--output"
-            || arg == "-o"
-            || arg.starts_with("--output=")
-            || arg.starts_with("-o=")
-        {
-            let output_value = if let Some(value) = arg.strip_prefix("--output=") {
-                value.to_string()
-            } else if let Some(value) = arg.strip_prefix("-o=") {
-                value.to_string()
-            } else {
-                i += 1;
-                if i >= args.len() {
-                    return Err("--output/-o requires an argument".to_string());
-                }
-                args[i].clone()
-            };
+        } else if let Some(output_value) = take_value(args, &mut i, "--output", Some("-o"))? {
             options.output_file = output_value;
+            *output_given = true;
+        } else if arg == "--verbose" {
+            options.verbose = true;
             i += 1;
         } else if arg == "--preserve-output" || arg == "-p" {
             options.preserve_output = true;
             i += 1;
-        } else if arg == "--concurrency"
-            || arg == "-c"
-            || arg.starts_with("--concurrency=")
-            || arg.starts_with("-c=")
+        } else if let Some(max_value) = take_value(args, &mut i, "--max-output-bytes", None)? {
+            options.max_output_bytes = max_value
+                .parse::<usize>()
+                .ok()
+                .filter(|&n| n > 0)
+                .ok_or_else(|| format!("Invalid --max-output-bytes value: {}", max_value))?;
+        } else if let Some(concurrency_value) =
+            take_value(args, &mut i, "--concurrency", Some("-c"))?
         {
-            let concurrency_value = if let Some(value) = arg.strip_prefix("--concurrency=") {
-                value.to_string()
-            } else if let Some(value) = arg.strip_prefix("-c=") {
-                value.to_string()
-            } else {
-                i += 1;
-                if i >= args.len() {
-                    return Err("--concurrency/-c requires an argument".to_string());
-                }
-                args[i].clone()
-            };
             options.concurrency = concurrency_value
                 .parse::<usize>()
                 .map_err(|_| format!("Invalid concurrency value: {}", concurrency_value))?;
             if options.concurrency == 0 {
                 return Err("--concurrency/-c must be at least 1".to_string());
             }
+        } else if let Some(timeout_value) = take_value(args, &mut i, "--timeout", None)? {
+            let seconds = timeout_value
+                .parse::<f64>()
+                .ok()
+                .filter(|s| s.is_finite() && *s > 0.0)
+                .ok_or_else(|| format!("Invalid timeout value: {}", timeout_value))?;
+            options.timeout = Some(Duration::from_secs_f64(seconds));
+        } else if let Some(minutes) = take_value(args, &mut i, "--stall-timeout", None)? {
+            let minutes_value = minutes
+                .parse::<f64>()
+                .ok()
+                .filter(|m| m.is_finite() && *m > 0.0)
+                .ok_or_else(|| format!("Invalid --stall-timeout value: {}", minutes))?;
+            options.stall_timeout = Some(Duration::from_secs_f64(minutes_value * 60.0));
+        } else if let Some(limit) = take_value(args, &mut i, "--memory-limit", None)? {
+            options.memory_limit = Some(
+                parse_size(&limit)
+                    .filter(|&bytes| bytes > 0)
+                    .ok_or_else(|| format!("Invalid --memory-limit value: {}", limit))?,
+            );
+        } else if let Some(rlimit) = take_value(args, &mut i, "--rlimit", None)? {
+            if !cfg!(unix) {
+                return Err("--rlimit is only supported on Unix".to_string());
+            }
+            let (name, value) = parse_rlimit(&rlimit)?;
+            options.rlimits.retain(|(n, _)| *n != name);
+            options.rlimits.push((name, value));
+        } else if let Some(nice) = take_value(args, &mut i, "--nice", None)? {
+            if !cfg!(unix) {
+                return Err("--nice is only supported on Unix".to_string());
+            }
+            if !nice.contains('{') {
+                parse_nice(&nice)?;
+            }
+            options.nice = Some(nice);
+        } else if let Some(ionice) = take_value(args, &mut i, "--ionice", None)? {
+            if !cfg!(target_os = "linux") {
+                return Err("--ionice is only supported on Linux".to_string());
+            }
+            if !ionice.contains('{') {
+                parse_ionice(&ionice)?;
+            }
+            options.ionice = Some(ionice);
+        } else if let Some(condition) = take_value(args, &mut i, "--abort-if", None)? {
+            options.abort_if.push(Condition::parse(&condition)?);
+        } else if let Some(condition) = take_value(args, &mut i, "--stop-when", None)? {
+            options.stop_when.push(Condition::parse(&condition)?);
+        } else if let Some(retries) = take_value(args, &mut i, "--retries", None)? {
+            options.retries = retries
+                .parse()
+                .map_err(|_| format!("Invalid --retries value: {}", retries))?;
+        } else if let Some(delay) = take_value(args, &mut i, "--retry-delay", None)? {
+            let seconds = delay
+                .parse::<f64>()
+                .ok()
+                .filter(|s| s.is_finite() && *s >= 0.0)
+                .ok_or_else(|| format!("Invalid --retry-delay value: {}", delay))?;
+            options.retry_delay = Duration::from_secs_f64(seconds);
+        } else if let Some(count) = take_value(args, &mut i, "--max-failures", None)? {
+            options.max_failures = Some(
+                count
+                    .parse()
+                    .ok()
+                    .filter(|&n| n >= 1)
+                    .ok_or_else(|| format!("Invalid --max-failures value: {}", count))?,
+            );
+        } else if arg == "--record-status" {
+            options.record_status = true;
+            i += 1;
+        } else if arg == "--rerun-failed" {
+            options.rerun_failed = true;
+            i += 1;
+        } else if arg == "--record-time" {
+            options.record_time = true;
+            i += 1;
+        } else if arg == "--record-usage" {
+            options.record_usage = true;
+            i += 1;
+        } else if arg == "--record-meta" {
+            options.record_meta = true;
+            i += 1;
+        } else if let Some(gpus) = take_value(args, &mut i, "--gpus", None)? {
+            add_resource(&mut options.resources, "CUDA_VISIBLE_DEVICES", &gpus)?;
+        } else if let Some(resource) = take_value(args, &mut i, "--resource", None)? {
+            let (name, tokens) = resource
+                .split_once('=')
+                .filter(|(name, _)| !name.is_empty())
+                .ok_or_else(|| {
+                    format!(
+                        "Invalid --resource value (expected NAME=TOKEN,...): {}",
+                        resource
+                    )
+                })?;
+            add_resource(&mut options.resources, name, tokens)?;
+        } else if let Some(count) = take_value(args, &mut i, "--cpus-per-run", None)? {
+            let per_run = count
+                .parse::<usize>()
+                .ok()
+                .filter(|&n| n > 0)
+                .ok_or_else(|| format!("Invalid --cpus-per-run value: {}", count))?;
+            if options.resources.iter().any(|(n, _)| n == CPUS_VARIABLE) {
+                return Err(format!("Duplicate resource: {}", CPUS_VARIABLE));
+            }
+            let cpus = allowed_cpus()?;
+            let groups = cpu_groups(&cpus, per_run);
+            if groups.is_empty() {
+                return Err(format!(
+                    "--cpus-per-run {} needs more cores than the {} available",
+                    per_run,
+                    cpus.len()
+                ));
+            }
+            options.resources.push((CPUS_VARIABLE.to_string(), groups));
+        } else if let Some(dir) = take_value(args, &mut i, "--log-dir", None)? {
+            options.log_dir = Some(dir);
+        } else if let Some(count) = take_value(args, &mut i, "--repeat", None)? {
+            if options.repeats {
+                return Err("Cannot specify both --repeat and --repeats".to_string());
+            }
+            options.repeat = count
+                .parse()
+                .ok()
+                .filter(|&n| n >= 1)
+                .ok_or_else(|| format!("Invalid --repeat value: {}", count))?;
+        } else if let Some(count) = take_value(args, &mut i, "--repeats", None)? {
+            if options.repeat > 1 {
+                return Err("Cannot specify both --repeat and --repeats".to_string());
+            }
+            options.repeat = count
+                .parse()
+                .ok()
+                .filter(|&n| n >= 1)
+                .ok_or_else(|| format!("Invalid --repeats value: {}", count))?;
+            options.repeats = true;
+        } else if arg == "--auto-seed" {
+            options.auto_seed.get_or_insert(0);
+            i += 1;
+        } else if let Some(seed) = take_value(args, &mut i, "--sweep-seed", None)? {
+            let seed = seed
+                .parse::<u64>()
+                .map_err(|_| format!("Invalid sweep seed: {}", seed))?;
+            options.auto_seed = Some(seed);
+        } else if let Some(command) = take_value(args, &mut i, "--snapshot", None)? {
+            options.snapshot_commands.push(command);
+        } else if let Some(files) = take_value(args, &mut i, "--watch", None)? {
+            options.watch.extend(
+                files
+                    .split(',')
+                    .map(|f| f.trim())
+                    .filter(|f| !f.is_empty())
+                    .map(String::from),
+            );
+        } else if let Some(mode) = take_value(args, &mut i, "--watch-invalidate", None)? {
+            options.watch_keep_results = match mode.as_str() {
+                "all" => false,
+                "none" => true,
+                _ => {
+                    return Err(format!(
+                        "Invalid --watch-invalidate value: {} (expected all or none)",
+                        mode
+                    ));
+                }
+            };
+        } else if arg == "--force" {
+            options.force = true;
+            i += 1;
+        } else if arg == "--dry-run" {
+            options.dry_run = true;
+            i += 1;
+        } else if let Some(patterns) = take_value(args, &mut i, "--collect", None)? {
+            for pattern in patterns
+                .split(',')
+                .map(|p| p.trim())
+                .filter(|p| !p.is_empty())
+            {
+                let path = std::path::Path::new(pattern);
+                if path.is_absolute() || path.components().any(|c| c.as_os_str() == "..") {
+                    return Err(format!(
+                        "Invalid --collect pattern (expected a path inside the working directory): {}",
+                        pattern
+                    ));
+                }
+                options.collect.push(pattern.to_string());
+            }
+        } else if arg == "--collect-copy" {
+            options.collect_copy = true;
+            i += 1;
+        } else if arg == "--clean-env" {
+            options.clean_env = true;
+            i += 1;
+        } else if let Some(names) = take_value(args, &mut i, "--pass-env", None)? {
+            options.clean_env = true;
+            for name in names.split(',').map(str::trim) {
+                if name.is_empty() || name.contains('=') {
+                    return Err(format!("Invalid --pass-env value: {}", names));
+                }
+                options.pass_env.push(name.to_string());
+            }
+        } else if let Some(command) = take_value(args, &mut i, "--setup", None)? {
+            options.setup = Some(command);
+        } else if let Some(command) = take_value(args, &mut i, "--teardown", None)? {
+            options.teardown = Some(command);
+        } else if let Some(template) = take_value(args, &mut i, "--workdir", None)? {
+            if template.is_empty() {
+                return Err("Invalid --workdir: empty path".to_string());
+            }
+            options.workdir = Some(template);
+        } else if let Some(template) = take_value(args, &mut i, "--stdin-file", None)? {
+            if template.is_empty() {
+                return Err("Invalid --stdin-file: empty path".to_string());
+            }
+            options.stdin_file = Some(template);
+        } else if arg == "--scratch" {
+            options.scratch = true;
+            i += 1;
+        } else if arg == "--keep-failed-scratch" {
+            options.keep_failed_scratch = true;
+            i += 1;
+        } else if let Some(dir) = take_value(args, &mut i, "--artifact-dir", None)? {
+            options.artifact_dir = dir;
+        } else if let Some(check) = take_value(args, &mut i, "--verify-input", None)? {
+            options.verify_inputs.push(parse_input_check(&check)?);
+        } else if let Some(path) = take_value(args, &mut i, "--track-input", None)? {
+            let checksum =
+                sha256_file(&path).map_err(|e| format!("Failed to read {}: {}", path, e))?;
+            options.track_inputs.push((path, checksum));
+        } else if arg == "--verify-each-run" {
+            options.verify_each_run = true;
+            i += 1;
+        } else if let Some(expr) = take_value(args, &mut i, "--cost", None)? {
+            options.cost = Some(
+                CostExpr::parse(&expr)
+                    .map_err(|e| format!("Invalid --cost expression {}: {}", expr, e))?,
+            );
+        } else if let Some(budget) = take_value(args, &mut i, "--cost-budget", None)? {
+            let budget = budget
+                .parse::<f64>()
+                .ok()
+                .filter(|b| b.is_finite() && *b >= 0.0)
+                .ok_or_else(|| format!("Invalid cost budget: {}", budget))?;
+            options.cost_budget = Some(budget);
+        } else if let Some(expr) = take_value(args, &mut i, "--expected-cost", None)? {
+            options.expected_cost = Some(
+                CostExpr::parse(&expr)
+                    .map_err(|e| format!("Invalid --expected-cost expression {}: {}", expr, e))?,
+            );
+        } else if arg == "--costliest-first" {
+            options.costliest_first = true;
+            i += 1;
+        } else if let Some(limit) = take_value(args, &mut i, "--limit", None)? {
+            options.limit = Some(
+                limit
+                    .parse()
+                    .ok()
+                    .filter(|&n| n >= 1)
+                    .ok_or_else(|| format!("Invalid --limit value: {}", limit))?,
+            );
+        } else if arg == "--shuffle" || arg.starts_with("--shuffle=") {
+            // The seed is optional: --shuffle, --shuffle=SEED or --shuffle SEED
+            let seed = match arg.strip_prefix("--shuffle=") {
+                Some(seed) => Some(seed),
+                None => args.get(i + 1).map(|s| s.as_str()).filter(is_seed),
+            };
+            i += if seed.is_some() && arg == "--shuffle" {
+                2
+            } else {
+                1
+            };
+            options.shuffle = Some(match seed {
+                Some(seed) => seed
+                    .parse()
+                    .map_err(|_| format!("Invalid --shuffle seed: {}", seed))?,
+                None => random_seed(),
+            });
+        } else if let Some(name_value) = take_value(args, &mut i, "--name", None)? {
+            if name_value.is_empty() || name_value.contains(['/', '\\']) {
+                return Err(format!("Invalid sweep name: {}", name_value));
+            }
+            options.name = Some(name_value);
+        } else if let Some(file_value) = take_value(args, &mut i, "--file", Some("-f"))? {
+            *script_path = Some(file_value);
+        } else if let Some(shell_value) = take_value(args, &mut i, "--shell", None)? {
+            if shell_value.trim().is_empty() {
+                return Err("--shell requires a program".to_string());
+            }
+            options.shell = Some(shell_value);
+        } else if arg == "--pty" {
+            if !cfg!(unix) {
+                return Err("--pty is only supported on Unix".to_string());
+            }
+            options.pty = true;
+            i += 1;
+        } else if let Some(env_value) = take_value(args, &mut i, "--env", None)? {
+            let (key, value) = env_value
+                .split_once('=')
+                .filter(|(key, _)| !key.is_empty())
+                .ok_or_else(|| {
+                    format!("Invalid --env value (expected KEY=VALUE): {}", env_value)
+                })?;
+            options.env.push((key.to_string(), value.to_string()));
+        } else if let Some(path) = take_value(args, &mut i, "--env-file", None)? {
+            if options.env_file.is_some() {
+                return Err("--env-file can only be given once".to_string());
+            }
+            options.file_env = read_env_file(&path)?;
+            options.env_file = Some(path);
+        } else if let Some(tag_value) = take_value(args, &mut i, "--tag", None)? {
+            let (key, value) = tag_value
+                .split_once('=')
+                .filter(|(key, _)| !key.is_empty())
+                .ok_or_else(|| {
+                    format!("Invalid --tag value (expected KEY=VALUE): {}", tag_value)
+                })?;
+            if options.tags.iter().any(|(k, _)| k == key) {
+                return Err(format!("Duplicate tag: {}", key));
+            }
+            options.tags.push((key.to_string(), value.to_string()));
+        } else if let Some(spec) = take_value(args, &mut i, "--parser", None)? {
+            // Regex patterns may contain commas, so they are never split
+            if spec.starts_with("regex:") {
+                options.parsers.push(parser_from_spec(&spec)?);
+            } else {
+                for name in spec.split(',') {
+                    options.parsers.push(parser_from_spec(name.trim())?);
+                }
+            }
+        } else if let Some(action) = take_value(args, &mut i, "--on-mismatch", None)? {
+            options.on_mismatch = match action.as_str() {
+                "ask" => MismatchAction::Ask,
+                "migrate" => MismatchAction::Migrate,
+                "new" => MismatchAction::New,
+                "abort" => MismatchAction::Abort,
+                _ => {
+                    return Err(format!(
+                        "Invalid --on-mismatch value: {} (expected ask, migrate, new or abort)",
+                        action
+                    ));
+                }
+            };
+        } else if let Some(describe_value) = take_value(args, &mut i, "--describe", None)? {
+            let (param, description) = describe_value
+                .split_once('=')
+                .filter(|(param, _)| !param.is_empty())
+                .ok_or_else(|| {
+                    format!(
+                        "Invalid --describe value (expected NAME=DESCRIPTION): {}",
+                        describe_value
+                    )
+                })?;
+            let param = param.trim().to_uppercase().replace('-', "_");
+            options.descriptions.retain(|(p, _)| *p != param);
+            options.descriptions.push((param, description.to_string()));
+        } else if let Some(names) = take_value(args, &mut i, "--no-split", None)? {
+            options.no_split.extend(
+                names
+                    .split(',')
+                    .map(|name| name.trim().to_uppercase().replace('-', "_"))
+                    .filter(|name| !name.is_empty()),
+            );
+        } else if arg == "--stage" {
+            if i + 2 >= args.len() {
+                return Err("--stage requires a name and a command".to_string());
+            }
+            let name = args[i + 1].clone();
+            if name.is_empty() || name.contains(['.', ',']) {
+                return Err(format!("Invalid stage name: {}", name));
+            }
+            if stages.iter().any(|s| s.name == name) {
+                return Err(format!("Duplicate stage name: {}", name));
+            }
+            let command = vec!["bash".to_string(), "-c".to_string(), args[i + 2].clone()];
+            stages.push(Stage { name, command });
+            i += 3;
+        } else if let Some(count) = take_value(args, &mut i, "--samples", None)? {
+            options.samples = Some(
+                count
+                    .parse()
+                    .ok()
+                    .filter(|&n| n >= 1)
+                    .ok_or_else(|| format!("Invalid --samples value: {}", count))?,
+            );
+        } else if let Some(count) = take_value(args, &mut i, "--sample", None)? {
+            options.sample = Some(
+                count
+                    .parse()
+                    .ok()
+                    .filter(|&n| n >= 1)
+                    .ok_or_else(|| format!("Invalid --sample value: {}", count))?,
+            );
+        } else if let Some(strategy) = take_value(args, &mut i, "--strategy", None)? {
+            options.strategy = match strategy.as_str() {
+                "random" => Strategy::Random,
+                "lhs" => Strategy::Lhs,
+                "sobol" => Strategy::Sobol,
+                _ => {
+                    return Err(format!(
+                        "Invalid --strategy value: {} (expected random, lhs or sobol)",
+                        strategy
+                    ));
+                }
+            };
+        } else if let Some(metric) = take_value(args, &mut i, "--optimize", None)? {
+            options.optimize = Some(metric.trim().to_string());
+        } else if arg == "--maximize" {
+            options.maximize = true;
+            i += 1;
+        } else if let Some(budget) = take_value(args, &mut i, "--budget", None)? {
+            options.budget = Some(
+                budget
+                    .parse()
+                    .ok()
+                    .filter(|&n| n >= 1)
+                    .ok_or_else(|| format!("Invalid --budget value: {}", budget))?,
+            );
+        } else if let Some(halving) = take_value(args, &mut i, "--halving", None)? {
+            options.halving = Some(parse_halving(&halving)?);
+        } else if let Some(metric) = take_value(args, &mut i, "--halving-metric", None)? {
+            options.halving_metric = Some(metric.trim().to_string());
+        } else if let Some(eta) = take_value(args, &mut i, "--halving-eta", None)? {
+            options.halving_eta = eta
+                .parse()
+                .ok()
+                .filter(|&n| n >= 2)
+                .ok_or_else(|| format!("Invalid --halving-eta value: {}", eta))?;
+        } else if let Some(seed) = take_value(args, &mut i, "--sample-seed", None)? {
+            options.sample_seed = seed
+                .parse()
+                .map_err(|_| format!("Invalid sample seed: {}", seed))?;
+        } else if let Some(constraint) = take_value(args, &mut i, "--where", None)? {
+            split_comparison(&constraint)
+                .map_err(|e| format!("Invalid --where condition {}: {}", constraint, e))?;
+            options.constraints.push(constraint);
+        } else if let Some(entry) = take_value(args, &mut i, "--preset", None)? {
+            let mut entries = vec![entry];
+            while i < args.len() && parse_preset(&args[i]).is_some() {
+                entries.push(args[i].clone());
+                i += 1;
+            }
+            for entry in entries {
+                let preset = parse_preset(&entry).ok_or_else(|| {
+                    format!(
+                        "Invalid --preset value (expected NAME=LABEL:{{KEY=VALUE,...}}): {}",
+                        entry
+                    )
+                })?;
+                // Unquoted braces are expanded by the shell into model=small:layers=2
+                // model=small:dim=128, which set one preset together
+                match presets
+                    .iter_mut()
+                    .find(|p| p.name == preset.name && p.label == preset.label)
+                {
+                    Some(existing) => {
+                        for (key, value) in preset.members {
+                            if existing.members.iter().any(|(k, _)| *k == key) {
+                                return Err(format!(
+                                    "--preset {}={} sets {} twice",
+                                    preset.name, preset.label, key
+                                ));
+                            }
+                            existing.members.push((key, value));
+                        }
+                    }
+                    None => presets.push(preset),
+                }
+            }
+        } else if let Some(zip_value) = take_value(args, &mut i, "--zip", None)? {
+            let names: Vec<String> = zip_value
+                .split(',')
+                .map(|name| name.trim().to_uppercase().replace('-', "_"))
+                .collect();
+            if names.len() < 2 || names.iter().any(|name| name.is_empty()) {
+                return Err(format!(
+                    "Invalid --zip value (expected two or more parameter names like model,config): {}",
+                    zip_value
+                ));
+            }
+            options.zip.push(names);
+        } else if let Some(skip_value) = take_value(args, &mut i, "--skip", None)? {
+            options.skip.push(parse_filter(&skip_value)?);
+        } else if let Some(exclude_value) = take_value(args, &mut i, "--exclude", None)? {
+            // Another name for --skip
+            options.skip.push(parse_filter(&exclude_value)?);
+        } else if let Some(only_value) = take_value(args, &mut i, "--only", None)? {
+            if only_value.contains('=') {
+                options.only.push(parse_filter(&only_value)?);
+            } else {
+                options.only_indices.extend(parse_indices(&only_value)?);
+            }
+        } else if let Some(shard_value) = take_value(args, &mut i, "--shard", None)? {
+            let shard = shard_value
+                .split_once('/')
+                .and_then(|(k, n)| Some((k.trim().parse().ok()?, n.trim().parse().ok()?)))
+                .filter(|&(k, n): &(usize, usize)| k >= 1 && k <= n)
+                .ok_or_else(|| {
+                    format!(
+                        "Invalid --shard value (expected K/N with 1 <= K <= N): {}",
+                        shard_value
+                    )
+                })?;
+            options.shard = Some(shard);
+        } else if let Some(max_value) = take_value(args, &mut i, "--max-combinations", None)? {
+            options.max_combinations = max_value
+                .parse::<usize>()
+                .map_err(|_| format!("Invalid --max-combinations value: {}", max_value))?;
+        } else if take_value(args, &mut i, "--profile", None)?.is_some() {
+            // Already applied before parsing
+        } else if let Some(format) = take_value(args, &mut i, "--error-format", None)? {
+            // Read by json_errors before parsing, so errors while parsing are formatted too
+            if format != "text" && format != "json" {
+                return Err(format!(
+                    "Invalid --error-format value: {} (expected text or json)",
+                    format
+                ));
+            }
+        } else if arg == "--yes" {
+            options.yes = true;
+            i += 1;
+        } else if arg == "--preserve-case" {
+            options.preserve_case = true;
+            i += 1;
+        } else if arg == "--no-dedup" {
+            options.dedup = false;
             i += 1;
         } else if arg == "-h" || arg == "--help" {
             // Return a special error that indicates help was requested
-            return Err("HELP_REQUESTED".to_string());
+            return Err(HELP_REQUESTED.to_string());
         } else if let Some(stripped) = arg.strip_prefix("--") {
+            // Anything that isn't exactly an option (or alias) is a parameter. Option names
+            // are lowercase, so parameters written in uppercase never collide with options.
             // Handle both "--param value" and "--param=value" syntax
             let (name, value) = if let Some(eq_pos) = stripped.find('=') {
-                let param_name = stripped[..eq_pos].to_uppercase().replace('-', "_");
+                let param_name = stripped[..eq_pos].to_string();
                 let param_value = stripped[eq_pos + 1..].to_string();
                 (param_name, param_value)
             } else {
-                let param_name = stripped.to_uppercase().replace('-', "_");
                 i += 1;
                 if i >= args.len() {
                     return Err(format!("Parameter --{} requires a value", stripped));
                 }
-                (param_name, args[i].clone())
+                (stripped.to_string(), args[i].clone())
+            };
+            // "--flag:--use-amp on,off" is the parameter USE_AMP, which adds --use-amp to the
+            // command when on
+            let name = match name.strip_prefix("flag:") {
+                Some(flag) => {
+                    let param = flag.trim_start_matches('-');
+                    if !flag.starts_with('-')
+                        || param.is_empty()
+                        || param.contains(char::is_whitespace)
+                    {
+                        return Err(format!("Invalid flag: {}", flag));
+                    }
+                    let param = param.to_uppercase().replace('-', "_");
+                    options.flags.push((param.clone(), flag.to_string()));
+                    param
+                }
+                None => name,
+            };
+            // "--batch-size@BS" exports the parameter BATCH_SIZE as $BS
+            let (name, var) = match name.split_once('@') {
+                Some((name, var)) => (name, Some(var)),
+                None => (name.as_str(), None),
             };
+            let written = name.replace('-', "_");
+            let name = written.to_uppercase();
+            if let Some(var) = var {
+                if var.is_empty() || !var.chars().all(|c| c.is_alphanumeric() || c == '_') {
+                    return Err(format!("Invalid environment variable name: {}", var));
+                }
+                options.env_names.push((name.clone(), var.to_string()));
+            }
+            spelled.push((name.clone(), written));
             params.push((name, value));
             i += 1;
         } else if let Some(stripped) = arg.strip_prefix("-") {
@@ -133,6 +1614,7 @@ pub fn parse_args(args: &[String]) -> ParseResult {
                     return Err(format!("Parameter {} requires a value", arg));
                 }
                 let param_value = args[i].clone();
+                spelled.push((param_name.clone(), stripped.to_string()));
                 params.push((param_name, param_value));
                 i += 1;
             } else if let Some(eq_pos) = stripped.find('=') {
@@ -141,6 +1623,7 @@ pub fn parse_args(args: &[String]) -> ParseResult {
                 if short_opt.len() == 1 {
                     let param_name = short_opt.to_uppercase();
                     let param_value = stripped[eq_pos + 1..].to_string();
+                    spelled.push((param_name.clone(), short_opt.to_string()));
                     params.push((param_name, param_value));
                     i += 1;
                 } else {
@@ -154,25 +1637,817 @@ pub fn parse_args(args: &[String]) -> ParseResult {
         }
     }
 
-    if options.stdout_only && options.stderr_only {
-        return Err("Cannot specify both --stdout and --stderr".to_string());
+    Ok(i)
+}
+
+// Whether a value of a --flag parameter switches its flag on, or None if it is not a
+// switch value
+pub fn flag_value(value: &str) -> Option<bool> {
+    match value.trim().to_lowercase().as_str() {
+        "on" | "true" | "yes" | "1" => Some(true),
+        "off" | "false" | "no" | "0" => Some(false),
+        _ => None,
     }
+}
 
-    let mut command = args[i..].to_vec();
+// Match an option that takes a value, accepting "--opt value", "--opt=value" and
+// the same forms for the short alias. Advances `i` past the consumed arguments.
+fn take_value(
+    args: &[String],
+    i: &mut usize,
+    long: &str,
+    short: Option<&str>,
+) -> Result<Option<String>, String> {
+    let arg = &args[*i];
+    let names = std::iter::once(long).chain(short);
 
-    // If no command provided, read from stdin (for heredoc usage)
-    if command.is_empty() {
-        let mut stdin_content = String::new();
-        if let Err(e) = io::stdin().read_to_string(&mut stdin_content) {
-            return Err(format!("Failed to read from stdin: {}", e));
+    for name in names {
+        if arg == name {
+            if *i + 1 >= args.len() {
+                return Err(match short {
+                    Some(short) => format!("{}/{} requires an argument", long, short),
+                    None => format!("{} requires an argument", long),
+                });
+            }
+            *i += 2;
+            return Ok(Some(args[*i - 1].clone()));
+        }
+        if let Some(value) = arg.strip_prefix(name).and_then(|v| v.strip_prefix('=')) {
+            *i += 1;
+            return Ok(Some(value.to_string()));
         }
+    }
 
-        if !stdin_content.trim().is_empty() {
-            command = vec!["bash".to_string(), "-c".to_string(), stdin_content];
-        } else {
-            return Err("No command specified and no input from stdin".to_string());
+    Ok(None)
+}
+
+// KEY=VALUE lines of an --env-file. Blank lines and # comments are skipped, an
+// "export " prefix is allowed and quotes around a value are removed.
+fn read_env_file(path: &str) -> Result<Vec<(String, String)>, String> {
+    let content = fs::read_to_string(path).map_err(|e| format!("{}: {}", path, e))?;
+    let mut vars = Vec::new();
+    for (n, line) in content.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        let line = line.strip_prefix("export ").unwrap_or(line);
+        let (key, value) = line
+            .split_once('=')
+            .map(|(key, value)| (key.trim(), value.trim()))
+            .filter(|(key, _)| !key.is_empty() && !key.contains(char::is_whitespace))
+            .ok_or_else(|| format!("{}:{}: expected KEY=VALUE", path, n + 1))?;
+        let value = ['"', '\'']
+            .iter()
+            .find_map(|&quote| {
+                value
+                    .strip_prefix(quote)
+                    .and_then(|v| v.strip_suffix(quote))
+            })
+            .unwrap_or(value);
+        vars.push((key.to_string(), value.to_string()));
+    }
+    Ok(vars)
+}
+
+// The command running a script read from stdin with `shell` (bash by default). A shell
+// given as a single program gets the script with -c, e.g. "zsh -c SCRIPT"; otherwise the
+// script follows its arguments ("perl -e SCRIPT"). A shell ending in "-", like
+// "python -", reads the script from a new file of the temporary directory instead, only
+// readable by the user and removed with the returned TempFile.
+fn shell_command(
+    shell: Option<&str>,
+    script: String,
+) -> Result<(Vec<String>, Option<TempFile>), String> {
+    let mut command: Vec<String> = shell
+        .unwrap_or("bash")
+        .split_whitespace()
+        .map(String::from)
+        .collect();
+    if !find_program(&command[0]) {
+        return Err(match shell {
+            None => "bash is not available; use --shell to run the script with another shell \
+                     or interpreter"
+                .to_string(),
+            Some(_) => format!("{} is not available (--shell)", command[0]),
+        });
+    }
+    if command.len() > 1 && command.last().is_some_and(|arg| arg == "-") {
+        let hash = sha256_hex(script.as_bytes());
+        let path = std::env::temp_dir().join(format!(
+            "runexp-script-{}-{}",
+            std::process::id(),
+            &hash[..12]
+        ));
+        let mut open = fs::OpenOptions::new();
+        open.write(true).create_new(true);
+        #[cfg(unix)]
+        std::os::unix::fs::OpenOptionsExt::mode(&mut open, 0o600);
+        open.open(&path)
+            .and_then(|mut file| file.write_all(script.as_bytes()))
+            .map_err(|e| format!("Failed to write {}: {}", path.display(), e))?;
+        *command.last_mut().unwrap() = path.to_string_lossy().to_string();
+        return Ok((command, Some(TempFile(path))));
+    }
+    if command.len() == 1 {
+        command.push("-c".to_string());
+    }
+    command.push(script);
+    Ok((command, None))
+}
+
+// Whether `program` is a path to a file or can be found on the PATH
+fn find_program(program: &str) -> bool {
+    if program.contains(['/', '\\']) {
+        return Path::new(program).is_file();
+    }
+    std::env::var_os("PATH").is_some_and(|paths| {
+        std::env::split_paths(&paths).any(|dir| {
+            dir.join(program).is_file()
+                || (cfg!(windows) && dir.join(format!("{}.exe", program)).is_file())
+        })
+    })
+}
+
+// A size like "16G", "512M" or "1.5G" in bytes; K, M, G and T are powers of 1024
+fn parse_size(value: &str) -> Option<u64> {
+    let value = value.trim();
+    let (number, unit) = match value.char_indices().last()? {
+        (i, c) if c.is_ascii_alphabetic() => (&value[..i], c.to_ascii_uppercase()),
+        _ => (value, 'B'),
+    };
+    let exponent = "BKMGT".find(unit)?;
+    let number: f64 = number.trim().parse().ok()?;
+    let bytes = number * 1024f64.powi(exponent as i32);
+    (bytes.is_finite() && bytes >= 0.0).then_some(bytes as u64)
+}
+
+// Resources --rlimit can limit, named like in `ulimit` and setrlimit(2) without RLIMIT_
+const RLIMIT_NAMES: [&str; 9] = [
+    "as", "core", "cpu", "data", "fsize", "memlock", "nofile", "nproc", "stack",
+];
+
+// Parse "nofile=4096", "core=0", "as=16G" or "stack=unlimited"
+fn parse_rlimit(value: &str) -> Result<(String, Option<u64>), String> {
+    let (name, limit) = value
+        .split_once('=')
+        .ok_or_else(|| format!("Invalid --rlimit value (expected NAME=VALUE): {}", value))?;
+    let name = name.trim().to_lowercase();
+    if !RLIMIT_NAMES.contains(&name.as_str()) {
+        return Err(format!(
+            "Unknown --rlimit resource {} (expected one of {})",
+            name,
+            RLIMIT_NAMES.join(", ")
+        ));
+    }
+    let limit = match limit.trim() {
+        "unlimited" => None,
+        size => Some(parse_size(size).ok_or_else(|| format!("Invalid --rlimit value: {}", value))?),
+    };
+    Ok((name, limit))
+}
+
+// Disjoint groups of `per_run` of the cores, in order, as lists like "0-3" or "0-1,4-5";
+// left-over cores are not used
+fn cpu_groups(cpus: &[usize], per_run: usize) -> Vec<String> {
+    cpus.chunks_exact(per_run)
+        .map(|group| {
+            let mut ranges: Vec<(usize, usize)> = Vec::new();
+            for &cpu in group {
+                match ranges.last_mut() {
+                    Some((_, end)) if *end + 1 == cpu => *end = cpu,
+                    _ => ranges.push((cpu, cpu)),
+                }
+            }
+            ranges
+                .iter()
+                .map(|&(start, end)| {
+                    if start == end {
+                        start.to_string()
+                    } else {
+                        format!("{}-{}", start, end)
+                    }
+                })
+                .collect::<Vec<_>>()
+                .join(",")
+        })
+        .collect()
+}
+
+// Add the comma-separated `tokens` as the exclusive resource `name`
+fn add_resource(
+    resources: &mut Vec<(String, Vec<String>)>,
+    name: &str,
+    tokens: &str,
+) -> Result<(), String> {
+    if resources.iter().any(|(n, _)| n == name) {
+        return Err(format!("Duplicate resource: {}", name));
+    }
+    let mut list: Vec<String> = Vec::new();
+    for token in tokens.split(',').map(str::trim) {
+        if token.is_empty() || list.iter().any(|t| t == token) {
+            return Err(format!("Invalid tokens for {}: {}", name, tokens));
         }
+        list.push(token.to_string());
+    }
+    resources.push((name.to_string(), list));
+    Ok(())
+}
+
+// Parse a filter like "GPU=4,BATCHSIZE=64" into (parameter, pattern) pairs.
+// Parameter names are normalized the same way as on the command line.
+fn parse_filter(value: &str) -> Result<Vec<(String, String)>, String> {
+    value
+        .split(',')
+        .map(|cond| {
+            let (name, pattern) = cond.split_once('=').ok_or_else(|| {
+                format!("Invalid filter condition (expected NAME=VALUE): {}", cond)
+            })?;
+            Ok((
+                name.trim().to_uppercase().replace('-', "_"),
+                pattern.trim().to_string(),
+            ))
+        })
+        .collect()
+}
+
+// Parse --only indices like "3,7,12" or slices like "0:100" and "500:" into ranges with
+// the end excluded
+fn parse_indices(value: &str) -> Result<Vec<(usize, usize)>, String> {
+    let invalid = || {
+        format!(
+            "Invalid --only value (expected NAME=PATTERN,..., indices like 3,7 or a slice like 0:100): {}",
+            value
+        )
+    };
+    value
+        .split(',')
+        .map(|item| {
+            let item = item.trim();
+            match item.split_once(':') {
+                Some((start, end)) => {
+                    let start = start.trim().parse().map_err(|_| invalid())?;
+                    let end = match end.trim() {
+                        "" => usize::MAX,
+                        end => end.parse().map_err(|_| invalid())?,
+                    };
+                    if end <= start {
+                        return Err(invalid());
+                    }
+                    Ok((start, end))
+                }
+                None => {
+                    let index: usize = item.parse().map_err(|_| invalid())?;
+                    Ok((index, index + 1))
+                }
+            }
+        })
+        .collect()
+}
+
+// Parse a --preset entry like model=small:{layers=2,dim=128}, or one member of it like
+// model=small:layers=2, with the names normalized like parameter names
+fn parse_preset(entry: &str) -> Option<Preset> {
+    let normalize = |name: &str| name.trim().to_uppercase().replace('-', "_");
+    let valid =
+        |name: &str| !name.is_empty() && name.chars().all(|c| c.is_alphanumeric() || c == '_');
+    let (name, rest) = entry.split_once('=').filter(|_| !entry.starts_with('-'))?;
+    let (label, members) = rest.split_once(':')?;
+    let members = match members.strip_prefix('{') {
+        Some(members) => members.strip_suffix('}')?,
+        None => members,
+    };
+    let members = members
+        .split(',')
+        .map(|member| {
+            let (key, value) = member.split_once('=')?;
+            Some((normalize(key), value.trim().to_string()))
+        })
+        .collect::<Option<Vec<_>>>()?;
+    let name = normalize(name);
+    (valid(&name) && !label.is_empty() && members.iter().all(|(key, _)| valid(key))).then(|| {
+        Preset {
+            name,
+            label: label.to_string(),
+            members,
+        }
+    })
+}
+
+// The parameters of the --preset entries, one zip group per preset: a parameter whose
+// values are the labels of its entries, and one parameter per member. All values are
+// taken as written.
+fn preset_params(presets: &[Preset]) -> Result<Vec<Vec<(String, String)>>, String> {
+    let mut names: Vec<&str> = Vec::new();
+    for preset in presets {
+        if !names.contains(&preset.name.as_str()) {
+            names.push(&preset.name);
+        }
+    }
+    let mut groups = Vec::new();
+    let mut defined: Vec<String> = Vec::new();
+    for name in names {
+        let entries: Vec<&Preset> = presets.iter().filter(|p| p.name == name).collect();
+        let keys: Vec<&String> = entries[0].members.iter().map(|(key, _)| key).collect();
+        let mut labels: Vec<&str> = Vec::new();
+        for entry in &entries {
+            let other: Vec<&String> = entry.members.iter().map(|(key, _)| key).collect();
+            if other.len() != keys.len() || !keys.iter().all(|key| other.contains(key)) {
+                return Err(format!(
+                    "--preset {}={} sets other parameters than {}={}",
+                    name, entry.label, name, entries[0].label
+                ));
+            }
+            labels.push(&entry.label);
+        }
+        let join = |values: Vec<&str>| {
+            let quoted: Vec<String> = values.into_iter().map(quote_literal).collect();
+            quoted.join(",")
+        };
+        let mut group = vec![(name.to_string(), join(labels))];
+        for key in keys {
+            let values = entries
+                .iter()
+                .map(|entry| {
+                    let (_, value) = entry.members.iter().find(|(k, _)| k == key).unwrap();
+                    value.as_str()
+                })
+                .collect();
+            group.push((key.clone(), join(values)));
+        }
+        for (param, _) in &group {
+            if defined.contains(param) {
+                return Err(format!(
+                    "Parameter {} is set by more than one --preset",
+                    param
+                ));
+            }
+            defined.push(param.clone());
+        }
+        groups.push(group);
+    }
+    Ok(groups)
+}
+
+// Whether the argument after --shuffle is its seed
+fn is_seed(arg: &&str) -> bool {
+    arg.parse::<u64>().is_ok()
+}
+
+// A seed for --shuffle without one, from the clock
+fn random_seed() -> u64 {
+    let now = std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .unwrap_or_default();
+    now.as_secs() ^ u64::from(now.subsec_nanos()) << 20
+}
+
+// Parse --halving NAME=MIN:MAX, the budget parameter and its smallest and largest value
+fn parse_halving(value: &str) -> Result<(String, f64, f64), String> {
+    let invalid = || format!("Invalid --halving value (expected NAME=MIN:MAX): {}", value);
+    let (name, range) = value.split_once('=').ok_or_else(invalid)?;
+    let (min, max) = range.split_once(':').ok_or_else(invalid)?;
+    let name = name.trim().to_uppercase().replace('-', "_");
+    let min: f64 = min.trim().parse().map_err(|_| invalid())?;
+    let max: f64 = max.trim().parse().map_err(|_| invalid())?;
+    if name.is_empty() || !(min > 0.0 && max > min && max.is_finite()) {
+        return Err(invalid());
+    }
+    Ok((name, min, max))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn strings(args: &[&str]) -> Vec<String> {
+        args.iter().map(|s| s.to_string()).collect()
+    }
+
+    #[test]
+    fn test_expand_aliases() {
+        let aliases = parse_aliases("conc=concurrency,out=output").unwrap();
+        let args = strings(&[
+            "--conc",
+            "4",
+            "--out=r.csv",
+            "--gpu",
+            "--conc",
+            "--stage",
+            "a",
+            "--conc",
+            "cmd",
+            "--conc",
+        ]);
+
+        // Values following options and parameters are never rewritten, nor is the command
+        assert_eq!(
+            expand_aliases(&args, &aliases),
+            strings(&[
+                "--concurrency",
+                "4",
+                "--output=r.csv",
+                "--gpu",
+                "--conc",
+                "--stage",
+                "a",
+                "--conc",
+                "cmd",
+                "--conc",
+            ])
+        );
+    }
+
+    #[test]
+    fn test_option_env_var() {
+        assert_eq!(option_env_var("output"), "RUNEXP_OUTPUT");
+        assert_eq!(
+            option_env_var("max-combinations"),
+            "RUNEXP_MAX_COMBINATIONS"
+        );
+    }
+
+    #[test]
+    fn test_cpu_groups() {
+        assert_eq!(cpu_groups(&[0, 1, 2, 3, 4], 2), ["0-1", "2-3"]);
+        assert_eq!(cpu_groups(&[0, 2, 3, 5, 6, 7], 3), ["0,2-3", "5-7"]);
+        assert_eq!(cpu_groups(&[4], 1), ["4"]);
+        assert!(cpu_groups(&[0, 1], 4).is_empty());
+    }
+
+    #[test]
+    fn test_parse_size() {
+        assert_eq!(parse_size("16G"), Some(16 << 30));
+        assert_eq!(parse_size("512m"), Some(512 << 20));
+        assert_eq!(parse_size("1.5K"), Some(1536));
+        assert_eq!(parse_size("4096"), Some(4096));
+        assert_eq!(parse_size("16GB"), None);
+        assert_eq!(parse_size("G"), None);
+        assert_eq!(parse_size("-1G"), None);
+    }
+
+    #[test]
+    fn test_parse_rlimit() {
+        assert_eq!(
+            parse_rlimit("nofile=4096"),
+            Ok(("nofile".to_string(), Some(4096)))
+        );
+        assert_eq!(
+            parse_rlimit("AS=16G"),
+            Ok(("as".to_string(), Some(16 << 30)))
+        );
+        assert_eq!(
+            parse_rlimit("stack=unlimited"),
+            Ok(("stack".to_string(), None))
+        );
+        assert!(parse_rlimit("files=10").is_err());
+        assert!(parse_rlimit("core").is_err());
+        assert!(parse_rlimit("core=-1").is_err());
+    }
+
+    #[test]
+    fn test_read_env_file() {
+        let path = std::env::temp_dir().join("runexp_test_env_file.env");
+        let path = path.to_string_lossy().to_string();
+        fs::write(
+            &path,
+            "# cluster\nexport SLURM_PARTITION=gpu\n\nTOKEN = \"a b=c\"\nEMPTY=\nQUOTE='x'\n",
+        )
+        .unwrap();
+        let vars = read_env_file(&path).unwrap();
+        let expected = [
+            ("SLURM_PARTITION", "gpu"),
+            ("TOKEN", "a b=c"),
+            ("EMPTY", ""),
+            ("QUOTE", "x"),
+        ]
+        .map(|(k, v)| (k.to_string(), v.to_string()));
+        assert_eq!(vars, expected);
+
+        fs::write(&path, "A=1\nnot a variable\n").unwrap();
+        let error = read_env_file(&path).unwrap_err();
+        assert!(error.ends_with(":2: expected KEY=VALUE"));
+        let _ = fs::remove_file(&path);
     }
 
-    Ok((params, command, options))
+    #[test]
+    fn test_parse_aliases_validation() {
+        assert!(parse_aliases("conc=nonexistent").is_err());
+        assert!(parse_aliases("output=metrics").is_err());
+        assert!(parse_aliases("conc").is_err());
+        assert_eq!(parse_aliases("").unwrap(), vec![]);
+    }
+
+    #[test]
+    fn test_exclude_is_skip() {
+        let args = strings(&[
+            "--x",
+            "1,2",
+            "--exclude",
+            "x=2",
+            "--skip",
+            "X=3",
+            "--metrics",
+            "m",
+            "true",
+        ]);
+        let (_, _, options) = parse_args(&args).unwrap();
+        assert_eq!(
+            options.skip,
+            [
+                vec![("X".to_string(), "2".to_string())],
+                vec![("X".to_string(), "3".to_string())]
+            ]
+        );
+    }
+
+    #[test]
+    fn test_short_param_v() {
+        // -v is a parameter, not --verbose
+        let args = strings(&["-v", "1,2", "--metrics", "m", "true"]);
+        let (params, _, options) = parse_args(&args).unwrap();
+        assert_eq!(params, [("V".to_string(), "1,2".to_string())]);
+        assert!(!options.verbose);
+    }
+
+    #[test]
+    fn test_repeats() {
+        let args = strings(&["--repeats", "1", "-m", "m", "true"]);
+        let (_, _, options) = parse_args(&args).unwrap();
+        assert_eq!(options.repetitions(), Some((1, 0)));
+        let args = strings(&["--repeat", "3", "-m", "m", "true"]);
+        let (_, _, options) = parse_args(&args).unwrap();
+        assert_eq!(options.repetitions(), Some((3, 1)));
+        let args = strings(&["--repeat", "3", "--repeats", "3", "-m", "m", "true"]);
+        assert!(parse_args(&args).is_err());
+    }
+
+    #[test]
+    fn test_builtins_in_tags_and_name() {
+        let today = crate::evaluator::builtin("today").unwrap();
+        let args = strings(&[
+            "--tag",
+            "home={env(HOME)}",
+            "--tag",
+            "label={x}",
+            "--name",
+            "sweep-{today}",
+            "--x",
+            "1",
+            "true",
+        ]);
+        let (_, _, options) = parse_args(&args).unwrap();
+        let home = std::env::var("HOME").unwrap_or_default();
+        assert_eq!(options.tags[0], ("home".to_string(), home));
+        // Only builtins are replaced
+        assert_eq!(options.tags[1].1, "{x}");
+        assert_eq!(options.name, Some(format!("sweep-{}", today)));
+        assert_eq!(options.output_file, format!("sweep-{}.csv", today));
+    }
+
+    #[test]
+    fn test_env_names() {
+        let args = strings(&[
+            "--batch-size@BS",
+            "32,64",
+            "--lr@learning_rate=0.1",
+            "-m",
+            "m",
+            "true",
+        ]);
+        let (params, _, options) = parse_args(&args).unwrap();
+        assert_eq!(params[0], ("BATCH_SIZE".to_string(), "32,64".to_string()));
+        assert_eq!(params[1], ("LR".to_string(), "0.1".to_string()));
+        assert_eq!(
+            options.env_names,
+            [
+                ("BATCH_SIZE".to_string(), "BS".to_string()),
+                ("LR".to_string(), "learning_rate".to_string())
+            ]
+        );
+        let args = strings(&["--a@B", "1", "--b", "2", "-m", "m", "true"]);
+        assert!(parse_args(&args).is_err());
+        let args = strings(&["--a@B-C", "1", "-m", "m", "true"]);
+        assert!(parse_args(&args).is_err());
+    }
+
+    #[test]
+    fn test_preserve_case() {
+        let args = strings(&[
+            "--batchSize",
+            "32,64",
+            "--preserve-case",
+            "--learning-rate@LR",
+            "0.1",
+            "--x",
+            "1",
+            "-N=2",
+            "-m",
+            "m",
+            "true",
+        ]);
+        let (params, _, options) = parse_args(&args).unwrap();
+        assert_eq!(params[0].0, "BATCHSIZE");
+        assert_eq!(
+            options.env_names,
+            [
+                ("LEARNING_RATE".to_string(), "LR".to_string()),
+                ("BATCHSIZE".to_string(), "batchSize".to_string()),
+                ("X".to_string(), "x".to_string())
+            ]
+        );
+        // Names differing only in case are still the same column
+        let args = strings(&[
+            "--preserve-case",
+            "--ab",
+            "1",
+            "--aB",
+            "2",
+            "-m",
+            "m",
+            "true",
+        ]);
+        assert!(parse_args(&args).is_err());
+    }
+
+    #[test]
+    fn test_flag_params() {
+        let args = strings(&["--flag:--use-amp", "on,off", "-m", "m", "true"]);
+        let (params, _, options) = parse_args(&args).unwrap();
+        assert_eq!(params, [("USE_AMP".to_string(), "on,off".to_string())]);
+        assert_eq!(
+            options.flags,
+            [("USE_AMP".to_string(), "--use-amp".to_string())]
+        );
+        let args = strings(&["--flag:-v=true,false", "-m", "m", "true"]);
+        let (_, _, options) = parse_args(&args).unwrap();
+        assert_eq!(options.flags, [("V".to_string(), "-v".to_string())]);
+        let args = strings(&["--flag:--use-amp", "on,maybe", "-m", "m", "true"]);
+        assert!(parse_args(&args).is_err());
+        let args = strings(&["--flag:use-amp", "on", "-m", "m", "true"]);
+        assert!(parse_args(&args).is_err());
+    }
+
+    #[test]
+    fn test_presets() {
+        let args = strings(&[
+            "--preset",
+            "model=small:{layers=2,dim=128}",
+            "model=medium:{dim=128,layers=6}",
+            "--preset=size=tiny:{heads=4}",
+            "--lr",
+            "0.1",
+            "-m",
+            "m",
+            "python",
+            "train.py",
+        ]);
+        let (params, stages, options) = parse_args(&args).unwrap();
+        assert_eq!(stages[0].command, ["python", "train.py"]);
+        assert_eq!(params[1].1, "\"small\",\"medium\"");
+        assert_eq!(
+            options.zip,
+            [vec!["MODEL", "LAYERS", "DIM"], vec!["SIZE", "HEADS"]]
+        );
+        // Values that repeat still pair up with their preset
+        let combinations = crate::evaluator::evaluate_params(&params, &options.zip, None).unwrap();
+        let values: Vec<String> = combinations
+            .iter()
+            .map(|c| {
+                format!(
+                    "{}:{},{}",
+                    c.params["MODEL"], c.params["LAYERS"], c.params["DIM"]
+                )
+            })
+            .collect();
+        assert_eq!(values, ["small:2,128", "medium:6,128"]);
+
+        let args = strings(&["--preset", "a=x:{n=1}", "a=y:{m=1}", "-m", "m", "true"]);
+        assert!(parse_args(&args).is_err());
+        let args = strings(&["--preset", "a=x:{n=1}", "b=y:{n=2}", "-m", "m", "true"]);
+        assert!(parse_args(&args).is_err());
+        let args = strings(&["--preset", "a=x:{n=1}", "--n", "2", "-m", "m", "true"]);
+        assert!(parse_args(&args).is_err());
+        let args = strings(&["--preset", "a=x", "-m", "m", "true"]);
+        assert!(parse_args(&args).is_err());
+
+        // The words of a shell brace expansion
+        let args = strings(&[
+            "--preset",
+            "model=small:layers=2",
+            "model=small:dim=128",
+            "model=large:layers=12",
+            "model=large:dim=768",
+            "-m",
+            "m",
+            "true",
+        ]);
+        let (params, _, _) = parse_args(&args).unwrap();
+        assert_eq!(
+            params[2],
+            ("DIM".to_string(), "\"128\",\"768\"".to_string())
+        );
+    }
+
+    #[test]
+    fn test_no_split() {
+        let args = strings(&[
+            "--no-split",
+            "devices",
+            "--devices",
+            "0,1",
+            "-m",
+            "m",
+            "true",
+        ]);
+        let (params, _, _) = parse_args(&args).unwrap();
+        assert_eq!(params, [("DEVICES".to_string(), "\"0,1\"".to_string())]);
+        let args = strings(&["--no-split", "x", "--devices", "0,1", "-m", "m", "true"]);
+        assert!(parse_args(&args).is_err());
+    }
+
+    #[test]
+    fn test_parse_indices() {
+        assert_eq!(parse_indices("3,7,12").unwrap(), [(3, 4), (7, 8), (12, 13)]);
+        assert_eq!(parse_indices("0:100").unwrap(), [(0, 100)]);
+        assert_eq!(
+            parse_indices("2, 500:").unwrap(),
+            [(2, 3), (500, usize::MAX)]
+        );
+        assert!(parse_indices("5:5").is_err());
+        assert!(parse_indices("a").is_err());
+
+        let args = strings(&[
+            "--x", "1:9", "--only", "0:2", "--only", "X=3", "-m", "m", "true",
+        ]);
+        let (_, _, options) = parse_args(&args).unwrap();
+        assert_eq!(options.only_indices, [(0, 2)]);
+        assert_eq!(options.only.len(), 1);
+    }
+
+    #[test]
+    fn test_shuffle_seed() {
+        let shuffle = |args: &[&str]| {
+            let args = strings(args);
+            parse_args(&args).map(|(params, _, options)| (params.len(), options.shuffle))
+        };
+        assert_eq!(
+            shuffle(&["--shuffle", "7", "--x", "1", "--metrics", "m", "true"]),
+            Ok((1, Some(7)))
+        );
+        assert_eq!(
+            shuffle(&["--shuffle=7", "--x", "1", "--metrics", "m", "true"]),
+            Ok((1, Some(7)))
+        );
+        let (params, seed) = shuffle(&["--shuffle", "--x", "1", "--metrics", "m", "true"]).unwrap();
+        assert_eq!(params, 1);
+        assert!(seed.is_some());
+        assert!(shuffle(&["--shuffle=a", "--x", "1", "--metrics", "m", "true"]).is_err());
+        let args = strings(&["--shuffle", "7", "--profile=x"]);
+        assert_eq!(find_option_value(&args, "--profile").as_deref(), Some("x"));
+    }
+
+    #[test]
+    fn test_parse_error_position() {
+        let args: Vec<String> = ["--x", "1", "--concurrency", "abc", "true"]
+            .iter()
+            .map(|s| s.to_string())
+            .collect();
+        assert_eq!(
+            parse_args(&args).unwrap_err(),
+            Error::Usage {
+                message: "Invalid concurrency value: abc".to_string(),
+                arg: Some(3),
+            }
+        );
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_shell_command() {
+        let script = "echo $LR\n".to_string();
+        assert_eq!(
+            shell_command(None, script.clone()).unwrap().0,
+            strings(&["bash", "-c", "echo $LR\n"])
+        );
+        assert_eq!(
+            shell_command(Some("sh -e -c"), script.clone()).unwrap().0,
+            strings(&["sh", "-e", "-c", "echo $LR\n"])
+        );
+
+        // The script file is private to the user, not overwritten, and removed with its guard
+        let (command, file) = shell_command(Some("sh -"), script.clone()).unwrap();
+        assert_eq!(command[0], "sh");
+        assert_eq!(fs::read_to_string(&command[1]).unwrap(), script);
+        let mode = std::os::unix::fs::PermissionsExt::mode(
+            &fs::metadata(&command[1]).unwrap().permissions(),
+        );
+        assert_eq!(mode & 0o777, 0o600);
+        assert!(shell_command(Some("sh -"), script.clone()).is_err());
+        drop(file);
+        assert!(!Path::new(&command[1]).exists());
+
+        let error = shell_command(Some("runexp-no-such-shell"), script).unwrap_err();
+        assert!(error.contains("runexp-no-such-shell is not available"));
+    }
 }
@@ -0,0 +1,273 @@
+use crate::Sweep;
+use crate::csv::read_csv;
+use crate::diff::is_metric_column;
+use crate::error::Error;
+use crate::evaluator::{depends_on, evaluate_params, format_float, is_internal};
+use crate::history::lower_is_better;
+use crate::metadata::{metadata_path, metadata_value, read_metadata};
+use crate::parser::parse_args;
+
+// `runexp refine [FILE] --metric M --around-best`: run a finer grid around the best
+// combination of a finished sweep and append its runs to the same results file. The
+// sweep is set up again from the command line recorded in its metadata; every parameter
+// with numeric values then gets the best value and `--factor - 1` points towards each of
+// its neighbouring values in the original grid, and every other parameter keeps only its
+// best value. Internal parameters and those computed from others or from IDX keep their
+// definition.
+pub fn run_refine(args: &[String]) -> Result<(), Error> {
+    let mut file = None;
+    let mut metric = None;
+    let mut lower = None;
+    let mut factor = 2;
+    let mut dry_run = false;
+    let mut yes = false;
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        let mut value = || {
+            args.next()
+                .map(|v| v.as_str())
+                .ok_or_else(|| Error::usage(format!("{} requires a value", arg)))
+        };
+        match arg.as_str() {
+            "--metric" => metric = Some(value()?.to_string()),
+            "--maximize" => lower = Some(false),
+            "--minimize" => lower = Some(true),
+            // The only kind of refinement so far
+            "--around-best" => {}
+            "--factor" => {
+                let v = value()?;
+                factor = v.parse().ok().filter(|&f| f >= 2).ok_or_else(|| {
+                    Error::usage(format!("Invalid factor (expected an integer >= 2): {}", v))
+                })?;
+            }
+            "--dry-run" => dry_run = true,
+            "--yes" => yes = true,
+            _ if file.is_none() && !arg.starts_with('-') => file = Some(arg.clone()),
+            _ => return Err(Error::usage(format!("Unexpected argument: {}", arg))),
+        }
+    }
+    let metric = metric.ok_or_else(|| {
+        Error::usage(
+            "Usage: runexp refine [FILE] --metric M [--around-best] [--factor N] [--maximize | --minimize]",
+        )
+    })?;
+    let lower = lower.unwrap_or_else(|| lower_is_better(&metric));
+    let file = file.unwrap_or_else(|| "results.csv".to_string());
+
+    let path = metadata_path(&file);
+    let metadata = read_metadata(&path).map_err(|message| Error::Io { path, message })?;
+    let invocation: Vec<String> = (0..)
+        .map_while(|i| metadata_value(&metadata, &format!("arg.{}", i)))
+        .map(String::from)
+        .collect();
+    if invocation.is_empty() {
+        return Err(Error::Incompatible {
+            message: format!("The metadata of {} has no recorded command line", file),
+            path: file,
+        });
+    }
+    let (params, stages, mut options) = parse_args(&invocation)?;
+    if options.optimize.is_some() || options.halving.is_some() {
+        return Err(Error::usage(
+            "Sweeps with --optimize or --halving cannot be refined",
+        ));
+    }
+
+    let records = read_csv(&file).map_err(|message| Error::Io {
+        path: file.clone(),
+        message,
+    })?;
+    let incompatible = |message: String| Error::Incompatible {
+        path: file.clone(),
+        message,
+    };
+    let (header, rows) = records
+        .split_first()
+        .ok_or_else(|| incompatible(format!("Empty results file: {}", file)))?;
+    let column = header
+        .iter()
+        .position(|c| is_metric_column(c, std::slice::from_ref(&metric)))
+        .ok_or_else(|| incompatible(format!("No column for metric {} in {}", metric, file)))?;
+    let best = rows
+        .iter()
+        .filter_map(|row| {
+            let value: f64 = row.get(column)?.parse().ok()?;
+            value.is_finite().then_some((row, value))
+        })
+        .min_by(|a, b| {
+            let order = a.1.total_cmp(&b.1);
+            if lower { order } else { order.reverse() }
+        })
+        .map(|(row, _)| row)
+        .ok_or_else(|| incompatible(format!("No run in {} has a value for {}", file, metric)))?;
+    let best_value = |name: &str| -> Result<String, Error> {
+        let idx = header
+            .iter()
+            .position(|c| c == name)
+            .ok_or_else(|| incompatible(format!("No column for parameter {} in {}", name, file)))?;
+        Ok(best[idx].clone())
+    };
+
+    let mut described = Vec::new();
+    for (name, _) in params.iter().filter(|(name, _)| !is_internal(name)) {
+        described.push(format!("{}={}", name, best_value(name)?));
+    }
+    println!(
+        "Best {}={}: {}",
+        header[column],
+        best[column],
+        described.join(" ")
+    );
+
+    let sampling = options.sampling();
+    let mut refined = Vec::new();
+    for (name, definition) in &params {
+        let zipped = options.zip.iter().any(|group| group.contains(name));
+        if is_internal(name) || depends_on(definition, &params) {
+            refined.push((name.clone(), definition.clone()));
+            continue;
+        }
+        let combinations = evaluate_params(&[(name.clone(), definition.clone())], &[], sampling)?;
+        let values: Vec<String> = combinations
+            .into_iter()
+            .map(|mut combo| combo.params.remove(name).unwrap_or_default())
+            .collect();
+        let best = best_value(name)?;
+        let values = match refine_values(&values, &best, factor) {
+            Some(values) if !zipped => values,
+            _ => vec![best],
+        };
+        refined.push((name.clone(), values.join(",")));
+    }
+    let grid: Vec<String> = refined
+        .iter()
+        .zip(&params)
+        .filter(|(new, old)| new != old)
+        .map(|((name, values), _)| format!("{}: {}", name, values))
+        .collect();
+    println!("Refined grid: {}", grid.join("; "));
+
+    // The refined grid replaces the original one, including its distributions and any
+    // subset of it that was chosen
+    options.output_file = file;
+    options.samples = None;
+    options.sample = None;
+    options.shard = None;
+    options.dry_run |= dry_run;
+    options.yes |= yes;
+    Sweep::from_parts(refined, stages, options)
+        .run()
+        .map(|_| ())
+}
+
+// The best value and `factor - 1` evenly spaced points towards each of its neighbours
+// among the values of the original grid, in ascending order. Values spanning two orders
+// of magnitude or more are spaced logarithmically, and integers stay integers. None if
+// the values are not all numbers or there is only one of them.
+fn refine_values(values: &[String], best: &str, factor: usize) -> Option<Vec<String>> {
+    let mut numbers: Vec<f64> = values
+        .iter()
+        .map(|v| v.trim().parse::<f64>().ok().filter(|v| v.is_finite()))
+        .collect::<Option<_>>()?;
+    numbers.sort_by(f64::total_cmp);
+    numbers.dedup();
+    let best: f64 = best.trim().parse().ok()?;
+    if numbers.len() < 2 {
+        return None;
+    }
+    let integers = numbers.iter().all(|v| v.fract() == 0.0);
+    let log = numbers[0] > 0.0 && numbers[numbers.len() - 1] / numbers[0] >= 100.0;
+    let scale = |v: f64| if log { v.ln() } else { v };
+    let unscale = |v: f64| if log { v.exp() } else { v };
+
+    let position = numbers.partition_point(|&v| v < best);
+    let neighbours = [
+        position.checked_sub(1).map(|i| numbers[i]),
+        numbers
+            .get(position)
+            .filter(|&&v| v > best)
+            .or_else(|| numbers.get(position + 1))
+            .copied(),
+    ];
+    let mut refined = vec![best];
+    for neighbour in neighbours.into_iter().flatten() {
+        for i in 1..factor {
+            let t = i as f64 / factor as f64;
+            let value = unscale(scale(best) + (scale(neighbour) - scale(best)) * t);
+            refined.push(if integers { value.round() } else { value });
+        }
+    }
+    refined.sort_by(f64::total_cmp);
+    refined.dedup();
+    Some(refined.into_iter().map(format_float).collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn strings(values: &[&str]) -> Vec<String> {
+        values.iter().map(|v| v.to_string()).collect()
+    }
+
+    #[test]
+    fn test_refine_values() {
+        let values = strings(&["0.1", "0.2", "0.3", "0.4"]);
+        assert_eq!(
+            refine_values(&values, "0.2", 2).unwrap(),
+            ["0.15", "0.2", "0.25"]
+        );
+        assert_eq!(
+            refine_values(&values, "0.4", 4).unwrap(),
+            ["0.325", "0.35", "0.375", "0.4"]
+        );
+        // Logarithmic spacing for values spanning orders of magnitude
+        let values = strings(&["0.0001", "0.001", "0.01"]);
+        assert_eq!(
+            refine_values(&values, "0.001", 2).unwrap(),
+            ["0.000316227766017", "0.001", "0.00316227766017"]
+        );
+        // Integers stay integers
+        let values = strings(&["32", "64", "128"]);
+        assert_eq!(refine_values(&values, "64", 2).unwrap(), ["48", "64", "96"]);
+        let values = strings(&["1", "2"]);
+        assert_eq!(refine_values(&values, "1", 4).unwrap(), ["1", "2"]);
+
+        assert!(refine_values(&strings(&["adam", "sgd"]), "adam", 2).is_none());
+        assert!(refine_values(&strings(&["3"]), "3", 2).is_none());
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_refine_appends_runs() {
+        let dir = std::env::temp_dir().join("runexp_test_refine");
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(&dir).unwrap();
+        let output = dir.join("results.csv").to_string_lossy().to_string();
+
+        let args = strings(&[
+            "--metrics",
+            "loss",
+            "-o",
+            &output,
+            "--x",
+            "0,10,20,30",
+            "--opt",
+            "adam,sgd",
+            "--y",
+            "X*2",
+            "bash",
+            "-c",
+            "echo \"loss: $(( (X-12)*(X-12) + ${#OPT} ))\"",
+        ]);
+        let (params, stages, options) = parse_args(&args).unwrap();
+        Sweep::from_parts(params, stages, options).run().unwrap();
+
+        run_refine(&strings(&[&output, "--metric", "loss", "--factor", "2"])).unwrap();
+        let records = read_csv(&output).unwrap();
+        let rows: Vec<String> = records[9..].iter().map(|row| row[..3].join(",")).collect();
+        assert_eq!(rows, ["5,sgd,10", "15,sgd,30"]);
+
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+}
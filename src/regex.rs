@@ -0,0 +1,450 @@
+// Minimal backtracking regular expressions, enough for pulling values out of log lines.
+// Supports literals, `.`, classes (`[a-z_]`, `[^,]`), `\d \w \s` and their negations,
+// groups (`(...)`, `(?:...)`, named `(?<name>...)` / `(?P<name>...)`), alternation,
+// the quantifiers `* + ? {m} {m,} {m,n}` (greedy, or lazy with a trailing `?`) and `^ $`.
+
+#[derive(Debug, Clone)]
+enum ClassItem {
+    Range(char, char),
+    Space,
+}
+
+#[derive(Debug, Clone)]
+enum Node {
+    Char(char),
+    Any,
+    Class(Vec<ClassItem>, bool),
+    Start,
+    End,
+    // Capture index, or None for a non-capturing group
+    Group(Box<Node>, Option<usize>),
+    Concat(Vec<Node>),
+    Alt(Vec<Node>),
+    // Node, minimum, maximum, greedy
+    Repeat(Box<Node>, usize, Option<usize>, bool),
+}
+
+#[derive(Debug, Clone)]
+pub struct Regex {
+    root: Node,
+    // Name of each capture group (index 0 is the whole match)
+    names: Vec<Option<String>>,
+}
+
+// Start and end (in chars) of each group in a match; None for groups that did not take part
+type Captures = Vec<Option<(usize, usize)>>;
+
+impl Regex {
+    pub fn new(pattern: &str) -> Result<Self, String> {
+        let mut parser = Parser {
+            chars: pattern.chars().collect(),
+            pos: 0,
+            names: vec![None],
+        };
+        let root = parser.parse_alt()?;
+        if parser.pos < parser.chars.len() {
+            return Err(format!("Unmatched ')' in regex: {}", pattern));
+        }
+        Ok(Regex {
+            root,
+            names: parser.names,
+        })
+    }
+
+    // Names of the named groups, in pattern order
+    pub fn group_names(&self) -> Vec<&str> {
+        self.names.iter().filter_map(|n| n.as_deref()).collect()
+    }
+
+    // Text of each named group in the leftmost match, if any
+    pub fn captures(&self, text: &str) -> Option<Vec<(String, Option<String>)>> {
+        let chars: Vec<char> = text.chars().collect();
+        let caps = self.find(&chars)?;
+        Some(
+            self.names
+                .iter()
+                .zip(&caps)
+                .filter_map(|(name, span)| {
+                    let text = span.map(|(s, e)| chars[s..e].iter().collect());
+                    name.clone().map(|name| (name, text))
+                })
+                .collect(),
+        )
+    }
+
+    fn find(&self, input: &[char]) -> Option<Captures> {
+        for start in 0..=input.len() {
+            let mut caps: Captures = vec![None; self.names.len()];
+            let matched = match_node(&self.root, input, start, &mut caps, &mut |end, caps| {
+                caps[0] = Some((start, end));
+                true
+            });
+            if matched {
+                return Some(caps);
+            }
+        }
+        None
+    }
+}
+
+// Continuation-passing backtracking: `k` is called with the end position of each way
+// the node can match, and the first continuation to succeed wins.
+fn match_node(
+    node: &Node,
+    input: &[char],
+    pos: usize,
+    caps: &mut Captures,
+    k: &mut dyn FnMut(usize, &mut Captures) -> bool,
+) -> bool {
+    match node {
+        Node::Char(c) => pos < input.len() && input[pos] == *c && k(pos + 1, caps),
+        Node::Any => pos < input.len() && k(pos + 1, caps),
+        Node::Class(items, negated) => {
+            pos < input.len() && class_matches(items, input[pos]) != *negated && k(pos + 1, caps)
+        }
+        Node::Start => pos == 0 && k(pos, caps),
+        Node::End => pos == input.len() && k(pos, caps),
+        Node::Group(inner, None) => match_node(inner, input, pos, caps, k),
+        Node::Group(inner, Some(idx)) => {
+            let idx = *idx;
+            match_node(inner, input, pos, caps, &mut |end, caps| {
+                let saved = caps[idx];
+                caps[idx] = Some((pos, end));
+                if k(end, caps) {
+                    return true;
+                }
+                caps[idx] = saved;
+                false
+            })
+        }
+        Node::Concat(nodes) => match_seq(nodes, input, pos, caps, k),
+        Node::Alt(alternatives) => {
+            for alternative in alternatives {
+                if match_node(alternative, input, pos, caps, k) {
+                    return true;
+                }
+            }
+            false
+        }
+        Node::Repeat(inner, min, max, greedy) => {
+            match_repeat(inner, (*min, *max, *greedy), 0, input, pos, caps, k)
+        }
+    }
+}
+
+fn match_seq(
+    nodes: &[Node],
+    input: &[char],
+    pos: usize,
+    caps: &mut Captures,
+    k: &mut dyn FnMut(usize, &mut Captures) -> bool,
+) -> bool {
+    match nodes.split_first() {
+        None => k(pos, caps),
+        Some((first, rest)) => match_node(first, input, pos, caps, &mut |next, caps| {
+            match_seq(rest, input, next, caps, k)
+        }),
+    }
+}
+
+fn match_repeat(
+    inner: &Node,
+    (min, max, greedy): (usize, Option<usize>, bool),
+    count: usize,
+    input: &[char],
+    pos: usize,
+    caps: &mut Captures,
+    k: &mut dyn FnMut(usize, &mut Captures) -> bool,
+) -> bool {
+    let can_stop = count >= min;
+    let can_continue = max.is_none_or(|max| count < max);
+
+    if !greedy && can_stop && k(pos, caps) {
+        return true;
+    }
+    // An iteration must consume input, otherwise `(a*)*` would loop forever
+    if can_continue
+        && match_node(inner, input, pos, caps, &mut |next, caps| {
+            next != pos && match_repeat(inner, (min, max, greedy), count + 1, input, next, caps, k)
+        })
+    {
+        return true;
+    }
+    greedy && can_stop && k(pos, caps)
+}
+
+fn class_matches(items: &[ClassItem], c: char) -> bool {
+    items.iter().any(|item| match item {
+        ClassItem::Range(lo, hi) => *lo <= c && c <= *hi,
+        ClassItem::Space => c.is_whitespace(),
+    })
+}
+
+struct Parser {
+    chars: Vec<char>,
+    pos: usize,
+    names: Vec<Option<String>>,
+}
+
+impl Parser {
+    fn peek(&self) -> Option<char> {
+        self.chars.get(self.pos).copied()
+    }
+
+    fn eat(&mut self, c: char) -> bool {
+        if self.peek() == Some(c) {
+            self.pos += 1;
+            true
+        } else {
+            false
+        }
+    }
+
+    fn parse_alt(&mut self) -> Result<Node, String> {
+        let mut alternatives = vec![self.parse_concat()?];
+        while self.eat('|') {
+            alternatives.push(self.parse_concat()?);
+        }
+        Ok(if alternatives.len() == 1 {
+            alternatives.pop().unwrap()
+        } else {
+            Node::Alt(alternatives)
+        })
+    }
+
+    fn parse_concat(&mut self) -> Result<Node, String> {
+        let mut nodes = Vec::new();
+        while let Some(c) = self.peek() {
+            if c == '|' || c == ')' {
+                break;
+            }
+            let atom = self.parse_atom()?;
+            nodes.push(self.parse_quantifier(atom)?);
+        }
+        Ok(Node::Concat(nodes))
+    }
+
+    fn parse_atom(&mut self) -> Result<Node, String> {
+        let c = self.peek().ok_or("Unexpected end of regex")?;
+        self.pos += 1;
+        match c {
+            '.' => Ok(Node::Any),
+            '^' => Ok(Node::Start),
+            '$' => Ok(Node::End),
+            '[' => self.parse_class(),
+            '(' => self.parse_group(),
+            '\\' => self.parse_escape(),
+            '*' | '+' | '?' | '{' => Err(format!("Nothing to repeat before '{}' in regex", c)),
+            c => Ok(Node::Char(c)),
+        }
+    }
+
+    fn parse_group(&mut self) -> Result<Node, String> {
+        let index = if self.eat('?') {
+            if self.eat(':') {
+                None
+            } else {
+                // (?<name>...) or (?P<name>...)
+                self.eat('P');
+                if !self.eat('<') {
+                    return Err("Unsupported group syntax in regex".to_string());
+                }
+                let mut name = String::new();
+                while let Some(c) = self.peek() {
+                    self.pos += 1;
+                    if c == '>' {
+                        break;
+                    }
+                    name.push(c);
+                }
+                if name.is_empty() {
+                    return Err("Empty group name in regex".to_string());
+                }
+                self.names.push(Some(name));
+                Some(self.names.len() - 1)
+            }
+        } else {
+            self.names.push(None);
+            Some(self.names.len() - 1)
+        };
+
+        let inner = self.parse_alt()?;
+        if !self.eat(')') {
+            return Err("Unclosed group in regex".to_string());
+        }
+        Ok(Node::Group(Box::new(inner), index))
+    }
+
+    fn parse_escape(&mut self) -> Result<Node, String> {
+        let c = self.peek().ok_or("Trailing backslash in regex")?;
+        self.pos += 1;
+        Ok(match c {
+            'd' | 'w' | 's' => Node::Class(escape_class(c), false),
+            'D' | 'W' | 'S' => Node::Class(escape_class(c.to_ascii_lowercase()), true),
+            'n' => Node::Char('\n'),
+            't' => Node::Char('\t'),
+            'r' => Node::Char('\r'),
+            c => Node::Char(c),
+        })
+    }
+
+    fn parse_class(&mut self) -> Result<Node, String> {
+        let negated = self.eat('^');
+        let mut items = Vec::new();
+        let mut first = true;
+        loop {
+            let c = self.peek().ok_or("Unclosed character class in regex")?;
+            self.pos += 1;
+            if c == ']' && !first {
+                break;
+            }
+            first = false;
+
+            let lo = if c == '\\' {
+                let escaped = self.peek().ok_or("Trailing backslash in regex")?;
+                self.pos += 1;
+                match escaped {
+                    'd' | 'w' | 's' => {
+                        items.extend(escape_class(escaped));
+                        continue;
+                    }
+                    'n' => '\n',
+                    't' => '\t',
+                    'r' => '\r',
+                    other => other,
+                }
+            } else {
+                c
+            };
+
+            // A range like a-z; a '-' before ']' is a literal
+            if self.peek() == Some('-') && self.chars.get(self.pos + 1).is_some_and(|&c| c != ']') {
+                self.pos += 1;
+                let hi = self.peek().ok_or("Unclosed character class in regex")?;
+                self.pos += 1;
+                if hi < lo {
+                    return Err(format!("Invalid range {}-{} in regex", lo, hi));
+                }
+                items.push(ClassItem::Range(lo, hi));
+            } else {
+                items.push(ClassItem::Range(lo, lo));
+            }
+        }
+        Ok(Node::Class(items, negated))
+    }
+
+    fn parse_quantifier(&mut self, atom: Node) -> Result<Node, String> {
+        let (min, max) = match self.peek() {
+            Some('*') => (0, None),
+            Some('+') => (1, None),
+            Some('?') => (0, Some(1)),
+            Some('{') => {
+                let close = self.chars[self.pos..]
+                    .iter()
+                    .position(|&c| c == '}')
+                    .ok_or("Unclosed '{' in regex")?;
+                let body: String = self.chars[self.pos + 1..self.pos + close].iter().collect();
+                let number = |s: &str| {
+                    s.trim()
+                        .parse::<usize>()
+                        .map_err(|_| format!("Invalid repetition {{{}}} in regex", body))
+                };
+                let bounds = match body.split_once(',') {
+                    None => (number(&body)?, Some(number(&body)?)),
+                    Some((min, "")) => (number(min)?, None),
+                    Some((min, max)) => (number(min)?, Some(number(max)?)),
+                };
+                self.pos += close;
+                bounds
+            }
+            _ => return Ok(atom),
+        };
+        self.pos += 1;
+        let greedy = !self.eat('?');
+        Ok(Node::Repeat(Box::new(atom), min, max, greedy))
+    }
+}
+
+fn escape_class(c: char) -> Vec<ClassItem> {
+    match c {
+        'd' => vec![ClassItem::Range('0', '9')],
+        'w' => vec![
+            ClassItem::Range('a', 'z'),
+            ClassItem::Range('A', 'Z'),
+            ClassItem::Range('0', '9'),
+            ClassItem::Range('_', '_'),
+        ],
+        _ => vec![ClassItem::Space],
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn matches(pattern: &str, text: &str) -> bool {
+        Regex::new(pattern).unwrap().captures(text).is_some()
+    }
+
+    fn capture(pattern: &str, text: &str, name: &str) -> Option<String> {
+        Regex::new(pattern)
+            .unwrap()
+            .captures(text)?
+            .into_iter()
+            .find(|(n, _)| n == name)?
+            .1
+    }
+
+    #[test]
+    fn test_regex_match() {
+        assert!(matches("a.c", "xxabcxx"));
+        assert!(!matches("^a.c$", "xxabcxx"));
+        assert!(matches("^(foo|bar)+$", "foobarfoo"));
+        assert!(matches(r"^\d{2,3}$", "123"));
+        assert!(!matches(r"^\d{2,3}$", "1234"));
+        assert!(matches(r"^[^,\s]+$", "a.b-c"));
+        assert!(!matches(r"^[^,\s]+$", "a b"));
+        assert!(matches(r"^(a*)*$", "aaa"));
+        assert!(matches(r"\(x\)", "f(x)"));
+    }
+
+    #[test]
+    fn test_regex_captures() {
+        let number = r"(?<loss>-?\d+(?:\.\d+)?)";
+        assert_eq!(
+            capture(
+                &format!(r"loss=\({}\)", number),
+                "step 3 loss=(0.25) lr=1",
+                "loss"
+            ),
+            Some("0.25".to_string())
+        );
+        assert_eq!(
+            capture(r"epoch (?P<epoch>\d+)", "epoch 12 of 20", "epoch"),
+            Some("12".to_string())
+        );
+        // Greedy and lazy quantifiers
+        assert_eq!(
+            capture("(?<x>a.*b)", "aXbYb", "x"),
+            Some("aXbYb".to_string())
+        );
+        assert_eq!(
+            capture("(?<x>a.*?b)", "aXbYb", "x"),
+            Some("aXb".to_string())
+        );
+        // Optional group that did not participate
+        assert_eq!(capture("x(?<y>y)?", "x", "y"), None);
+
+        let re = Regex::new(r"(?<a>\d+)/(?<b>\d+)").unwrap();
+        assert_eq!(re.group_names(), vec!["a", "b"]);
+    }
+
+    #[test]
+    fn test_regex_errors() {
+        assert!(Regex::new("(abc").is_err());
+        assert!(Regex::new("abc)").is_err());
+        assert!(Regex::new("*a").is_err());
+        assert!(Regex::new("[abc").is_err());
+        assert!(Regex::new("a{x}").is_err());
+    }
+}
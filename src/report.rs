@@ -0,0 +1,221 @@
+use crate::csv::read_csv;
+use crate::error::Error;
+use crate::metadata::{metadata_path, metadata_value, read_metadata};
+
+// Render a results file and its sweep metadata as a Markdown or HTML report.
+// Captured stdout/stderr columns are left out to keep the tables readable.
+pub fn run_report(args: &[String]) -> Result<(), Error> {
+    let mut html = false;
+    let mut file = None;
+    for arg in args {
+        match arg.as_str() {
+            "--html" => html = true,
+            "--markdown" => html = false,
+            _ if file.is_none() && !arg.starts_with('-') => file = Some(arg.clone()),
+            _ => return Err(Error::usage(format!("Unexpected argument: {}", arg))),
+        }
+    }
+    let file = file.unwrap_or_else(|| "results.csv".to_string());
+
+    let records = read_csv(&file).map_err(|message| Error::Io {
+        path: file.clone(),
+        message,
+    })?;
+    if records.is_empty() {
+        return Err(Error::Incompatible {
+            message: format!("Empty results file: {}", file),
+            path: file,
+        });
+    }
+    let metadata = read_metadata(&metadata_path(&file)).unwrap_or_default();
+    let report = build_report(&file, &records, &metadata);
+
+    if html {
+        print!("{}", report.to_html());
+    } else {
+        print!("{}", report.to_markdown());
+    }
+    Ok(())
+}
+
+struct Report {
+    title: String,
+    // Sweep metadata other than parameter descriptions
+    metadata: Vec<(String, String)>,
+    // (parameter, description)
+    parameters: Vec<(String, String)>,
+    header: Vec<String>,
+    rows: Vec<Vec<String>>,
+}
+
+fn build_report(file: &str, records: &[Vec<String>], metadata: &[(String, String)]) -> Report {
+    let is_output = |h: &str| {
+        h == "stdout" || h == "stderr" || h.ends_with(".stdout") || h.ends_with(".stderr")
+    };
+    let keep: Vec<usize> = (0..records[0].len())
+        .filter(|&idx| !is_output(&records[0][idx]))
+        .collect();
+    let select = |record: &Vec<String>| -> Vec<String> {
+        keep.iter()
+            .map(|&idx| record.get(idx).cloned().unwrap_or_default())
+            .collect()
+    };
+
+    let params: Vec<String> = metadata_value(metadata, "params")
+        .map(|p| {
+            p.split(',')
+                .filter(|s| !s.is_empty())
+                .map(String::from)
+                .collect()
+        })
+        .unwrap_or_default();
+    let parameters = params
+        .iter()
+        .map(|param| {
+            let description = metadata_value(metadata, &format!("describe.{}", param));
+            (param.clone(), description.unwrap_or("").to_string())
+        })
+        .collect();
+
+    Report {
+        title: format!("Sweep report: {}", file),
+        metadata: metadata
+            .iter()
+            .filter(|(key, _)| !key.starts_with("describe.") && key != "params")
+            .cloned()
+            .collect(),
+        parameters,
+        header: select(&records[0]),
+        rows: records[1..].iter().map(select).collect(),
+    }
+}
+
+impl Report {
+    fn to_markdown(&self) -> String {
+        let cell = |s: &str| s.replace('|', "\\|").replace('\n', " ");
+        let table = |header: &[String], rows: &[Vec<String>]| {
+            let mut out = format!(
+                "| {} |\n|{}\n",
+                header
+                    .iter()
+                    .map(|h| cell(h))
+                    .collect::<Vec<_>>()
+                    .join(" | "),
+                " --- |".repeat(header.len())
+            );
+            for row in rows {
+                let row: Vec<String> = row.iter().map(|v| cell(v)).collect();
+                out += &format!("| {} |\n", row.join(" | "));
+            }
+            out
+        };
+
+        let mut out = format!("# {}\n\n", self.title);
+        if !self.metadata.is_empty() {
+            out += "## Sweep\n\n";
+            for (key, value) in &self.metadata {
+                out += &format!("- **{}**: {}\n", key, cell(value));
+            }
+            out += "\n";
+        }
+        if !self.parameters.is_empty() {
+            out += "## Parameters\n\n";
+            let rows: Vec<Vec<String>> = self
+                .parameters
+                .iter()
+                .map(|(p, d)| vec![p.clone(), d.clone()])
+                .collect();
+            out += &table(&["Parameter".to_string(), "Description".to_string()], &rows);
+            out += "\n";
+        }
+        out += &format!("## Results ({} rows)\n\n", self.rows.len());
+        out += &table(&self.header, &self.rows);
+        out
+    }
+
+    fn to_html(&self) -> String {
+        let esc = |s: &str| {
+            s.replace('&', "&amp;")
+                .replace('<', "&lt;")
+                .replace('>', "&gt;")
+                .replace('"', "&quot;")
+        };
+        let table = |header: &[String], rows: &[Vec<String>]| {
+            let mut out = String::from("<table>\n<tr>");
+            for h in header {
+                out += &format!("<th>{}</th>", esc(h));
+            }
+            out += "</tr>\n";
+            for row in rows {
+                out += "<tr>";
+                for v in row {
+                    out += &format!("<td>{}</td>", esc(v));
+                }
+                out += "</tr>\n";
+            }
+            out += "</table>\n";
+            out
+        };
+
+        let mut out = format!(
+            "<!DOCTYPE html>\n<html>\n<head><meta charset=\"utf-8\"><title>{0}</title></head>\n<body>\n<h1>{0}</h1>\n",
+            esc(&self.title)
+        );
+        if !self.metadata.is_empty() {
+            out += "<h2>Sweep</h2>\n<ul>\n";
+            for (key, value) in &self.metadata {
+                out += &format!("<li><b>{}</b>: {}</li>\n", esc(key), esc(value));
+            }
+            out += "</ul>\n";
+        }
+        if !self.parameters.is_empty() {
+            out += "<h2>Parameters</h2>\n";
+            let rows: Vec<Vec<String>> = self
+                .parameters
+                .iter()
+                .map(|(p, d)| vec![p.clone(), d.clone()])
+                .collect();
+            out += &table(&["Parameter".to_string(), "Description".to_string()], &rows);
+        }
+        out += &format!("<h2>Results ({} rows)</h2>\n", self.rows.len());
+        out += &table(&self.header, &self.rows);
+        out += "</body>\n</html>\n";
+        out
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_markdown_report() {
+        let records: Vec<Vec<String>> = vec![
+            vec!["GPU".into(), "accuracy".into(), "stdout".into()],
+            vec!["1".into(), "0.9".into(), "accuracy: 0.9\n".into()],
+        ];
+        let metadata = vec![
+            ("params".to_string(), "GPU".to_string()),
+            ("metrics".to_string(), "accuracy".to_string()),
+            (
+                "describe.GPU".to_string(),
+                "number of GPUs used".to_string(),
+            ),
+        ];
+
+        let markdown = build_report("results.csv", &records, &metadata).to_markdown();
+        assert!(markdown.contains("- **metrics**: accuracy"));
+        assert!(markdown.contains("| GPU | number of GPUs used |"));
+        assert!(markdown.contains("| GPU | accuracy |"));
+        assert!(markdown.contains("| 1 | 0.9 |"));
+        assert!(!markdown.contains("stdout"));
+    }
+
+    #[test]
+    fn test_report_errors() {
+        let args = ["--pdf".to_string()];
+        assert_eq!(run_report(&args).unwrap_err().exit_code(), 2);
+        let args = ["runexp_test_missing_report.csv".to_string()];
+        assert_eq!(run_report(&args).unwrap_err().kind(), "io");
+    }
+}
@@ -0,0 +1,89 @@
+use crate::csv::escape_csv_field;
+use std::fs::{File, OpenOptions};
+use std::io::Write;
+
+// Receives the results of a sweep as rows of strings. The executor decides which
+// columns exist (see executor::Column); a sink only decides how rows are stored.
+pub trait ResultSink: Send {
+    // Called once per invocation, before any rows
+    fn write_header(&mut self, columns: &[String]) -> Result<(), String>;
+
+    // Called after each successful run, with one value per header column
+    fn append_row(&mut self, values: &[String]) -> Result<(), String>;
+
+    // Called once after the last run
+    fn finalize(&mut self) -> Result<(), String> {
+        Ok(())
+    }
+}
+
+// The results CSV. Rows are appended and flushed one at a time so an interrupted
+// sweep keeps every finished run.
+pub struct CsvSink {
+    path: String,
+    // Resuming an existing file: its header is already in place
+    resume: bool,
+}
+
+impl CsvSink {
+    pub fn new(path: &str, resume: bool) -> Self {
+        CsvSink {
+            path: path.to_string(),
+            resume,
+        }
+    }
+}
+
+fn csv_line(values: &[String]) -> String {
+    values
+        .iter()
+        .map(|v| escape_csv_field(v))
+        .collect::<Vec<_>>()
+        .join(",")
+}
+
+impl ResultSink for CsvSink {
+    fn write_header(&mut self, columns: &[String]) -> Result<(), String> {
+        if self.resume {
+            return Ok(());
+        }
+        let mut file = File::create(&self.path)
+            .map_err(|e| format!("Failed to create results file: {}", e))?;
+        writeln!(file, "{}", csv_line(columns))
+            .map_err(|e| format!("Failed to write to file: {}", e))
+    }
+
+    fn append_row(&mut self, values: &[String]) -> Result<(), String> {
+        let mut file = OpenOptions::new()
+            .append(true)
+            .open(&self.path)
+            .map_err(|e| format!("Failed to open results file for appending: {}", e))?;
+        writeln!(file, "{}", csv_line(values))
+            .map_err(|e| format!("Failed to write to file: {}", e))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_csv_sink_resume_keeps_header() {
+        let path = std::env::temp_dir().join("runexp_test_csv_sink.csv");
+        let path = path.to_string_lossy().to_string();
+        let row = |values: &[&str]| values.iter().map(|v| v.to_string()).collect::<Vec<_>>();
+
+        let mut sink = CsvSink::new(&path, false);
+        sink.write_header(&row(&["GPU", "note"])).unwrap();
+        sink.append_row(&row(&["1", "a,b"])).unwrap();
+
+        let mut resumed = CsvSink::new(&path, true);
+        resumed.write_header(&row(&["GPU", "note"])).unwrap();
+        resumed.append_row(&row(&["2", "c"])).unwrap();
+
+        let content = std::fs::read_to_string(&path).unwrap();
+        assert_eq!(content, "GPU,note\n1,\"a,b\"\n2,c\n");
+
+        let _ = std::fs::remove_file(&path);
+    }
+}
@@ -0,0 +1,289 @@
+use crate::metadata::{format_metadata, parse_metadata};
+use crate::parser::Options;
+use crate::timestamp::DateTime;
+use std::fs::{self, OpenOptions};
+use std::io::Write;
+use std::process::Command;
+
+// The environment a sweep ran in is appended to `<output>.snapshot` every time the
+// sweep starts, so resumed sweeps keep a record of each machine that contributed rows.
+// Each snapshot is a block of `key=value` lines, in the format of the metadata file.
+pub fn snapshot_path(output_file: &str) -> String {
+    format!("{}.snapshot", output_file)
+}
+
+// Describe the machine and the invocation. Probes that fail (e.g. no NVIDIA driver)
+// are left out.
+pub fn take_snapshot(options: &Options) -> Vec<(String, String)> {
+    let mut entries = vec![
+        ("started".to_string(), DateTime::now().iso()),
+        ("invocation".to_string(), invocation(options)),
+        (
+            "runexp_version".to_string(),
+            env!("CARGO_PKG_VERSION").to_string(),
+        ),
+        ("os".to_string(), std::env::consts::OS.to_string()),
+        ("arch".to_string(), std::env::consts::ARCH.to_string()),
+    ];
+    let mut add = |key: &str, value: Option<String>| {
+        if let Some(value) = value.filter(|v| !v.is_empty()) {
+            entries.push((key.to_string(), value));
+        }
+    };
+
+    add("hostname", command_output("hostname", &[]));
+    if cfg!(windows) {
+        add("kernel", command_output("cmd", &["/C", "ver"]));
+    } else {
+        add("kernel", command_output("uname", &["-srm"]));
+    }
+    add(
+        "cpus",
+        std::thread::available_parallelism()
+            .ok()
+            .map(|n| n.to_string()),
+    );
+    add("cpu_model", cpu_model());
+    add("memory", proc_field("/proc/meminfo", "MemTotal"));
+
+    // One entry per GPU, e.g. gpu.0=NVIDIA A100-SXM4-80GB, 535.104.05, 81920 MiB
+    let gpus = command_output(
+        "nvidia-smi",
+        &[
+            "--query-gpu=index,name,driver_version,memory.total",
+            "--format=csv,noheader",
+        ],
+    );
+    for line in gpus.iter().flat_map(|gpus| gpus.lines()) {
+        if let Some((index, rest)) = line.split_once(',') {
+            add(
+                &format!("gpu.{}", index.trim()),
+                Some(rest.trim().to_string()),
+            );
+        }
+    }
+    add(
+        "cuda",
+        command_output("nvidia-smi", &[]).and_then(|out| {
+            let (_, rest) = out.split_once("CUDA Version:")?;
+            rest.split_whitespace().next().map(|v| v.to_string())
+        }),
+    );
+
+    for tool in &options.snapshot_commands {
+        let mut words = tool.split_whitespace();
+        let output = words.next().and_then(|program| {
+            let args: Vec<&str> = words.collect();
+            command_output(program, &args)
+        });
+        // Only the first line, e.g. "Python 3.11.7"
+        add(
+            &format!("tool.{}", tool),
+            output.and_then(|out| out.lines().next().map(|l| l.trim().to_string())),
+        );
+    }
+
+    entries
+}
+
+pub fn append_snapshot(path: &str, entries: &[(String, String)]) -> Result<(), String> {
+    let mut file = OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(path)
+        .map_err(|e| format!("Failed to write snapshot file {}: {}", path, e))?;
+    let separator = match fs::metadata(path) {
+        Ok(meta) if meta.len() > 0 => "\n",
+        _ => "",
+    };
+    write!(file, "{}{}", separator, format_metadata(entries))
+        .map_err(|e| format!("Failed to write snapshot file {}: {}", path, e))
+}
+
+// The most recent snapshot recorded for a results file, if any
+pub fn last_snapshot(path: &str) -> Option<Vec<(String, String)>> {
+    let content = fs::read_to_string(path).ok()?;
+    let block = content
+        .split("\n\n")
+        .filter(|block| !block.trim().is_empty())
+        .last()?;
+    Some(parse_metadata(block))
+}
+
+// Differences between the environment of an earlier start and the current one, as
+// "key: before -> after" lines. The time and command line of a start are expected to
+// differ, and tools are compared only when both snapshots ran them, since each
+// invocation picks its own --snapshot commands.
+pub fn snapshot_drift(previous: &[(String, String)], current: &[(String, String)]) -> Vec<String> {
+    let value = |entries: &[(String, String)], key: &str| {
+        entries
+            .iter()
+            .find(|(k, _)| k == key)
+            .map(|(_, v)| v.clone())
+    };
+    let mut seen = Vec::new();
+    let mut drift = Vec::new();
+    for (key, _) in previous.iter().chain(current) {
+        if key == "started" || key == "invocation" || seen.contains(&key) {
+            continue;
+        }
+        seen.push(key);
+        let (before, after) = (value(previous, key), value(current, key));
+        if before == after || (key.starts_with("tool.") && (before.is_none() || after.is_none())) {
+            continue;
+        }
+        drift.push(format!(
+            "{}: {} -> {}",
+            key,
+            before.as_deref().unwrap_or("(none)"),
+            after.as_deref().unwrap_or("(none)")
+        ));
+    }
+    drift
+}
+
+// The commit checked out in the working directory, with "-dirty" appended when tracked
+// files have uncommitted changes; None outside a git repository
+pub fn git_commit() -> Option<String> {
+    let commit = command_output("git", &["rev-parse", "HEAD"])?;
+    let dirty = command_output("git", &["status", "--porcelain", "--untracked-files=no"])
+        .is_some_and(|status| !status.is_empty());
+    Some(if dirty {
+        format!("{}-dirty", commit)
+    } else {
+        commit
+    })
+}
+
+// The command line as it could be typed again; empty when used as a library
+pub fn invocation(options: &Options) -> String {
+    std::iter::once("runexp".to_string())
+        .chain(options.invocation.iter().map(|arg| shell_quote(arg)))
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+// Quote an argument for a POSIX shell unless it only contains safe characters
+pub fn shell_quote(arg: &str) -> String {
+    let plain = !arg.is_empty()
+        && arg
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || "-_=.,:/+@%".contains(c));
+    if plain {
+        arg.to_string()
+    } else {
+        format!("'{}'", arg.replace('\'', "'\\''"))
+    }
+}
+
+// Trimmed stdout of a successful command, or its stderr if stdout is empty
+// (e.g. `python --version` before Python 3.4)
+fn command_output(program: &str, args: &[&str]) -> Option<String> {
+    let output = Command::new(program).args(args).output().ok()?;
+    if !output.status.success() {
+        return None;
+    }
+    let text = if output.stdout.trim_ascii().is_empty() {
+        output.stderr
+    } else {
+        output.stdout
+    };
+    Some(String::from_utf8_lossy(&text).trim().to_string())
+}
+
+// Value of a "Name: value" line in a /proc file
+fn proc_field(path: &str, name: &str) -> Option<String> {
+    let content = fs::read_to_string(path).ok()?;
+    content.lines().find_map(|line| {
+        let (key, value) = line.split_once(':')?;
+        (key.trim() == name).then(|| value.trim().to_string())
+    })
+}
+
+fn cpu_model() -> Option<String> {
+    if cfg!(target_os = "macos") {
+        command_output("sysctl", &["-n", "machdep.cpu.brand_string"])
+    } else {
+        proc_field("/proc/cpuinfo", "model name")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_invocation_is_quoted() {
+        let options = Options {
+            invocation: ["--lr", "0.1,0.01", "bash", "-c", "echo it's $LR"]
+                .map(String::from)
+                .to_vec(),
+            ..Options::default()
+        };
+        assert_eq!(
+            invocation(&options),
+            "runexp --lr 0.1,0.01 bash -c 'echo it'\\''s $LR'"
+        );
+    }
+
+    #[test]
+    fn test_snapshot_drift() {
+        let entries = |pairs: &[(&str, &str)]| -> Vec<(String, String)> {
+            pairs
+                .iter()
+                .map(|(k, v)| (k.to_string(), v.to_string()))
+                .collect()
+        };
+        let path = std::env::temp_dir().join("runexp_test_drift.csv.snapshot");
+        let path = path.to_string_lossy().to_string();
+        let _ = fs::remove_file(&path);
+        let first = entries(&[
+            ("started", "2024-01-01T00:00:00"),
+            ("hostname", "node1"),
+            ("gpu.0", "A100"),
+            ("tool.python3 --version", "Python 3.10.4"),
+        ]);
+        append_snapshot(&path, &first).unwrap();
+        append_snapshot(&path, &entries(&[("hostname", "node1\nrack 2")])).unwrap();
+        assert_eq!(
+            last_snapshot(&path),
+            Some(entries(&[("hostname", "node1\nrack 2")]))
+        );
+        let _ = fs::remove_file(&path);
+
+        let same = entries(&[
+            ("started", "2024-02-01T00:00:00"),
+            ("hostname", "node1"),
+            ("gpu.0", "A100"),
+        ]);
+        assert!(snapshot_drift(&first, &same).is_empty());
+
+        let moved = entries(&[
+            ("hostname", "node2"),
+            ("tool.python3 --version", "Python 3.12.1"),
+        ]);
+        assert_eq!(
+            snapshot_drift(&first, &moved),
+            vec![
+                "hostname: node1 -> node2",
+                "gpu.0: A100 -> (none)",
+                "tool.python3 --version: Python 3.10.4 -> Python 3.12.1",
+            ]
+        );
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_snapshot_records_tools() {
+        let options = Options {
+            snapshot_commands: vec!["echo tool 1.2".to_string(), "false".to_string()],
+            ..Options::default()
+        };
+        let entries = take_snapshot(&options);
+        let value = |key: &str| entries.iter().find(|(k, _)| k == key).map(|(_, v)| v);
+        assert_eq!(value("os"), Some(&std::env::consts::OS.to_string()));
+        assert_eq!(value("tool.echo tool 1.2"), Some(&"tool 1.2".to_string()));
+        // Failing commands are left out
+        assert_eq!(value("tool.false"), None);
+    }
+}
@@ -0,0 +1,134 @@
+// Writer for tar archives in the ustar format, used by `runexp bundle`
+
+use std::fs::File;
+use std::io::{self, Write};
+use std::path::Path;
+use std::time::UNIX_EPOCH;
+
+pub struct TarWriter<W: Write> {
+    out: W,
+}
+
+impl<W: Write> TarWriter<W> {
+    pub fn new(out: W) -> Self {
+        TarWriter { out }
+    }
+
+    // Copy a file into the archive without reading it into memory
+    pub fn append_file(&mut self, name: &str, path: &Path) -> Result<(), String> {
+        let file =
+            File::open(path).map_err(|e| format!("Failed to read {}: {}", path.display(), e))?;
+        let metadata = file
+            .metadata()
+            .map_err(|e| format!("Failed to read {}: {}", path.display(), e))?;
+        let mtime = metadata
+            .modified()
+            .ok()
+            .and_then(|t| t.duration_since(UNIX_EPOCH).ok())
+            .map_or(0, |d| d.as_secs());
+        let size = metadata.len();
+        self.write_header(name, size, mtime)?;
+        self.write_content(file, size)
+    }
+
+    // Two empty blocks mark the end of the archive
+    pub fn finish(mut self) -> Result<W, String> {
+        self.out.write_all(&[0; 1024]).map_err(write_error)?;
+        Ok(self.out)
+    }
+
+    fn write_header(&mut self, name: &str, size: u64, mtime: u64) -> Result<(), String> {
+        // Sizes are stored as 11 octal digits
+        if size >= 1 << 33 {
+            return Err(format!("{} is too large for a tar archive", name));
+        }
+        let (prefix, name) = split_name(name)?;
+
+        let mut header = [0u8; 512];
+        let mut field = |offset: usize, value: &[u8]| {
+            header[offset..offset + value.len()].copy_from_slice(value);
+        };
+        field(0, name.as_bytes());
+        field(100, b"0000644\0");
+        field(108, b"0000000\0");
+        field(116, b"0000000\0");
+        field(124, format!("{:011o}\0", size).as_bytes());
+        field(136, format!("{:011o}\0", mtime & 0o77777777777).as_bytes());
+        field(156, b"0");
+        field(257, b"ustar\0");
+        field(263, b"00");
+        field(345, prefix.as_bytes());
+
+        // The checksum is computed with its own field filled with spaces
+        header[148..156].copy_from_slice(b"        ");
+        let checksum: u32 = header.iter().map(|&b| b as u32).sum();
+        header[148..156].copy_from_slice(format!("{:06o}\0 ", checksum).as_bytes());
+
+        self.out.write_all(&header).map_err(write_error)
+    }
+
+    fn write_content(&mut self, content: impl io::Read, size: u64) -> Result<(), String> {
+        let copied = io::copy(&mut content.take(size), &mut self.out).map_err(write_error)?;
+        if copied != size {
+            return Err("File changed while it was archived".to_string());
+        }
+        let padding = (512 - size % 512) % 512;
+        self.out
+            .write_all(&vec![0; padding as usize])
+            .map_err(write_error)
+    }
+}
+
+fn write_error(e: io::Error) -> String {
+    format!("Failed to write archive: {}", e)
+}
+
+// Names longer than 100 bytes are split at a slash into a prefix of up to 155 bytes
+fn split_name(name: &str) -> Result<(&str, &str), String> {
+    if name.len() <= 100 {
+        return Ok(("", name));
+    }
+    name.match_indices('/')
+        .map(|(i, _)| (&name[..i], &name[i + 1..]))
+        .find(|(prefix, rest)| prefix.len() <= 155 && rest.len() <= 100 && !rest.is_empty())
+        .ok_or_else(|| format!("Path too long for a tar archive: {}", name))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_tar_layout() {
+        let path = std::env::temp_dir().join("runexp_test_tar.csv");
+        std::fs::write(&path, "X,y\n1,2\n").unwrap();
+        let mut tar = TarWriter::new(Vec::new());
+        tar.append_file("sweep/results.csv", &path).unwrap();
+        let archive = tar.finish().unwrap();
+        let _ = std::fs::remove_file(&path);
+
+        // Header, one block of content, two end blocks
+        assert_eq!(archive.len(), 4 * 512);
+        assert!(archive.starts_with(b"sweep/results.csv\0"));
+        assert_eq!(&archive[124..136], b"00000000010\0");
+        assert_eq!(&archive[257..263], b"ustar\0");
+        let checksum: u32 = archive[..512]
+            .iter()
+            .enumerate()
+            .map(|(i, &b)| {
+                if (148..156).contains(&i) {
+                    32
+                } else {
+                    b as u32
+                }
+            })
+            .sum();
+        let recorded = std::str::from_utf8(&archive[148..154]).unwrap();
+        assert_eq!(u32::from_str_radix(recorded, 8).unwrap(), checksum);
+        assert_eq!(&archive[512..520], b"X,y\n1,2\n");
+
+        let long = format!("{}/{}", "d".repeat(120), "f".repeat(90));
+        assert_eq!(split_name(&long).unwrap().1, "f".repeat(90));
+        assert!(split_name(&"x".repeat(101)).is_err());
+    }
+}
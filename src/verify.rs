@@ -0,0 +1,93 @@
+use crate::error::Error;
+use crate::hash::sha256_file;
+use std::collections::HashMap;
+use std::fs;
+use std::time::SystemTime;
+
+// Input files checked against a known SHA-256 (--verify-input data.bin=sha256:HEX),
+// so results are never produced from a silently changed dataset.
+
+// Parse "PATH=sha256:HEX" (the "sha256:" prefix is optional) into (path, lowercase hex)
+pub fn parse_input_check(value: &str) -> Result<(String, String), String> {
+    let (path, checksum) = value
+        .rsplit_once('=')
+        .filter(|(path, _)| !path.is_empty())
+        .ok_or_else(|| {
+            format!(
+                "Invalid --verify-input value (expected PATH=sha256:HEX): {}",
+                value
+            )
+        })?;
+    let hex = checksum.strip_prefix("sha256:").unwrap_or(checksum);
+    if hex.len() != 64 || !hex.chars().all(|c| c.is_ascii_hexdigit()) {
+        return Err(format!(
+            "Invalid checksum for {} (expected sha256: and 64 hex digits): {}",
+            path, checksum
+        ));
+    }
+    Ok((path.to_string(), hex.to_lowercase()))
+}
+
+// Verifies the inputs, hashing a file again only when its size or modification time
+// changed since it was last found to match, so checking before every run is cheap
+#[derive(Default)]
+pub struct InputVerifier {
+    verified: HashMap<String, (u64, Option<SystemTime>)>,
+}
+
+impl InputVerifier {
+    pub fn verify(&mut self, inputs: &[(String, String)]) -> Result<(), Error> {
+        for (path, expected) in inputs {
+            let metadata = fs::metadata(path).map_err(|e| Error::io(path, e))?;
+            let stamp = (metadata.len(), metadata.modified().ok());
+            if self.verified.get(path) == Some(&stamp) {
+                continue;
+            }
+            let actual = sha256_file(path).map_err(|e| Error::io(path, e))?;
+            if actual != *expected {
+                self.verified.remove(path);
+                return Err(Error::Input {
+                    path: path.clone(),
+                    message: format!(
+                        "{} does not match its checksum: expected sha256:{}, found sha256:{}",
+                        path, expected, actual
+                    ),
+                });
+            }
+            self.verified.insert(path.clone(), stamp);
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::hash::sha256_hex;
+
+    #[test]
+    fn test_verify_inputs() {
+        let path = std::env::temp_dir().join("runexp_test_verify.bin");
+        let path = path.to_string_lossy().to_string();
+        fs::write(&path, "dataset v1").unwrap();
+
+        let value = format!(
+            "{}=sha256:{}",
+            path,
+            sha256_hex(b"dataset v1").to_uppercase()
+        );
+        let check = parse_input_check(&value).unwrap();
+        assert_eq!(check, (path.clone(), sha256_hex(b"dataset v1")));
+        assert!(parse_input_check("data.bin=sha256:abc").is_err());
+        assert!(parse_input_check("data.bin").is_err());
+
+        let mut verifier = InputVerifier::default();
+        verifier.verify(std::slice::from_ref(&check)).unwrap();
+        fs::write(&path, "dataset v2.1").unwrap();
+        let error = verifier.verify(&[check]).unwrap_err();
+        assert_eq!(error.exit_code(), 6);
+        assert!(error.to_string().contains("does not match its checksum"));
+
+        let _ = fs::remove_file(&path);
+    }
+}
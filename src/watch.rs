@@ -0,0 +1,92 @@
+use crate::hash::sha256_hex;
+use std::fs;
+use std::thread;
+use std::time::Duration;
+
+// Support for --watch: files are compared by content, so saving a file without
+// changing it (or touching it) does not start a new round.
+const POLL_INTERVAL: Duration = Duration::from_millis(500);
+// Editors often write a file in several steps; wait until it stops changing
+const SETTLE_INTERVAL: Duration = Duration::from_millis(200);
+
+// Content hash of each watched file, None while it does not exist
+pub fn file_states(files: &[String]) -> Vec<Option<String>> {
+    files
+        .iter()
+        .map(|file| fs::read(file).ok().map(|content| sha256_hex(&content)))
+        .collect()
+}
+
+// Block until one of the files differs from `states`, update `states` and return the
+// names of the changed files
+pub fn wait_for_change(files: &[String], states: &mut Vec<Option<String>>) -> Vec<String> {
+    let mut current = file_states(files);
+    while current == *states {
+        thread::sleep(POLL_INTERVAL);
+        current = file_states(files);
+    }
+    loop {
+        thread::sleep(SETTLE_INTERVAL);
+        let settled = file_states(files);
+        if settled == current {
+            break;
+        }
+        current = settled;
+    }
+
+    let changed = files
+        .iter()
+        .zip(states.iter().zip(&current))
+        .filter(|(_, (before, after))| before != after)
+        .map(|(file, _)| file.clone())
+        .collect();
+    *states = current;
+    changed
+}
+
+// Move the results of the previous round aside, so every combination runs again.
+// They stay available for comparison, e.g. with `runexp diff results.csv.prev results.csv`.
+pub fn invalidate_results(output_file: &str) -> Result<Option<String>, String> {
+    if !std::path::Path::new(output_file).exists() {
+        return Ok(None);
+    }
+    let previous = format!("{}.prev", output_file);
+    fs::rename(output_file, &previous)
+        .map_err(|e| format!("Failed to move {} to {}: {}", output_file, previous, e))?;
+    Ok(Some(previous))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_wait_for_change_compares_content() {
+        let dir = std::env::temp_dir();
+        let files: Vec<String> = ["runexp_test_watch_a.txt", "runexp_test_watch_b.txt"]
+            .iter()
+            .map(|name| dir.join(name).to_string_lossy().to_string())
+            .collect();
+        fs::write(&files[0], "lr = 0.1").unwrap();
+        let _ = fs::remove_file(&files[1]);
+        let mut states = file_states(&files);
+        assert!(states[0].is_some() && states[1].is_none());
+
+        let writer = {
+            let files = files.clone();
+            thread::spawn(move || {
+                // Rewriting the same content is not a change
+                fs::write(&files[0], "lr = 0.1").unwrap();
+                thread::sleep(Duration::from_millis(700));
+                fs::write(&files[1], "new").unwrap();
+            })
+        };
+        assert_eq!(wait_for_change(&files, &mut states), vec![files[1].clone()]);
+        assert_eq!(states, file_states(&files));
+        writer.join().unwrap();
+
+        for file in &files {
+            let _ = fs::remove_file(file);
+        }
+    }
+}